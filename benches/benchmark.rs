@@ -80,6 +80,22 @@ fn call_lua_function(c: &mut Criterion) {
     });
 }
 
+fn call_lua_function_many(c: &mut Criterion) {
+    let lua = Lua::new();
+    let function = lua
+        .load("function(a, b, c) return a + b + c end")
+        .eval::<LuaFunction>()
+        .unwrap();
+
+    c.bench_function("call Lua function [sum] 3 1000", |b| {
+        b.iter(|| {
+            for i in 0..1000 {
+                let _result: i64 = function.call((i, i + 1, i + 2)).unwrap();
+            }
+        });
+    });
+}
+
 fn call_sum_callback(c: &mut Criterion) {
     let lua = Lua::new();
     let callback = lua
@@ -219,6 +235,51 @@ fn call_userdata_method(c: &mut Criterion) {
     });
 }
 
+fn call_bound_userdata_method(c: &mut Criterion) {
+    struct UserData(i64);
+    impl LuaUserData for UserData {
+        fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("method", |_, this, ()| Ok(this.0));
+        }
+    }
+
+    let lua = Lua::new();
+    let userdata = lua.create_userdata(UserData(10)).unwrap();
+
+    c.bench_function("call [userdata method by name] 10", |b| {
+        b.iter_batched(
+            || collect_gc_twice(&lua),
+            |_| {
+                for _ in 0..10 {
+                    let index = userdata.get_metatable().unwrap();
+                    let method: LuaFunction = match index.get(LuaMetaMethod::Index).unwrap() {
+                        LuaValue::Table(methods) => methods.get("method").unwrap(),
+                        LuaValue::Function(index) => {
+                            index.call((userdata.clone(), "method")).unwrap()
+                        }
+                        _ => unreachable!(),
+                    };
+                    method.call::<_, i64>(userdata.clone()).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    let method = userdata.bind_method("method").unwrap();
+    c.bench_function("call [bound userdata method] 10", |b| {
+        b.iter_batched(
+            || collect_gc_twice(&lua),
+            |_| {
+                for _ in 0..10 {
+                    method.call::<_, i64>(()).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
 fn call_async_userdata_method(c: &mut Criterion) {
     #[derive(Clone, Copy)]
     struct UserData(i64);
@@ -259,12 +320,14 @@ criterion_group! {
         create_array,
         create_string_table,
         call_lua_function,
+        call_lua_function_many,
         call_sum_callback,
         call_async_sum_callback,
         call_concat_callback,
         create_registry_values,
         create_userdata,
         call_userdata_method,
+        call_bound_userdata_method,
         call_async_userdata_method,
 }
 
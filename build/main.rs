@@ -224,6 +224,9 @@ fn main() {
     #[cfg(all(feature = "lua51", feature = "luajit"))]
     compile_error!("You can enable only one of the features: lua54, lua53, lua52, lua51, luajit");
 
+    #[cfg(all(feature = "tokio", not(feature = "async")))]
+    compile_error!("The tokio feature requires the async feature to be enabled");
+
     // We don't support "vendored module" mode on windows
     #[cfg(all(feature = "vendored", feature = "module", target_os = "windows"))]
     compile_error!(
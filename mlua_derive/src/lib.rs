@@ -1,7 +1,10 @@
 use proc_macro::TokenStream;
 use proc_macro2::{Ident, Span};
 use quote::quote;
-use syn::{parse_macro_input, AttributeArgs, Error, ItemFn};
+use syn::{
+    parse_macro_input, Attribute, AttributeArgs, Data, DeriveInput, Error, Fields, ItemFn, Meta,
+    NestedMeta,
+};
 
 #[cfg(feature = "macros")]
 use {
@@ -39,6 +42,228 @@ pub fn lua_module(attr: TokenStream, item: TokenStream) -> TokenStream {
     wrapped.into()
 }
 
+#[proc_macro_derive(LuaIntEnum)]
+pub fn lua_int_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = &input.ident;
+    let name = ident.to_string();
+
+    let variants = match input.data {
+        Data::Enum(ref data) => &data.variants,
+        _ => {
+            let err = Error::new(
+                Span::call_site(),
+                "LuaIntEnum can only be derived for enums",
+            );
+            return err.to_compile_error().into();
+        }
+    };
+    if !input.generics.params.is_empty() {
+        let err = Error::new_spanned(&input.generics, "LuaIntEnum does not support generics");
+        return err.to_compile_error().into();
+    }
+    if let Some(v) = variants.iter().find(|v| !matches!(v.fields, Fields::Unit)) {
+        let err = Error::new_spanned(v, "LuaIntEnum variants cannot have fields");
+        return err.to_compile_error().into();
+    }
+
+    let idents = variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
+    let expected = quote! {
+        [#(#ident::#idents as ::mlua::Integer),*]
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<::std::vec::Vec<_>>()
+            .join(", ")
+    };
+
+    let wrapped = quote! {
+        impl<'lua> ::mlua::ToLua<'lua> for #ident {
+            fn to_lua(self, _: &'lua ::mlua::Lua) -> ::mlua::Result<::mlua::Value<'lua>> {
+                ::std::result::Result::Ok(::mlua::Value::Integer(self as ::mlua::Integer))
+            }
+        }
+
+        impl<'lua> ::mlua::FromLua<'lua> for #ident {
+            fn from_lua(value: ::mlua::Value<'lua>, _: &'lua ::mlua::Lua) -> ::mlua::Result<Self> {
+                let i = match value {
+                    ::mlua::Value::Integer(i) => i,
+                    ::mlua::Value::Number(n) if n as ::mlua::Integer as ::mlua::Number == n => {
+                        n as ::mlua::Integer
+                    }
+                    _ => {
+                        return ::std::result::Result::Err(::mlua::Error::FromLuaConversionError {
+                            from: value.type_name(),
+                            to: #name,
+                            message: ::std::option::Option::Some("expected integer".to_string()),
+                        })
+                    }
+                };
+                #(
+                    if i == #ident::#idents as ::mlua::Integer {
+                        return ::std::result::Result::Ok(#ident::#idents);
+                    }
+                )*
+                ::std::result::Result::Err(::mlua::Error::FromLuaConversionError {
+                    from: "integer",
+                    to: #name,
+                    message: ::std::option::Option::Some(format!(
+                        "invalid value {}, expected one of {}",
+                        i, #expected
+                    )),
+                })
+            }
+        }
+    };
+
+    wrapped.into()
+}
+
+#[proc_macro_derive(FromLua, attributes(mlua))]
+pub fn from_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = &input.ident;
+    let name = ident.to_string();
+
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                let err = Error::new_spanned(
+                    &data.fields,
+                    "FromLua can only be derived for structs with named fields",
+                );
+                return err.to_compile_error().into();
+            }
+        },
+        _ => {
+            let err = Error::new(Span::call_site(), "FromLua can only be derived for structs");
+            return err.to_compile_error().into();
+        }
+    };
+    if !input.generics.params.is_empty() {
+        let err = Error::new_spanned(&input.generics, "FromLua does not support generics");
+        return err.to_compile_error().into();
+    }
+
+    let deny_unknown_fields = match mlua_flags(&input.attrs, &["deny_unknown_fields"]) {
+        Ok(flags) => flags.contains(&"deny_unknown_fields"),
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let mut inits = Vec::with_capacity(fields.len());
+    let mut field_names = Vec::with_capacity(fields.len());
+    let mut any_flatten = false;
+    for field in fields {
+        let flatten = match mlua_flags(&field.attrs, &["flatten"]) {
+            Ok(flags) => flags.contains(&"flatten"),
+            Err(err) => return err.to_compile_error().into(),
+        };
+        if flatten && deny_unknown_fields {
+            let err = Error::new_spanned(
+                field,
+                "`deny_unknown_fields` is not supported together with `flatten`",
+            );
+            return err.to_compile_error().into();
+        }
+        let field_ident = field.ident.as_ref().expect("named field");
+        let field_ty = &field.ty;
+        let field_name = field_ident.to_string();
+        any_flatten |= flatten;
+        inits.push(if flatten {
+            quote! {
+                #field_ident: <#field_ty as ::mlua::FromLua<'lua>>::from_lua(
+                    ::mlua::Value::Table(table.clone()),
+                    lua,
+                )?
+            }
+        } else {
+            quote! { #field_ident: table.get::<_, #field_ty>(#field_name)? }
+        });
+        field_names.push(field_name);
+    }
+
+    // Only flattened fields need the `Lua` instance
+    let lua = if any_flatten {
+        quote! { lua }
+    } else {
+        quote! { _ }
+    };
+
+    let check_unknown_fields = if deny_unknown_fields {
+        quote! {
+            for pair in table.clone().pairs::<::mlua::Value, ::mlua::Value>() {
+                let (key, _) = pair?;
+                let known = match key {
+                    ::mlua::Value::String(ref key) => {
+                        [#(#field_names),*].iter().any(|name| name.as_bytes() == key.as_bytes())
+                    }
+                    _ => false,
+                };
+                if !known {
+                    return ::std::result::Result::Err(::mlua::Error::FromLuaConversionError {
+                        from: "table",
+                        to: #name,
+                        message: ::std::option::Option::Some(format!(
+                            "unknown field `{}`",
+                            key.to_string_lossy()?
+                        )),
+                    });
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let wrapped = quote! {
+        impl<'lua> ::mlua::FromLua<'lua> for #ident {
+            fn from_lua(value: ::mlua::Value<'lua>, #lua: &'lua ::mlua::Lua) -> ::mlua::Result<Self> {
+                let table = match value {
+                    ::mlua::Value::Table(table) => table,
+                    _ => {
+                        return ::std::result::Result::Err(::mlua::Error::FromLuaConversionError {
+                            from: value.type_name(),
+                            to: #name,
+                            message: ::std::option::Option::Some("expected table".to_string()),
+                        })
+                    }
+                };
+                #check_unknown_fields
+                ::std::result::Result::Ok(#ident {
+                    #(#inits,)*
+                })
+            }
+        }
+    };
+
+    wrapped.into()
+}
+
+// Returns the `#[mlua(...)]` flags set in `attrs`, failing on flags missing from `allowed`
+fn mlua_flags(attrs: &[Attribute], allowed: &[&'static str]) -> Result<Vec<&'static str>, Error> {
+    let mut flags = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("mlua")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in &list.nested {
+                    let flag = match nested {
+                        NestedMeta::Meta(Meta::Path(path)) => {
+                            allowed.iter().find(|flag| path.is_ident(flag))
+                        }
+                        _ => None,
+                    };
+                    match flag {
+                        Some(flag) => flags.push(*flag),
+                        None => return Err(Error::new_spanned(nested, "unknown mlua attribute")),
+                    }
+                }
+            }
+            meta => return Err(Error::new_spanned(meta, "expected `#[mlua(...)]`")),
+        }
+    }
+    Ok(flags)
+}
+
 #[cfg(feature = "macros")]
 fn to_ident(tt: &TokenTree) -> TokenStream2 {
     let s: TokenStream = tt.clone().into();
@@ -2,21 +2,29 @@
 
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::ffi::{CStr, CString};
+use std::convert::TryInto;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::hash::{BuildHasher, Hash};
 use std::string::String as StdString;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bstr::{BStr, BString};
 use num_traits::cast;
 
+#[cfg(feature = "chrono")]
+use {
+    crate::lua::DateTimeFormat,
+    chrono::{DateTime, SecondsFormat, Utc},
+};
+
 use crate::error::{Error, Result};
 use crate::function::Function;
 use crate::lua::Lua;
 use crate::string::String;
 use crate::table::Table;
 use crate::thread::Thread;
-use crate::types::{LightUserData, MaybeSend};
-use crate::userdata::{AnyUserData, UserData};
+use crate::types::{Integer, LightUserData, MaybeSend};
+use crate::userdata::{AnyUserData, UserData, WeakUserData};
 use crate::value::{FromLua, Nil, ToLua, Value};
 
 impl<'lua> ToLua<'lua> for Value<'lua> {
@@ -125,6 +133,27 @@ impl<'lua> FromLua<'lua> for AnyUserData<'lua> {
     }
 }
 
+impl<'lua> ToLua<'lua> for WeakUserData<'lua> {
+    fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Table(Table(self.0)))
+    }
+}
+
+impl<'lua> FromLua<'lua> for WeakUserData<'lua> {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<WeakUserData<'lua>> {
+        match value {
+            Value::Table(t) if t.get_metatable() == Some(lua.weak_userdata_token_metatable()?) => {
+                Ok(WeakUserData(t.0))
+            }
+            _ => Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "WeakUserData",
+                message: Some("expected a weak userdata handle".to_string()),
+            }),
+        }
+    }
+}
+
 impl<'lua, T: 'static + MaybeSend + UserData> ToLua<'lua> for T {
     fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
         Ok(Value::UserData(lua.create_userdata(self)?))
@@ -231,6 +260,29 @@ impl<'lua> ToLua<'lua> for Cow<'_, str> {
     }
 }
 
+impl<'lua> ToLua<'lua> for Cow<'_, [u8]> {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::String(lua.create_string(&*self)?))
+    }
+}
+
+impl<'lua> ToLua<'lua> for &OsStr {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        let s = self.to_str().ok_or_else(|| Error::ToLuaConversionError {
+            from: "OsStr",
+            to: "string",
+            message: Some("invalid utf-8 encoding".to_string()),
+        })?;
+        Ok(Value::String(lua.create_string(s)?))
+    }
+}
+
+impl<'lua> ToLua<'lua> for OsString {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        self.as_os_str().to_lua(lua)
+    }
+}
+
 impl<'lua> ToLua<'lua> for Box<str> {
     fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
         Ok(Value::String(lua.create_string(&*self)?))
@@ -344,6 +396,12 @@ macro_rules! lua_convert_int {
                 let ty = value.type_name();
                 (if let Some(i) = lua.coerce_integer(value.clone())? {
                     cast(i)
+                } else if !lua.coercion_policy().allows_integer_float() {
+                    return Err(Error::FromLuaConversionError {
+                        from: ty,
+                        to: stringify!($x),
+                        message: Some("expected integer".to_string()),
+                    });
                 } else {
                     cast(lua.coerce_number(value)?.ok_or_else(|| {
                         Error::FromLuaConversionError {
@@ -416,6 +474,136 @@ macro_rules! lua_convert_float {
 lua_convert_float!(f32);
 lua_convert_float!(f64);
 
+// Converts a number of seconds to a `Duration`, `to` names the target type in errors.
+fn duration_from_lua<'lua>(
+    value: Value<'lua>,
+    lua: &'lua Lua,
+    to: &'static str,
+) -> Result<(Duration, bool)> {
+    let ty = value.type_name();
+    let secs = match value {
+        Value::Integer(i) => {
+            let duration = Duration::from_secs((i as i128).unsigned_abs() as u64);
+            return Ok((duration, i < 0));
+        }
+        value => lua
+            .coerce_number(value)?
+            .ok_or_else(|| Error::FromLuaConversionError {
+                from: ty,
+                to,
+                message: Some("expected number or string coercible to number".to_string()),
+            })?,
+    };
+    if !secs.is_finite() || secs.abs() >= u64::MAX as f64 {
+        return Err(Error::FromLuaConversionError {
+            from: ty,
+            to,
+            message: Some("number out of range".to_string()),
+        });
+    }
+    Ok((Duration::from_secs_f64(secs.abs()), secs < 0.0))
+}
+
+impl<'lua> ToLua<'lua> for Duration {
+    fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Number(self.as_secs_f64()))
+    }
+}
+
+impl<'lua> FromLua<'lua> for Duration {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<Self> {
+        let ty = value.type_name();
+        match duration_from_lua(value, lua, "Duration")? {
+            (duration, false) => Ok(duration),
+            (_, true) => Err(Error::FromLuaConversionError {
+                from: ty,
+                to: "Duration",
+                message: Some("negative duration".to_string()),
+            }),
+        }
+    }
+}
+
+impl<'lua> ToLua<'lua> for SystemTime {
+    fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
+        let secs = match self.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs_f64(),
+            Err(err) => -err.duration().as_secs_f64(),
+        };
+        Ok(Value::Number(secs))
+    }
+}
+
+impl<'lua> FromLua<'lua> for SystemTime {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<Self> {
+        let ty = value.type_name();
+        let time = match duration_from_lua(value, lua, "SystemTime")? {
+            (duration, false) => UNIX_EPOCH.checked_add(duration),
+            (duration, true) => UNIX_EPOCH.checked_sub(duration),
+        };
+        time.ok_or_else(|| Error::FromLuaConversionError {
+            from: ty,
+            to: "SystemTime",
+            message: Some("time out of range".to_string()),
+        })
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<'lua> ToLua<'lua> for DateTime<Utc> {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        match lua.datetime_format() {
+            DateTimeFormat::Timestamp => match self.timestamp_subsec_nanos() {
+                0 => cast(self.timestamp()).map(Value::Integer).ok_or_else(|| {
+                    Error::ToLuaConversionError {
+                        from: "DateTime",
+                        to: "integer",
+                        message: Some("timestamp out of range".to_string()),
+                    }
+                }),
+                nanos => Ok(Value::Number(
+                    self.timestamp() as f64 + nanos as f64 / 1_000_000_000.0,
+                )),
+            },
+            DateTimeFormat::Iso8601 => self
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                .to_lua(lua),
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<'lua> FromLua<'lua> for DateTime<Utc> {
+    fn from_lua(value: Value<'lua>, _: &'lua Lua) -> Result<Self> {
+        let ty = value.type_name();
+        let conversion_error = |message: &str| Error::FromLuaConversionError {
+            from: ty,
+            to: "DateTime",
+            message: Some(message.to_string()),
+        };
+        let datetime = match value {
+            #[allow(clippy::useless_conversion)]
+            Value::Integer(i) => DateTime::from_timestamp(i.into(), 0),
+            Value::Number(n) if n.is_finite() && n.abs() < i64::MAX as f64 => {
+                let secs = n.floor();
+                let nanos = ((n - secs) * 1_000_000_000.0) as u32;
+                DateTime::from_timestamp(secs as i64, nanos.min(999_999_999))
+            }
+            Value::Number(_) => None,
+            Value::String(s) => {
+                let datetime = s
+                    .to_str()
+                    .ok()
+                    .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                    .ok_or_else(|| conversion_error("expected an ISO 8601 (RFC 3339) string"))?;
+                Some(datetime.with_timezone(&Utc))
+            }
+            _ => return Err(conversion_error("expected number or string")),
+        };
+        datetime.ok_or_else(|| conversion_error("time out of range"))
+    }
+}
+
 impl<'lua, T> ToLua<'lua> for &[T]
 where
     T: Clone + ToLua<'lua>,
@@ -427,39 +615,71 @@ where
     }
 }
 
-macro_rules! lua_convert_array {
-    ($($N:literal)+) => {
-        $(
-            impl<'lua, T> ToLua<'lua> for [T; $N]
-            where
-                T: Clone + ToLua<'lua>,
-            {
-                fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
-                    (&self).to_lua(lua)
-                }
+impl<'lua, T, const N: usize> ToLua<'lua> for [T; N]
+where
+    T: ToLua<'lua>,
+{
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Table(lua.create_sequence_from(self)?))
+    }
+}
+
+impl<'lua, T, const N: usize> ToLua<'lua> for &[T; N]
+where
+    T: Clone + ToLua<'lua>,
+{
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Table(
+            lua.create_sequence_from(self.iter().cloned())?,
+        ))
+    }
+}
+
+impl<'lua, T, const N: usize> FromLua<'lua> for [T; N]
+where
+    T: FromLua<'lua>,
+{
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<Self> {
+        let table = match value {
+            Value::Table(table) => table,
+            _ => {
+                return Err(Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "array",
+                    message: Some("expected table".to_string()),
+                })
             }
+        };
+
+        let len = table.len()?;
+        if len != N as Integer {
+            return Err(Error::FromLuaConversionError {
+                from: "table",
+                to: "array",
+                message: Some(format!("expected table of length {}, got {}", N, len)),
+            });
+        }
 
-            impl<'lua, T> ToLua<'lua> for &[T; $N]
-            where
-                T: Clone + ToLua<'lua>,
-            {
-                fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
-                    Ok(Value::Table(
-                        lua.create_sequence_from(self.iter().cloned())?,
-                    ))
+        let mut values = Vec::with_capacity(N);
+        for i in 1..=N {
+            match table.get::<_, Value>(i)? {
+                Nil => {
+                    return Err(Error::FromLuaConversionError {
+                        from: "table",
+                        to: "array",
+                        message: Some(format!("missing value at index {}", i)),
+                    })
                 }
+                value => values.push(T::from_lua(value, lua)?),
             }
-        )+
+        }
+        Ok(mlua_expect!(
+            values.try_into().ok(),
+            "array length mismatch"
+        ))
     }
 }
 
-lua_convert_array! {
-    0  1  2  3  4  5  6  7  8  9
-   10 11 12 13 14 15 16 17 18 19
-   20 21 22 23 24 25 26 27 28 29
-   30 31 32
-}
-
 impl<'lua, T: ToLua<'lua>> ToLua<'lua> for Box<[T]> {
     fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
         Ok(Value::Table(lua.create_sequence_from(self.into_vec())?))
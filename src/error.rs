@@ -70,6 +70,23 @@ pub enum Error {
     StackError,
     /// Too many arguments to `Function::bind`
     BindError,
+    /// Lua code has exceeded the maximum call depth set by [`Lua::set_max_lua_call_depth`].
+    ///
+    /// [`Lua::set_max_lua_call_depth`]: struct.Lua.html#method.set_max_lua_call_depth
+    RecursionLimit,
+    /// Lua code has executed more VM instructions than allowed by [`Lua::set_instruction_limit`].
+    ///
+    /// [`Lua::set_instruction_limit`]: struct.Lua.html#method.set_instruction_limit
+    InstructionLimitReached,
+    /// Creating a userdata would exceed the limit set by [`Lua::set_max_userdata`].
+    ///
+    /// [`Lua::set_max_userdata`]: struct.Lua.html#method.set_max_userdata
+    UserDataLimitReached,
+    /// An asynchronous call did not complete within the time allowed by
+    /// [`Function::call_async_timeout`].
+    ///
+    /// [`Function::call_async_timeout`]: struct.Function.html#method.call_async_timeout
+    Timeout,
     /// A Rust value could not be converted to a Lua value.
     ToLuaConversionError {
         /// Name of the Rust type that could not be converted.
@@ -215,6 +232,10 @@ impl fmt::Display for Error {
                 fmt,
                 "too many arguments to Function::bind"
             ),
+            Error::RecursionLimit => write!(fmt, "maximum Lua call depth exceeded"),
+            Error::InstructionLimitReached => write!(fmt, "instruction limit reached"),
+            Error::UserDataLimitReached => write!(fmt, "too many userdata"),
+            Error::Timeout => write!(fmt, "operation timed out"),
             Error::ToLuaConversionError { from, to, ref message } => {
                 write!(fmt, "error converting {} to Lua {}", from, to)?;
                 match *message {
@@ -278,6 +299,83 @@ impl Error {
     pub fn external<T: Into<Box<dyn StdError + Send + Sync>>>(err: T) -> Error {
         Error::ExternalError(err.into().into())
     }
+
+    /// Returns the frames of the Lua traceback attached to a [`CallbackError`], innermost first.
+    ///
+    /// The raw traceback string is still available in the `traceback` field. Lines that are not
+    /// recognized as stack frames (like `...` for skipped levels) are left out.
+    ///
+    /// Returns `None` for other kinds of errors.
+    ///
+    /// [`CallbackError`]: #variant.CallbackError
+    pub fn traceback_frames(&self) -> Option<Vec<TracebackFrame>> {
+        match *self {
+            Error::CallbackError { ref traceback, .. } => Some(
+                traceback
+                    .lines()
+                    .filter_map(TracebackFrame::parse)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A single frame of a Lua stack traceback.
+///
+/// Returned by [`Error::traceback_frames`].
+///
+/// [`Error::traceback_frames`]: enum.Error.html#method.traceback_frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracebackFrame {
+    /// Short source of the function, eg. `script.lua`, `[string "..."]`, or `[C]` for functions
+    /// implemented in C or Rust.
+    pub source: StdString,
+    /// Line that was executing, if known.
+    pub line: Option<u32>,
+    /// Name of the function, if known.
+    pub name: Option<StdString>,
+}
+
+impl TracebackFrame {
+    /// Returns `true` if the frame belongs to a function implemented in C or Rust.
+    pub fn is_c(&self) -> bool {
+        self.source == "[C]"
+    }
+
+    // Parses a line produced by `luaL_traceback`, which looks like `\tsource:line: in what 'name'`
+    // (the line number is omitted for C functions, and Lua 5.1 uses `?` as description).
+    fn parse(line: &str) -> Option<TracebackFrame> {
+        let line = line.strip_prefix('\t')?;
+        // Sources like `[string "a: b"]` may contain the separator, so skip them
+        let skip = if line.starts_with('[') {
+            line.find("]:")? + 1
+        } else {
+            0
+        };
+        let sep = skip + line[skip..].find(": ")?;
+        let (location, description) = (&line[..sep], &line[sep + 2..]);
+
+        let (source, line_no) = match location.rfind(':') {
+            Some(i) if i >= skip => match location[i + 1..].parse() {
+                Ok(n) => (&location[..i], Some(n)),
+                Err(_) => (location, None),
+            },
+            _ => (location, None),
+        };
+        let name = match (description.find('\''), description.rfind('\'')) {
+            (Some(start), Some(end)) if start < end => {
+                Some(description[start + 1..end].to_string())
+            }
+            _ => None,
+        };
+
+        Some(TracebackFrame {
+            source: source.to_string(),
+            line: line_no,
+            name,
+        })
+    }
 }
 
 pub trait ExternalError {
@@ -321,6 +419,30 @@ impl std::convert::From<Utf8Error> for Error {
     }
 }
 
+/// Wraps an [`anyhow::Error`] into an [`Error::ExternalError`].
+///
+/// This allows using `?` on `anyhow::Result` values inside of Rust callbacks. The `anyhow` error
+/// chain is preserved and available from the [`Error::CallbackError`] cause when the error comes
+/// back from Lua.
+///
+/// The opposite conversion is provided by `anyhow` itself, since `Error` implements
+/// [`std::error::Error`]. For an [`Error::CallbackError`], the Lua traceback is part of the
+/// message and the original error follows it in the chain.
+///
+/// Requires `feature = "anyhow"`
+///
+/// [`anyhow::Error`]: https://docs.rs/anyhow/1.0/anyhow/struct.Error.html
+/// [`Error::ExternalError`]: enum.Error.html#variant.ExternalError
+/// [`Error::CallbackError`]: enum.Error.html#variant.CallbackError
+/// [`std::error::Error`]: https://doc.rust-lang.org/std/error/trait.Error.html
+#[cfg(feature = "anyhow")]
+#[cfg_attr(docsrs, doc(cfg(feature = "anyhow")))]
+impl std::convert::From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        Error::external(err)
+    }
+}
+
 #[cfg(feature = "serialize")]
 impl serde::ser::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
@@ -1,16 +1,24 @@
+use std::ffi::CStr;
+use std::mem;
 use std::os::raw::{c_int, c_void};
 use std::ptr;
 use std::slice;
+use std::string::String as StdString;
 
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::lua::{CallPolicyGuard, CoercionPolicy, CoercionPolicyGuard};
+use crate::table::{Table, TableSequence};
 use crate::types::LuaRef;
 use crate::util::{assert_stack, check_stack, error_traceback, pop_error, protect_lua, StackGuard};
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, ToLuaMulti, Value};
 
 #[cfg(feature = "async")]
 use {futures_core::future::LocalBoxFuture, futures_util::future};
 
+#[cfg(feature = "timeout")]
+use {futures_timer::Delay, futures_util::future::Either, std::time::Duration};
+
 /// Handle to an internal Lua function.
 #[derive(Clone, Debug)]
 pub struct Function<'lua>(pub(crate) LuaRef<'lua>);
@@ -59,7 +67,7 @@ impl<'lua> Function<'lua> {
     pub fn call<A: ToLuaMulti<'lua>, R: FromLuaMulti<'lua>>(&self, args: A) -> Result<R> {
         let lua = self.0.lua;
 
-        let args = args.to_lua_multi(lua)?;
+        let mut args = args.to_lua_multi(lua)?;
         let nargs = args.len() as c_int;
 
         let results = unsafe {
@@ -69,15 +77,16 @@ impl<'lua> Function<'lua> {
             ffi::lua_pushcfunction(lua.state, error_traceback);
             let stack_start = ffi::lua_gettop(lua.state);
             lua.push_ref(&self.0);
-            for arg in args {
+            for arg in args.drain_all() {
                 lua.push_value(arg)?;
             }
+            lua.cache_multivalue(args);
             let ret = ffi::lua_pcall(lua.state, nargs, ffi::LUA_MULTRET, stack_start);
             if ret != ffi::LUA_OK {
                 return Err(pop_error(lua.state, ret));
             }
             let nresults = ffi::lua_gettop(lua.state) - stack_start;
-            let mut results = MultiValue::new();
+            let mut results = lua.new_or_cached_multivalue();
             assert_stack(lua.state, 2);
             for _ in 0..nresults {
                 results.push_front(lua.pop_value());
@@ -88,6 +97,136 @@ impl<'lua> Function<'lua> {
         R::from_lua_multi(results, lua)
     }
 
+    /// Calls the function, passing `args` as function arguments, and returns all of its results.
+    ///
+    /// This is the same as `call::<_, MultiValue>`, useful when the number of returned values
+    /// varies between calls.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let find: Function = lua.load("string.find").eval()?;
+    ///
+    /// assert_eq!(find.call_raw(("hello", "ll"))?.len(), 2);
+    /// assert_eq!(find.call_raw(("hello", "x"))?.len(), 1);
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn call_raw<A: ToLuaMulti<'lua>>(&self, args: A) -> Result<MultiValue<'lua>> {
+        self.call(args)
+    }
+
+    /// Calls the function, passing the values of `args` in order as function arguments, and returns
+    /// all of its results.
+    ///
+    /// This is useful when the arguments are built at runtime, for example into a `Vec<Value>`,
+    /// as the values are borrowed rather than converted from a tuple or moved into a
+    /// [`MultiValue`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let concat: Function = lua.load("function(...) return table.concat({...}, ',') end").eval()?;
+    ///
+    /// let args = (1..=3).map(Value::Integer).collect::<Vec<_>>();
+    /// let result = concat.call_slice(&args)?;
+    /// assert_eq!(lua.unpack_multi::<String>(result)?, "1,2,3");
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`MultiValue`]: struct.MultiValue.html
+    pub fn call_slice(&self, args: &[Value<'lua>]) -> Result<MultiValue<'lua>> {
+        let lua = self.0.lua;
+        let nargs = args.len() as c_int;
+
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, nargs + 3)?;
+
+            ffi::lua_pushcfunction(lua.state, error_traceback);
+            let stack_start = ffi::lua_gettop(lua.state);
+            lua.push_ref(&self.0);
+            for arg in args {
+                lua.push_value_ref(arg)?;
+            }
+            let ret = ffi::lua_pcall(lua.state, nargs, ffi::LUA_MULTRET, stack_start);
+            if ret != ffi::LUA_OK {
+                return Err(pop_error(lua.state, ret));
+            }
+            let nresults = ffi::lua_gettop(lua.state) - stack_start;
+            let mut results = lua.new_or_cached_multivalue();
+            assert_stack(lua.state, 2);
+            for _ in 0..nresults {
+                results.push_front(lua.pop_value());
+            }
+            ffi::lua_pop(lua.state, 1);
+            Ok(results)
+        }
+    }
+
+    /// Calls the function like [`call`], applying `policy` to value conversions made during the
+    /// call.
+    ///
+    /// The policy controls whether strings and numbers, or integers and floats, are implicitly
+    /// converted to each other when Lua values are converted to Rust types. If the function was
+    /// created from Rust, the policy applies to the conversion of its arguments and to
+    /// conversions made while it runs. It also applies to the conversion of the returned values.
+    /// Other Rust functions called during the call, for example from Lua code, are not affected,
+    /// and neither is Lua code itself: Lua operators coerce values as usual.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{CoercionPolicy, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let double = lua.create_function(|_, n: i64| Ok(n * 2))?;
+    ///
+    /// assert_eq!(double.call_with_policy::<_, i64>("21", CoercionPolicy::LENIENT)?, 42);
+    /// assert!(double.call_with_policy::<_, i64>("21", CoercionPolicy::STRICT).is_err());
+    /// assert_eq!(double.call_with_policy::<_, i64>(21, CoercionPolicy::STRICT)?, 42);
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`call`]: #method.call
+    pub fn call_with_policy<A: ToLuaMulti<'lua>, R: FromLuaMulti<'lua>>(
+        &self,
+        args: A,
+        policy: CoercionPolicy,
+    ) -> Result<R> {
+        let lua = self.0.lua;
+        // Rust functions keep their `Callback` userdata as the first upvalue
+        let callback = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 2)?;
+
+            lua.push_ref(&self.0);
+            if ffi::lua_getupvalue(lua.state, -1, 1).is_null() {
+                ptr::null()
+            } else {
+                ffi::lua_touserdata(lua.state, -1) as *const c_void
+            }
+        };
+
+        let results = {
+            let _call_policy = CallPolicyGuard::new(callback, policy);
+            self.call::<_, MultiValue>(args)?
+        };
+        let _policy = CoercionPolicyGuard::new(policy);
+        R::from_lua_multi(results, lua)
+    }
+
     /// Returns a Feature that, when polled, calls `self`, passing `args` as function arguments,
     /// and drives the execution.
     ///
@@ -132,11 +271,80 @@ impl<'lua> Function<'lua> {
         }
     }
 
+    /// Returns a Future that, when polled, calls `self`, passing `args` as function arguments,
+    /// and fails with [`Error::Timeout`] if the call does not complete within `timeout`.
+    ///
+    /// Works like [`call_async`], racing the call against a timer. If the timer fires first, the
+    /// coroutine running the function is abandoned in its suspended state and later garbage
+    /// collected. Side effects performed by the function before the timeout (eg. modified
+    /// globals) are not rolled back, and pending Rust futures awaited by the function are
+    /// dropped without being polled to completion.
+    ///
+    /// The timeout is only noticed when the function yields, that is while it awaits an async
+    /// Rust function. Lua code that runs without yielding (eg. a CPU-bound loop) is not
+    /// interrupted, use [`Lua::set_instruction_limit`] or [`Lua::set_hook`] to bound it.
+    ///
+    /// Requires `feature = "timeout"`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use futures_timer::Delay;
+    /// # use mlua::{Error, Lua, Result};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    ///
+    /// let sleep = lua.create_async_function(move |_lua, n: u64| async move {
+    ///     Delay::new(Duration::from_millis(n)).await;
+    ///     Ok(())
+    /// })?;
+    ///
+    /// sleep.call_async_timeout::<_, ()>(10, Duration::from_secs(1)).await?;
+    /// match sleep.call_async_timeout::<_, ()>(1000, Duration::from_millis(10)).await {
+    ///     Err(Error::Timeout) => {}
+    ///     r => panic!("expected timeout, got {:?}", r),
+    /// }
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::Timeout`]: enum.Error.html#variant.Timeout
+    /// [`call_async`]: #method.call_async
+    /// [`Lua::set_instruction_limit`]: struct.Lua.html#method.set_instruction_limit
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    #[cfg(feature = "timeout")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "timeout")))]
+    pub fn call_async_timeout<'fut, A, R>(
+        &self,
+        args: A,
+        timeout: Duration,
+    ) -> LocalBoxFuture<'fut, Result<R>>
+    where
+        'lua: 'fut,
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua> + 'fut,
+    {
+        let call = self.call_async(args);
+        Box::pin(async move {
+            match future::select(call, Delay::new(timeout)).await {
+                Either::Left((result, _)) => result,
+                Either::Right(((), _)) => Err(Error::Timeout),
+            }
+        })
+    }
+
     /// Returns a function that, when called, calls `self`, passing `args` as the first set of
     /// arguments.
     ///
     /// If any arguments are passed to the returned function, they will be passed after `args`.
     ///
+    /// The returned function is a C closure holding `self` and `args` as upvalues, so no Lua
+    /// code is interpreted and no argument list is built on the Rust side when it is called. It is
+    /// a regular Lua function and can be stored in the registry or passed to Lua like any other.
+    ///
     /// # Examples
     ///
     /// ```
@@ -206,6 +414,74 @@ impl<'lua> Function<'lua> {
         }
     }
 
+    /// Converts the function into a boxed Rust closure.
+    ///
+    /// Calling the closure is equivalent to calling [`call`] on this function, which allows
+    /// storing Lua functions behind the same closure type as native Rust callbacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let callbacks: Vec<Box<dyn Fn(i32) -> Result<i32>>> = vec![
+    ///     Box::new(|x| Ok(x + 1)),
+    ///     lua.load("function(x) return x * 2 end")
+    ///         .eval::<Function>()?
+    ///         .into_boxed_fn(),
+    /// ];
+    ///
+    /// let results = callbacks.iter().map(|f| f(3)).collect::<Result<Vec<_>>>()?;
+    /// assert_eq!(results, vec![4, 6]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`call`]: #method.call
+    pub fn into_boxed_fn<A, R>(self) -> Box<dyn Fn(A) -> Result<R> + 'lua>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        Box::new(move |args| self.call(args))
+    }
+
+    /// Calls the function and returns an iterator over the sequence table it returns.
+    ///
+    /// This is a shorthand for calling the function with `args`, and then iterating the resulting
+    /// table with [`Table::sequence_values`]. Each element is converted to `V` lazily as the
+    /// iterator advances. Returns an error if the function fails or does not return a table.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let squares: Function = lua.load(r#"
+    ///     function(n)
+    ///         local t = {}
+    ///         for i = 1, n do t[i] = i * i end
+    ///         return t
+    ///     end
+    /// "#).eval()?;
+    ///
+    /// let values = squares.into_iter::<_, i64>(4)?.collect::<Result<Vec<_>>>()?;
+    /// assert_eq!(values, vec![1, 4, 9, 16]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Table::sequence_values`]: struct.Table.html#method.sequence_values
+    pub fn into_iter<A, V>(self, args: A) -> Result<TableSequence<'lua, V>>
+    where
+        A: ToLuaMulti<'lua>,
+        V: FromLua<'lua>,
+    {
+        Ok(self.call::<_, Table>(args)?.sequence_values())
+    }
+
     /// Dumps the function as a binary chunk.
     ///
     /// If `strip` is true, the binary representation may not include all debug information
@@ -238,6 +514,85 @@ impl<'lua> Function<'lua> {
 
         data
     }
+
+    /// Returns information about where the function was defined.
+    ///
+    /// Returns `None` for functions implemented in C or Rust.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let add: Function = lua
+    ///     .load("\n\nreturn function(a, b)\n  return a + b\nend")
+    ///     .set_name("=adder")?
+    ///     .eval()?;
+    ///
+    /// let source = add.source()?.unwrap();
+    /// assert_eq!(source.name, "adder");
+    /// assert_eq!((source.line_defined, source.last_line_defined), (3, 5));
+    /// assert_eq!(source.text, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn source(&self) -> Result<Option<FunctionSource>> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 1)?;
+
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            lua.push_ref(&self.0);
+            mlua_assert!(
+                ffi::lua_getinfo(lua.state, cstr!(">S"), &mut ar) != 0,
+                "lua_getinfo failed with `>S`"
+            );
+
+            if CStr::from_ptr(ar.what).to_bytes() == b"C" {
+                return Ok(None);
+            }
+
+            let source = CStr::from_ptr(ar.source).to_bytes();
+            // Lua uses "?" for chunks loaded without a name
+            let text = match source.first() {
+                Some(b'=') | Some(b'@') => None,
+                _ if source == b"?" => None,
+                _ => Some(StdString::from_utf8_lossy(source).into_owned()),
+            };
+
+            Ok(Some(FunctionSource {
+                name: CStr::from_ptr(ar.short_src.as_ptr())
+                    .to_string_lossy()
+                    .into_owned(),
+                line_defined: ar.linedefined as i32,
+                last_line_defined: ar.lastlinedefined as i32,
+                text,
+            }))
+        }
+    }
+}
+
+/// Information about where a Lua function was defined.
+///
+/// Returned by [`Function::source`].
+///
+/// [`Function::source`]: struct.Function.html#method.source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionSource {
+    /// Printable name of the chunk the function was loaded from, as used in error messages.
+    pub name: StdString,
+    /// Line where the function definition starts, or `0` for a main chunk.
+    pub line_defined: i32,
+    /// Line where the function definition ends, or `0` for a main chunk.
+    pub last_line_defined: i32,
+    /// Source code of the chunk the function was loaded from.
+    ///
+    /// Only available if Lua retained it as the chunk name, which is the case for chunks loaded
+    /// from a string by Lua's `load` function without an explicit name. Chunk names starting
+    /// with `=` or `@` are never treated as source code.
+    pub text: Option<StdString>,
 }
 
 impl<'lua> PartialEq for Function<'lua> {
@@ -1,7 +1,12 @@
+use std::cell::UnsafeCell;
+use std::convert::TryFrom;
 use std::ffi::CStr;
 use std::marker::PhantomData;
+use std::mem;
 use std::os::raw::{c_char, c_int};
+use std::rc::Rc;
 
+use crate::error::Error;
 use crate::ffi::{self, lua_Debug, lua_State};
 use crate::lua::Lua;
 use crate::util::callback_error;
@@ -9,30 +14,62 @@ use crate::util::callback_error;
 /// Contains information about currently executing Lua code.
 ///
 /// The `Debug` structure is provided as a parameter to the hook function set with
-/// [`Lua::set_hook`]. You may call the methods on this structure to retrieve information about the
-/// Lua code executing at the time that the hook function was called. Further information can be
+/// [`Lua::set_hook`], and to the closure passed to [`Lua::inspect_stack`]. You may call the
+/// methods on this structure to retrieve information about the Lua code executing at the time
+/// that the hook function was called (or the stack was inspected). Further information can be
 /// found in the [Lua 5.3 documentation][lua_doc].
 ///
 /// [lua_doc]: https://www.lua.org/manual/5.3/manual.html#lua_Debug
 /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+/// [`Lua::inspect_stack`]: struct.Lua.html#method.inspect_stack
 #[derive(Clone)]
 pub struct Debug<'a> {
-    ar: *mut lua_Debug,
+    ar: ActivationRecord,
     state: *mut lua_State,
     _phantom: PhantomData<&'a ()>,
 }
 
+// Activation record passed to a hook by Lua, or filled in by `lua_getstack`
+#[derive(Clone)]
+enum ActivationRecord {
+    Borrowed(*mut lua_Debug),
+    Owned(Rc<UnsafeCell<lua_Debug>>),
+}
+
+impl ActivationRecord {
+    fn get(&self) -> *mut lua_Debug {
+        match self {
+            ActivationRecord::Borrowed(ar) => *ar,
+            ActivationRecord::Owned(ar) => ar.get(),
+        }
+    }
+}
+
 impl<'a> Debug<'a> {
+    // Returns information about the function running at `level` of the call stack of `state`.
+    // The record refers to the running frame, so the caller must not let it outlive the frame.
+    pub(crate) unsafe fn from_stack(state: *mut lua_State, level: c_int) -> Option<Debug<'a>> {
+        let mut ar: lua_Debug = mem::zeroed();
+        if ffi::lua_getstack(state, level, &mut ar) == 0 {
+            return None;
+        }
+        Some(Debug {
+            ar: ActivationRecord::Owned(Rc::new(UnsafeCell::new(ar))),
+            state,
+            _phantom: PhantomData,
+        })
+    }
+
     /// Corresponds to the `n` what mask.
     pub fn names(&self) -> DebugNames<'a> {
         unsafe {
             mlua_assert!(
-                ffi::lua_getinfo(self.state, cstr!("n"), self.ar) != 0,
+                ffi::lua_getinfo(self.state, cstr!("n"), self.ar.get()) != 0,
                 "lua_getinfo failed with `n`"
             );
             DebugNames {
-                name: ptr_to_str((*self.ar).name),
-                name_what: ptr_to_str((*self.ar).namewhat),
+                name: ptr_to_str((*self.ar.get()).name),
+                name_what: ptr_to_str((*self.ar.get()).namewhat),
             }
         }
     }
@@ -41,15 +78,15 @@ impl<'a> Debug<'a> {
     pub fn source(&self) -> DebugSource<'a> {
         unsafe {
             mlua_assert!(
-                ffi::lua_getinfo(self.state, cstr!("S"), self.ar) != 0,
+                ffi::lua_getinfo(self.state, cstr!("S"), self.ar.get()) != 0,
                 "lua_getinfo failed with `S`"
             );
             DebugSource {
-                source: ptr_to_str((*self.ar).source),
-                short_src: ptr_to_str((*self.ar).short_src.as_ptr()),
-                line_defined: (*self.ar).linedefined as i32,
-                last_line_defined: (*self.ar).lastlinedefined as i32,
-                what: ptr_to_str((*self.ar).what),
+                source: ptr_to_str((*self.ar.get()).source),
+                short_src: ptr_to_str((*self.ar.get()).short_src.as_ptr()),
+                line_defined: (*self.ar.get()).linedefined as i32,
+                last_line_defined: (*self.ar.get()).lastlinedefined as i32,
+                what: ptr_to_str((*self.ar.get()).what),
             }
         }
     }
@@ -58,10 +95,10 @@ impl<'a> Debug<'a> {
     pub fn curr_line(&self) -> i32 {
         unsafe {
             mlua_assert!(
-                ffi::lua_getinfo(self.state, cstr!("l"), self.ar) != 0,
+                ffi::lua_getinfo(self.state, cstr!("l"), self.ar.get()) != 0,
                 "lua_getinfo failed with `l`"
             );
-            (*self.ar).currentline as i32
+            (*self.ar.get()).currentline as i32
         }
     }
 
@@ -70,10 +107,10 @@ impl<'a> Debug<'a> {
     pub fn is_tail_call(&self) -> bool {
         unsafe {
             mlua_assert!(
-                ffi::lua_getinfo(self.state, cstr!("t"), self.ar) != 0,
+                ffi::lua_getinfo(self.state, cstr!("t"), self.ar.get()) != 0,
                 "lua_getinfo failed with `t`"
             );
-            (*self.ar).currentline != 0
+            (*self.ar.get()).currentline != 0
         }
     }
 
@@ -81,15 +118,15 @@ impl<'a> Debug<'a> {
     pub fn stack(&self) -> DebugStack {
         unsafe {
             mlua_assert!(
-                ffi::lua_getinfo(self.state, cstr!("u"), self.ar) != 0,
+                ffi::lua_getinfo(self.state, cstr!("u"), self.ar.get()) != 0,
                 "lua_getinfo failed with `u`"
             );
             DebugStack {
-                num_ups: (*self.ar).nups as i32,
+                num_ups: (*self.ar.get()).nups as i32,
                 #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-                num_params: (*self.ar).nparams as i32,
+                num_params: (*self.ar.get()).nparams as i32,
                 #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-                is_vararg: (*self.ar).isvararg != 0,
+                is_vararg: (*self.ar.get()).isvararg != 0,
             }
         }
     }
@@ -163,18 +200,48 @@ impl HookTriggers {
     pub(crate) fn count(&self) -> c_int {
         self.every_nth_instruction.unwrap_or(0) as c_int
     }
+
+    // Checks whether the hook event `event` was requested by these triggers.
+    pub(crate) fn includes(&self, event: c_int) -> bool {
+        match event {
+            ffi::LUA_HOOKCALL => self.on_calls,
+            ffi::LUA_HOOKRET => self.on_returns,
+            ffi::LUA_HOOKLINE => self.every_line,
+            ffi::LUA_HOOKCOUNT => self.every_nth_instruction.is_some(),
+            // `LUA_HOOKTAILRET` in Lua 5.1 and LuaJIT
+            #[cfg(any(feature = "lua51", feature = "luajit"))]
+            ffi::LUA_HOOKTAILCALL => self.on_returns,
+            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+            ffi::LUA_HOOKTAILCALL => self.on_calls,
+            _ => false,
+        }
+    }
 }
 
 pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_Debug) {
     callback_error(state, |_| {
         let debug = Debug {
-            ar,
+            ar: ActivationRecord::Borrowed(ar),
             state,
             _phantom: PhantomData,
         };
 
         let lua = mlua_expect!(Lua::make_from_ptr(state), "cannot make Lua instance");
-        let hook_cb = mlua_expect!(lua.hook_callback(), "no hook callback set in hook_proc");
+        let event = (*ar).event;
+
+        let max_depth = lua.max_lua_call_depth();
+        if max_depth > 0 && event == ffi::LUA_HOOKCALL && stack_depth_exceeds(state, max_depth) {
+            return Err(Error::RecursionLimit);
+        }
+
+        if event == ffi::LUA_HOOKCOUNT && !lua.count_instructions()? {
+            return Ok(());
+        }
+
+        let hook_cb = match lua.hook_callback() {
+            Some((hook_cb, triggers)) if triggers.includes(event) => hook_cb,
+            _ => return Ok(()),
+        };
 
         #[allow(clippy::match_wild_err_arm)]
         match hook_cb.try_borrow_mut() {
@@ -186,6 +253,17 @@ pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_De
     })
 }
 
+// Checks whether the call stack of `state` has more than `depth` active levels.
+// Probes a single level instead of measuring the whole stack, as this runs on every call.
+unsafe fn stack_depth_exceeds(state: *mut lua_State, depth: usize) -> bool {
+    let level = match c_int::try_from(depth) {
+        Ok(level) => level,
+        Err(_) => return false,
+    };
+    let mut ar: lua_Debug = mem::zeroed();
+    ffi::lua_getstack(state, level, &mut ar) != 0
+}
+
 unsafe fn ptr_to_str<'a>(input: *const c_char) -> Option<&'a [u8]> {
     if input.is_null() {
         None
@@ -101,34 +101,53 @@ mod value;
 #[doc(hidden)]
 pub use crate::ffi::lua_State;
 
-pub use crate::error::{Error, ExternalError, ExternalResult, Result};
-pub use crate::function::Function;
+pub use crate::error::{Error, ExternalError, ExternalResult, Result, TracebackFrame};
+pub use crate::function::{Function, FunctionSource};
 pub use crate::hook::{Debug, DebugNames, DebugSource, DebugStack, HookTriggers};
-pub use crate::lua::{AsChunk, Chunk, ChunkMode, GCMode, Lua, LuaOptions};
-pub use crate::multi::Variadic;
-pub use crate::scope::Scope;
+pub use crate::lua::{
+    AsChunk, Chunk, ChunkMode, CoercionPolicy, GCMode, GcPauseGuard, GlobalsSnapshot, Lua,
+    LuaOptions, RegistryNamespace, StaticUserDataMethods,
+};
+pub use crate::multi::{BoundedVariadic, SeqTable, Validated, Variadic};
+pub use crate::scope::{NonStaticUserDataBuilder, Scope};
 pub use crate::stdlib::StdLib;
 pub use crate::string::String;
-pub use crate::table::{Table, TableExt, TablePairs, TableSequence};
+pub use crate::table::{Table, TableAccess, TableAccessKind, TableExt, TablePairs, TableSequence};
 pub use crate::thread::{Thread, ThreadStatus};
 pub use crate::types::{Integer, LightUserData, Number, RegistryKey};
 pub use crate::userdata::{
-    AnyUserData, MetaMethod, UserData, UserDataFields, UserDataMetatable, UserDataMethods,
+    AnyUserData, DynamicMethods, MetaMethod, MetaMethodSet, UserData, UserDataFields,
+    UserDataMetatable, UserDataMethods, WeakUserData,
+};
+pub use crate::value::{
+    FromLua, FromLuaMulti, FrozenValue, MultiValue, Nil, ToLua, ToLuaMulti, Value,
 };
-pub use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
 #[cfg(feature = "async")]
 pub use crate::thread::AsyncThread;
 
+#[cfg(feature = "chrono")]
+pub use crate::lua::DateTimeFormat;
+
 #[cfg(feature = "serialize")]
 #[doc(inline)]
-pub use crate::serde::{ser::Options as SerializeOptions, LuaSerdeExt};
+pub use crate::serde::{
+    de::Options as DeserializeOptions, ser::Options as SerializeOptions, LuaSerdeExt, SerializeRefs,
+};
+
+#[cfg(any(feature = "json", feature = "yaml"))]
+#[doc(inline)]
+pub use crate::serde::SerializeFormat;
 
 pub mod prelude;
 #[cfg(feature = "serialize")]
 #[cfg_attr(docsrs, doc(cfg(feature = "serialize")))]
 pub mod serde;
 
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+pub mod tokio;
+
 #[cfg(any(feature = "mlua_derive"))]
 #[allow(unused_imports)]
 #[macro_use]
@@ -190,6 +209,79 @@ extern crate mlua_derive;
 #[cfg_attr(docsrs, doc(cfg(feature = "macros")))]
 pub use mlua_derive::chunk;
 
+/// Derive [`ToLua`] and [`FromLua`] for a fieldless enum, converting it to and from the integer
+/// value of its discriminant.
+///
+/// Converting from Lua accepts integers (and floats with an integer value) matching one of the
+/// discriminants, and returns an error listing the valid values otherwise.
+///
+/// ```
+/// use mlua::{Lua, LuaIntEnum, Result};
+///
+/// #[derive(Clone, Copy, Debug, PartialEq, LuaIntEnum)]
+/// enum Level {
+///     Low = 1,
+///     Medium = 2,
+///     High = 3,
+/// }
+///
+/// fn main() -> Result<()> {
+///     let lua = Lua::new();
+///     lua.globals().set("level", Level::Medium)?;
+///     assert_eq!(lua.load("level + 1").eval::<Level>()?, Level::High);
+///     assert!(lua.load("4").eval::<Level>().is_err());
+///     Ok(())
+/// }
+/// ```
+///
+/// [`ToLua`]: trait.ToLua.html
+/// [`FromLua`]: trait.FromLua.html
+#[cfg(feature = "macros")]
+#[cfg_attr(docsrs, doc(cfg(feature = "macros")))]
+pub use mlua_derive::LuaIntEnum;
+
+/// Derive [`FromLua`] for a struct with named fields, reading each field from a Lua table.
+///
+/// Every field is read from the table entry with the same name, using the `FromLua`
+/// implementation of the field type. Fields marked with `#[mlua(flatten)]` are instead converted
+/// from the whole table, like `#[serde(flatten)]`, so the fields of a nested struct can be
+/// placed directly in the parent table.
+///
+/// With `#[mlua(deny_unknown_fields)]` on the struct, a table with a key that does not match any
+/// field is rejected with an error naming that key, which catches typos in config files. It
+/// cannot be combined with `#[mlua(flatten)]`.
+///
+/// ```
+/// use mlua::{FromLua, Lua, Result};
+///
+/// #[derive(FromLua)]
+/// struct Limits {
+///     max_depth: u32,
+///     timeout: Option<f64>,
+/// }
+///
+/// #[derive(FromLua)]
+/// struct Config {
+///     name: String,
+///     #[mlua(flatten)]
+///     limits: Limits,
+/// }
+///
+/// fn main() -> Result<()> {
+///     let lua = Lua::new();
+///     let config: Config = lua.load("{ name = 'app', max_depth = 3 }").eval()?;
+///     assert_eq!(config.name, "app");
+///     assert_eq!(config.limits.max_depth, 3);
+///     assert_eq!(config.limits.timeout, None);
+///     Ok(())
+/// }
+/// ```
+///
+/// [`FromLua`]: trait.FromLua.html
+#[cfg(feature = "macros")]
+#[cfg_attr(docsrs, doc(cfg(feature = "macros")))]
+pub use mlua_derive::FromLua;
+
 #[cfg(any(feature = "module"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "module")))]
 pub use mlua_derive::lua_module;
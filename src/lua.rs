@@ -1,13 +1,17 @@
-use std::any::TypeId;
-use std::cell::{RefCell, UnsafeCell};
+use std::any::{self, Any, TypeId};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::ffi::CString;
 use std::fmt;
+use std::io::{self, Read};
 use std::marker::PhantomData;
 use std::os::raw::{c_char, c_int, c_void};
 use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::string::String as StdString;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard, RwLock, Weak};
-use std::{mem, ptr, str};
+use std::{cmp, mem, ptr, str};
 
 use crate::error::{Error, Result};
 use crate::ffi;
@@ -16,20 +20,23 @@ use crate::hook::{hook_proc, Debug, HookTriggers};
 use crate::scope::Scope;
 use crate::stdlib::StdLib;
 use crate::string::String;
-use crate::table::Table;
-use crate::thread::Thread;
+use crate::table::{Table, TableAccess};
+use crate::thread::{Thread, ThreadStatus};
 use crate::types::{
-    Callback, HookCallback, Integer, LightUserData, LuaRef, MaybeSend, Number, RegistryKey,
+    Callback, CloseHandler, ErrorHandler, HookCallback, Integer, LightUserData, LuaRef, MaybeSend,
+    Number, RegistryExpireHandler, RegistryKey, TableAccessHook,
 };
 use crate::userdata::{
-    AnyUserData, MetaMethod, UserData, UserDataCell, UserDataFields, UserDataMethods,
+    AnyUserData, DynamicMethods, DynamicUserData, LiveUserData, MetaMethod, UserData, UserDataCell,
+    UserDataFields, UserDataMethods,
 };
 use crate::util::{
     self, assert_stack, callback_error, check_stack, get_destructed_userdata_metatable,
     get_gc_metatable_for, get_gc_userdata, get_main_state, get_userdata, get_wrapped_error,
-    init_error_registry, init_gc_metatable_for, init_userdata_metatable, pop_error, protect_lua,
-    push_gc_userdata, push_string, push_table, push_userdata, push_wrapped_error, rawset_field,
-    safe_pcall, safe_xpcall, StackGuard, WrappedError, WrappedPanic,
+    init_error_registry, init_gc_metatable_for, init_userdata_metatable, init_userdata_tostring,
+    pop_error, protect_lua, push_gc_userdata, push_string, push_table, push_userdata,
+    push_wrapped_error, rawset_field, safe_pcall, safe_xpcall, short_type_name, StackGuard,
+    WrappedError, WrappedPanic,
 };
 use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
@@ -50,6 +57,15 @@ use {
 #[cfg(feature = "serialize")]
 use serde::Serialize;
 
+#[cfg(any(feature = "toml", feature = "yaml"))]
+use crate::serde::LuaSerdeExt;
+
+#[cfg(any(feature = "json", feature = "yaml"))]
+use {
+    crate::serde::{SerializeChecked, SerializeFormat},
+    std::io::Write,
+};
+
 /// Top level Lua struct which holds the Lua state itself.
 pub struct Lua {
     pub(crate) state: *mut ffi::lua_State,
@@ -61,15 +77,51 @@ pub struct Lua {
     _no_ref_unwind_safe: PhantomData<UnsafeCell<()>>,
 }
 
+// Named registry value holding the tables of `Lua::registry_namespace`
+const REGISTRY_NAMESPACES_KEY: &str = "__mlua_registry_namespaces";
+const MODULE_SEARCHER_KEY: &str = "__mlua_module_searcher";
+
+// Maximum number of empty `MultiValue`s kept for reuse
+const MULTIVALUE_CACHE_SIZE: usize = 32;
+// Larger allocations are freed instead of cached, so rare big calls do not stay pinned in memory
+const MULTIVALUE_CACHE_MAX_CAPACITY: usize = 16;
+
+thread_local! {
+    // Empty `MultiValue` allocations kept for reuse across calls. Kept per thread rather than in
+    // `ExtraData`, so calls don't need to lock `extra` to use it.
+    static MULTIVALUE_CACHE: RefCell<Vec<Vec<Value<'static>>>> =
+        RefCell::new(Vec::with_capacity(MULTIVALUE_CACHE_SIZE));
+    // Coercion policy consulted by value conversions. Read on every conversion, so it is kept
+    // per thread rather than behind the `extra` lock.
+    static COERCION_POLICY: Cell<CoercionPolicy> = const { Cell::new(CoercionPolicy::LENIENT) };
+    // Policy requested by `Function::call_with_policy`, with the address of the `Callback`
+    // userdata of the Rust function it applies to
+    static CALL_POLICY: Cell<Option<(*const c_void, CoercionPolicy)>> = const { Cell::new(None) };
+}
+
+// Metatable registered for a static userdata type by `push_userdata_metatable`
+struct RegisteredUserData {
+    // Registry reference to the metatable
+    table_id: c_int,
+    // Metatable pointer, as stored in `registered_userdata_mt`
+    table_ptr: isize,
+    type_name: &'static str,
+    // Number of live values using the metatable
+    instances: Arc<AtomicUsize>,
+}
+
 // Data associated with the Lua.
 struct ExtraData {
-    registered_userdata: HashMap<TypeId, c_int>,
+    registered_userdata: HashMap<TypeId, RegisteredUserData>,
     registered_userdata_mt: HashSet<isize>,
     registry_unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
+    // Number of values stored by `create_registry_value` that have not been removed yet
+    registry_values: usize,
 
     libs: StdLib,
     mem_info: *mut MemoryInfo,
     safe: bool, // Same as in the Lua struct
+    disable_bytecode: bool,
 
     ref_thread: *mut ffi::lua_State,
     ref_stack_size: c_int,
@@ -81,6 +133,25 @@ struct ExtraData {
     prealloc_wrapped_errors: Vec<c_int>,
 
     hook_callback: Option<HookCallback>,
+    hook_triggers: HookTriggers,
+    // Instruction count passed to `lua_sethook`
+    hook_count: c_int,
+    // Instructions counted for the user hook since it was last called
+    hook_instructions: u64,
+    max_lua_call_depth: usize,
+    instruction_limit: u64,
+    instructions_executed: u64,
+    // Userdata values created by this state that have not been dropped yet
+    live_userdata: Arc<AtomicUsize>,
+    max_userdata: usize,
+    uncaught_error_handler: Option<ErrorHandler>,
+    // Number of chunks currently running, errors are reported for the outermost one only
+    chunk_depth: usize,
+    registry_expire_handler: Option<RegistryExpireHandler>,
+    close_handlers: Vec<CloseHandler>,
+    table_access_hook: Option<TableAccessHook>,
+    #[cfg(feature = "chrono")]
+    datetime_format: DateTimeFormat,
 }
 
 #[cfg_attr(any(feature = "lua51", feature = "luajit"), allow(dead_code))]
@@ -105,6 +176,188 @@ pub enum GCMode {
     Generational,
 }
 
+/// How `DateTime<Utc>` values are converted to Lua.
+///
+/// Converting from Lua accepts both representations regardless of this setting.
+///
+/// Requires `feature = "chrono"`
+#[cfg(feature = "chrono")]
+#[cfg_attr(docsrs, doc(cfg(feature = "chrono")))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateTimeFormat {
+    /// Number of seconds since the Unix epoch. An integer for whole seconds, a float otherwise.
+    #[default]
+    Timestamp,
+    /// An ISO 8601 (RFC 3339) string such as `2021-05-01T12:30:00Z`.
+    Iso8601,
+}
+
+/// Controls which implicit coercions apply when converting Lua values to Rust types.
+///
+/// The policy is consulted by [`Lua::coerce_string`], [`Lua::coerce_integer`],
+/// [`Lua::coerce_number`] and the [`FromLua`] implementations for Rust strings and numbers.
+/// [`CoercionPolicy::LENIENT`] matches Lua's own behavior and is always used outside of
+/// [`Function::call_with_policy`].
+///
+/// [`Lua::coerce_string`]: struct.Lua.html#method.coerce_string
+/// [`Lua::coerce_integer`]: struct.Lua.html#method.coerce_integer
+/// [`Lua::coerce_number`]: struct.Lua.html#method.coerce_number
+/// [`FromLua`]: trait.FromLua.html
+/// [`CoercionPolicy::LENIENT`]: #associatedconstant.LENIENT
+/// [`Function::call_with_policy`]: struct.Function.html#method.call_with_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoercionPolicy {
+    string_number: bool,
+    integer_float: bool,
+}
+
+impl CoercionPolicy {
+    /// Allows all coercions supported by Lua.
+    pub const LENIENT: CoercionPolicy = CoercionPolicy {
+        string_number: true,
+        integer_float: true,
+    };
+
+    /// Disallows all coercions: values must already have the requested type.
+    pub const STRICT: CoercionPolicy = CoercionPolicy {
+        string_number: false,
+        integer_float: false,
+    };
+
+    /// Sets whether strings are converted to numbers and numbers to strings.
+    pub const fn string_number(mut self, enabled: bool) -> Self {
+        self.string_number = enabled;
+        self
+    }
+
+    /// Sets whether integers are converted to floats and floats to integers.
+    pub const fn integer_float(mut self, enabled: bool) -> Self {
+        self.integer_float = enabled;
+        self
+    }
+
+    /// Returns `true` if strings and numbers are converted to each other.
+    pub const fn allows_string_number(&self) -> bool {
+        self.string_number
+    }
+
+    /// Returns `true` if integers and floats are converted to each other.
+    pub const fn allows_integer_float(&self) -> bool {
+        self.integer_float
+    }
+
+    // Returns `false` if converting `v` to `target` would need a disallowed coercion
+    fn allows(&self, v: &Value, target: CoercionTarget) -> bool {
+        match (v, target) {
+            (Value::Integer(_), CoercionTarget::Number) => self.integer_float,
+            (Value::Number(_), CoercionTarget::Integer) => self.integer_float,
+            (Value::String(_), CoercionTarget::Integer) => self.string_number,
+            (Value::String(_), CoercionTarget::Number) => self.string_number,
+            (Value::Integer(_), CoercionTarget::String) => self.string_number,
+            (Value::Number(_), CoercionTarget::String) => self.string_number,
+            _ => true,
+        }
+    }
+}
+
+impl Default for CoercionPolicy {
+    fn default() -> Self {
+        CoercionPolicy::LENIENT
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CoercionTarget {
+    String,
+    Integer,
+    Number,
+}
+
+// Makes a coercion policy active on this thread until dropped
+pub(crate) struct CoercionPolicyGuard(CoercionPolicy);
+
+impl CoercionPolicyGuard {
+    pub(crate) fn new(policy: CoercionPolicy) -> Self {
+        CoercionPolicyGuard(COERCION_POLICY.with(|p| p.replace(policy)))
+    }
+}
+
+impl Drop for CoercionPolicyGuard {
+    fn drop(&mut self) {
+        let _ = COERCION_POLICY.try_with(|p| p.set(self.0));
+    }
+}
+
+// Requests a coercion policy for the next call of the Rust function whose `Callback` userdata is
+// at `callback`, the previous request is restored when dropped
+pub(crate) struct CallPolicyGuard(Option<(*const c_void, CoercionPolicy)>);
+
+impl CallPolicyGuard {
+    pub(crate) fn new(callback: *const c_void, policy: CoercionPolicy) -> Self {
+        CallPolicyGuard(CALL_POLICY.with(|p| p.replace(Some((callback, policy)))))
+    }
+}
+
+impl Drop for CallPolicyGuard {
+    fn drop(&mut self) {
+        let _ = CALL_POLICY.try_with(|p| p.set(self.0));
+    }
+}
+
+// Returns the policy requested for the callback at `callback`, or the lenient one
+fn take_call_policy(callback: *const c_void) -> CoercionPolicy {
+    CALL_POLICY.with(|p| match p.get() {
+        Some((target, policy)) if target == callback => {
+            p.set(None);
+            policy
+        }
+        _ => CoercionPolicy::LENIENT,
+    })
+}
+
+/// A guard that keeps the Lua GC stopped while it's alive.
+///
+/// This struct is created by the [`Lua::gc_pause_guard`] method.
+///
+/// [`Lua::gc_pause_guard`]: struct.Lua.html#method.gc_pause_guard
+#[must_use = "the GC is restarted as soon as the guard is dropped"]
+pub struct GcPauseGuard<'lua> {
+    lua: &'lua Lua,
+    restart: bool,
+}
+
+impl<'lua> Drop for GcPauseGuard<'lua> {
+    fn drop(&mut self) {
+        if self.restart {
+            self.lua.gc_restart();
+        }
+    }
+}
+
+// Tracks the number of running chunks, see `Lua::report_uncaught_error`.
+struct ChunkDepthGuard<'lua> {
+    lua: &'lua Lua,
+    outermost: bool,
+}
+
+impl<'lua> ChunkDepthGuard<'lua> {
+    fn new(lua: &'lua Lua) -> Self {
+        let mut extra = mlua_expect!(lua.extra.lock(), "extra is poisoned");
+        extra.chunk_depth += 1;
+        ChunkDepthGuard {
+            lua,
+            outermost: extra.chunk_depth == 1,
+        }
+    }
+}
+
+impl<'lua> Drop for ChunkDepthGuard<'lua> {
+    fn drop(&mut self) {
+        let mut extra = mlua_expect!(self.lua.extra.lock(), "extra is poisoned");
+        extra.chunk_depth -= 1;
+    }
+}
+
 /// Controls Lua interpreter behavior such as Rust panics handling.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
@@ -122,12 +375,45 @@ pub struct LuaOptions {
     /// [`pcall`]: https://www.lua.org/manual/5.3/manual.html#pdf-pcall
     /// [`xpcall`]: https://www.lua.org/manual/5.3/manual.html#pdf-xpcall
     pub catch_rust_panics: bool,
+
+    /// Reject binary chunks (precompiled bytecode) passed to [`Lua::load`].
+    ///
+    /// Lua does not verify bytecode, so loading untrusted bytecode can crash the interpreter.
+    /// If enabled, loading a chunk starting with the [`LUA_SIGNATURE`] escape byte fails with a
+    /// runtime error. Text chunks are not affected, even if they start with a `#` line.
+    /// Scripts are not restricted by this option, see [`disable_script_bytecode_loading`].
+    ///
+    /// States created with [`Lua::new`] or [`Lua::new_with`] reject binary chunks in safe mode
+    /// anyway, so this option only makes a difference for [`Lua::unsafe_new`] and
+    /// [`Lua::unsafe_new_with`].
+    ///
+    /// Default: **disabled**
+    ///
+    /// [`Lua::load`]: struct.Lua.html#method.load
+    /// [`LUA_SIGNATURE`]: https://www.lua.org/source/5.4/lua.h.html#LUA_SIGNATURE
+    /// [`disable_script_bytecode_loading`]: #structfield.disable_script_bytecode_loading
+    /// [`Lua::new`]: struct.Lua.html#method.new
+    /// [`Lua::new_with`]: struct.Lua.html#method.new_with
+    /// [`Lua::unsafe_new`]: struct.Lua.html#method.unsafe_new
+    /// [`Lua::unsafe_new_with`]: struct.Lua.html#method.unsafe_new_with
+    pub disable_bytecode_loading: Option<bool>,
+
+    /// Replace the Lua functions `load`, `loadstring`, `loadfile` and `dofile` with wrappers
+    /// that only accept text chunks.
+    ///
+    /// This keeps scripts from loading bytecode (for example, produced by `string.dump`), which
+    /// Lua does not verify. The wrappers are installed as globals when the state is created.
+    ///
+    /// Default: **disabled**
+    pub disable_script_bytecode_loading: bool,
 }
 
 impl Default for LuaOptions {
     fn default() -> Self {
         LuaOptions {
             catch_rust_panics: true,
+            disable_bytecode_loading: None,
+            disable_script_bytecode_loading: false,
         }
     }
 }
@@ -145,6 +431,22 @@ impl LuaOptions {
         self.catch_rust_panics = enabled;
         self
     }
+
+    /// Sets [`disable_bytecode_loading`] option.
+    ///
+    /// [`disable_bytecode_loading`]: #structfield.disable_bytecode_loading
+    pub fn disable_bytecode_loading(mut self, disabled: bool) -> Self {
+        self.disable_bytecode_loading = Some(disabled);
+        self
+    }
+
+    /// Sets [`disable_script_bytecode_loading`] option.
+    ///
+    /// [`disable_script_bytecode_loading`]: #structfield.disable_script_bytecode_loading
+    pub fn disable_script_bytecode_loading(mut self, disabled: bool) -> Self {
+        self.disable_script_bytecode_loading = disabled;
+        self
+    }
 }
 
 #[cfg(feature = "async")]
@@ -152,6 +454,11 @@ pub(crate) static ASYNC_POLL_PENDING: u8 = 0;
 #[cfg(feature = "async")]
 pub(crate) static WAKER_REGISTRY_KEY: u8 = 0;
 pub(crate) static EXTRA_REGISTRY_KEY: u8 = 0;
+static OUTPUT_HANDLERS_REGISTRY_KEY: u8 = 0;
+static EXPIRED_THREADS_REGISTRY_KEY: u8 = 0;
+static WEAK_USERDATA_REGISTRY_KEY: u8 = 0;
+static WEAK_USERDATA_TOKEN_MT_REGISTRY_KEY: u8 = 0;
+static CONST_GLOBALS_REGISTRY_KEY: u8 = 0;
 
 /// Requires `feature = "send"`
 #[cfg(feature = "send")]
@@ -173,13 +480,19 @@ impl Drop for Lua {
                         && extra.ref_stack_top as usize == extra.ref_free.len(),
                     "reference leak detected"
                 );
-                let mut unref_list =
-                    mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned");
-                *unref_list = None;
+                // Do not hold the unref list lock while closing, `RegistryKey`s owned by userdata
+                // are dropped during `lua_close` and lock it to check whether the state is gone.
+                // The `extra` lock stays held, so nothing run by `lua_close` may take it.
+                *mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned") = None;
                 ffi::lua_close(mlua_expect!(self.main_state, "main_state is null"));
                 if !extra.mem_info.is_null() {
                     Box::from_raw(extra.mem_info);
                 }
+                let close_handlers = mem::take(&mut extra.close_handlers);
+                drop(extra);
+                for handler in close_handlers.into_iter().rev() {
+                    handler();
+                }
             }
         }
     }
@@ -249,7 +562,9 @@ impl Lua {
             mlua_expect!(lua.disable_c_modules(), "Error during disabling C modules");
         }
         lua.safe = true;
-        mlua_expect!(lua.extra.lock(), "extra is poisoned").safe = true;
+        let mut extra = mlua_expect!(lua.extra.lock(), "extra is poisoned");
+        extra.safe = true;
+        drop(extra);
 
         Ok(lua)
     }
@@ -350,7 +665,10 @@ impl Lua {
             load_from_std_lib(state, libs),
             "Error during loading standard libraries"
         );
-        mlua_expect!(lua.extra.lock(), "extra is poisoned").libs |= libs;
+        let mut extra = mlua_expect!(lua.extra.lock(), "extra is poisoned");
+        extra.libs |= libs;
+        extra.disable_bytecode = options.disable_bytecode_loading.unwrap_or(false);
+        drop(extra);
 
         if !options.catch_rust_panics {
             mlua_expect!(
@@ -374,6 +692,13 @@ impl Lua {
             )
         }
 
+        if options.disable_script_bytecode_loading {
+            mlua_expect!(
+                lua.disable_lua_bytecode_loading(),
+                "Error during applying option `disable_script_bytecode_loading`"
+            );
+        }
+
         lua
     }
 
@@ -435,16 +760,33 @@ impl Lua {
             registered_userdata: HashMap::new(),
             registered_userdata_mt: HashSet::new(),
             registry_unref_list: Arc::new(Mutex::new(Some(Vec::new()))),
+            registry_values: 0,
             ref_thread,
             libs: StdLib::NONE,
             mem_info: ptr::null_mut(),
             safe: false,
+            disable_bytecode: false,
             // We need 1 extra stack space to move values in and out of the ref stack.
             ref_stack_size: ffi::LUA_MINSTACK - 1,
             ref_stack_top: 0,
             ref_free: Vec::new(),
             prealloc_wrapped_errors: Vec::new(),
             hook_callback: None,
+            hook_triggers: HookTriggers::default(),
+            hook_count: 0,
+            hook_instructions: 0,
+            max_lua_call_depth: 0,
+            instruction_limit: 0,
+            instructions_executed: 0,
+            live_userdata: Arc::new(AtomicUsize::new(0)),
+            max_userdata: 0,
+            uncaught_error_handler: None,
+            chunk_depth: 0,
+            registry_expire_handler: None,
+            close_handlers: Vec::new(),
+            table_access_hook: None,
+            #[cfg(feature = "chrono")]
+            datetime_format: DateTimeFormat::default(),
         }));
 
         mlua_expect!(
@@ -601,7 +943,9 @@ impl Lua {
         unsafe {
             let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
             extra.hook_callback = Some(Arc::new(RefCell::new(callback)));
-            ffi::lua_sethook(state, Some(hook_proc), triggers.mask(), triggers.count());
+            extra.hook_triggers = triggers;
+            extra.hook_instructions = 0;
+            update_hook(state, &mut extra);
         }
         Ok(())
     }
@@ -617,8 +961,273 @@ impl Lua {
         let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
         unsafe {
             extra.hook_callback = None;
-            ffi::lua_sethook(state, None, 0, 0);
+            extra.hook_triggers = HookTriggers::default();
+            update_hook(state, &mut extra);
+        }
+    }
+
+    /// Gets information about the function running at the given `level` of the call stack.
+    ///
+    /// Level 0 is the currently running function, so when called from a Rust callback it's the
+    /// callback itself, and level 1 is the function that called it. The [`Debug`] structure is
+    /// passed to `f`, as it's only valid while the inspected function is running, and the result
+    /// of `f` is returned. Returns `None` (without calling `f`) if `level` is greater than the
+    /// stack depth.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let log = lua.create_function(|lua, msg: String| {
+    ///     let line = lua.inspect_stack(1, |caller| caller.curr_line());
+    ///     println!("line {}: {}", line.expect("called from Lua"), msg);
+    ///     Ok(())
+    /// })?;
+    /// lua.globals().set("log", log)?;
+    ///
+    /// lua.load(r#"log("hello")"#).exec()
+    /// # }
+    /// ```
+    ///
+    /// [`Debug`]: struct.Debug.html
+    pub fn inspect_stack<R, F>(&self, level: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(&Debug) -> R,
+    {
+        let level = c_int::try_from(level).ok()?;
+        let debug = unsafe { Debug::from_stack(self.state, level)? };
+        Some(f(&debug))
+    }
+
+    /// Sets the maximum depth of the Lua call stack.
+    ///
+    /// Once a Lua function is called that would pass this depth, an [`Error::RecursionLimit`] is
+    /// raised instead, so deeply recursive scripts fail with a catchable error. Tail calls do not
+    /// increase the depth, except in Lua 5.1 which keeps them in the call stack. The limit is
+    /// enforced by a call hook that coexists with the one set by [`set_hook`], and applies to each
+    /// thread separately.
+    /// Returns previous limit (zero means no limit).
+    ///
+    /// For LuaJIT the limit is not checked for compiled code, as it does not trigger hooks.
+    ///
+    /// [`Error::RecursionLimit`]: enum.Error.html#variant.RecursionLimit
+    /// [`set_hook`]: #method.set_hook
+    pub fn set_max_lua_call_depth(&self, depth: usize) -> Result<usize> {
+        let state = self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let prev_depth = extra.max_lua_call_depth;
+        extra.max_lua_call_depth = depth;
+        unsafe { update_hook(state, &mut extra) };
+        Ok(prev_depth)
+    }
+
+    /// Sets the maximum number of VM instructions that Lua code may execute.
+    ///
+    /// Once the limit is reached, an [`Error::InstructionLimitReached`] is raised from the
+    /// currently executing Lua code, and Lua code keeps being interrupted until the limit is
+    /// reset. Setting a new limit restarts counting from zero, and zero removes the limit.
+    /// Returns previous limit (zero means no limit).
+    ///
+    /// The limit is enforced by a count hook that coexists with the one set by [`set_hook`]. If
+    /// the user hook also counts instructions, the limit is checked at the smaller of both
+    /// intervals.
+    ///
+    /// For LuaJIT the instructions of compiled code are not counted, as it does not trigger hooks.
+    ///
+    /// [`Error::InstructionLimitReached`]: enum.Error.html#variant.InstructionLimitReached
+    /// [`set_hook`]: #method.set_hook
+    pub fn set_instruction_limit(&self, count: u64) -> Result<u64> {
+        let state = self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let prev_limit = extra.instruction_limit;
+        extra.instruction_limit = count;
+        extra.instructions_executed = 0;
+        unsafe { update_hook(state, &mut extra) };
+        Ok(prev_limit)
+    }
+
+    /// Sets the maximum number of live userdata values.
+    ///
+    /// Once the limit is reached, creating another userdata returns an
+    /// [`Error::UserDataLimitReached`] (which can be caught by `pcall` when raised in Lua code)
+    /// until some userdata are garbage collected, destroyed or taken back to Rust.
+    /// Returns previous limit (zero means no limit).
+    ///
+    /// [`Error::UserDataLimitReached`]: enum.Error.html#variant.UserDataLimitReached
+    pub fn set_max_userdata(&self, max: usize) -> usize {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        mem::replace(&mut extra.max_userdata, max)
+    }
+
+    /// Returns the Rust type names of userdata types with a registered metatable.
+    ///
+    /// A metatable is registered when the first value of a `'static` userdata type is created,
+    /// and is kept for later values of the same type. The names are sorted and come from
+    /// [`std::any::type_name`]. Metatables of scoped (non-`'static`) userdata are not included.
+    ///
+    /// [`std::any::type_name`]: https://doc.rust-lang.org/std/any/fn.type_name.html
+    pub fn registered_metatables(&self) -> Vec<&'static str> {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let mut names: Vec<_> = (extra.registered_userdata.values())
+            .map(|reg| reg.type_name)
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Removes registered userdata metatables that have no live values, returning the number of
+    /// removed metatables.
+    ///
+    /// A value stays live until it is garbage collected, destroyed or taken back to Rust, so
+    /// run a full garbage collection first (see [`gc_collect`]) to release unreachable values.
+    /// The metatable is registered again when a new value of the same type is created.
+    ///
+    /// [`gc_collect`]: #method.gc_collect
+    pub fn clear_unused_metatables(&self) -> usize {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let unused: Vec<TypeId> = (extra.registered_userdata.iter())
+            .filter(|(_, reg)| reg.instances.load(Ordering::Relaxed) == 0)
+            .map(|(&type_id, _)| type_id)
+            .collect();
+        for type_id in &unused {
+            let reg = mlua_expect!(
+                extra.registered_userdata.remove(type_id),
+                "registered userdata not found"
+            );
+            extra.registered_userdata_mt.remove(&reg.table_ptr);
+            unsafe { ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, reg.table_id) };
+        }
+        unused.len()
+    }
+
+    /// Sets how `DateTime<Utc>` values are converted to Lua.
+    ///
+    /// Default: [`DateTimeFormat::Timestamp`]
+    ///
+    /// Requires `feature = "chrono"`
+    ///
+    /// [`DateTimeFormat::Timestamp`]: enum.DateTimeFormat.html#variant.Timestamp
+    #[cfg(feature = "chrono")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "chrono")))]
+    pub fn set_datetime_format(&self, format: DateTimeFormat) {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.datetime_format = format;
+    }
+
+    #[cfg(feature = "chrono")]
+    pub(crate) fn datetime_format(&self) -> DateTimeFormat {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.datetime_format
+    }
+
+    pub(crate) fn coercion_policy(&self) -> CoercionPolicy {
+        COERCION_POLICY.with(Cell::get)
+    }
+
+    /// Sets a handler that is invoked whenever a top-level chunk fails.
+    ///
+    /// The handler is called with the error returned from [`Chunk::exec`], [`Chunk::eval`] or
+    /// [`Chunk::call`] (or their async counterparts) right before it's returned to the caller.
+    /// The error itself is left intact, which makes this handler a convenient place for
+    /// centralized error reporting.
+    ///
+    /// Chunks run while another chunk is running (for example, from a Rust callback) are not
+    /// reported on their own, so the handler is invoked once, for the outermost chunk.
+    ///
+    /// Only one handler can be set at a time, setting a new one replaces the previous handler.
+    ///
+    /// [`Chunk::exec`]: struct.Chunk.html#method.exec
+    /// [`Chunk::eval`]: struct.Chunk.html#method.eval
+    /// [`Chunk::call`]: struct.Chunk.html#method.call
+    pub fn set_uncaught_error_handler<F>(&self, handler: F)
+    where
+        F: 'static + MaybeSend + Fn(&Error),
+    {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.uncaught_error_handler = Some(Arc::new(handler));
+    }
+
+    /// Removes any handler previously set by `set_uncaught_error_handler`.
+    pub fn remove_uncaught_error_handler(&self) {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.uncaught_error_handler = None;
+    }
+
+    /// Registers a handler that is invoked when the Lua state is closed.
+    ///
+    /// The handlers run when the `Lua` instance owning the state is dropped, after `lua_close`
+    /// has finished, so all userdata are already finalized and no Lua code can run anymore.
+    /// This makes them suitable for releasing host-side resources tied to the state. Handlers
+    /// run in the reverse order of registration (LIFO).
+    ///
+    /// States not owned by `mlua` (for example in module mode) are never closed by it, so the
+    /// handlers are not invoked for them.
+    pub fn on_close<F>(&self, handler: F)
+    where
+        F: 'static + MaybeSend + FnOnce(),
+    {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.close_handlers.push(Box::new(handler));
+    }
+
+    /// Sets a handler that is invoked whenever Lua code indexes a `nil` value.
+    ///
+    /// The handler receives the key and can either return a value for the expression or an
+    /// error with a more helpful message. It's installed as the `__index` metamethod of the
+    /// `nil` type, which is shared by all threads of this Lua state.
+    ///
+    /// The handler is state-wide: it applies to every chunk, environment and coroutine, including
+    /// library code that relies on indexing `nil` to raise an error. Install it only when all code
+    /// running in this state expects it, and use [`remove_nil_index_handler`] to restore the
+    /// default behaviour.
+    ///
+    /// Without a handler, Lua raises its own error, which names the indexed variable when that
+    /// information is available (e.g. `attempt to index a nil value (local 'config')`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_nil_index_handler(|_, _key| Ok(Value::Nil))?;
+    /// // Safe navigation through missing tables
+    /// assert_eq!(lua.load("local t = {}; return t.a.b.c").eval::<Value>()?, Value::Nil);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`remove_nil_index_handler`]: #method.remove_nil_index_handler
+    pub fn set_nil_index_handler<F>(&self, handler: F) -> Result<()>
+    where
+        F: 'static + MaybeSend + for<'lua> Fn(&'lua Lua, Value<'lua>) -> Result<Value<'lua>>,
+    {
+        let metatable = self.create_table()?;
+        metatable.raw_set(
+            "__index",
+            self.create_function(move |lua, (_, key): (Value, Value)| handler(lua, key))?,
+        )?;
+        unsafe { self.set_nil_metatable(Some(metatable)) }
+    }
+
+    /// Removes any handler previously set by `set_nil_index_handler`.
+    pub fn remove_nil_index_handler(&self) -> Result<()> {
+        unsafe { self.set_nil_metatable(None) }
+    }
+
+    unsafe fn set_nil_metatable(&self, metatable: Option<Table>) -> Result<()> {
+        let _sg = StackGuard::new(self.state);
+        check_stack(self.state, 2)?;
+
+        ffi::lua_pushnil(self.state);
+        match metatable {
+            Some(metatable) => self.push_ref(&metatable.0),
+            None => ffi::lua_pushnil(self.state),
         }
+        ffi::lua_setmetatable(self.state, -2);
+        Ok(())
     }
 
     /// Returns the amount of memory (in bytes) currently used inside this Lua state.
@@ -642,6 +1251,9 @@ impl Lua {
     /// a `Error::MemoryError` is generated instead.
     /// Returns previous limit (zero means no limit).
     ///
+    /// The limit can be set below the current usage, in which case new allocations fail until
+    /// enough memory is released (or the limit is raised), while the state itself stays usable.
+    ///
     /// Does not work on module mode where Lua state is managed externally.
     ///
     /// Requires `feature = "lua54/lua53/lua52"`
@@ -679,6 +1291,23 @@ impl Lua {
         unsafe { ffi::lua_gc(state, ffi::LUA_GCRESTART, 0) };
     }
 
+    /// Stops the Lua GC until the returned guard is dropped.
+    ///
+    /// No automatic garbage collection happens while the guard is alive, which is useful for
+    /// latency-sensitive sections of code. When the guard is dropped, the GC is restarted unless
+    /// it was already stopped when the guard was created.
+    ///
+    /// The GC state cannot be checked in Lua 5.1 and LuaJIT, so there the GC is always restarted.
+    pub fn gc_pause_guard<'lua>(&'lua self) -> GcPauseGuard<'lua> {
+        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+        let restart = self.gc_is_running();
+        #[cfg(any(feature = "lua51", feature = "luajit"))]
+        let restart = true;
+
+        self.gc_stop();
+        GcPauseGuard { lua: self, restart }
+    }
+
     /// Perform a full garbage-collection cycle.
     ///
     /// It may be necessary to call this function twice to collect all currently unreachable
@@ -704,6 +1333,12 @@ impl Lua {
     ///
     /// if `kbytes` is 0, then this is the same as calling `gc_step`. Returns true if this step has
     /// finished a collection cycle.
+    ///
+    /// Together with [`gc_stop`], this allows to run the collector incrementally within a time
+    /// budget (e.g. once per frame) instead of doing full collections. In the Lua 5.4
+    /// generational mode a step performs a minor (or major, if needed) collection instead.
+    ///
+    /// [`gc_stop`]: #method.gc_stop
     pub fn gc_step_kbytes(&self, kbytes: c_int) -> Result<bool> {
         let state = self.main_state.unwrap_or(self.state);
         unsafe {
@@ -719,6 +1354,11 @@ impl Lua {
     /// Returns the previous value of 'pause'. More information can be found in the [Lua 5.3
     /// documentation][lua_doc].
     ///
+    /// The value is only used by the incremental collector, in the Lua 5.4 generational mode it
+    /// has no effect until the collector is switched back with [`gc_inc`].
+    ///
+    /// [`gc_inc`]: #method.gc_inc
+    ///
     /// [lua_doc]: https://www.lua.org/manual/5.3/manual.html#2.5
     pub fn gc_set_pause(&self, pause: c_int) -> c_int {
         let state = self.main_state.unwrap_or(self.state);
@@ -730,6 +1370,10 @@ impl Lua {
     /// Returns the previous value of the 'step multiplier'. More information can be found in the
     /// Lua 5.x [documentation][lua_doc].
     ///
+    /// Like for [`gc_set_pause`], the value has no effect in the Lua 5.4 generational mode.
+    ///
+    /// [`gc_set_pause`]: #method.gc_set_pause
+    ///
     /// [lua_doc]: https://www.lua.org/manual/5.3/manual.html#2.5
     pub fn gc_set_step_multiplier(&self, step_multiplier: c_int) -> c_int {
         let state = self.main_state.unwrap_or(self.state);
@@ -741,6 +1385,13 @@ impl Lua {
     /// Returns the previous mode (always `GCMode::Incremental` in Lua < 5.4).
     /// More information can be found in the Lua 5.x [documentation][lua_doc].
     ///
+    /// A zero `pause` or `step_multiplier` keeps the current value. `step_size` is only supported
+    /// by Lua 5.4 and ignored by other versions, where this method is equivalent to calling
+    /// [`gc_set_pause`] and [`gc_set_step_multiplier`].
+    ///
+    /// [`gc_set_pause`]: #method.gc_set_pause
+    /// [`gc_set_step_multiplier`]: #method.gc_set_step_multiplier
+    ///
     /// [lua_doc]: https://www.lua.org/manual/5.4/manual.html#2.5.1
     pub fn gc_inc(&self, pause: c_int, step_multiplier: c_int, step_size: c_int) -> GCMode {
         let state = self.main_state.unwrap_or(self.state);
@@ -816,32 +1467,188 @@ impl Lua {
         }
     }
 
-    fn load_chunk<'lua>(
+    /// Compiles a Lua chunk read incrementally from `reader`, returning it as a function.
+    ///
+    /// Unlike [`load`], the source is passed to Lua in small blocks as it's read, so the whole
+    /// script is never held in a single buffer. `name` is used as the chunk name, in the same way
+    /// as [`Chunk::set_name`]. The chunk is not executed and uses the global environment.
+    ///
+    /// An I/O error returned by the reader is passed through as [`Error::ExternalError`]. Binary
+    /// chunks are only accepted if this `Lua` was created with `unsafe_new`.
+    ///
+    /// [`load`]: #method.load
+    /// [`Chunk::set_name`]: struct.Chunk.html#method.set_name
+    /// [`Error::ExternalError`]: enum.Error.html#variant.ExternalError
+    pub fn load_from_reader<'lua, R: Read>(
         &'lua self,
-        source: &[u8],
-        name: Option<&CString>,
-        env: Option<Value<'lua>>,
-        mode: Option<ChunkMode>,
+        reader: R,
+        name: &str,
     ) -> Result<Function<'lua>> {
-        unsafe {
-            let _sg = StackGuard::new(self.state);
-            check_stack(self.state, 1)?;
+        struct ChunkReader<R> {
+            reader: R,
+            buf: Vec<u8>,
+            // Bytes already read into `buf` but not yet passed to Lua
+            pending: usize,
+            error: Option<io::Error>,
+            panic: Option<Box<dyn Any + Send + 'static>>,
+        }
 
-            let mode_str = match mode {
-                Some(ChunkMode::Binary) if self.safe => {
-                    return Err(Error::SafetyError(
-                        "binary chunks are disabled in safe mode".to_string(),
-                    ))
+        impl<R: Read> ChunkReader<R> {
+            fn read(&mut self) -> io::Result<usize> {
+                loop {
+                    match self.reader.read(&mut self.buf) {
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        res => return res,
+                    }
                 }
-                Some(ChunkMode::Binary) => cstr!("b"),
-                Some(ChunkMode::Text) => cstr!("t"),
-                None if source.starts_with(ffi::LUA_SIGNATURE) && self.safe => {
-                    return Err(Error::SafetyError(
+            }
+        }
+
+        unsafe extern "C" fn read_block<R: Read>(
+            _: *mut ffi::lua_State,
+            data: *mut c_void,
+            size: *mut usize,
+        ) -> *const c_char {
+            let chunk_reader = &mut *(data as *mut ChunkReader<R>);
+            *size = 0;
+            if chunk_reader.pending > 0 {
+                *size = mem::take(&mut chunk_reader.pending);
+                return chunk_reader.buf.as_ptr() as *const c_char;
+            }
+            if chunk_reader.error.is_some() || chunk_reader.panic.is_some() {
+                return ptr::null();
+            }
+            // Rust panics must not unwind through the Lua parser
+            match catch_unwind(AssertUnwindSafe(|| chunk_reader.read())) {
+                Ok(Ok(n)) if n > 0 => {
+                    *size = n;
+                    return chunk_reader.buf.as_ptr() as *const c_char;
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => chunk_reader.error = Some(err),
+                Err(panic) => chunk_reader.panic = Some(panic),
+            }
+            ptr::null()
+        }
+
+        let name = CString::new(name).map_err(|e| Error::ToLuaConversionError {
+            from: "&str",
+            to: "string",
+            message: Some(e.to_string()),
+        })?;
+
+        let mut chunk_reader = ChunkReader {
+            reader,
+            buf: vec![0; 8192],
+            pending: 0,
+            error: None,
+            panic: None,
+        };
+        // Look at the first block to reject binary chunks in safe mode on every Lua version
+        chunk_reader.pending = chunk_reader.read().map_err(Error::external)?;
+        if self.safe && chunk_reader.pending > 0 && chunk_reader.buf[0] == ffi::LUA_SIGNATURE[0] {
+            return Err(Error::SafetyError(
+                "binary chunks are disabled in safe mode".to_string(),
+            ));
+        }
+        if chunk_reader.pending > 0 && chunk_reader.buf[0] == ffi::LUA_SIGNATURE[0] {
+            self.check_bytecode_enabled()?;
+        }
+        let mode = if self.safe { cstr!("t") } else { cstr!("bt") };
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            let status = ffi::lua_load(
+                self.state,
+                read_block::<R>,
+                &mut chunk_reader as *mut ChunkReader<R> as *mut c_void,
+                name.as_ptr(),
+                mode,
+            );
+            if let Some(panic) = chunk_reader.panic.take() {
+                resume_unwind(panic);
+            }
+            if let Some(err) = chunk_reader.error.take() {
+                return Err(Error::external(err));
+            }
+            match status {
+                ffi::LUA_OK => Ok(Function(self.pop_ref())),
+                err => Err(pop_error(self.state, err)),
+            }
+        }
+    }
+
+    /// Loads a binary chunk previously produced by [`Chunk::into_bytecode`] or
+    /// [`Function::dump`], returning it as a function.
+    ///
+    /// `name` is used as the chunk name, in the same way as [`Chunk::set_name`]. The chunk is not
+    /// executed and uses the global environment.
+    ///
+    /// Bytecode is not verified by Lua and malformed input can crash the interpreter, so this
+    /// returns [`Error::RuntimeError`] unless this `Lua` was created with `unsafe_new`, or if
+    /// [`LuaOptions::disable_bytecode_loading`] is set. Bytecode is also only compatible with the
+    /// same Lua version it was produced by.
+    ///
+    /// [`Chunk::into_bytecode`]: struct.Chunk.html#method.into_bytecode
+    /// [`Function::dump`]: struct.Function.html#method.dump
+    /// [`Chunk::set_name`]: struct.Chunk.html#method.set_name
+    /// [`Error::RuntimeError`]: enum.Error.html#variant.RuntimeError
+    /// [`LuaOptions::disable_bytecode_loading`]: struct.LuaOptions.html#structfield.disable_bytecode_loading
+    pub fn load_bytecode<'lua>(&'lua self, bytecode: &[u8], name: &str) -> Result<Function<'lua>> {
+        let name = CString::new(name).map_err(|e| Error::ToLuaConversionError {
+            from: "&str",
+            to: "string",
+            message: Some(e.to_string()),
+        })?;
+        self.check_bytecode_enabled()?;
+        if self.safe {
+            return Err(Error::RuntimeError(
+                "binary chunks are disabled in safe mode".to_string(),
+            ));
+        }
+        self.load_chunk(bytecode, Some(&name), None, Some(ChunkMode::Binary))
+    }
+
+    fn load_chunk<'lua>(
+        &'lua self,
+        source: &[u8],
+        name: Option<&CString>,
+        env: Option<Value<'lua>>,
+        mode: Option<ChunkMode>,
+    ) -> Result<Function<'lua>> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            let mode_str = match mode {
+                Some(ChunkMode::Binary) if self.safe => {
+                    return Err(Error::SafetyError(
+                        "binary chunks are disabled in safe mode".to_string(),
+                    ))
+                }
+                Some(ChunkMode::Binary) => cstr!("b"),
+                Some(ChunkMode::Text) => cstr!("t"),
+                Some(ChunkMode::TextAndBinary) | None
+                    if source.starts_with(ffi::LUA_SIGNATURE) && self.safe =>
+                {
+                    return Err(Error::SafetyError(
                         "binary chunks are disabled in safe mode".to_string(),
                     ))
                 }
-                None => cstr!("bt"),
+                Some(ChunkMode::TextAndBinary) | None => cstr!("bt"),
             };
+            // Rejected before reaching Lua, bytecode is not even inspected in text mode
+            let is_binary = source.first() == Some(&ffi::LUA_SIGNATURE[0]);
+            if is_binary && mode == Some(ChunkMode::Text) {
+                return Err(Error::RuntimeError(
+                    "attempt to load a binary chunk (mode is 't')".to_string(),
+                ));
+            }
+            if is_binary {
+                self.check_bytecode_enabled()?;
+            }
 
             match ffi::luaL_loadbufferx(
                 self.state,
@@ -865,6 +1672,150 @@ impl Lua {
         }
     }
 
+    // Replaces the global `load`, `loadstring`, `loadfile` and `dofile` functions with wrappers
+    // that only accept text chunks.
+    fn disable_lua_bytecode_loading(&self) -> Result<()> {
+        #[cfg(feature = "lua51")]
+        let helpers = (
+            // Lua 5.1 does not support the `mode` argument, binary chunks are detected by their
+            // first byte like `lua_load` does
+            self.create_function(|_, chunk: String| {
+                Ok(chunk.as_bytes().first() == Some(&ffi::LUA_SIGNATURE[0]))
+            })?,
+            // Reads a chunk for `loadfile`, skipping the first line if it starts with `#`
+            self.create_function(|lua, filename: Option<StdString>| {
+                let (data, chunkname) = match filename {
+                    Some(filename) => (std::fs::read(&filename), format!("@{}", filename)),
+                    None => {
+                        let mut data = Vec::new();
+                        let res = io::stdin().read_to_end(&mut data);
+                        (res.map(|_| data), "=stdin".to_string())
+                    }
+                };
+                let mut data = match data {
+                    Ok(data) => data,
+                    Err(err) => {
+                        let msg = format!("cannot open {}: {}", &chunkname[1..], err);
+                        return (Nil, msg).to_lua_multi(lua);
+                    }
+                };
+                if data.first() == Some(&b'#') {
+                    let end = data.iter().position(|&c| c == b'\n').unwrap_or(data.len());
+                    data.drain(..end);
+                }
+                if data.first() == Some(&ffi::LUA_SIGNATURE[0]) {
+                    let msg = "attempt to load a binary chunk (mode is 't')";
+                    return (Nil, msg).to_lua_multi(lua);
+                }
+                (lua.create_string(&data)?, chunkname).to_lua_multi(lua)
+            })?,
+        );
+        #[cfg(not(feature = "lua51"))]
+        let helpers = ();
+
+        #[cfg(not(feature = "lua51"))]
+        let source = r#"
+            local load, loadstring, loadfile, dofile = load, loadstring, loadfile, dofile
+            local error, type = error, type
+
+            -- Arguments after `mode` are passed through, as an explicit `nil` environment differs
+            -- from a missing one
+            if type(load) == "function" then
+                _G.load = function(chunk, chunkname, _, ...)
+                    return load(chunk, chunkname, "t", ...)
+                end
+            end
+            if type(loadstring) == "function" then
+                _G.loadstring = function(chunk, chunkname, _, ...)
+                    return loadstring(chunk, chunkname, "t", ...)
+                end
+            end
+            if type(loadfile) == "function" then
+                _G.loadfile = function(filename, _, ...)
+                    return loadfile(filename, "t", ...)
+                end
+                if type(dofile) == "function" then
+                    _G.dofile = function(filename)
+                        local func, err = loadfile(filename, "t")
+                        if func == nil then
+                            error(err, 0)
+                        end
+                        return func()
+                    end
+                end
+            end
+        "#;
+        #[cfg(feature = "lua51")]
+        let source = r#"
+            local is_binary, read_file = ...
+            local load, loadstring, loadfile, dofile = load, loadstring, loadfile, dofile
+            local error, type = error, type
+            local binary_error = "attempt to load a binary chunk (mode is 't')"
+
+            if type(load) == "function" then
+                _G.load = function(reader, chunkname)
+                    local first, rejected = true, false
+                    local func, err = load(function()
+                        local piece = reader()
+                        if first and type(piece) == "string" and piece ~= "" then
+                            first = false
+                            if is_binary(piece) then
+                                rejected = true
+                                return nil
+                            end
+                        end
+                        return piece
+                    end, chunkname)
+                    if rejected then
+                        return nil, binary_error
+                    end
+                    return func, err
+                end
+            end
+            if type(loadstring) == "function" then
+                _G.loadstring = function(chunk, chunkname)
+                    if type(chunk) == "string" and is_binary(chunk) then
+                        return nil, binary_error
+                    end
+                    return loadstring(chunk, chunkname)
+                end
+
+                local function load_text_file(filename)
+                    local chunk, chunkname = read_file(filename)
+                    if chunk == nil then
+                        return nil, chunkname
+                    end
+                    return loadstring(chunk, chunkname)
+                end
+                if type(loadfile) == "function" then
+                    _G.loadfile = load_text_file
+                end
+                if type(dofile) == "function" then
+                    _G.dofile = function(filename)
+                        local func, err = load_text_file(filename)
+                        if func == nil then
+                            error(err, 0)
+                        end
+                        return func()
+                    end
+                end
+            end
+        "#;
+
+        self.load(source)
+            .set_name("=__mlua_disable_bytecode")?
+            .call(helpers)
+    }
+
+    fn check_bytecode_enabled(&self) -> Result<()> {
+        if mlua_expect!(self.extra.lock(), "extra is poisoned").disable_bytecode {
+            return Err(Error::RuntimeError(
+                "bytecode loading is disabled".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
     /// Create and return an interned Lua string. Lua strings can be arbitrary [u8] data including
     /// embedded nulls, so in addition to `&str` and `&String`, you can also pass plain `&[u8]`
     /// here.
@@ -927,7 +1878,67 @@ impl Lua {
         }
     }
 
+    /// Creates a table from an iterator of dotted paths and values, building nested tables.
+    ///
+    /// Each path is split on `.`, and intermediate tables are created as needed, so the entries
+    /// `("a.b", 1)` and `("a.c", 2)` produce `{ a = { b = 1, c = 2 } }`. If the same path appears
+    /// more than once, the last value wins.
+    ///
+    /// Returns an error if a path contains an empty segment, or if a path is used both as a value
+    /// and as a table of nested values (eg. `"a"` and `"a.b"`).
+    pub fn create_nested_table<'lua, K, V, I>(&'lua self, iter: I) -> Result<Table<'lua>>
+    where
+        K: AsRef<str>,
+        V: ToLua<'lua>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let root = self.create_table()?;
+        // Tables created for intermediate path segments, as opposed to table values
+        let mut branches = HashSet::new();
+        branches.insert(root.to_pointer());
+
+        for (path, value) in iter {
+            let path = path.as_ref();
+            let conflict = || Error::RuntimeError(format!("conflicting path '{}'", path));
+            let mut segments = path.split('.');
+            if segments.clone().any(|s| s.is_empty()) {
+                return Err(Error::RuntimeError(format!(
+                    "path '{}' contains an empty key",
+                    path
+                )));
+            }
+
+            let leaf = mlua_expect!(segments.next_back(), "split always yields a segment");
+            let mut table = root.clone();
+            for segment in segments {
+                table = match table.raw_get::<_, Value>(segment)? {
+                    Value::Nil => {
+                        let branch = self.create_table()?;
+                        branches.insert(branch.to_pointer());
+                        table.raw_set(segment, branch.clone())?;
+                        branch
+                    }
+                    Value::Table(t) if branches.contains(&t.to_pointer()) => t,
+                    _ => return Err(conflict()),
+                };
+            }
+
+            if let Value::Table(t) = table.raw_get::<_, Value>(leaf)? {
+                if branches.contains(&t.to_pointer()) {
+                    return Err(conflict());
+                }
+            }
+            table.raw_set(leaf, value)?;
+        }
+
+        Ok(root)
+    }
+
     /// Creates a table from an iterator of values, using `1..` as the keys.
+    ///
+    /// The array part of the table is preallocated using the lower bound of the iterator's
+    /// `size_hint`, so building a table from a `Vec` or another exact-size iterator does not
+    /// rehash while it's filled.
     pub fn create_sequence_from<'lua, T, I>(&'lua self, iter: I) -> Result<Table<'lua>>
     where
         T: ToLua<'lua>,
@@ -951,6 +1962,54 @@ impl Lua {
         }
     }
 
+    /// Creates a sequence table with the integers produced by the numeric `for` loop
+    /// `for i = start, stop, step do ... end`.
+    ///
+    /// Like Lua 5.4, the number of iterations is computed before the loop starts, so ranges
+    /// reaching the integer limits (eg. ending at `Integer::MAX`) never overflow and always
+    /// terminate. Returns an error if `step` is zero.
+    ///
+    /// The table is preallocated to hold all the values, so an error is returned if the range has
+    /// more than `c_int::MAX` values or the allocation fails (eg. because of the memory limit).
+    pub fn create_range_table<'lua>(
+        &'lua self,
+        start: Integer,
+        stop: Integer,
+        step: Integer,
+    ) -> Result<Table<'lua>> {
+        if step == 0 {
+            return Err(Error::RuntimeError("'for' step is zero".to_string()));
+        }
+
+        let (start, stop, step) = (start as i128, stop as i128, step as i128);
+        let count = if (step > 0 && start > stop) || (step < 0 && start < stop) {
+            0
+        } else {
+            (stop - start) / step + 1
+        };
+        if count > c_int::MAX as i128 {
+            return Err(Error::RuntimeError(format!(
+                "range is too large ({} values)",
+                count
+            )));
+        }
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 4)?;
+
+            push_table(self.state, count as c_int, 0)?;
+            for i in 0..count {
+                ffi::lua_pushinteger(self.state, (start + i * step) as Integer);
+                protect_lua(self.state, 2, 1, |state| {
+                    ffi::lua_rawseti(state, -2, (i + 1) as Integer);
+                })?;
+            }
+
+            Ok(Table(self.pop_ref()))
+        }
+    }
+
     /// Wraps a Rust function or closure, creating a callable Lua function handle to it.
     ///
     /// The function's return value is always a `Result`: If the function returns `Err`, the error
@@ -1032,6 +2091,103 @@ impl Lua {
         })
     }
 
+    /// Wraps a Rust closure with mutable state, creating a callable Lua function handle to it.
+    ///
+    /// Unlike [`create_function_mut`], the function can be re-entered from Lua (eg. through a
+    /// metamethod calling back into it) without failing with [`Error::RecursiveMutCallback`].
+    /// Instead of mutably borrowing the closure, the `state` is wrapped into a [`Cell`] which
+    /// is passed to every invocation.
+    ///
+    /// Re-entrant calls are not queued: they run immediately, nested in the call that triggered
+    /// them, and complete before it resumes. Changes made to the state by a nested call are
+    /// visible to the outer call as soon as the nested call returns.
+    ///
+    /// # Examples
+    ///
+    /// Count all nested tables:
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let count = lua.create_function_reentrant(0, |lua, t: Table, count| {
+    ///     count.set(count.get() + 1);
+    ///     let count_nested: mlua::Function = lua.globals().get("count")?;
+    ///     for value in t.sequence_values::<Table>() {
+    ///         count_nested.call::<_, ()>(value?)?;
+    ///     }
+    ///     Ok(count.get())
+    /// })?;
+    /// lua.globals().set("count", count.clone())?;
+    ///
+    /// let tables = lua.load("{ {}, { {}, {} } }").eval::<Table>()?;
+    /// assert_eq!(count.call::<_, i64>(tables)?, 5);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`create_function_mut`]: #method.create_function_mut
+    /// [`Error::RecursiveMutCallback`]: enum.Error.html#variant.RecursiveMutCallback
+    /// [`Cell`]: https://doc.rust-lang.org/std/cell/struct.Cell.html
+    pub fn create_function_reentrant<'lua, 'callback, S, A, R, F>(
+        &'lua self,
+        state: S,
+        func: F,
+    ) -> Result<Function<'lua>>
+    where
+        'lua: 'callback,
+        S: 'static + MaybeSend,
+        A: FromLuaMulti<'callback>,
+        R: ToLuaMulti<'callback>,
+        F: 'static + MaybeSend + Fn(&'callback Lua, A, &Cell<S>) -> Result<R>,
+    {
+        let state = Cell::new(state);
+        self.create_function(move |lua, args| func(lua, args, &state))
+    }
+
+    /// Wraps a Lua function, creating a proxy that passes every call through `interceptor`.
+    ///
+    /// The interceptor receives the wrapped `target` function and the raw call arguments, and
+    /// returns the raw results. It is free to inspect or modify the arguments before calling
+    /// `target` (or to not call it at all) and to modify the returned values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, MultiValue, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let add: Function = lua.load("function(a, b) return a + b end").eval()?;
+    ///
+    /// let proxy = lua.create_proxy_function(add, |_, target, args| {
+    ///     let args = args.into_iter().rev().collect::<MultiValue>();
+    ///     target.call(args)
+    /// })?;
+    /// assert_eq!(proxy.call::<_, i32>((1, 2))?, 3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_proxy_function<'lua, 'callback, F>(
+        &'lua self,
+        target: Function<'lua>,
+        interceptor: F,
+    ) -> Result<Function<'lua>>
+    where
+        'lua: 'callback,
+        F: 'static
+            + MaybeSend
+            + Fn(
+                &'callback Lua,
+                Function<'callback>,
+                MultiValue<'callback>,
+            ) -> Result<MultiValue<'callback>>,
+    {
+        let proxy = self.create_function(move |lua, (target, args): (Function, MultiValue)| {
+            interceptor(lua, target, args)
+        })?;
+        proxy.bind(target)
+    }
+
     /// Wraps a Rust async function or closure, creating a callable Lua function handle to it.
     ///
     /// While executing the function Rust will poll Future and if the result is not ready, call
@@ -1042,105 +2198,764 @@ impl Lua {
     /// in that case. Otherwise noop waker will be used if try to call the function outside of Rust
     /// executors.
     ///
-    /// The family of `call_async()` functions takes care about creating [`Thread`].
+    /// The family of `call_async()` functions takes care about creating [`Thread`].
+    ///
+    /// Requires `feature = "async"`
+    ///
+    /// # Examples
+    ///
+    /// Non blocking sleep:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use futures_timer::Delay;
+    /// use mlua::{Lua, Result};
+    ///
+    /// async fn sleep(_lua: &Lua, n: u64) -> Result<&'static str> {
+    ///     Delay::new(Duration::from_millis(n)).await;
+    ///     Ok("done")
+    /// }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let lua = Lua::new();
+    ///     lua.globals().set("sleep", lua.create_async_function(sleep)?)?;
+    ///     let res: String = lua.load("return sleep(...)").call_async(100).await?; // Sleep 100ms
+    ///     assert_eq!(res, "done");
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// [`Thread`]: struct.Thread.html
+    /// [`AsyncThread`]: struct.AsyncThread.html
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub fn create_async_function<'lua, 'callback, A, R, F, FR>(
+        &'lua self,
+        func: F,
+    ) -> Result<Function<'lua>>
+    where
+        'lua: 'callback,
+        A: FromLuaMulti<'callback>,
+        R: ToLuaMulti<'callback>,
+        F: 'static + MaybeSend + Fn(&'callback Lua, A) -> FR,
+        FR: 'lua + Future<Output = Result<R>>,
+    {
+        self.create_async_callback(Box::new(move |lua, args| {
+            let args = match A::from_lua_multi(args, lua) {
+                Ok(args) => args,
+                Err(e) => return Box::pin(future::err(e)),
+            };
+            Box::pin(func(lua, args).and_then(move |ret| future::ready(ret.to_lua_multi(lua))))
+        }))
+    }
+
+    /// Wraps a Lua function into a new thread (or coroutine).
+    ///
+    /// Equivalent to `coroutine.create`.
+    pub fn create_thread<'lua>(&'lua self, func: Function<'lua>) -> Result<Thread<'lua>> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 3)?;
+
+            let thread_state = protect_lua(self.state, 0, 1, |state| ffi::lua_newthread(state))?;
+            self.push_ref(&func.0);
+            ffi::lua_xmove(self.state, thread_state, 1);
+
+            Ok(Thread(self.pop_ref()))
+        }
+    }
+
+    /// Resumes each thread once with its arguments and returns the resulting statuses.
+    ///
+    /// This is useful for cooperative schedulers that step a pool of coroutines at once.
+    /// The values yielded or returned by the threads are discarded. An error resuming one
+    /// thread does not prevent the others from being resumed.
+    pub fn resume_round<'lua>(
+        &'lua self,
+        threads: &[(&Thread<'lua>, MultiValue<'lua>)],
+    ) -> Vec<Result<ThreadStatus>> {
+        threads
+            .iter()
+            .map(|(thread, args)| {
+                thread
+                    .resume_status::<_, MultiValue>(args.clone())
+                    .map(|(status, _)| status)
+            })
+            .collect()
+    }
+
+    /// Runs a Lua function as a new coroutine until it finishes, collecting everything it yields.
+    ///
+    /// The coroutine is first resumed with `args` and then without arguments after every yield.
+    /// Returns the values of each yield in order together with the final return values.
+    ///
+    /// An error raised inside the coroutine stops the run and is returned; the values yielded so far
+    /// are discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let func: Function = lua.load(r#"
+    ///     function(n)
+    ///         for i = 1, n do
+    ///             coroutine.yield(i)
+    ///         end
+    ///         return "done"
+    ///     end
+    /// "#).eval()?;
+    ///
+    /// let (yields, ret) = lua.run_coroutine::<_, String>(func, 2)?;
+    /// assert_eq!(yields.len(), 2);
+    /// assert_eq!(ret, "done");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn run_coroutine<'lua, A, R>(
+        &'lua self,
+        func: Function<'lua>,
+        args: A,
+    ) -> Result<(Vec<MultiValue<'lua>>, R)>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let thread = self.create_thread(func)?;
+        let mut yields = Vec::new();
+        let (mut status, mut results) = thread.resume_status::<_, MultiValue>(args)?;
+        while status == ThreadStatus::Resumable {
+            yields.push(results);
+            let (next_status, next_results) = thread.resume_status::<_, MultiValue>(())?;
+            status = next_status;
+            results = next_results;
+        }
+        Ok((yields, R::from_lua_multi(results, self)?))
+    }
+
+    /// Create a Lua userdata object from a custom userdata type.
+    pub fn create_userdata<T>(&self, data: T) -> Result<AnyUserData>
+    where
+        T: 'static + MaybeSend + UserData,
+    {
+        unsafe { self.make_userdata(UserDataCell::new(data)) }
+    }
+
+    /// Create a Lua userdata object whose methods are looked up in a runtime dispatch table.
+    ///
+    /// Every method lookup from Lua consults `methods`, so methods added to (or removed from) a
+    /// clone of the [`DynamicMethods`] table become callable (or not) immediately.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{DynamicMethods, Lua, MultiValue, Result, ToLuaMulti};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let methods = DynamicMethods::new();
+    /// lua.globals().set("plugin", lua.create_dynamic_userdata(methods.clone())?)?;
+    ///
+    /// methods.insert("greet", |lua, _args: MultiValue| "hello".to_lua_multi(lua));
+    /// assert_eq!(lua.load("plugin:greet()").eval::<String>()?, "hello");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`DynamicMethods`]: struct.DynamicMethods.html
+    pub fn create_dynamic_userdata<'lua>(
+        &'lua self,
+        methods: DynamicMethods,
+    ) -> Result<AnyUserData<'lua>> {
+        self.create_userdata(DynamicUserData(methods))
+    }
+
+    /// Create a Lua userdata object from a custom serializable userdata type.
+    ///
+    /// Requires `feature = "serialize"`
+    #[cfg(feature = "serialize")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serialize")))]
+    pub fn create_ser_userdata<T>(&self, data: T) -> Result<AnyUserData>
+    where
+        T: 'static + MaybeSend + UserData + Serialize,
+    {
+        unsafe { self.make_userdata(UserDataCell::new_ser(data)) }
+    }
+
+    /// Converts a TOML document into a Lua value.
+    ///
+    /// The document is parsed and then converted using [`LuaSerdeExt::to_value`], so TOML tables
+    /// become Lua tables and arrays become Lua sequences.
+    ///
+    /// Requires `feature = "toml"`
+    ///
+    /// [`LuaSerdeExt::to_value`]: serde/trait.LuaSerdeExt.html#tymethod.to_value
+    #[cfg(feature = "toml")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "toml")))]
+    pub fn value_from_toml<'lua>(&'lua self, document: &str) -> Result<Value<'lua>> {
+        let value: toml_crate::Value = toml_crate::from_str(document)
+            .map_err(|err| Error::DeserializeError(err.to_string()))?;
+        self.to_value(&value)
+    }
+
+    /// Converts a Lua value into a TOML document.
+    ///
+    /// The value is converted using [`LuaSerdeExt::from_value`] and must be a table, as required
+    /// by the TOML format.
+    ///
+    /// Requires `feature = "toml"`
+    ///
+    /// [`LuaSerdeExt::from_value`]: serde/trait.LuaSerdeExt.html#tymethod.from_value
+    #[cfg(feature = "toml")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "toml")))]
+    pub fn value_to_toml<'lua>(&'lua self, value: Value<'lua>) -> Result<StdString> {
+        let value: toml_crate::Value = self.from_value(value)?;
+        toml_crate::to_string(&value).map_err(|err| Error::SerializeError(err.to_string()))
+    }
+
+    /// Converts a YAML document into a Lua value.
+    ///
+    /// The document is parsed and then converted using [`LuaSerdeExt::to_value`], so YAML
+    /// mappings become Lua tables and sequences become Lua sequences.
+    ///
+    /// Requires `feature = "yaml"`
+    ///
+    /// [`LuaSerdeExt::to_value`]: serde/trait.LuaSerdeExt.html#tymethod.to_value
+    #[cfg(feature = "yaml")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "yaml")))]
+    pub fn value_from_yaml<'lua>(&'lua self, document: &str) -> Result<Value<'lua>> {
+        let value: serde_yaml::Value = serde_yaml::from_str(document)
+            .map_err(|err| Error::DeserializeError(err.to_string()))?;
+        self.to_value(&value)
+    }
+
+    /// Converts a Lua value into a YAML document.
+    ///
+    /// The value is converted using [`LuaSerdeExt::from_value`].
+    ///
+    /// Requires `feature = "yaml"`
+    ///
+    /// [`LuaSerdeExt::from_value`]: serde/trait.LuaSerdeExt.html#tymethod.from_value
+    #[cfg(feature = "yaml")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "yaml")))]
+    pub fn value_to_yaml<'lua>(&'lua self, value: Value<'lua>) -> Result<StdString> {
+        let value: serde_yaml::Value = self.from_value(value)?;
+        serde_yaml::to_string(&value).map_err(|err| Error::SerializeError(err.to_string()))
+    }
+
+    /// Serializes a Lua value into `writer` using the given [`SerializeFormat`].
+    ///
+    /// Output is written incrementally while tables are traversed, so large tables are never
+    /// held in memory as a whole. Consider wrapping `writer` in a [`BufWriter`] to avoid many
+    /// small writes.
+    ///
+    /// Recursive tables cannot be represented and result in an [`Error::SerializeError`]. Since
+    /// the cycle is only found when it is reached, some output may already have been written.
+    ///
+    /// Requires `feature = "json"` or `feature = "yaml"`
+    ///
+    /// [`SerializeFormat`]: serde/enum.SerializeFormat.html
+    /// [`BufWriter`]: https://doc.rust-lang.org/std/io/struct.BufWriter.html
+    /// [`Error::SerializeError`]: enum.Error.html#variant.SerializeError
+    #[cfg(any(feature = "json", feature = "yaml"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "json", feature = "yaml"))))]
+    pub fn serialize_value_to_writer<'lua, W: Write>(
+        &'lua self,
+        value: Value<'lua>,
+        writer: W,
+        format: SerializeFormat,
+    ) -> Result<()> {
+        let visited = RefCell::new(HashSet::new());
+        let value = SerializeChecked::new(&value, &visited);
+        match format {
+            #[cfg(feature = "json")]
+            SerializeFormat::Json => serde_json::to_writer(writer, &value)
+                .map_err(|err| Error::SerializeError(err.to_string())),
+            #[cfg(feature = "json")]
+            SerializeFormat::JsonPretty => serde_json::to_writer_pretty(writer, &value)
+                .map_err(|err| Error::SerializeError(err.to_string())),
+            #[cfg(feature = "yaml")]
+            SerializeFormat::Yaml => serde_yaml::to_writer(writer, &value)
+                .map_err(|err| Error::SerializeError(err.to_string())),
+        }
+    }
+
+    /// Returns a handle to the global environment.
+    pub fn globals(&self) -> Table {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+            ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+            #[cfg(any(feature = "lua51", feature = "luajit"))]
+            ffi::lua_pushvalue(self.state, ffi::LUA_GLOBALSINDEX);
+            Table(self.pop_ref())
+        }
+    }
+
+    /// Creates an environment table that exposes only selected variables of `parent`.
+    ///
+    /// Reading a name listed in `allow` that is not set in the new table falls back to `parent`
+    /// (including its own `__index` chain), other names read as `nil`. Assignments are stored in
+    /// the new table and never reach `parent`. Since `parent` can itself be created by this
+    /// function, environments can be layered, e.g. for plugins loaded by other plugins.
+    ///
+    /// The returned table is meant to be passed to [`Chunk::set_environment`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let env = lua.child_env(lua.globals(), &["assert", "string"])?;
+    ///
+    /// lua.load("x = string.upper('hi'); assert(os == nil)")
+    ///     .set_environment(env.clone())?
+    ///     .exec()?;
+    /// assert_eq!(env.get::<_, String>("x")?, "HI");
+    /// assert!(!lua.globals().contains_key("x")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Chunk::set_environment`]: struct.Chunk.html#method.set_environment
+    pub fn child_env<'lua>(&'lua self, parent: Table<'lua>, allow: &[&str]) -> Result<Table<'lua>> {
+        let allowed = self.create_table_from(allow.iter().map(|&name| (name, true)))?;
+        let index = self
+            .create_function(
+                |_, (parent, allowed, _, key): (Table, Table, Value, Value)| {
+                    if allowed.raw_get::<_, bool>(key.clone())? {
+                        return parent.get::<_, Value>(key);
+                    }
+                    Ok(Nil)
+                },
+            )?
+            .bind((parent, allowed))?;
+
+        let metatable = self.create_table()?;
+        metatable.raw_set("__index", index)?;
+        let env = self.create_table()?;
+        env.set_metatable(Some(metatable));
+        Ok(env)
+    }
+
+    /// Captures the current contents of the globals table, to be restored later with
+    /// [`restore_globals`].
+    ///
+    /// The snapshot is shallow: it records which values are assigned to which global names, but
+    /// tables (like `string` or `math`) are shared with the running state, so changes made inside
+    /// them are not undone. The globals metatable is not captured either.
+    ///
+    /// [`restore_globals`]: #method.restore_globals
+    pub fn snapshot_globals(&self) -> Result<GlobalsSnapshot> {
+        let copy = self.create_table()?;
+        for pair in self.globals().pairs::<Value, Value>() {
+            let (k, v) = pair?;
+            copy.raw_set(k, v)?;
+        }
+        Ok(GlobalsSnapshot(self.create_registry_value(copy)?))
+    }
+
+    /// Resets the globals table to the contents captured by [`snapshot_globals`].
+    ///
+    /// Globals set since the snapshot was taken are removed, and globals that were reassigned or
+    /// removed get their previous values back. The same snapshot can be restored any number of
+    /// times, which makes it a cheap way to run independent scripts in the same `Lua` instance.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let snapshot = lua.snapshot_globals()?;
+    ///
+    /// lua.load("counter = 1; print = nil").exec()?;
+    /// lua.restore_globals(&snapshot)?;
+    ///
+    /// assert_eq!(lua.load("counter").eval::<Option<i32>>()?, None);
+    /// assert!(lua.load("print").eval::<Option<mlua::Function>>()?.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`snapshot_globals`]: #method.snapshot_globals
+    pub fn restore_globals(&self, snapshot: &GlobalsSnapshot) -> Result<()> {
+        let saved: Table = self.registry_value(&snapshot.0)?;
+        let globals = self.globals();
+
+        // Keys can't be removed while traversing the table, so collect them first
+        let mut added = Vec::new();
+        for pair in globals.clone().pairs::<Value, Value>() {
+            let (k, _) = pair?;
+            if saved.raw_get::<_, Value>(k.clone())? == Nil {
+                added.push(k);
+            }
+        }
+        for k in added {
+            globals.raw_set(k, Nil)?;
+        }
+        for pair in saved.pairs::<Value, Value>() {
+            let (k, v) = pair?;
+            globals.raw_set(k, v)?;
+        }
+        Ok(())
+    }
+
+    /// Sets a global variable that scripts can read but not reassign.
+    ///
+    /// Constant globals are kept in a separate table which is installed as the `__index` of the
+    /// globals metatable. A `__newindex` metamethod rejects assignments to these names with a
+    /// runtime error, other assignments are stored as usual. Calling this function again with the
+    /// same name replaces the value.
+    ///
+    /// Since constants are not stored in the globals table itself, raw accesses bypass them:
+    /// `rawget(_G, name)` returns `nil`, `pairs(_G)` does not list them, and `rawset(_G, name, v)`
+    /// stores a regular global that shadows the constant.
+    ///
+    /// Returns an error if the globals table already has a metatable with `__index` or
+    /// `__newindex` set by someone else.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_const_global("VERSION", "1.2.3")?;
+    ///
+    /// assert_eq!(lua.load("VERSION").eval::<String>()?, "1.2.3");
+    /// assert!(lua.load(r#"VERSION = "2.0""#).exec().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_const_global<'lua, K, V>(&'lua self, name: K, value: V) -> Result<()>
+    where
+        K: ToLua<'lua>,
+        V: ToLua<'lua>,
+    {
+        let name = name.to_lua(self)?;
+        let consts = self.const_globals()?;
+        self.globals().raw_set(name.clone(), Nil)?;
+        consts.raw_set(name, value)
+    }
+
+    // Returns the table of constant globals, installing the globals metatable on first use.
+    fn const_globals<'lua>(&'lua self) -> Result<Table<'lua>> {
+        let consts_key = &CONST_GLOBALS_REGISTRY_KEY as *const u8 as *const c_void;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            ffi::lua_rawgetp(self.state, ffi::LUA_REGISTRYINDEX, consts_key);
+            if ffi::lua_istable(self.state, -1) != 0 {
+                return Ok(Table(self.pop_ref()));
+            }
+        }
+
+        let globals = self.globals();
+        let metatable = match globals.get_metatable() {
+            Some(mt) => {
+                if mt.contains_key("__index")? || mt.contains_key("__newindex")? {
+                    return Err(Error::RuntimeError(
+                        "globals metatable already has __index or __newindex".to_string(),
+                    ));
+                }
+                mt
+            }
+            None => self.create_table()?,
+        };
+
+        let consts = self.create_table()?;
+        let newindex =
+            self.create_function(|_, (consts, t, k, v): (Table, Table, Value, Value)| {
+                if consts.raw_get::<_, Value>(k.clone())? != Nil {
+                    let name = match k {
+                        Value::String(ref s) => s.to_string_lossy().into_owned(),
+                        _ => k.type_name().to_string(),
+                    };
+                    return Err(Error::RuntimeError(format!(
+                        "cannot assign to constant global '{}'",
+                        name
+                    )));
+                }
+                t.raw_set(k, v)
+            })?;
+        metatable.raw_set("__index", consts.clone())?;
+        metatable.raw_set("__newindex", newindex.bind(consts.clone())?)?;
+        globals.set_metatable(Some(metatable));
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            self.push_ref(&consts.0);
+            protect_lua(self.state, 1, 0, |state| {
+                ffi::lua_rawsetp(state, ffi::LUA_REGISTRYINDEX, consts_key);
+            })?;
+        }
+
+        Ok(consts)
+    }
+
+    /// Redirects script output written to standard output and standard error to Rust sinks.
+    ///
+    /// `print`, `io.write` and `io.stdout` write to `stdout`, and `io.stderr` writes to `stderr`.
+    /// `io.stdout` and `io.stderr` are replaced with objects supporting `write`, `flush` and
+    /// `setvbuf`, and `io.output()` returns the redirected stdout unless another file was set as
+    /// the default output.
+    ///
+    /// Script output is split into three streams: `print`, `write` (`io.write` and `io.stdout`)
+    /// and `stderr`, each with at most one handler. This method sets the handlers of all three
+    /// streams at once, replacing any set by a previous call, [`set_print_handler`] or
+    /// [`set_write_handler`]. Sinks set with [`Thread::set_output`] take precedence over the
+    /// `print` and `write` handlers for their threads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// use std::io::Write;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Sink(Arc<Mutex<Vec<u8>>>);
+    ///
+    /// impl Write for Sink {
+    ///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    ///         self.0.lock().unwrap().write(buf)
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let (stdout, stderr) = (Sink::default(), Sink::default());
+    /// lua.redirect_io(stdout.clone(), stderr.clone())?;
+    /// lua.load(r#"io.write("x") io.stderr:write("y")"#).exec()?;
+    ///
+    /// assert_eq!(*stdout.0.lock().unwrap(), b"x");
+    /// assert_eq!(*stderr.0.lock().unwrap(), b"y");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`set_print_handler`]: #method.set_print_handler
+    /// [`set_write_handler`]: #method.set_write_handler
+    /// [`Thread::set_output`]: struct.Thread.html#method.set_output
+    pub fn redirect_io<O, E>(&self, mut stdout: O, mut stderr: E) -> Result<()>
+    where
+        O: 'static + MaybeSend + std::io::Write,
+        E: 'static + MaybeSend + std::io::Write,
+    {
+        let handlers = self.output_handlers()?;
+        let write_out = self.create_function_mut(move |_, data: String| {
+            stdout.write_all(data.as_bytes()).map_err(Error::external)
+        })?;
+        let write_err = self.create_function_mut(move |_, data: String| {
+            stderr.write_all(data.as_bytes()).map_err(Error::external)
+        })?;
+        handlers.raw_set("print", write_out.clone())?;
+        handlers.raw_set("write", write_out)?;
+        handlers.raw_set("stderr", write_err)
+    }
+
+    /// Sets a handler receiving the output of the global `print` function.
+    ///
+    /// `print` is replaced with a wrapper that formats its arguments exactly like the stock
+    /// implementation (converted with `tostring`, separated by tabs and followed by a newline),
+    /// and passes the whole line to `handler` instead of writing it to stdout. This is useful to
+    /// route script output to a logger, REPL or editor.
     ///
-    /// Requires `feature = "async"`
+    /// This sets the handler of the `print` stream, replacing the one set by a previous call or by
+    /// [`redirect_io`]. Sinks set with [`Thread::set_output`] take precedence for their threads.
+    /// [`remove_print_handler`] restores the default behavior.
     ///
     /// # Examples
     ///
-    /// Non blocking sleep:
-    ///
     /// ```
-    /// use std::time::Duration;
-    /// use futures_timer::Delay;
-    /// use mlua::{Lua, Result};
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// use std::sync::{Arc, Mutex};
     ///
-    /// async fn sleep(_lua: &Lua, n: u64) -> Result<&'static str> {
-    ///     Delay::new(Duration::from_millis(n)).await;
-    ///     Ok("done")
-    /// }
+    /// let lines = Arc::new(Mutex::new(Vec::new()));
+    /// let lines2 = lines.clone();
+    /// lua.set_print_handler(move |_, line| lines2.lock().unwrap().push(line.to_vec()))?;
+    /// lua.load(r#"print("hello", 1, nil)"#).exec()?;
     ///
-    /// #[tokio::main]
-    /// async fn main() -> Result<()> {
-    ///     let lua = Lua::new();
-    ///     lua.globals().set("sleep", lua.create_async_function(sleep)?)?;
-    ///     let res: String = lua.load("return sleep(...)").call_async(100).await?; // Sleep 100ms
-    ///     assert_eq!(res, "done");
-    ///     Ok(())
-    /// }
+    /// assert_eq!(*lines.lock().unwrap(), vec![b"hello\t1\tnil\n".to_vec()]);
+    /// # Ok(())
+    /// # }
     /// ```
     ///
-    /// [`Thread`]: struct.Thread.html
-    /// [`AsyncThread`]: struct.AsyncThread.html
-    #[cfg(feature = "async")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
-    pub fn create_async_function<'lua, 'callback, A, R, F, FR>(
-        &'lua self,
-        func: F,
-    ) -> Result<Function<'lua>>
+    /// [`redirect_io`]: #method.redirect_io
+    /// [`Thread::set_output`]: struct.Thread.html#method.set_output
+    /// [`remove_print_handler`]: #method.remove_print_handler
+    pub fn set_print_handler<F>(&self, handler: F) -> Result<()>
     where
-        'lua: 'callback,
-        A: FromLuaMulti<'callback>,
-        R: ToLuaMulti<'callback>,
-        F: 'static + MaybeSend + Fn(&'callback Lua, A) -> FR,
-        FR: 'lua + Future<Output = Result<R>>,
+        F: 'static + MaybeSend + Fn(&Lua, &[u8]),
     {
-        self.create_async_callback(Box::new(move |lua, args| {
-            let args = match A::from_lua_multi(args, lua) {
-                Ok(args) => args,
-                Err(e) => return Box::pin(future::err(e)),
-            };
-            Box::pin(func(lua, args).and_then(move |ret| future::ready(ret.to_lua_multi(lua))))
-        }))
+        self.set_output_handler("print", handler)
     }
 
-    /// Wraps a Lua function into a new thread (or coroutine).
+    /// Removes any handler previously set by [`set_print_handler`].
     ///
-    /// Equivalent to `coroutine.create`.
-    pub fn create_thread<'lua>(&'lua self, func: Function<'lua>) -> Result<Thread<'lua>> {
-        unsafe {
-            let _sg = StackGuard::new(self.state);
-            check_stack(self.state, 3)?;
+    /// [`set_print_handler`]: #method.set_print_handler
+    pub fn remove_print_handler(&self) -> Result<()> {
+        self.output_handlers()?.raw_set("print", Nil)
+    }
 
-            let thread_state = protect_lua(self.state, 0, 1, |state| ffi::lua_newthread(state))?;
-            self.push_ref(&func.0);
-            ffi::lua_xmove(self.state, thread_state, 1);
+    /// Sets a handler receiving the output of the `io.write` function.
+    ///
+    /// Each call of `io.write` (or `io.stdout:write`) concatenates its string or number arguments
+    /// and passes the result to `handler` instead of writing to the standard output. Numbers are
+    /// formatted like the stock implementation does. It returns the file it was called on, as
+    /// usual.
+    ///
+    /// This sets the handler of the `write` stream, the same rules as for [`set_print_handler`]
+    /// apply. [`remove_write_handler`] restores the default behavior.
+    ///
+    /// [`set_print_handler`]: #method.set_print_handler
+    /// [`remove_write_handler`]: #method.remove_write_handler
+    pub fn set_write_handler<F>(&self, handler: F) -> Result<()>
+    where
+        F: 'static + MaybeSend + Fn(&Lua, &[u8]),
+    {
+        self.set_output_handler("write", handler)
+    }
 
-            Ok(Thread(self.pop_ref()))
-        }
+    /// Removes any handler previously set by [`set_write_handler`].
+    ///
+    /// [`set_write_handler`]: #method.set_write_handler
+    pub fn remove_write_handler(&self) -> Result<()> {
+        self.output_handlers()?.raw_set("write", Nil)
     }
 
-    /// Create a Lua userdata object from a custom userdata type.
-    pub fn create_userdata<T>(&self, data: T) -> Result<AnyUserData>
+    /// Sets a Rust function used by `require` to find Lua modules.
+    ///
+    /// A searcher is inserted into `package.searchers` (`package.loaders` in Lua 5.1 and LuaJIT)
+    /// right after the `package.preload` searcher. When `require` looks up a module, `loader`
+    /// is called with the module name and may return a function which is then called by
+    /// `require` to load the module, or `None` to defer to the next searcher. For example,
+    /// modules can be served from an in-memory map or a virtual filesystem.
+    ///
+    /// Caching is done by `require` in `package.loaded`, exactly like for the default searchers,
+    /// so `loader` is called at most once per (successfully loaded) module.
+    /// Calling this method again replaces the previous loader.
+    ///
+    /// Requires the `package` library to be loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// lua.set_module_loader(|lua, name| match name {
+    ///     "foo.bar" => Ok(Some(lua.load("return { answer = 42 }").into_function()?)),
+    ///     _ => Ok(None),
+    /// })?;
+    /// assert_eq!(lua.load(r#"require("foo.bar").answer"#).eval::<i64>()?, 42);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_module_loader<F>(&self, loader: F) -> Result<()>
     where
-        T: 'static + MaybeSend + UserData,
+        F: 'static + MaybeSend + for<'lua> Fn(&'lua Lua, &str) -> Result<Option<Function<'lua>>>,
     {
-        unsafe { self.make_userdata(UserDataCell::new(data)) }
+        let package = match self.globals().raw_get::<_, Value>("package")? {
+            Value::Table(package) => package,
+            _ => {
+                return Err(Error::RuntimeError(
+                    "package library is not loaded".to_string(),
+                ))
+            }
+        };
+        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+        let searchers: Table = package.get("searchers")?;
+        #[cfg(any(feature = "lua51", feature = "luajit"))]
+        let searchers: Table = package.get("loaders")?;
+
+        let searcher = self.create_function(move |lua, name: String| {
+            let name = name.to_str()?;
+            match loader(lua, name)? {
+                Some(func) => Ok(Value::Function(func)),
+                #[cfg(feature = "lua54")]
+                None => format!("no module '{}' in Rust loader", name).to_lua(lua),
+                #[cfg(not(feature = "lua54"))]
+                None => format!("\n\tno module '{}' in Rust loader", name).to_lua(lua),
+            }
+        })?;
+
+        // Replace the previously installed searcher if any
+        if let Value::Function(prev) = self.named_registry_value(MODULE_SEARCHER_KEY)? {
+            for i in 1..=searchers.raw_len() {
+                if searchers.raw_get::<_, Value>(i)? == Value::Function(prev.clone()) {
+                    searchers.raw_set(i, searcher.clone())?;
+                    return self.set_named_registry_value(MODULE_SEARCHER_KEY, searcher);
+                }
+            }
+        }
+        searchers.raw_insert(2, searcher.clone())?;
+        self.set_named_registry_value(MODULE_SEARCHER_KEY, searcher)
     }
 
-    /// Create a Lua userdata object from a custom serializable userdata type.
-    ///
-    /// Requires `feature = "serialize"`
-    #[cfg(feature = "serialize")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "serialize")))]
-    pub fn create_ser_userdata<T>(&self, data: T) -> Result<AnyUserData>
+    fn set_output_handler<F>(&self, name: &str, handler: F) -> Result<()>
     where
-        T: 'static + MaybeSend + UserData + Serialize,
+        F: 'static + MaybeSend + Fn(&Lua, &[u8]),
     {
-        unsafe { self.make_userdata(UserDataCell::new_ser(data)) }
+        let handler = self.create_function(move |lua, data: String| {
+            handler(lua, data.as_bytes());
+            Ok(())
+        })?;
+        self.output_handlers()?.raw_set(name, handler)
     }
 
-    /// Returns a handle to the global environment.
-    pub fn globals(&self) -> Table {
-        unsafe {
-            let _sg = StackGuard::new(self.state);
-            assert_stack(self.state, 1);
-            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-            ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
-            #[cfg(any(feature = "lua51", feature = "luajit"))]
-            ffi::lua_pushvalue(self.state, ffi::LUA_GLOBALSINDEX);
-            Table(self.pop_ref())
+    /// Makes the global `pairs` function honour the `__pairs` metamethod.
+    ///
+    /// Lua 5.2 and later already call `__pairs` (and therefore [`MetaMethod::Pairs`]) from the
+    /// built-in `pairs`, so this is a no-op there. On Lua 5.1 and LuaJIT the global `pairs` is
+    /// replaced with a wrapper that calls the `__pairs` metamethod when the value has one and
+    /// falls back to the original `pairs` otherwise.
+    ///
+    /// The base library must be loaded before calling this function.
+    ///
+    /// [`MetaMethod::Pairs`]: enum.MetaMethod.html#variant.Pairs
+    pub fn enable_pairs_metamethod(&self) -> Result<()> {
+        #[cfg(any(feature = "lua51", feature = "luajit"))]
+        {
+            // Userdata metatables are protected by `__metatable`, so look up `__pairs` from Rust
+            let get_pairs = self.create_function(|_, value: Value| match value {
+                Value::Table(t) => match t.get_metatable() {
+                    Some(mt) => mt.raw_get::<_, Value>("__pairs"),
+                    None => Ok(Nil),
+                },
+                Value::UserData(ud) => ud.get_metatable()?.get::<_, Value>(MetaMethod::Pairs),
+                _ => Ok(Nil),
+            })?;
+            self.load(
+                r#"
+                local get_pairs = ...
+                local pairs = pairs
+                _G.pairs = function(t)
+                    local metamethod = get_pairs(t)
+                    if metamethod ~= nil then
+                        return metamethod(t)
+                    end
+                    return pairs(t)
+                end
+            "#,
+            )
+            .set_name("=__mlua_pairs")?
+            .call::<_, ()>(get_pairs)?;
         }
+        Ok(())
     }
 
     /// Returns a handle to the active `Thread`. For calls to `Lua` this will be the main Lua thread,
@@ -1154,6 +2969,36 @@ impl Lua {
         }
     }
 
+    /// Returns `true` if the active Lua thread is a coroutine rather than the main Lua thread.
+    ///
+    /// For calls to `Lua` this is always `false`, for parameters given to a callback this tells
+    /// whether the callback was called from within a coroutine, e.g. to decide whether yielding
+    /// is possible. See also [`current_thread`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let in_coroutine = lua.create_function(|lua, ()| Ok(lua.is_in_coroutine()))?;
+    /// lua.globals().set("in_coroutine", in_coroutine)?;
+    /// assert!(!lua.load("in_coroutine()").eval::<bool>()?);
+    /// let wrapped = lua.load("coroutine.wrap(function() return in_coroutine() end)()");
+    /// assert!(wrapped.eval::<bool>()?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`current_thread`]: #method.current_thread
+    pub fn is_in_coroutine(&self) -> bool {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            ffi::lua_pushthread(self.state) == 0
+        }
+    }
+
     /// Calls the given function with a `Scope` parameter, giving the function the ability to create
     /// userdata and callbacks from rust types that are !Send or non-'static.
     ///
@@ -1174,6 +3019,28 @@ impl Lua {
     /// dropped. `Function` types will error when called, and `AnyUserData` will be typeless. It
     /// would be impossible to prevent handles to scoped values from escaping anyway, since you
     /// would always be able to smuggle them through Lua state.
+    ///
+    /// The returned value must be `'static`, so handles borrowing `Lua` cannot be returned from
+    /// the scope. Owned data such as a [`RegistryKey`] or bytes copied out of a scoped userdata can
+    /// be returned directly:
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, String};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let data = vec![1u8, 2, 3];
+    /// let (bytes, key) = lua.scope(|scope| {
+    ///     let f = scope.create_function(|lua, ()| lua.create_string(&data))?;
+    ///     let s: String = f.call(())?;
+    ///     Ok((s.as_bytes().to_vec(), lua.create_registry_value(s)?))
+    /// })?;
+    /// assert_eq!(bytes, data);
+    /// assert_eq!(lua.registry_value::<String>(&key)?, "\x01\x02\x03");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`RegistryKey`]: struct.RegistryKey.html
     pub fn scope<'lua, 'scope, R, F>(&'lua self, f: F) -> Result<R>
     where
         'lua: 'scope,
@@ -1208,10 +3075,13 @@ impl Lua {
     /// behavior.
     ///
     /// To succeed, the value must be a string (in which case this is a no-op), an integer, or a
-    /// number.
+    /// number. Numbers are not converted while a strict [`CoercionPolicy`] is active.
+    ///
+    /// [`CoercionPolicy`]: struct.CoercionPolicy.html
     pub fn coerce_string<'lua>(&'lua self, v: Value<'lua>) -> Result<Option<String<'lua>>> {
         Ok(match v {
             Value::String(s) => Some(s),
+            v if !self.coercion_policy().allows(&v, CoercionTarget::String) => None,
             v => unsafe {
                 let _sg = StackGuard::new(self.state);
                 check_stack(self.state, 4)?;
@@ -1234,10 +3104,14 @@ impl Lua {
     ///
     /// To succeed, the value must be an integer, a floating point number that has an exact
     /// representation as an integer, or a string that can be converted to an integer. Refer to the
-    /// Lua manual for details.
+    /// Lua manual for details. Disallowed conversions of the active [`CoercionPolicy`] are not
+    /// applied.
+    ///
+    /// [`CoercionPolicy`]: struct.CoercionPolicy.html
     pub fn coerce_integer(&self, v: Value) -> Result<Option<Integer>> {
         Ok(match v {
             Value::Integer(i) => Some(i),
+            v if !self.coercion_policy().allows(&v, CoercionTarget::Integer) => None,
             v => unsafe {
                 let _sg = StackGuard::new(self.state);
                 check_stack(self.state, 2)?;
@@ -1258,10 +3132,14 @@ impl Lua {
     /// behavior.
     ///
     /// To succeed, the value must be a number or a string that can be converted to a number. Refer
-    /// to the Lua manual for details.
+    /// to the Lua manual for details. Disallowed conversions of the active [`CoercionPolicy`] are
+    /// not applied.
+    ///
+    /// [`CoercionPolicy`]: struct.CoercionPolicy.html
     pub fn coerce_number(&self, v: Value) -> Result<Option<Number>> {
         Ok(match v {
             Value::Number(n) => Some(n),
+            v if !self.coercion_policy().allows(&v, CoercionTarget::Number) => None,
             v => unsafe {
                 let _sg = StackGuard::new(self.state);
                 check_stack(self.state, 2)?;
@@ -1355,6 +3233,57 @@ impl Lua {
         self.set_named_registry_value(name, Nil)
     }
 
+    /// Returns a handle to a namespace of named registry values.
+    ///
+    /// Values set through the handle are stored in a separate table per namespace, so the same
+    /// value name used in different namespaces (or with [`set_named_registry_value`]) never
+    /// collides. This allows independent libraries to share one `Lua` instance, each using its own
+    /// namespace, e.g. named after the crate.
+    ///
+    /// Namespace names are still global to the Lua state: every `Lua` instance which shares the
+    /// underlying main state gets the same namespace for the same name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let crate_a = lua.registry_namespace("crate_a")?;
+    /// let crate_b = lua.registry_namespace("crate_b")?;
+    ///
+    /// crate_a.set("config", "a")?;
+    /// crate_b.set("config", "b")?;
+    /// assert_eq!(crate_a.get::<_, String>("config")?, "a");
+    /// assert_eq!(crate_b.get::<_, String>("config")?, "b");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`set_named_registry_value`]: #method.set_named_registry_value
+    pub fn registry_namespace(&self, name: &str) -> Result<RegistryNamespace<'_>> {
+        let namespaces = match self.named_registry_value(REGISTRY_NAMESPACES_KEY)? {
+            Some(namespaces) => namespaces,
+            None => {
+                let namespaces = self.create_table()?;
+                self.set_named_registry_value(REGISTRY_NAMESPACES_KEY, namespaces.clone())?;
+                namespaces
+            }
+        };
+        let table = match namespaces.raw_get::<_, Option<Table>>(name)? {
+            Some(table) => table,
+            None => {
+                let table = self.create_table()?;
+                namespaces.raw_set(name, table.clone())?;
+                table
+            }
+        };
+        Ok(RegistryNamespace {
+            name: name.to_string(),
+            table,
+        })
+    }
+
     /// Place a value in the Lua registry with an auto-generated key.
     ///
     /// This value will be available to rust from all `Lua` instances which share the same main
@@ -1375,7 +3304,10 @@ impl Lua {
                 ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
             })?;
 
-            let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            if registry_id != ffi::LUA_REFNIL {
+                extra.registry_values += 1;
+            }
 
             Ok(RegistryKey {
                 registry_id,
@@ -1422,8 +3354,13 @@ impl Lua {
         if !self.owns_registry_value(&key) {
             return Err(Error::MismatchedRegistryKey);
         }
+        let registry_id = key.take();
+        if registry_id != ffi::LUA_REFNIL {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            extra.registry_values -= 1;
+        }
         unsafe {
-            ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, key.take());
+            ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, registry_id);
         }
         Ok(())
     }
@@ -1444,18 +3381,139 @@ impl Lua {
     /// Unlike normal handle values, `RegistryKey`s do not automatically remove themselves on Drop,
     /// but you can call this method to remove any unreachable registry values not manually removed
     /// by `Lua::remove_registry_value`.
+    ///
+    /// The freed registry slots are reused by subsequent registry values. It's safe to call this
+    /// method at any time, including from inside a callback.
+    ///
+    /// If a handler was set by [`on_registry_expire`], it is called for every removed value.
+    ///
+    /// [`on_registry_expire`]: #method.on_registry_expire
     pub fn expire_registry_values(&self) {
-        unsafe {
-            let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
-            let mut unref_list =
-                mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned");
-            let unref_list = mem::replace(&mut *unref_list, Some(Vec::new()));
-            for id in mlua_expect!(unref_list, "unref list not set") {
+        let (ids, handler) = unsafe {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            let unref_list = {
+                let mut unref_list =
+                    mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned");
+                mem::replace(&mut *unref_list, Some(Vec::new()))
+            };
+            let ids = mlua_expect!(unref_list, "unref list not set");
+            for &id in &ids {
+                if id != ffi::LUA_REFNIL {
+                    extra.registry_values -= 1;
+                }
                 ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, id);
             }
+            (ids, extra.registry_expire_handler.clone())
+        };
+
+        // The handler is called without holding the lock, so it's free to use this `Lua` again
+        if let Some(handler) = handler {
+            // Keys of nil values share the same id, they don't refer to any registry slot
+            for registry_id in ids.into_iter().filter(|&id| id != ffi::LUA_REFNIL) {
+                handler(RegistryKey {
+                    registry_id,
+                    unref_list: Arc::new(Mutex::new(None)),
+                });
+            }
+        }
+    }
+
+    /// Sets a handler that is invoked for every registry value removed by
+    /// [`expire_registry_values`].
+    ///
+    /// The handler receives a detached copy of the dropped `RegistryKey`: it has the same
+    /// [`RegistryKey::id`] and hashes and formats like the original key, but no longer refers to
+    /// any value, so it can only be used to clean up associated Rust-side bookkeeping. Keys of
+    /// `nil` values do not occupy a registry slot and are not reported.
+    ///
+    /// Only one handler can be set at a time, setting a new one replaces the previous handler.
+    ///
+    /// [`expire_registry_values`]: #method.expire_registry_values
+    /// [`RegistryKey::id`]: struct.RegistryKey.html#method.id
+    pub fn on_registry_expire<F>(&self, handler: F)
+    where
+        F: 'static + MaybeSend + Fn(RegistryKey),
+    {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.registry_expire_handler = Some(Arc::new(handler));
+    }
+
+    /// Sets a callback invoked for every read and write of tables traced with
+    /// [`Table::set_traced`].
+    ///
+    /// The callback receives the table, the key and the value that was read or written. An error
+    /// returned by the callback is raised in the Lua code that accessed the table. Accessing a
+    /// traced table from inside the callback (other than through the raw methods) reports that
+    /// access too.
+    ///
+    /// Only one callback can be set at a time, setting a new one replaces the previous callback.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use mlua::{Lua, Result, TableAccessKind};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let writes = Arc::new(Mutex::new(Vec::new()));
+    /// let writes2 = writes.clone();
+    /// lua.trace_table_access(move |_, access| {
+    ///     if access.kind == TableAccessKind::Write {
+    ///         writes2.lock().unwrap().push(access.key.type_name());
+    ///     }
+    ///     Ok(())
+    /// });
+    ///
+    /// let state = lua.create_table()?;
+    /// state.set_traced(true)?;
+    /// lua.globals().set("state", state)?;
+    /// lua.load("state.x = 1; state[1] = state.x").exec()?;
+    /// assert_eq!(*writes.lock().unwrap(), vec!["string", "integer"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Table::set_traced`]: struct.Table.html#method.set_traced
+    pub fn trace_table_access<F>(&self, callback: F)
+    where
+        F: 'static + MaybeSend + Fn(&Lua, TableAccess) -> Result<()>,
+    {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.table_access_hook = Some(Arc::new(callback));
+    }
+
+    /// Removes the callback set by [`trace_table_access`].
+    ///
+    /// Traced tables keep working, but their accesses are no longer reported.
+    ///
+    /// [`trace_table_access`]: #method.trace_table_access
+    pub fn remove_table_access_trace(&self) {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.table_access_hook = None;
+    }
+
+    pub(crate) fn report_table_access(&self, access: TableAccess) -> Result<()> {
+        let hook = mlua_expect!(self.extra.lock(), "extra is poisoned")
+            .table_access_hook
+            .clone();
+        match hook {
+            Some(hook) => hook(self, access),
+            None => Ok(()),
         }
     }
 
+    /// Returns the number of values stored in the registry by [`create_registry_value`].
+    ///
+    /// Values whose `RegistryKey`s have been dropped are counted until they are removed by
+    /// [`expire_registry_values`]. Useful for diagnosing leaked registry values.
+    ///
+    /// [`create_registry_value`]: #method.create_registry_value
+    /// [`expire_registry_values`]: #method.expire_registry_values
+    pub fn registry_len(&self) -> usize {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.registry_values
+    }
+
     // Uses 2 stack spaces, does not call checkstack
     pub(crate) unsafe fn push_value(&self, value: Value) -> Result<()> {
         match value {
@@ -1507,6 +3565,25 @@ impl Lua {
         Ok(())
     }
 
+    // Same as `push_value` but borrows the value, only errors are cloned
+    // Uses 2 stack spaces, does not call checkstack
+    pub(crate) unsafe fn push_value_ref(&self, value: &Value) -> Result<()> {
+        match value {
+            Value::String(s) => self.push_ref(&s.0),
+            Value::Table(t) => self.push_ref(&t.0),
+            Value::Function(f) => self.push_ref(&f.0),
+            Value::Thread(t) => self.push_ref(&t.0),
+            Value::UserData(ud) => self.push_ref(&ud.0),
+            Value::Error(e) => push_wrapped_error(self.state, e.clone())?,
+            Value::Nil
+            | Value::Boolean(_)
+            | Value::LightUserData(_)
+            | Value::Integer(_)
+            | Value::Number(_) => self.push_value(value.clone())?,
+        }
+        Ok(())
+    }
+
     // Uses 2 stack spaces, does not call checkstack
     pub(crate) unsafe fn pop_value(&self) -> Value {
         let state = self.state;
@@ -1617,11 +3694,37 @@ impl Lua {
         }
     }
 
+    // Returns an empty `MultiValue`, reusing a previously cached allocation if available.
+    pub(crate) fn new_or_cached_multivalue(&self) -> MultiValue<'_> {
+        MULTIVALUE_CACHE
+            .try_with(|cache| cache.borrow_mut().pop())
+            .ok()
+            .flatten()
+            .map(MultiValue::from_empty_vec)
+            .unwrap_or_default()
+    }
+
+    // Clears the `MultiValue` and keeps its allocation for `new_or_cached_multivalue`.
+    pub(crate) fn cache_multivalue(&self, values: MultiValue) {
+        let values = values.into_empty_vec();
+        if values.capacity() == 0 || values.capacity() > MULTIVALUE_CACHE_MAX_CAPACITY {
+            return;
+        }
+        // The cache is gone while the thread is being torn down, the allocation is freed then
+        let _ = MULTIVALUE_CACHE.try_with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.len() < MULTIVALUE_CACHE_SIZE {
+                cache.push(values);
+            }
+        });
+    }
+
     pub(crate) unsafe fn push_userdata_metatable<T: 'static + UserData>(&self) -> Result<()> {
         let type_id = TypeId::of::<T>();
-        if let Some(&table_id) = mlua_expect!(self.extra.lock(), "extra is poisoned")
+        if let Some(table_id) = mlua_expect!(self.extra.lock(), "extra is poisoned")
             .registered_userdata
             .get(&type_id)
+            .map(|reg| reg.table_id)
         {
             ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, table_id as Integer);
             return Ok(());
@@ -1700,6 +3803,10 @@ impl Lua {
             extra_tables_count += 1;
         }
 
+        if T::default_tostring() {
+            init_userdata_tostring(self.state, metatable_index, &short_type_name::<T>())?;
+        }
+
         init_userdata_metatable::<UserDataCell<T>>(
             self.state,
             metatable_index,
@@ -1718,7 +3825,13 @@ impl Lua {
         })?;
 
         let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
-        extra.registered_userdata.insert(type_id, id);
+        let registered = RegisteredUserData {
+            table_id: id,
+            table_ptr: ptr as isize,
+            type_name: any::type_name::<T>(),
+            instances: Arc::new(AtomicUsize::new(0)),
+        };
+        extra.registered_userdata.insert(type_id, registered);
         extra.registered_userdata_mt.insert(ptr as isize);
 
         Ok(())
@@ -1795,19 +3908,27 @@ impl Lua {
                 let lua = &mut *lua;
                 lua.state = state;
 
-                let mut args = MultiValue::new();
+                let mut args = lua.new_or_cached_multivalue();
                 args.reserve(nargs as usize);
                 for _ in 0..nargs {
                     args.push_front(lua.pop_value());
                 }
 
-                let results = (*func)(lua, args)?;
+                // Nested callbacks use the lenient policy unless the call targets them
+                let _policy = CoercionPolicyGuard::new(take_call_policy(func as *const c_void));
+
+                #[cfg(debug_assertions)]
+                let top = ffi::lua_gettop(state);
+                let mut results = (*func)(lua, args)?;
+                #[cfg(debug_assertions)]
+                util::assert_callback_stack(state, top);
                 let nresults = results.len() as c_int;
 
                 check_stack(state, nresults)?;
-                for r in results {
+                for r in results.drain_all() {
                     lua.push_value(r)?;
                 }
+                lua.cache_multivalue(results);
 
                 Ok(nresults)
             })
@@ -1862,13 +3983,17 @@ impl Lua {
                 let lua = &mut *lua;
                 lua.state = state;
 
-                let mut args = MultiValue::new();
+                let mut args = lua.new_or_cached_multivalue();
                 args.reserve(nargs as usize);
                 for _ in 0..nargs {
                     args.push_front(lua.pop_value());
                 }
 
+                #[cfg(debug_assertions)]
+                let top = ffi::lua_gettop(state);
                 let fut = (*func)(lua, args);
+                #[cfg(debug_assertions)]
+                util::assert_callback_stack(state, top);
                 push_gc_userdata(state, fut)?;
                 push_gc_userdata(state, lua.clone())?;
 
@@ -1910,7 +4035,13 @@ impl Lua {
 
                 let mut ctx = Context::from_waker(&waker);
 
-                match (*fut).as_mut().poll(&mut ctx) {
+                #[cfg(debug_assertions)]
+                let top = ffi::lua_gettop(state);
+                let poll = (*fut).as_mut().poll(&mut ctx);
+                #[cfg(debug_assertions)]
+                util::assert_callback_stack(state, top);
+
+                match poll {
                     Poll::Pending => {
                         check_stack(state, 1)?;
                         ffi::lua_pushboolean(state, 0);
@@ -1984,12 +4115,16 @@ impl Lua {
     where
         T: 'static + UserData,
     {
+        let mut data = data;
+        self.track_userdata(&mut data)?;
+
         let _sg = StackGuard::new(self.state);
         check_stack(self.state, 2)?;
 
         // If we unable to push metatable, then we should not push userdata.
         // Otherwise we can have a memory leak.
         self.push_userdata_metatable::<T>()?;
+        self.count_userdata_instance::<T>(&mut data);
         push_userdata(self.state, data)?;
         ffi::lua_rotate(self.state, -2, 1);
         ffi::lua_setmetatable(self.state, -2);
@@ -1997,6 +4132,27 @@ impl Lua {
         Ok(AnyUserData(self.pop_ref()))
     }
 
+    // Counts a new userdata value against the limit set by `set_max_userdata`
+    pub(crate) fn track_userdata<T>(&self, data: &mut UserDataCell<T>) -> Result<()> {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let live = extra.live_userdata.fetch_add(1, Ordering::Relaxed);
+        data.set_live(LiveUserData(extra.live_userdata.clone()));
+        if extra.max_userdata > 0 && live >= extra.max_userdata {
+            // Dropping `data` releases the slot again
+            return Err(Error::UserDataLimitReached);
+        }
+        Ok(())
+    }
+
+    // Counts a new value of the (registered) userdata type `T`
+    fn count_userdata_instance<T: 'static>(&self, data: &mut UserDataCell<T>) {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        if let Some(reg) = extra.registered_userdata.get(&TypeId::of::<T>()) {
+            reg.instances.fetch_add(1, Ordering::Relaxed);
+            data.set_instance(LiveUserData(reg.instances.clone()));
+        }
+    }
+
     pub(crate) fn clone(&self) -> Self {
         Lua {
             state: self.state,
@@ -2060,9 +4216,415 @@ impl Lua {
         })
     }
 
-    pub(crate) unsafe fn hook_callback(&self) -> Option<HookCallback> {
+    pub(crate) unsafe fn hook_callback(&self) -> Option<(HookCallback, HookTriggers)> {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let triggers = extra.hook_triggers;
+        extra.hook_callback.clone().map(|cb| (cb, triggers))
+    }
+
+    pub(crate) fn max_lua_call_depth(&self) -> usize {
         let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
-        extra.hook_callback.clone()
+        extra.max_lua_call_depth
+    }
+
+    // Accounts for the instructions executed since the previous count event. Returns an error once
+    // the instruction limit is reached, otherwise whether the user hook is due.
+    pub(crate) fn count_instructions(&self) -> Result<bool> {
+        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let count = extra.hook_count as u64;
+        if extra.instruction_limit > 0 {
+            extra.instructions_executed += count;
+            if extra.instructions_executed >= extra.instruction_limit {
+                return Err(Error::InstructionLimitReached);
+            }
+        }
+        match extra.hook_triggers.every_nth_instruction {
+            Some(n) => {
+                extra.hook_instructions += count;
+                if extra.hook_instructions < n as u64 {
+                    return Ok(false);
+                }
+                extra.hook_instructions = 0;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Returns a weak-keyed table of output handlers, creating it if needed.
+    // Threads are mapped to the sinks set by `Thread::set_output`, and the `print`, `write` and
+    // `stderr` fields hold the handlers of the corresponding streams.
+    // On first use replaces `print`, `io.write`, `io.stdout` and `io.stderr` with wrappers passing
+    // output to the sink of the running thread or the stream handler, falling back to the
+    // original functions when neither is set.
+    pub(crate) fn output_handlers<'lua>(&'lua self) -> Result<Table<'lua>> {
+        let handlers_key = &OUTPUT_HANDLERS_REGISTRY_KEY as *const u8 as *const c_void;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            ffi::lua_rawgetp(self.state, ffi::LUA_REGISTRYINDEX, handlers_key);
+            if ffi::lua_istable(self.state, -1) != 0 {
+                return Ok(Table(self.pop_ref()));
+            }
+        }
+
+        let handlers = self.create_table()?;
+        let handlers_mt = self.create_table()?;
+        handlers_mt.raw_set("__mode", "k")?;
+        handlers.set_metatable(Some(handlers_mt));
+
+        let running = self.create_function(|lua, ()| Ok(lua.current_thread()))?;
+        self.load(
+            r##"
+            local handlers, running = ...
+            local error, select, setmetatable, tostring, type =
+                error, select, setmetatable, tostring, type
+            local format = type(string) == "table" and string.format or nil
+            local mathtype = type(math) == "table" and math.type or nil
+
+            -- Thread sinks replace the standard output only
+            local function sink_for(stream)
+                if stream ~= "stderr" then
+                    local sink = handlers[running()]
+                    if sink ~= nil then
+                        return sink
+                    end
+                end
+                return handlers[stream]
+            end
+
+            local print = print
+            _G.print = function(...)
+                local sink = sink_for("print")
+                if sink == nil then
+                    return print(...)
+                end
+                local line = ""
+                for i = 1, select("#", ...) do
+                    local s = tostring((select(i, ...)))
+                    if type(s) ~= "string" then
+                        error("'tostring' must return a string to 'print'", 2)
+                    end
+                    line = i > 1 and line .. "\t" .. s or s
+                end
+                sink(line .. "\n")
+            end
+
+            if type(io) ~= "table" then
+                return
+            end
+
+            -- Concatenates the arguments of `write`, formatting numbers with `LUA_INTEGER_FMT`
+            -- and `LUA_NUMBER_FMT` like the stock implementation
+            local function concat(...)
+                local data = ""
+                for i = 1, select("#", ...) do
+                    local s = select(i, ...)
+                    if type(s) == "number" and format ~= nil then
+                        if mathtype ~= nil and mathtype(s) == "integer" then
+                            s = format("%d", s)
+                        else
+                            s = format("%.14g", s)
+                        end
+                    elseif type(s) ~= "string" and type(s) ~= "number" then
+                        error("bad argument #" .. i .. " to 'write' (string expected, got "
+                            .. type(s) .. ")", 3)
+                    end
+                    data = data .. s
+                end
+                return data
+            end
+
+            local function std_file(stream, file)
+                local methods = {}
+                function methods:write(...)
+                    local sink = sink_for(stream)
+                    if sink == nil then
+                        local ok, err, code = file:write(...)
+                        if ok == nil then
+                            return nil, err, code
+                        end
+                        return self
+                    end
+                    sink(concat(...))
+                    return self
+                end
+                function methods:flush()
+                    file:flush()
+                    return self
+                end
+                function methods:setvbuf(...) return file:setvbuf(...) end
+                function methods:close() return nil, "cannot close standard file" end
+                return setmetatable({}, {
+                    __index = methods,
+                    __name = "FILE*",
+                    __tostring = function() return tostring(file) end,
+                })
+            end
+
+            local stdout, stderr = std_file("write", io.stdout), std_file("stderr", io.stderr)
+            io.stdout, io.stderr = stdout, stderr
+
+            local output, close = io.output, io.close
+            local current = stdout
+            io.output = function(file)
+                if file == nil then
+                    return current
+                elseif file == stdout or file == stderr then
+                    current = file
+                else
+                    current = output(file)
+                end
+                return current
+            end
+            io.close = function(file)
+                file = file or current
+                if file == stdout or file == stderr then
+                    return file:close()
+                end
+                return close(file)
+            end
+            io.write = function(...)
+                return current:write(...)
+            end
+        "##,
+        )
+        .set_name("=__mlua_output_handlers")?
+        .call::<_, ()>((handlers.clone(), running))?;
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            self.push_ref(&handlers.0);
+            protect_lua(self.state, 1, 0, |state| {
+                ffi::lua_rawsetp(state, ffi::LUA_REGISTRYINDEX, handlers_key);
+            })?;
+        }
+
+        Ok(handlers)
+    }
+
+    // Returns a weak-keyed table of threads created by scopes that have ended, creating it if needed.
+    pub(crate) fn expired_threads<'lua>(&'lua self) -> Result<Table<'lua>> {
+        self.registry_table(&EXPIRED_THREADS_REGISTRY_KEY, Some("k"))
+    }
+
+    // Returns a table mapping weak userdata tokens to their userdata, creating it if needed.
+    // Both keys and values are weak, so an entry goes away with either its token or its userdata.
+    pub(crate) fn weak_userdata<'lua>(&'lua self) -> Result<Table<'lua>> {
+        self.registry_table(&WEAK_USERDATA_REGISTRY_KEY, Some("kv"))
+    }
+
+    // Returns the metatable shared by all weak userdata tokens, creating it if needed.
+    // It is used to tell tokens apart from arbitrary tables.
+    pub(crate) fn weak_userdata_token_metatable<'lua>(&'lua self) -> Result<Table<'lua>> {
+        self.registry_table(&WEAK_USERDATA_TOKEN_MT_REGISTRY_KEY, None)
+    }
+
+    // Replaces the methods of the userdata type `T` with the ones registered in `methods`.
+    // Userdata metatables are shared among all instances of a type, so they all see the change.
+    pub(crate) fn replace_userdata_methods<'lua, T: 'static + UserData>(
+        &'lua self,
+        ud: &LuaRef<'lua>,
+        methods: StaticUserDataMethods<'lua, T>,
+    ) -> Result<()> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 6)?;
+
+            self.push_userdata_ref(ud, false)?;
+            ffi::lua_getmetatable(self.state, -1);
+            let metatable_index = ffi::lua_absindex(self.state, -1);
+
+            #[cfg(feature = "async")]
+            let methods_nrec = methods.methods.len() + methods.async_methods.len();
+            #[cfg(not(feature = "async"))]
+            let methods_nrec = methods.methods.len();
+            push_table(self.state, 0, methods_nrec as c_int)?;
+            for (k, m) in methods.methods {
+                self.push_value(Value::Function(self.create_callback(m)?))?;
+                rawset_field(self.state, -2, &k)?;
+            }
+            #[cfg(feature = "async")]
+            for (k, m) in methods.async_methods {
+                self.push_value(Value::Function(self.create_async_callback(m)?))?;
+                rawset_field(self.state, -2, &k)?;
+            }
+            util::set_userdata_methods(self.state, metatable_index, -1)?;
+            ffi::lua_pop(self.state, 1);
+
+            for (k, m) in methods.meta_methods {
+                self.push_value(Value::Function(self.create_callback(m)?))?;
+                util::set_userdata_metamethod(self.state, metatable_index, k.validate()?.name())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns a table stored in the registry under `key`, creating it if needed.
+    // If `mode` is set, the table is created weak with the given `__mode`.
+    fn registry_table<'lua>(
+        &'lua self,
+        key: &'static u8,
+        mode: Option<&str>,
+    ) -> Result<Table<'lua>> {
+        let key = key as *const u8 as *const c_void;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            ffi::lua_rawgetp(self.state, ffi::LUA_REGISTRYINDEX, key);
+            if ffi::lua_istable(self.state, -1) != 0 {
+                return Ok(Table(self.pop_ref()));
+            }
+        }
+
+        let table = self.create_table()?;
+        if let Some(mode) = mode {
+            let metatable = self.create_table()?;
+            metatable.raw_set("__mode", mode)?;
+            table.set_metatable(Some(metatable));
+        }
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 1)?;
+
+            self.push_ref(&table.0);
+            protect_lua(self.state, 1, 0, |state| {
+                ffi::lua_rawsetp(state, ffi::LUA_REGISTRYINDEX, key);
+            })?;
+        }
+
+        Ok(table)
+    }
+
+    // Checks whether the thread at index `idx` was created by a scope that has ended.
+    // Uses 2 stack spaces, does not call checkstack.
+    pub(crate) unsafe fn is_thread_expired(&self, idx: c_int) -> bool {
+        let idx = ffi::lua_absindex(self.state, idx);
+        let expired_key = &EXPIRED_THREADS_REGISTRY_KEY as *const u8 as *const c_void;
+        ffi::lua_rawgetp(self.state, ffi::LUA_REGISTRYINDEX, expired_key);
+        if ffi::lua_istable(self.state, -1) == 0 {
+            ffi::lua_pop(self.state, 1);
+            return false;
+        }
+        ffi::lua_pushvalue(self.state, idx);
+        ffi::lua_rawget(self.state, -2);
+        let expired = ffi::lua_toboolean(self.state, -1) != 0;
+        ffi::lua_pop(self.state, 2);
+        expired
+    }
+
+    // Runs a chunk and passes its error (if any) to the uncaught error handler without consuming
+    // it. Chunks run from inside another chunk (e.g. by a callback) are not reported, so that the
+    // handler is invoked once, for the outermost chunk.
+    fn report_uncaught_error<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let guard = ChunkDepthGuard::new(self);
+        let res = f();
+        if let Err(ref err) = res {
+            if guard.outermost {
+                self.call_uncaught_error_handler(err);
+            }
+        }
+        res
+    }
+
+    // Same as `report_uncaught_error`, but for a chunk run asynchronously.
+    // The chunk counts as running while its future is polled.
+    #[cfg(feature = "async")]
+    fn report_uncaught_error_async<'fut, T: 'fut>(
+        &'fut self,
+        mut fut: LocalBoxFuture<'fut, Result<T>>,
+    ) -> LocalBoxFuture<'fut, Result<T>> {
+        Box::pin(future::poll_fn(move |cx| {
+            let guard = ChunkDepthGuard::new(self);
+            let poll = fut.as_mut().poll(cx);
+            if let Poll::Ready(Err(ref err)) = poll {
+                if guard.outermost {
+                    self.call_uncaught_error_handler(err);
+                }
+            }
+            poll
+        }))
+    }
+
+    fn call_uncaught_error_handler(&self, err: &Error) {
+        let handler = {
+            let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            extra.uncaught_error_handler.clone()
+        };
+        if let Some(handler) = handler {
+            handler(err);
+        }
+    }
+}
+
+/// Contents of the globals table captured by [`Lua::snapshot_globals`].
+///
+/// The snapshot keeps the captured values alive in the Lua registry, and can only be restored
+/// into the same Lua state with [`Lua::restore_globals`].
+///
+/// [`Lua::snapshot_globals`]: struct.Lua.html#method.snapshot_globals
+/// [`Lua::restore_globals`]: struct.Lua.html#method.restore_globals
+#[derive(Debug)]
+pub struct GlobalsSnapshot(RegistryKey);
+
+/// A namespace of named registry values, returned by [`Lua::registry_namespace`].
+///
+/// [`Lua::registry_namespace`]: struct.Lua.html#method.registry_namespace
+#[derive(Clone, Debug)]
+pub struct RegistryNamespace<'lua> {
+    name: StdString,
+    table: Table<'lua>,
+}
+
+impl<'lua> RegistryNamespace<'lua> {
+    /// Returns the name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets a named value in the namespace.
+    ///
+    /// This is the namespaced version of [`Lua::set_named_registry_value`].
+    ///
+    /// [`Lua::set_named_registry_value`]: struct.Lua.html#method.set_named_registry_value
+    pub fn set<S, T>(&self, name: &S, t: T) -> Result<()>
+    where
+        S: AsRef<[u8]> + ?Sized,
+        T: ToLua<'lua>,
+    {
+        let lua = self.table.0.lua;
+        self.table.raw_set(lua.create_string(name)?, t)
+    }
+
+    /// Gets a named value from the namespace, `Nil` if it was never set.
+    ///
+    /// This is the namespaced version of [`Lua::named_registry_value`].
+    ///
+    /// [`Lua::named_registry_value`]: struct.Lua.html#method.named_registry_value
+    pub fn get<S, T>(&self, name: &S) -> Result<T>
+    where
+        S: AsRef<[u8]> + ?Sized,
+        T: FromLua<'lua>,
+    {
+        let lua = self.table.0.lua;
+        self.table.raw_get(lua.create_string(name)?)
+    }
+
+    /// Removes a named value from the namespace.
+    ///
+    /// This is the namespaced version of [`Lua::unset_named_registry_value`].
+    ///
+    /// [`Lua::unset_named_registry_value`]: struct.Lua.html#method.unset_named_registry_value
+    pub fn remove<S>(&self, name: &S) -> Result<()>
+    where
+        S: AsRef<[u8]> + ?Sized,
+    {
+        self.set(name, Nil)
     }
 }
 
@@ -2079,10 +4641,18 @@ pub struct Chunk<'lua, 'a> {
 }
 
 /// Represents chunk mode (text or binary).
-#[derive(Clone, Copy, Debug)]
+///
+/// Maps to the `mode` argument of the Lua [`load`] function.
+///
+/// [`load`]: https://www.lua.org/manual/5.4/manual.html#pdf-load
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChunkMode {
+    /// Only text chunks (mode `"t"`).
     Text,
+    /// Only binary chunks (mode `"b"`).
     Binary,
+    /// Both text and binary chunks (mode `"bt"`).
+    TextAndBinary,
 }
 
 /// Trait for types [loadable by Lua] and convertible to a [`Chunk`]
@@ -2135,6 +4705,11 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
     /// All global variables (including the standard library!) are looked up in `_ENV`, so it may be
     /// necessary to populate the environment in order for scripts using custom environments to be
     /// useful.
+    ///
+    /// The environment is set right after the chunk is compiled, before any of its code runs. On
+    /// Lua 5.1 and LuaJIT, which have no `_ENV` upvalue, the function environment is set using
+    /// `setfenv` instead. Every load produces a new function, so loading the same source with
+    /// different environments does not share any state.
     pub fn set_environment<V: ToLua<'lua>>(mut self, env: V) -> Result<Chunk<'lua, 'a>> {
         // Prefer to propagate errors here and wrap to `Ok`
         self.env = Ok(Some(env.to_lua(self.lua)?));
@@ -2143,10 +4718,18 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
 
     /// Sets whether the chunk is text or binary (autodetected by default).
     ///
-    /// Lua does not check the consistency of binary chunks, therefore this mode is allowed only
-    /// for instances created with [`Lua::unsafe_new`].
+    /// Lua does not check the consistency of binary chunks, therefore loading them is allowed only
+    /// for instances created with [`Lua::unsafe_new`] and when not disabled by
+    /// [`LuaOptions::disable_bytecode_loading`], regardless of the mode. Setting the mode to
+    /// [`ChunkMode::Text`] rejects binary chunks for this load only, while
+    /// [`ChunkMode::TextAndBinary`] behaves like the default. Loading a binary chunk in text mode
+    /// results in [`Error::RuntimeError`].
     ///
     /// [`Lua::unsafe_new`]: struct.Lua.html#method.unsafe_new
+    /// [`LuaOptions::disable_bytecode_loading`]: struct.LuaOptions.html#structfield.disable_bytecode_loading
+    /// [`ChunkMode::Text`]: enum.ChunkMode.html#variant.Text
+    /// [`ChunkMode::TextAndBinary`]: enum.ChunkMode.html#variant.TextAndBinary
+    /// [`Error::RuntimeError`]: enum.Error.html#variant.RuntimeError
     pub fn set_mode(mut self, mode: ChunkMode) -> Chunk<'lua, 'a> {
         self.mode = Some(mode);
         self
@@ -2181,23 +4764,45 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
     /// If the chunk can be parsed as an expression, this loads and executes the chunk and returns
     /// the value that it evaluates to. Otherwise, the chunk is interpreted as a block as normal,
     /// and this is equivalent to calling `exec`.
+    ///
+    /// This mirrors the behavior of the standalone Lua REPL, so the same call accepts both
+    /// expressions and statements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// assert_eq!(lua.load("1 + 1").eval::<i32>()?, 2);
+    ///
+    /// // Statements are executed as a block
+    /// lua.load("x = 5").eval::<()>()?;
+    /// assert_eq!(lua.load("x").eval::<i32>()?, 5);
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn eval<R: FromLuaMulti<'lua>>(self) -> Result<R> {
         // Bytecode is always interpreted as a statement.
         // For source code, first try interpreting the lua as an expression by adding
         // "return", then as a statement. This is the same thing the
         // actual lua repl does.
-        if self.source.starts_with(ffi::LUA_SIGNATURE) {
-            self.call(())
-        } else if let Ok(function) = self.lua.load_chunk(
-            &self.expression_source(),
-            self.name.as_ref(),
-            self.env()?,
-            self.mode,
-        ) {
-            function.call(())
-        } else {
-            self.call(())
-        }
+        let lua = self.lua;
+        lua.report_uncaught_error(|| {
+            if self.source.starts_with(ffi::LUA_SIGNATURE) {
+                self.into_function()?.call(())
+            } else {
+                match lua.load_chunk(
+                    &self.expression_source(),
+                    self.name.as_ref(),
+                    self.env()?,
+                    self.mode,
+                ) {
+                    Ok(function) => function.call(()),
+                    Err(_) => self.into_function()?.call(()),
+                }
+            }
+        })
     }
 
     /// Asynchronously evaluate the chunk as either an expression or block.
@@ -2214,28 +4819,31 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
         'lua: 'fut,
         R: FromLuaMulti<'lua> + 'fut,
     {
-        if self.source.starts_with(ffi::LUA_SIGNATURE) {
-            self.call_async(())
-        } else if let Ok(function) = self.lua.load_chunk(
+        let lua = self.lua;
+        let fut = if self.source.starts_with(ffi::LUA_SIGNATURE) {
+            self.call_async_unreported(())
+        } else if let Ok(function) = lua.load_chunk(
             &self.expression_source(),
             self.name.as_ref(),
             match self.env() {
                 Ok(env) => env,
-                Err(e) => return Box::pin(future::err(e)),
+                Err(e) => return lua.report_uncaught_error_async(Box::pin(future::err(e))),
             },
             self.mode,
         ) {
             function.call_async(())
         } else {
-            self.call_async(())
-        }
+            self.call_async_unreported(())
+        };
+        lua.report_uncaught_error_async(fut)
     }
 
     /// Load the chunk function and call it with the given arguments.
     ///
     /// This is equivalent to `into_function` and calling the resulting function.
     pub fn call<A: ToLuaMulti<'lua>, R: FromLuaMulti<'lua>>(self, args: A) -> Result<R> {
-        self.into_function()?.call(args)
+        let lua = self.lua;
+        lua.report_uncaught_error(|| self.into_function()?.call(args))
     }
 
     /// Load the chunk function and asynchronously call it with the given arguments.
@@ -2248,6 +4856,17 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
     #[cfg(feature = "async")]
     #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
     pub fn call_async<'fut, A, R>(self, args: A) -> LocalBoxFuture<'fut, Result<R>>
+    where
+        'lua: 'fut,
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua> + 'fut,
+    {
+        let lua = self.lua;
+        lua.report_uncaught_error_async(self.call_async_unreported(args))
+    }
+
+    #[cfg(feature = "async")]
+    fn call_async_unreported<'fut, A, R>(self, args: A) -> LocalBoxFuture<'fut, Result<R>>
     where
         'lua: 'fut,
         A: ToLuaMulti<'lua>,
@@ -2267,6 +4886,16 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
             .load_chunk(self.source, self.name.as_ref(), self.env()?, self.mode)
     }
 
+    /// Compiles this chunk and returns its bytecode, without executing it.
+    ///
+    /// The result can be cached and loaded back later with [`Lua::load_bytecode`], skipping the
+    /// parsing step. Debug information is kept, so error messages still include line numbers.
+    ///
+    /// [`Lua::load_bytecode`]: struct.Lua.html#method.load_bytecode
+    pub fn into_bytecode(self) -> Result<Vec<u8>> {
+        Ok(self.into_function()?.dump(false))
+    }
+
     fn env(&self) -> Result<Option<Value<'lua>>> {
         self.env.clone()
     }
@@ -2555,7 +5184,43 @@ unsafe fn ref_stack_pop(mut extra: MutexGuard<ExtraData>) -> (c_int, MutexGuard<
     (extra.ref_stack_top, extra)
 }
 
-struct StaticUserDataMethods<'lua, T: 'static + UserData> {
+// Installs `hook_proc` with the events required by the user hook and the call depth and
+// instruction limits, or removes it if none of them is set.
+unsafe fn update_hook(state: *mut ffi::lua_State, extra: &mut ExtraData) {
+    let mut mask = 0;
+    let mut count = 0;
+    if extra.hook_callback.is_some() {
+        mask = extra.hook_triggers.mask();
+        count = extra.hook_triggers.count();
+    }
+    if extra.max_lua_call_depth > 0 {
+        mask |= ffi::LUA_MASKCALL;
+    }
+    if extra.instruction_limit > 0 {
+        let limit = cmp::min(extra.instruction_limit, c_int::MAX as u64) as c_int;
+        count = if count > 0 {
+            cmp::min(count, limit)
+        } else {
+            limit
+        };
+        mask |= ffi::LUA_MASKCOUNT;
+    }
+    extra.hook_count = count;
+    if mask == 0 {
+        ffi::lua_sethook(state, None, 0, 0);
+    } else {
+        ffi::lua_sethook(state, Some(hook_proc), mask, count);
+    }
+}
+
+/// Collection of methods registered for a `'static` userdata type.
+///
+/// Passed to the closure given to [`AnyUserData::replace_methods`], and used through the
+/// [`UserDataMethods`] trait.
+///
+/// [`AnyUserData::replace_methods`]: struct.AnyUserData.html#method.replace_methods
+/// [`UserDataMethods`]: trait.UserDataMethods.html
+pub struct StaticUserDataMethods<'lua, T: 'static + UserData> {
     methods: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
     #[cfg(feature = "async")]
     async_methods: Vec<(Vec<u8>, AsyncCallback<'lua, 'static>)>,
@@ -2612,6 +5277,19 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
             .push((name.as_ref().to_vec(), Self::box_async_method(method)));
     }
 
+    #[cfg(feature = "async")]
+    fn add_async_method_arc<S, A, R, M, MR>(&mut self, name: &S, method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, Arc<T>, A) -> MR,
+        MR: 'lua + Future<Output = Result<R>>,
+    {
+        self.async_methods
+            .push((name.as_ref().to_vec(), Self::box_async_method_arc(method)));
+    }
+
     fn add_function<S, A, R, F>(&mut self, name: &S, function: F)
     where
         S: AsRef<[u8]> + ?Sized,
@@ -2732,6 +5410,10 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
                         let ud = ud.try_borrow().map_err(|_| Error::UserDataBorrowError)?;
                         method(lua, &ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
                     }
+                    id if id == TypeId::of::<Arc<T>>() => {
+                        let ud = userdata.borrow::<Arc<T>>()?;
+                        method(lua, &ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                    }
                     id if id == TypeId::of::<Arc<Mutex<T>>>() => {
                         let ud = userdata.borrow::<Arc<Mutex<T>>>()?;
                         let ud = ud.try_lock().map_err(|_| Error::UserDataBorrowError)?;
@@ -2780,6 +5462,8 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
                             .map_err(|_| Error::UserDataBorrowMutError)?;
                         method(lua, &mut ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
                     }
+                    // Values shared through `Arc<T>` are never mutably borrowed
+                    id if id == TypeId::of::<Arc<T>>() => Err(Error::UserDataBorrowMutError),
                     id if id == TypeId::of::<Arc<Mutex<T>>>() => {
                         let ud = userdata.borrow::<Arc<Mutex<T>>>()?;
                         let mut ud = ud.try_lock().map_err(|_| Error::UserDataBorrowMutError)?;
@@ -2832,6 +5516,38 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
         })
     }
 
+    #[cfg(feature = "async")]
+    fn box_async_method_arc<A, R, M, MR>(method: M) -> AsyncCallback<'lua, 'static>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, Arc<T>, A) -> MR,
+        MR: 'lua + Future<Output = Result<R>>,
+    {
+        Box::new(move |lua, mut args| {
+            let fut_res = || {
+                if let Some(front) = args.pop_front() {
+                    let userdata = AnyUserData::from_lua(front, lua)?;
+                    if userdata.type_id()? != TypeId::of::<Arc<T>>() {
+                        return Err(Error::UserDataTypeMismatch);
+                    }
+                    let userdata = userdata.borrow::<Arc<T>>()?.clone();
+                    Ok(method(lua, userdata, A::from_lua_multi(args, lua)?))
+                } else {
+                    Err(Error::FromLuaConversionError {
+                        from: "missing argument",
+                        to: "userdata",
+                        message: None,
+                    })
+                }
+            };
+            match fut_res() {
+                Ok(fut) => Box::pin(fut.and_then(move |ret| future::ready(ret.to_lua_multi(lua)))),
+                Err(e) => Box::pin(future::err(e)),
+            }
+        })
+    }
+
     fn box_function<A, R, F>(function: F) -> Callback<'lua, 'static>
     where
         A: FromLuaMulti<'lua>,
@@ -3020,5 +5736,6 @@ macro_rules! lua_userdata_impl {
 
 #[cfg(not(feature = "send"))]
 lua_userdata_impl!(Rc<RefCell<T>>);
+lua_userdata_impl!(Arc<T>);
 lua_userdata_impl!(Arc<Mutex<T>>);
 lua_userdata_impl!(Arc<RwLock<T>>);
@@ -1,18 +1,21 @@
 #![allow(clippy::wrong_self_convention)]
 
+use std::fmt;
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::lua::Lua;
-use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti};
+use crate::table::Table;
+use crate::types::{Integer, Number};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
 /// Result is convertible to `MultiValue` following the common Lua idiom of returning the result
 /// on success, or in the case of an error, returning `nil` and an error message.
 impl<'lua, T: ToLua<'lua>, E: ToLua<'lua>> ToLuaMulti<'lua> for StdResult<T, E> {
     fn to_lua_multi(self, lua: &'lua Lua) -> Result<MultiValue<'lua>> {
-        let mut result = MultiValue::new();
+        let mut result = lua.new_or_cached_multivalue();
 
         match self {
             Ok(v) => result.push_front(v.to_lua(lua)?),
@@ -28,7 +31,7 @@ impl<'lua, T: ToLua<'lua>, E: ToLua<'lua>> ToLuaMulti<'lua> for StdResult<T, E>
 
 impl<'lua, T: ToLua<'lua>> ToLuaMulti<'lua> for T {
     fn to_lua_multi(self, lua: &'lua Lua) -> Result<MultiValue<'lua>> {
-        let mut v = MultiValue::new();
+        let mut v = lua.new_or_cached_multivalue();
         v.push_front(self.to_lua(lua)?);
         Ok(v)
     }
@@ -36,7 +39,9 @@ impl<'lua, T: ToLua<'lua>> ToLuaMulti<'lua> for T {
 
 impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for T {
     fn from_lua_multi(mut values: MultiValue<'lua>, lua: &'lua Lua) -> Result<Self> {
-        T::from_lua(values.pop_front().unwrap_or(Nil), lua)
+        let value = values.pop_front().unwrap_or(Nil);
+        lua.cache_multivalue(values);
+        T::from_lua(value, lua)
     }
 }
 
@@ -61,6 +66,10 @@ impl<'lua> FromLuaMulti<'lua> for MultiValue<'lua> {
 ///
 /// The [`MultiValue`] type is equivalent to `Variadic<Value>`.
 ///
+/// If a value cannot be converted to `T`, the returned [`Error::FromLuaConversionError`] message
+/// names the position of the value among the variadic values, starting from 1 (e.g.
+/// `bad vararg #3`).
+///
 /// # Examples
 ///
 /// ```
@@ -78,6 +87,7 @@ impl<'lua> FromLuaMulti<'lua> for MultiValue<'lua> {
 ///
 /// [`FromLua`]: trait.FromLua.html
 /// [`MultiValue`]: struct.MultiValue.html
+/// [`Error::FromLuaConversionError`]: enum.Error.html#variant.FromLuaConversionError
 #[derive(Debug, Clone)]
 pub struct Variadic<T>(Vec<T>);
 
@@ -131,24 +141,278 @@ impl<'lua, T: ToLua<'lua>> ToLuaMulti<'lua> for Variadic<T> {
 
 impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for Variadic<T> {
     fn from_lua_multi(values: MultiValue<'lua>, lua: &'lua Lua) -> Result<Self> {
-        values
-            .into_iter()
-            .map(|e| T::from_lua(e, lua))
-            .collect::<Result<Vec<T>>>()
-            .map(Variadic)
+        from_lua_varargs(values, lua).map(Variadic)
+    }
+}
+
+/// Wraps a variable number of `T`s, accepting at most `MAX` of them.
+///
+/// Works like [`Variadic`], but converting from more than `MAX` values fails with
+/// [`Error::FromLuaConversionError`]. The number of values is checked before any of them is
+/// converted, so an oversized argument list does not cause a `Vec<T>` to be allocated.
+///
+/// # Examples
+///
+/// ```
+/// # use mlua::{BoundedVariadic, Lua, Result};
+/// # fn main() -> Result<()> {
+/// # let lua = Lua::new();
+/// let add = lua.create_function(|_, vals: BoundedVariadic<f64, 3>| -> Result<f64> {
+///     Ok(vals.iter().sum())
+/// })?;
+/// lua.globals().set("add", add)?;
+/// assert_eq!(lua.load("add(3, 2, 5)").eval::<f32>()?, 10.0);
+/// assert!(lua.load("add(3, 2, 5, 1)").exec().is_err());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Variadic`]: struct.Variadic.html
+/// [`Error::FromLuaConversionError`]: enum.Error.html#variant.FromLuaConversionError
+#[derive(Debug, Clone)]
+pub struct BoundedVariadic<T, const MAX: usize>(Vec<T>);
+
+impl<T, const MAX: usize> BoundedVariadic<T, MAX> {
+    /// Creates an empty `BoundedVariadic` wrapper containing no values.
+    pub fn new() -> BoundedVariadic<T, MAX> {
+        BoundedVariadic(Vec::new())
+    }
+
+    /// Consumes the wrapper and returns the values.
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T, const MAX: usize> Default for BoundedVariadic<T, MAX> {
+    fn default() -> BoundedVariadic<T, MAX> {
+        BoundedVariadic::new()
+    }
+}
+
+impl<T, const MAX: usize> IntoIterator for BoundedVariadic<T, MAX> {
+    type Item = T;
+    type IntoIter = <Vec<T> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
     }
 }
 
+impl<T, const MAX: usize> Deref for BoundedVariadic<T, MAX> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, const MAX: usize> DerefMut for BoundedVariadic<T, MAX> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'lua, T: ToLua<'lua>, const MAX: usize> ToLuaMulti<'lua> for BoundedVariadic<T, MAX> {
+    fn to_lua_multi(self, lua: &'lua Lua) -> Result<MultiValue<'lua>> {
+        self.0.into_iter().map(|e| e.to_lua(lua)).collect()
+    }
+}
+
+impl<'lua, T: FromLua<'lua>, const MAX: usize> FromLuaMulti<'lua> for BoundedVariadic<T, MAX> {
+    fn from_lua_multi(values: MultiValue<'lua>, lua: &'lua Lua) -> Result<Self> {
+        if values.len() > MAX {
+            return Err(Error::FromLuaConversionError {
+                from: "variadic",
+                to: "BoundedVariadic",
+                message: Some(format!(
+                    "too many values: expected at most {}, got {}",
+                    MAX,
+                    values.len()
+                )),
+            });
+        }
+        from_lua_varargs(values, lua).map(BoundedVariadic)
+    }
+}
+
+/// Wraps a value returned to Lua together with a validator that checks it first.
+///
+/// When converted to Lua values, the validator is called with a reference to the wrapped value
+/// before any conversion happens. If it returns an error, the conversion fails with that error,
+/// so a Rust callback returning `Validated` raises an error in Lua instead of returning a value
+/// that does not satisfy the post-condition.
+///
+/// # Examples
+///
+/// ```
+/// # use mlua::{Error, Lua, Result, Validated};
+/// # fn main() -> Result<()> {
+/// # let lua = Lua::new();
+/// let non_negative = |n: &i64| match *n >= 0 {
+///     true => Ok(()),
+///     false => Err(Error::RuntimeError(format!("expected a non-negative value, got {}", n))),
+/// };
+/// let sub = lua.create_function(move |_, (a, b): (i64, i64)| {
+///     Ok(Validated::new(a - b, non_negative))
+/// })?;
+/// lua.globals().set("sub", sub)?;
+/// assert_eq!(lua.load("sub(3, 2)").eval::<i64>()?, 1);
+/// assert!(lua.load("sub(2, 3)").exec().is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Validated<T, F> {
+    value: T,
+    validator: F,
+}
+
+impl<T, F> Validated<T, F>
+where
+    F: FnOnce(&T) -> Result<()>,
+{
+    /// Wraps `value`, which is checked by `validator` before it is converted to Lua values.
+    pub fn new(value: T, validator: F) -> Validated<T, F> {
+        Validated { value, validator }
+    }
+
+    /// Consumes the wrapper and returns the value without validating it.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Validated<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Validated").field(&self.value).finish()
+    }
+}
+
+impl<'lua, T, F> ToLuaMulti<'lua> for Validated<T, F>
+where
+    T: ToLuaMulti<'lua>,
+    F: FnOnce(&T) -> Result<()>,
+{
+    fn to_lua_multi(self, lua: &'lua Lua) -> Result<MultiValue<'lua>> {
+        (self.validator)(&self.value)?;
+        self.value.to_lua_multi(lua)
+    }
+}
+
+/// Wraps a table returned to Lua, checking that it is a proper sequence.
+///
+/// When converted to a Lua value, the table must only have positive integer keys from `1` to `n`
+/// without holes (as determined by a raw traversal), otherwise the conversion fails with
+/// [`Error::ToLuaConversionError`]. This is useful for APIs that must return arrays, e.g. to be
+/// serialized as JSON, to catch accidental holes early.
+///
+/// # Examples
+///
+/// ```
+/// # use mlua::{Lua, Result, SeqTable};
+/// # fn main() -> Result<()> {
+/// # let lua = Lua::new();
+/// let make = lua.create_function(|lua, n: i64| {
+///     let t = lua.create_table()?;
+///     t.raw_set(1, "a")?;
+///     t.raw_set(n, "b")?;
+///     Ok(SeqTable::new(t))
+/// })?;
+/// lua.globals().set("make", make)?;
+/// assert_eq!(lua.load("#make(2)").eval::<i64>()?, 2);
+/// assert!(lua.load("make(3)").exec().is_err());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Error::ToLuaConversionError`]: enum.Error.html#variant.ToLuaConversionError
+#[derive(Debug, Clone)]
+pub struct SeqTable<'lua>(Table<'lua>);
+
+impl<'lua> SeqTable<'lua> {
+    /// Wraps `table`, which is checked to be a sequence before it is converted to a Lua value.
+    pub fn new(table: Table<'lua>) -> SeqTable<'lua> {
+        SeqTable(table)
+    }
+
+    /// Consumes the wrapper and returns the table without checking it.
+    pub fn into_inner(self) -> Table<'lua> {
+        self.0
+    }
+}
+
+impl<'lua> ToLua<'lua> for SeqTable<'lua> {
+    fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
+        let (mut count, mut max) = (0, 0);
+        for pair in self.0.clone().pairs::<Value, Value>() {
+            let index = match pair?.0 {
+                Value::Integer(i) if i > 0 => Some(i),
+                Value::Number(n) if n >= 1.0 && n.fract() == 0.0 && n <= Integer::MAX as Number => {
+                    Some(n as Integer)
+                }
+                _ => None,
+            };
+            let index = index.ok_or_else(|| Error::ToLuaConversionError {
+                from: "SeqTable",
+                to: "table",
+                message: Some("table has a non-positive-integer key".to_string()),
+            })?;
+            count += 1;
+            max = max.max(index);
+        }
+        if max != count {
+            return Err(Error::ToLuaConversionError {
+                from: "SeqTable",
+                to: "table",
+                message: Some(format!(
+                    "table is not a sequence: {} elements with largest index {}",
+                    count, max
+                )),
+            });
+        }
+        Ok(Value::Table(self.0))
+    }
+}
+
+// Converts every value into `T`, naming the position of the first value that fails.
+fn from_lua_varargs<'lua, T: FromLua<'lua>>(
+    values: MultiValue<'lua>,
+    lua: &'lua Lua,
+) -> Result<Vec<T>> {
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, e)| {
+            T::from_lua(e, lua).map_err(|err| match err {
+                Error::FromLuaConversionError { from, to, message } => {
+                    let message = match message {
+                        Some(message) => format!("bad vararg #{}: {}", i + 1, message),
+                        None => format!("bad vararg #{}", i + 1),
+                    };
+                    Error::FromLuaConversionError {
+                        from,
+                        to,
+                        message: Some(message),
+                    }
+                }
+                err => err,
+            })
+        })
+        .collect()
+}
+
 macro_rules! impl_tuple {
     () => (
         impl<'lua> ToLuaMulti<'lua> for () {
-            fn to_lua_multi(self, _: &'lua Lua) -> Result<MultiValue<'lua>> {
-                Ok(MultiValue::new())
+            fn to_lua_multi(self, lua: &'lua Lua) -> Result<MultiValue<'lua>> {
+                Ok(lua.new_or_cached_multivalue())
             }
         }
 
         impl<'lua> FromLuaMulti<'lua> for () {
-            fn from_lua_multi(_: MultiValue<'lua>, _: &'lua Lua) -> Result<Self> {
+            fn from_lua_multi(values: MultiValue<'lua>, lua: &'lua Lua) -> Result<Self> {
+                lua.cache_multivalue(values);
                 Ok(())
             }
         }
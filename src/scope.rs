@@ -1,10 +1,12 @@
 use std::any::Any;
-use std::cell::{Cell, RefCell};
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::{c_int, c_void};
 use std::ptr;
 use std::rc::Rc;
+use std::string::String as StdString;
 
 #[cfg(feature = "serialize")]
 use serde::Serialize;
@@ -13,13 +15,16 @@ use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
 use crate::lua::Lua;
+use crate::table::Table;
+use crate::thread::Thread;
 use crate::types::{Callback, LuaRef, MaybeSend};
 use crate::userdata::{
     AnyUserData, MetaMethod, UserData, UserDataCell, UserDataFields, UserDataMethods,
 };
 use crate::util::{
-    assert_stack, check_stack, get_userdata, init_userdata_metatable, protect_lua, push_table,
-    rawset_field, take_userdata, StackGuard,
+    assert_stack, check_stack, get_userdata, init_userdata_metatable, init_userdata_tostring,
+    is_destructed_userdata, is_meta_index_impl, protect_lua, push_table, rawset_field,
+    short_type_name, take_userdata, StackGuard,
 };
 use crate::value::{FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti, Value};
 
@@ -28,6 +33,7 @@ use {
     crate::types::AsyncCallback,
     futures_core::future::{Future, LocalBoxFuture},
     futures_util::future::{self, TryFutureExt},
+    std::sync::Arc,
 };
 
 /// Constructed by the [`Lua::scope`] method, allows temporarily creating Lua userdata and
@@ -39,16 +45,19 @@ use {
 pub struct Scope<'lua, 'scope> {
     lua: &'lua Lua,
     destructors: RefCell<Vec<(LuaRef<'lua>, DestructorCallback<'lua>)>>,
+    method_overrides: RefCell<Vec<(Table<'lua>, StdString, Value<'lua>)>>,
     _scope_invariant: PhantomData<Cell<&'scope ()>>,
 }
 
-type DestructorCallback<'lua> = Box<dyn Fn(LuaRef<'lua>) -> Vec<Box<dyn Any>> + 'lua>;
+// Returns an error (without invalidating the value) if the value is currently in use.
+type DestructorCallback<'lua> = Box<dyn Fn(LuaRef<'lua>) -> Result<Vec<Box<dyn Any>>> + 'lua>;
 
 impl<'lua, 'scope> Scope<'lua, 'scope> {
     pub(crate) fn new(lua: &'lua Lua) -> Scope<'lua, 'scope> {
         Scope {
             lua,
             destructors: RefCell::new(Vec::new()),
+            method_overrides: RefCell::new(Vec::new()),
             _scope_invariant: PhantomData,
         }
     }
@@ -107,6 +116,29 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         })
     }
 
+    /// Wraps a Rust closure with mutable state, creating a callable Lua function handle to it.
+    ///
+    /// This is a version of [`Lua::create_function_reentrant`] that creates a callback which
+    /// expires on scope drop. See [`Lua::scope`] and [`Scope::create_function`] for more details.
+    ///
+    /// [`Lua::create_function_reentrant`]: struct.Lua.html#method.create_function_reentrant
+    /// [`Lua::scope`]: struct.Lua.html#method.scope
+    /// [`Scope::create_function`]: #method.create_function
+    pub fn create_function_reentrant<'callback, S, A, R, F>(
+        &'callback self,
+        state: S,
+        func: F,
+    ) -> Result<Function<'lua>>
+    where
+        S: 'scope,
+        A: FromLuaMulti<'callback>,
+        R: ToLuaMulti<'callback>,
+        F: 'scope + Fn(&'callback Lua, A, &Cell<S>) -> Result<R>,
+    {
+        let state = Cell::new(state);
+        self.create_function(move |lua, args| func(lua, args, &state))
+    }
+
     /// Wraps a Rust async function or closure, creating a callable Lua function handle to it.
     ///
     /// This is a version of [`Lua::create_async_function`] that creates a callback which expires on
@@ -140,6 +172,62 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         }
     }
 
+    /// Creates a Lua thread (coroutine) with the given function as its main body.
+    ///
+    /// This is a version of [`Lua::create_thread`] that creates a thread which expires on scope
+    /// drop, which makes it suitable for running functions created by [`Scope::create_function`].
+    /// Once the scope ends, the thread is made dead without running any more of its code, so it
+    /// cannot be resumed from Lua (`coroutine.resume` fails), and resuming it from Rust results in
+    /// an `Error::CallbackDestructed` error. See [`Lua::scope`] for more details.
+    ///
+    /// [`Lua::create_thread`]: struct.Lua.html#method.create_thread
+    /// [`Lua::scope`]: struct.Lua.html#method.scope
+    /// [`Scope::create_function`]: #method.create_function
+    pub fn create_thread(&self, func: Function<'lua>) -> Result<Thread<'lua>> {
+        let thread = self.lua.create_thread(func)?;
+
+        // We need to pre-allocate the table to avoid failures in destructor.
+        let expired_threads = self.lua.expired_threads()?;
+        let destructor: DestructorCallback = Box::new(move |thread| {
+            let state = thread.lua.state;
+            unsafe {
+                let _sg = StackGuard::new(state);
+                assert_stack(state, 1);
+
+                thread.lua.push_ref(&thread);
+                let thread_state = ffi::lua_tothread(state, -1);
+                let mut ar: ffi::lua_Debug = mem::zeroed();
+                match ffi::lua_status(thread_state) {
+                    // Not started thread keeps its main function (and nothing else) on the stack
+                    ffi::LUA_OK if ffi::lua_getstack(thread_state, 0, &mut ar) == 0 => {
+                        ffi::lua_settop(thread_state, 0);
+                    }
+                    // Suspended thread is resumed once more with a hook raising an error before
+                    // any instruction runs, so it ends up dead for `coroutine.resume` too
+                    ffi::LUA_YIELD => {
+                        ffi::lua_sethook(
+                            thread_state,
+                            Some(expired_thread_hook),
+                            ffi::LUA_MASKCOUNT,
+                            1,
+                        );
+                        let mut nresults = 0;
+                        ffi::lua_resume(thread_state, state, 0, &mut nresults);
+                        ffi::lua_sethook(thread_state, None, 0, 0);
+                    }
+                    _ => {}
+                }
+            }
+            expired_threads.raw_set(Thread(thread), true)?;
+            Ok(vec![])
+        });
+        self.destructors
+            .borrow_mut()
+            .push((thread.0.clone(), destructor));
+
+        Ok(thread)
+    }
+
     /// Create a Lua userdata object from a custom userdata type.
     ///
     /// This is a version of [`Lua::create_userdata`] that creates a userdata which expires on
@@ -190,11 +278,19 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             let destructor: DestructorCallback = Box::new(move |ud| {
                 let state = ud.lua.state;
                 let _sg = StackGuard::new(state);
-                assert_stack(state, 2);
+                assert_stack(state, 3);
 
                 ud.lua.push_ref(&ud);
 
-                // We know the destructor has not run yet because we hold a reference to the userdata.
+                // The userdata is not garbage collected because we hold a reference to it, but its
+                // value could have been moved out by `AnyUserData::take`
+                if is_destructed_userdata(state, -1) {
+                    return Ok(vec![]);
+                }
+
+                // The userdata cannot be destructed while a method holds a borrow
+                let cell = &*get_userdata::<UserDataCell<T>>(state, -1);
+                cell.try_borrow_mut()?;
 
                 // Clear uservalue
                 #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
@@ -203,7 +299,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                 ud.lua.push_ref(&newtable.0);
                 ffi::lua_setuservalue(state, -2);
 
-                vec![Box::new(take_userdata::<UserDataCell<T>>(state))]
+                Ok(vec![Box::new(take_userdata::<UserDataCell<T>>(state))])
             });
             self.destructors
                 .borrow_mut()
@@ -225,14 +321,19 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
     ///
     /// The main limitation that comes from using non-'static userdata is that the produced userdata
     /// will no longer have a `TypeId` associated with it, becuase `TypeId` can only work for
-    /// 'static types. This means that it is impossible, once the userdata is created, to get a
-    /// reference to it back *out* of an `AnyUserData` handle. This also implies that the
+    /// 'static types. This means that `AnyUserData::borrow` cannot be used, once the userdata is
+    /// created, to get a reference to it back *out* of an `AnyUserData` handle; only
+    /// [`Scope::borrow_nonstatic`] can do it for userdata created with a builder, and only within
+    /// the scope. This also implies that the
     /// "function" type methods that can be added via [`UserDataMethods`] (the ones that accept
     /// `AnyUserData` as a first parameter) are vastly less useful. Also, there is no way to re-use
     /// a single metatable for multiple non-'static types, so there is a higher cost associated with
-    /// creating the userdata metatable each time a new userdata is created.
+    /// creating the userdata metatable each time a new userdata is created. Use
+    /// [`Scope::userdata_builder`] to share one metatable between userdata of the same type.
     ///
     /// [`Scope::create_userdata`]: #method.create_userdata
+    /// [`Scope::userdata_builder`]: #method.userdata_builder
+    /// [`Scope::borrow_nonstatic`]: #method.borrow_nonstatic
     /// [`Lua::create_userdata`]: struct.Lua.html#method.create_userdata
     /// [`Lua::scope`]: struct.Lua.html#method.scope
     /// [`UserDataMethods`]: trait.UserDataMethods.html
@@ -242,17 +343,15 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
     {
         let data = Rc::new(RefCell::new(data));
 
-        // 'callback outliving 'scope is a lie to make the types work out, required due to the
-        // inability to work with the more correct callback type that is universally quantified over
-        // 'lua. This is safe though, because `UserData::add_methods` does not get to pick the 'lua
-        // lifetime, so none of the static methods UserData types can add can possibly capture
-        // parameters.
-        fn wrap_method<'scope, 'lua, 'callback: 'scope, T: 'scope>(
-            scope: &Scope<'lua, 'scope>,
-            data: Rc<RefCell<T>>,
-            data_ptr: *mut c_void,
-            method: NonStaticMethod<'callback, T>,
-        ) -> Result<Function<'lua>> {
+        unsafe {
+            let lua = self.lua;
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 13)?;
+
+            let data_ptr = protect_lua(lua.state, 0, 1, |state| {
+                ffi::lua_newuserdata(state, mem::size_of::<UserDataCell<Rc<RefCell<T>>>>())
+            })?;
+
             // On methods that actually receive the userdata, we fake a type check on the passed in
             // userdata, where we pretend there is a unique type per call to
             // `Scope::create_nonstatic_userdata`. You can grab a method from a userdata and call
@@ -260,24 +359,289 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             // with a type mismatch, but here without this check would proceed as though you had
             // called the method on the original value (since we otherwise completely ignore the
             // first argument).
-            let check_ud_type = move |lua: &'callback Lua, value| {
+            let weak_data = Rc::downgrade(&data);
+            self.push_nonstatic_metatable(move |lua: &Lua, value| {
                 if let Some(Value::UserData(ud)) = value {
-                    unsafe {
-                        let _sg = StackGuard::new(lua.state);
-                        check_stack(lua.state, 3)?;
-                        lua.push_userdata_ref(&ud.0, false)?;
-                        if get_userdata(lua.state, -1) == data_ptr {
-                            return Ok(());
-                        }
+                    let _sg = StackGuard::new(lua.state);
+                    check_stack(lua.state, 3)?;
+                    lua.push_userdata_ref(&ud.0, false)?;
+                    if get_userdata(lua.state, -1) == data_ptr {
+                        return weak_data.upgrade().ok_or(Error::UserDataDestructed);
+                    }
+                };
+                Err(Error::UserDataTypeMismatch)
+            })?;
+
+            let mt_id = ffi::lua_topointer(lua.state, -1);
+            let mut data = UserDataCell::new(data);
+            lua.track_userdata(&mut data)?;
+            // Write userdata just before attaching metatable with `__gc` metamethod
+            ptr::write(data_ptr as _, data);
+            ffi::lua_setmetatable(lua.state, -2);
+            let ud = AnyUserData(lua.pop_ref());
+            lua.register_userdata_metatable(mt_id as isize);
+
+            self.add_nonstatic_destructor::<T>(&ud, move |state| {
+                // Deregister metatable
+                ffi::lua_getmetatable(state, -1);
+                let mt_id = ffi::lua_topointer(state, -1);
+                ffi::lua_pop(state, 1);
+                lua.deregister_userdata_metatable(mt_id as isize);
+            })?;
+
+            Ok(ud)
+        }
+    }
+
+    /// Creates a builder for non-'static userdata of type `T`, sharing a single metatable.
+    ///
+    /// The metatable and all the methods of `T` are created once, and then reused by every
+    /// userdata created by [`Scope::create_nonstatic_userdata_cached`] with this builder. This
+    /// avoids the cost of creating the metatable each time, which makes it better suited for
+    /// creating many userdata of the same type within a scope.
+    ///
+    /// The metatable stays registered until the last userdata created with the builder is
+    /// destructed.
+    ///
+    /// [`Scope::create_nonstatic_userdata_cached`]: #method.create_nonstatic_userdata_cached
+    pub fn userdata_builder<T>(&self) -> Result<NonStaticUserDataBuilder<'lua, 'scope, T>>
+    where
+        T: 'scope + UserData,
+    {
+        let instances = Rc::new(RefCell::new(HashSet::new()));
+
+        unsafe {
+            let lua = self.lua;
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 13)?;
+
+            // All the userdata created with the builder share the methods, so instead of comparing
+            // the userdata with a single pointer, we check that it's one of the builder instances.
+            let builder_instances = instances.clone();
+            self.push_nonstatic_metatable(move |lua: &Lua, value| {
+                if let Some(Value::UserData(ud)) = value {
+                    let _sg = StackGuard::new(lua.state);
+                    check_stack(lua.state, 3)?;
+                    lua.push_userdata_ref(&ud.0, false)?;
+                    let data_ptr = get_userdata::<UserDataCell<Rc<RefCell<T>>>>(lua.state, -1);
+                    if builder_instances
+                        .borrow()
+                        .contains(&(data_ptr as *mut c_void))
+                    {
+                        return Ok(Rc::clone(&*(*data_ptr).try_borrow()?));
                     }
                 };
                 Err(Error::UserDataTypeMismatch)
-            };
+            })?;
 
+            let mt_id = ffi::lua_topointer(lua.state, -1) as isize;
+            Ok(NonStaticUserDataBuilder {
+                metatable: lua.pop_ref(),
+                mt_id,
+                instances,
+                _phantom: PhantomData,
+            })
+        }
+    }
+
+    /// Create a Lua userdata object from a custom userdata type, reusing the metatable of the
+    /// given builder.
+    ///
+    /// This is a version of [`Scope::create_nonstatic_userdata`] which does not create a new
+    /// metatable for each userdata. See [`Scope::userdata_builder`] for more details.
+    ///
+    /// [`Scope::create_nonstatic_userdata`]: #method.create_nonstatic_userdata
+    /// [`Scope::userdata_builder`]: #method.userdata_builder
+    pub fn create_nonstatic_userdata_cached<T>(
+        &self,
+        builder: &NonStaticUserDataBuilder<'lua, 'scope, T>,
+        data: T,
+    ) -> Result<AnyUserData<'lua>>
+    where
+        T: 'scope + UserData,
+    {
+        let data = Rc::new(RefCell::new(data));
+
+        unsafe {
+            let lua = self.lua;
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 3)?;
+
+            let data_ptr = protect_lua(lua.state, 0, 1, |state| {
+                ffi::lua_newuserdata(state, mem::size_of::<UserDataCell<Rc<RefCell<T>>>>())
+            })?;
+            lua.push_ref(&builder.metatable);
+            let mut data = UserDataCell::new(data);
+            lua.track_userdata(&mut data)?;
+            // Write userdata just before attaching metatable with `__gc` metamethod
+            ptr::write(data_ptr as _, data);
+            ffi::lua_setmetatable(lua.state, -2);
+            let ud = AnyUserData(lua.pop_ref());
+
+            let mut instances = builder.instances.borrow_mut();
+            if instances.is_empty() {
+                lua.register_userdata_metatable(builder.mt_id);
+            }
+            instances.insert(data_ptr);
+            drop(instances);
+
+            let (instances, mt_id) = (builder.instances.clone(), builder.mt_id);
+            self.add_nonstatic_destructor::<T>(&ud, move |state| {
+                // Deregister metatable when the last instance is destructed
+                let mut instances = instances.borrow_mut();
+                instances.remove(&(get_userdata::<c_void>(state, -1)));
+                if instances.is_empty() {
+                    lua.deregister_userdata_metatable(mt_id);
+                }
+            })?;
+
+            Ok(ud)
+        }
+    }
+
+    /// Borrows the value of a non-'static userdata created with the given builder.
+    ///
+    /// This makes it possible to get a reference to the value of userdata created by
+    /// [`Scope::create_nonstatic_userdata_cached`] back out of an `AnyUserData` handle, even though
+    /// the userdata type has no `TypeId`. Since a `TypeId` cannot tell apart the lifetimes of `T`,
+    /// the type is checked using the builder instead: every userdata created with it holds a value
+    /// of exactly the type `T`, including its lifetimes.
+    ///
+    /// The userdata cannot be invalidated while the reference is held.
+    ///
+    /// Returns an error if the userdata was not created with `builder`, or if it's currently
+    /// mutably borrowed.
+    ///
+    /// [`Scope::create_nonstatic_userdata_cached`]: #method.create_nonstatic_userdata_cached
+    pub fn borrow_nonstatic<'a, T>(
+        &'a self,
+        builder: &NonStaticUserDataBuilder<'lua, 'scope, T>,
+        ud: &AnyUserData<'lua>,
+    ) -> Result<Ref<'a, T>>
+    where
+        T: 'scope + UserData,
+    {
+        unsafe {
+            let lua = self.lua;
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 3)?;
+
+            lua.push_userdata_ref(&ud.0, false)?;
+            let data_ptr = get_userdata::<UserDataCell<Rc<RefCell<T>>>>(lua.state, -1);
+            if !builder
+                .instances
+                .borrow()
+                .contains(&(data_ptr as *mut c_void))
+            {
+                return Err(Error::UserDataTypeMismatch);
+            }
+
+            // The value is kept alive by the userdata, which cannot be destructed while borrowed
+            let data = &*(&**(*data_ptr).try_borrow()? as *const RefCell<T>);
+            data.try_borrow().map_err(|_| Error::UserDataBorrowError)
+        }
+    }
+
+    /// Invalidates a userdata created by this scope before the scope ends.
+    ///
+    /// The userdata is destructed immediately, dropping the wrapped value, and any further access
+    /// to it from Lua results in an error, the same way as after the scope is dropped.
+    ///
+    /// Returns an error if the userdata was not created by this scope (or was already invalidated),
+    /// or if it's currently borrowed (eg. one of its methods is running).
+    pub fn invalidate(&self, ud: &AnyUserData<'lua>) -> Result<()> {
+        let mut destructors = self.destructors.borrow_mut();
+        let index = destructors
+            .iter()
+            .position(|(r, _)| *r == ud.0)
+            .ok_or_else(|| {
+                Error::RuntimeError("userdata is not owned by this scope".to_string())
+            })?;
+
+        let to_drop = (destructors[index].1)(ud.0.clone())?;
+        drop(destructors.remove(index));
+        drop(destructors);
+
+        drop(to_drop);
+        Ok(())
+    }
+
+    /// Temporarily replaces the method `name` of a userdata with `replacement`.
+    ///
+    /// The original method is restored when the scope is dropped. Methods are shared by all
+    /// userdata using the same metatable, so for `'static` userdata the override applies to every
+    /// value of that type, not just to `ud`. If the type has no method `name`, it is added for the
+    /// duration of the scope and removed afterwards.
+    ///
+    /// Returns an error if the userdata type does not define any methods.
+    pub fn override_method(
+        &self,
+        ud: &AnyUserData<'lua>,
+        name: &str,
+        replacement: Function<'lua>,
+    ) -> Result<()> {
+        let lua = self.lua;
+        let metatable = ud.get_metatable()?.0;
+        let methods = match metatable.raw_get::<_, Value>("__index")? {
+            Value::Function(index) => unsafe {
+                let _sg = StackGuard::new(lua.state);
+                check_stack(lua.state, 2)?;
+
+                // The methods table is the third upvalue of the `__index` wrapper
+                lua.push_ref(&index.0);
+                if is_meta_index_impl(lua.state, -1)
+                    && !ffi::lua_getupvalue(lua.state, -1, 3).is_null()
+                {
+                    lua.pop_value()
+                } else {
+                    Value::Nil
+                }
+            },
+            _ => Value::Nil,
+        };
+        let methods = match methods {
+            Value::Table(methods) => methods,
+            _ => {
+                return Err(Error::RuntimeError(format!(
+                    "userdata has no methods to override '{}'",
+                    name
+                )))
+            }
+        };
+
+        let original = methods.raw_get::<_, Value>(name)?;
+        methods.raw_set(name, replacement)?;
+        self.method_overrides
+            .borrow_mut()
+            .push((methods, name.to_string(), original));
+        Ok(())
+    }
+
+    // Pushes a new metatable for the non-'static userdata type `T` onto the stack, with all of
+    // its fields and methods. Methods receiving the userdata use `get_data` to check and resolve
+    // the passed in userdata.
+    // Uses 12 stack spaces, does not call checkstack.
+    unsafe fn push_nonstatic_metatable<'callback, T, F>(&self, get_data: F) -> Result<()>
+    where
+        'callback: 'scope,
+        T: 'scope + UserData,
+        F: 'scope + Clone + Fn(&'callback Lua, Option<Value<'callback>>) -> Result<Rc<RefCell<T>>>,
+    {
+        // 'callback outliving 'scope is a lie to make the types work out, required due to the
+        // inability to work with the more correct callback type that is universally quantified over
+        // 'lua. This is safe though, because `UserData::add_methods` does not get to pick the 'lua
+        // lifetime, so none of the static methods UserData types can add can possibly capture
+        // parameters.
+        fn wrap_method<'scope, 'lua, 'callback: 'scope, T: 'scope>(
+            scope: &Scope<'lua, 'scope>,
+            get_data: impl 'scope
+                + Fn(&'callback Lua, Option<Value<'callback>>) -> Result<Rc<RefCell<T>>>,
+            method: NonStaticMethod<'callback, T>,
+        ) -> Result<Function<'lua>> {
             match method {
                 NonStaticMethod::Method(method) => {
                     let f = Box::new(move |lua, mut args: MultiValue<'callback>| {
-                        check_ud_type(lua, args.pop_front())?;
+                        let data = get_data(lua, args.pop_front())?;
                         let data = data.try_borrow().map_err(|_| Error::UserDataBorrowError)?;
                         method(lua, &*data, args)
                     });
@@ -286,7 +650,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                 NonStaticMethod::MethodMut(method) => {
                     let method = RefCell::new(method);
                     let f = Box::new(move |lua, mut args: MultiValue<'callback>| {
-                        check_ud_type(lua, args.pop_front())?;
+                        let data = get_data(lua, args.pop_front())?;
                         let mut method = method
                             .try_borrow_mut()
                             .map_err(|_| Error::RecursiveMutCallback)?;
@@ -317,125 +681,127 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         T::add_fields(&mut ud_fields);
         T::add_methods(&mut ud_methods);
 
-        unsafe {
-            let lua = self.lua;
-            let _sg = StackGuard::new(lua.state);
-            check_stack(lua.state, 13)?;
+        let lua = self.lua;
 
-            let data_ptr = protect_lua(lua.state, 0, 1, |state| {
-                ffi::lua_newuserdata(state, mem::size_of::<UserDataCell<Rc<RefCell<T>>>>())
-            })?;
-            // Prepare metatable, add meta methods first and then meta fields
-            let meta_methods_nrec = ud_methods.meta_methods.len() + ud_fields.meta_fields.len() + 1;
-            push_table(lua.state, 0, meta_methods_nrec as c_int)?;
-
-            for (k, m) in ud_methods.meta_methods {
-                let data = data.clone();
-                lua.push_value(Value::Function(wrap_method(self, data, data_ptr, m)?))?;
-                rawset_field(lua.state, -2, k.validate()?.name())?;
-            }
-            for (k, f) in ud_fields.meta_fields {
-                lua.push_value(f(mem::transmute(lua))?)?;
-                rawset_field(lua.state, -2, k.validate()?.name())?;
-            }
-            let metatable_index = ffi::lua_absindex(lua.state, -1);
-
-            let mut field_getters_index = None;
-            let field_getters_nrec = ud_fields.field_getters.len();
-            if field_getters_nrec > 0 {
-                push_table(lua.state, 0, field_getters_nrec as c_int)?;
-                for (k, m) in ud_fields.field_getters {
-                    let data = data.clone();
-                    lua.push_value(Value::Function(wrap_method(self, data, data_ptr, m)?))?;
-                    rawset_field(lua.state, -2, &k)?;
-                }
-                field_getters_index = Some(ffi::lua_absindex(lua.state, -1));
+        // Prepare metatable, add meta methods first and then meta fields
+        let meta_methods_nrec = ud_methods.meta_methods.len() + ud_fields.meta_fields.len() + 1;
+        push_table(lua.state, 0, meta_methods_nrec as c_int)?;
+
+        for (k, m) in ud_methods.meta_methods {
+            let get_data = get_data.clone();
+            lua.push_value(Value::Function(wrap_method(self, get_data, m)?))?;
+            rawset_field(lua.state, -2, k.validate()?.name())?;
+        }
+        for (k, f) in ud_fields.meta_fields {
+            lua.push_value(f(mem::transmute(lua))?)?;
+            rawset_field(lua.state, -2, k.validate()?.name())?;
+        }
+        let metatable_index = ffi::lua_absindex(lua.state, -1);
+
+        let mut field_getters_index = None;
+        let field_getters_nrec = ud_fields.field_getters.len();
+        if field_getters_nrec > 0 {
+            push_table(lua.state, 0, field_getters_nrec as c_int)?;
+            for (k, m) in ud_fields.field_getters {
+                let get_data = get_data.clone();
+                lua.push_value(Value::Function(wrap_method(self, get_data, m)?))?;
+                rawset_field(lua.state, -2, &k)?;
             }
+            field_getters_index = Some(ffi::lua_absindex(lua.state, -1));
+        }
 
-            let mut field_setters_index = None;
-            let field_setters_nrec = ud_fields.field_setters.len();
-            if field_setters_nrec > 0 {
-                push_table(lua.state, 0, field_setters_nrec as c_int)?;
-                for (k, m) in ud_fields.field_setters {
-                    let data = data.clone();
-                    lua.push_value(Value::Function(wrap_method(self, data, data_ptr, m)?))?;
-                    rawset_field(lua.state, -2, &k)?;
-                }
-                field_setters_index = Some(ffi::lua_absindex(lua.state, -1));
+        let mut field_setters_index = None;
+        let field_setters_nrec = ud_fields.field_setters.len();
+        if field_setters_nrec > 0 {
+            push_table(lua.state, 0, field_setters_nrec as c_int)?;
+            for (k, m) in ud_fields.field_setters {
+                let get_data = get_data.clone();
+                lua.push_value(Value::Function(wrap_method(self, get_data, m)?))?;
+                rawset_field(lua.state, -2, &k)?;
             }
+            field_setters_index = Some(ffi::lua_absindex(lua.state, -1));
+        }
 
-            let mut methods_index = None;
-            let methods_nrec = ud_methods.methods.len();
-            if methods_nrec > 0 {
-                // Create table used for methods lookup
-                push_table(lua.state, 0, methods_nrec as c_int)?;
-                for (k, m) in ud_methods.methods {
-                    let data = data.clone();
-                    lua.push_value(Value::Function(wrap_method(self, data, data_ptr, m)?))?;
-                    rawset_field(lua.state, -2, &k)?;
-                }
-                methods_index = Some(ffi::lua_absindex(lua.state, -1));
+        let mut methods_index = None;
+        let methods_nrec = ud_methods.methods.len();
+        if methods_nrec > 0 {
+            // Create table used for methods lookup
+            push_table(lua.state, 0, methods_nrec as c_int)?;
+            for (k, m) in ud_methods.methods {
+                let get_data = get_data.clone();
+                lua.push_value(Value::Function(wrap_method(self, get_data, m)?))?;
+                rawset_field(lua.state, -2, &k)?;
             }
+            methods_index = Some(ffi::lua_absindex(lua.state, -1));
+        }
 
-            init_userdata_metatable::<UserDataCell<Rc<RefCell<T>>>>(
-                lua.state,
-                metatable_index,
-                field_getters_index,
-                field_setters_index,
-                methods_index,
-            )?;
+        if T::default_tostring() {
+            init_userdata_tostring(lua.state, metatable_index, &short_type_name::<T>())?;
+        }
 
-            let count = field_getters_index.map(|_| 1).unwrap_or(0)
-                + field_setters_index.map(|_| 1).unwrap_or(0)
-                + methods_index.map(|_| 1).unwrap_or(0);
-            ffi::lua_pop(lua.state, count);
+        init_userdata_metatable::<UserDataCell<Rc<RefCell<T>>>>(
+            lua.state,
+            metatable_index,
+            field_getters_index,
+            field_setters_index,
+            methods_index,
+        )?;
 
-            let mt_id = ffi::lua_topointer(lua.state, -1);
-            // Write userdata just before attaching metatable with `__gc` metamethod
-            ptr::write(data_ptr as _, UserDataCell::new(data));
-            ffi::lua_setmetatable(lua.state, -2);
-            let ud = AnyUserData(lua.pop_ref());
-            lua.register_userdata_metatable(mt_id as isize);
+        let count = field_getters_index.map(|_| 1).unwrap_or(0)
+            + field_setters_index.map(|_| 1).unwrap_or(0)
+            + methods_index.map(|_| 1).unwrap_or(0);
+        ffi::lua_pop(lua.state, count);
 
-            #[cfg(any(feature = "lua51", feature = "luajit"))]
-            let newtable = lua.create_table()?;
-            let destructor: DestructorCallback = Box::new(move |ud| {
-                let state = ud.lua.state;
-                let _sg = StackGuard::new(state);
-                assert_stack(state, 2);
+        Ok(())
+    }
 
-                ud.lua.push_ref(&ud);
+    // Adds a destructor for the non-'static userdata `ud` of type `T`. The `deregister` function
+    // is called first, with the userdata at the top of the stack.
+    fn add_nonstatic_destructor<T: 'scope>(
+        &self,
+        ud: &AnyUserData<'lua>,
+        deregister: impl 'lua + Fn(*mut ffi::lua_State),
+    ) -> Result<()> {
+        #[cfg(any(feature = "lua51", feature = "luajit"))]
+        let newtable = self.lua.create_table()?;
+        let destructor: DestructorCallback = Box::new(move |ud| unsafe {
+            let state = ud.lua.state;
+            let _sg = StackGuard::new(state);
+            assert_stack(state, 2);
 
-                // We know the destructor has not run yet because we hold a reference to the userdata.
+            ud.lua.push_ref(&ud);
 
-                // Deregister metatable
-                ffi::lua_getmetatable(state, -1);
-                let mt_id = ffi::lua_topointer(state, -1);
-                ffi::lua_pop(state, 1);
-                ud.lua.deregister_userdata_metatable(mt_id as isize);
+            // We know the destructor has not run yet because we hold a reference to the userdata,
+            // and `AnyUserData::take` cannot move out non-'static values.
 
-                // Clear uservalue
-                #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-                ffi::lua_pushnil(state);
-                #[cfg(any(feature = "lua51", feature = "luajit"))]
-                ud.lua.push_ref(&newtable.0);
-                ffi::lua_setuservalue(state, -2);
+            // The userdata cannot be destructed while borrowed by `Scope::borrow_nonstatic`
+            let cell = &*get_userdata::<UserDataCell<Rc<RefCell<T>>>>(state, -1);
+            cell.try_borrow()?
+                .try_borrow_mut()
+                .map_err(|_| Error::UserDataBorrowMutError)?;
 
-                // A hack to drop non-static `T`
-                unsafe fn seal<T>(t: T) -> Box<dyn FnOnce() + 'static> {
-                    let f: Box<dyn FnOnce()> = Box::new(move || drop(t));
-                    mem::transmute(f)
-                }
+            deregister(state);
 
-                let ud = Box::new(seal(take_userdata::<UserDataCell<Rc<RefCell<T>>>>(state)));
-                vec![ud]
-            });
-            self.destructors
-                .borrow_mut()
-                .push((ud.0.clone(), destructor));
+            // Clear uservalue
+            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+            ffi::lua_pushnil(state);
+            #[cfg(any(feature = "lua51", feature = "luajit"))]
+            ud.lua.push_ref(&newtable.0);
+            ffi::lua_setuservalue(state, -2);
 
-            Ok(ud)
-        }
+            // A hack to drop non-static `T`
+            unsafe fn seal<T>(t: T) -> Box<dyn FnOnce() + 'static> {
+                let f: Box<dyn FnOnce()> = Box::new(move || drop(t));
+                mem::transmute(f)
+            }
+
+            let ud = Box::new(seal(take_userdata::<UserDataCell<Rc<RefCell<T>>>>(state)));
+            Ok(vec![ud])
+        });
+        self.destructors
+            .borrow_mut()
+            .push((ud.0.clone(), destructor));
+        Ok(())
     }
 
     // Unsafe, because the callback can improperly capture any value with 'callback scope, such as
@@ -469,7 +835,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             ffi::lua_pushnil(state);
             ffi::lua_setupvalue(state, -2, 2);
 
-            vec![Box::new(ud1), Box::new(ud2)]
+            Ok(vec![Box::new(ud1), Box::new(ud2)])
         });
         self.destructors
             .borrow_mut()
@@ -538,7 +904,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                 data.push(Box::new(ud4));
             }
 
-            data
+            Ok(data)
         });
         self.destructors
             .borrow_mut()
@@ -550,22 +916,50 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
 
 impl<'lua, 'scope> Drop for Scope<'lua, 'scope> {
     fn drop(&mut self) {
+        // Restore overridden methods first, newest first, so that repeated overrides of the same
+        // method end up with the original one.
+        for (methods, name, original) in self.method_overrides.get_mut().drain(..).rev() {
+            let _ = methods.raw_set(name, original);
+        }
+
         // We separate the action of invalidating the userdata in Lua and actually dropping the
         // userdata type into two phases. This is so that, in the event a userdata drop panics, we
         // can be sure that all of the userdata in Lua is actually invalidated.
 
-        // All destructors are non-panicking, so this is fine
+        // All destructors are non-panicking, so this is fine.
+        // Values cannot be in use once the scope is dropped, so destructors never fail here.
         let to_drop = self
             .destructors
             .get_mut()
             .drain(..)
-            .flat_map(|(r, dest)| dest(r))
+            .flat_map(|(r, dest)| mlua_expect!(dest(r), "failed to destruct scoped value"))
             .collect::<Vec<_>>();
 
         drop(to_drop);
     }
 }
 
+// Count hook installed on threads created by a scope that has ended, to kill them.
+unsafe extern "C" fn expired_thread_hook(state: *mut ffi::lua_State, _ar: *mut ffi::lua_Debug) {
+    ffi::lua_pushstring(state, cstr!("cannot resume an expired coroutine"));
+    ffi::lua_error(state);
+}
+
+/// A metatable shared by non-'static userdata of type `T` created within a [`Scope`].
+///
+/// This struct is created by the [`Scope::userdata_builder`] method, and used with
+/// [`Scope::create_nonstatic_userdata_cached`].
+///
+/// [`Scope`]: struct.Scope.html
+/// [`Scope::userdata_builder`]: struct.Scope.html#method.userdata_builder
+/// [`Scope::create_nonstatic_userdata_cached`]: struct.Scope.html#method.create_nonstatic_userdata_cached
+pub struct NonStaticUserDataBuilder<'lua, 'scope, T> {
+    metatable: LuaRef<'lua>,
+    mt_id: isize,
+    instances: Rc<RefCell<HashSet<*mut c_void>>>,
+    _phantom: PhantomData<(Cell<&'scope ()>, Rc<RefCell<T>>)>,
+}
+
 enum NonStaticMethod<'lua, T> {
     Method(Box<dyn Fn(&'lua Lua, &T, MultiValue<'lua>) -> Result<MultiValue<'lua>>>),
     MethodMut(Box<dyn FnMut(&'lua Lua, &mut T, MultiValue<'lua>) -> Result<MultiValue<'lua>>>),
@@ -633,6 +1027,20 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         mlua_panic!("asynchronous methods are not supported for non-static userdata")
     }
 
+    #[cfg(feature = "async")]
+    fn add_async_method_arc<S, A, R, M, MR>(&mut self, _name: &S, _method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, Arc<T>, A) -> MR,
+        MR: 'lua + Future<Output = Result<R>>,
+    {
+        // The panic should never happen as async non-static code wouldn't compile
+        // Non-static lifetime must be bounded to 'lua lifetime
+        mlua_panic!("asynchronous methods are not supported for non-static userdata");
+    }
+
     fn add_function<S, A, R, F>(&mut self, name: &S, function: F)
     where
         S: AsRef<[u8]> + ?Sized,
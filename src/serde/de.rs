@@ -1,19 +1,103 @@
+use std::cmp::Ordering;
 use std::string::String as StdString;
+use std::vec;
 
 use serde::de::{self, IntoDeserializer};
 
 use crate::error::{Error, Result};
-use crate::table::{TablePairs, TableSequence};
+use crate::table::{Table, TablePairs, TableSequence};
+use crate::types::Number;
 use crate::value::Value;
 
 /// A struct for deserializing Lua values into Rust values.
 #[derive(Debug)]
-pub struct Deserializer<'lua>(Value<'lua>);
+pub struct Deserializer<'lua> {
+    value: Value<'lua>,
+    options: Options,
+}
+
+/// A struct with options to change default deserializer behavior.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Options {
+    /// If true, struct fields that are not present in a table are read using a regular
+    /// (non-raw) access. This follows the `__index` metamethod chain, so values inherited
+    /// from a metatable (eg. defaults) are also deserialized.
+    ///
+    /// Default: **false**
+    pub get_with_metamethods: bool,
+
+    /// If true, an attempt to deserialize types such as [`Function`], [`Thread`], [`LightUserData`]
+    /// (except [`null`]), [`UserData`] or [`Error`] will cause an error.
+    /// Otherwise these types are skipped when iterating tables, or deserialized as unit type.
+    ///
+    /// Default: **true**
+    ///
+    /// [`Function`]: ../../enum.Value.html#variant.Function
+    /// [`Thread`]: ../../enum.Value.html#variant.Thread
+    /// [`LightUserData`]: ../../enum.Value.html#variant.LightUserData
+    /// [`null`]: ../trait.LuaSerdeExt.html#tymethod.null
+    /// [`UserData`]: ../../enum.Value.html#variant.UserData
+    /// [`Error`]: ../../enum.Value.html#variant.Error
+    pub deny_unsupported_types: bool,
+
+    /// If true, table keys are sorted before deserializing a map, so entries are visited in a
+    /// deterministic order. Booleans come first, then numbers, then strings (compared bytewise).
+    ///
+    /// Default: **false**
+    pub sort_keys: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            get_with_metamethods: false,
+            deny_unsupported_types: true,
+            sort_keys: false,
+        }
+    }
+}
+
+impl Options {
+    /// Returns a new instance of `Options` with default parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets [`get_with_metamethods`] option.
+    ///
+    /// [`get_with_metamethods`]: #structfield.get_with_metamethods
+    pub fn get_with_metamethods(mut self, enabled: bool) -> Self {
+        self.get_with_metamethods = enabled;
+        self
+    }
+
+    /// Sets [`deny_unsupported_types`] option.
+    ///
+    /// [`deny_unsupported_types`]: #structfield.deny_unsupported_types
+    pub fn deny_unsupported_types(mut self, enabled: bool) -> Self {
+        self.deny_unsupported_types = enabled;
+        self
+    }
+
+    /// Sets [`sort_keys`] option.
+    ///
+    /// [`sort_keys`]: #structfield.sort_keys
+    pub fn sort_keys(mut self, enabled: bool) -> Self {
+        self.sort_keys = enabled;
+        self
+    }
+}
 
 impl<'lua> Deserializer<'lua> {
-    /// Creates a new Lua Deserializer for the `Value`.
+    /// Creates a new Lua Deserializer for the `Value` with default options.
     pub fn new(value: Value<'lua>) -> Self {
-        Deserializer(value)
+        Self::new_with_options(value, Options::default())
+    }
+
+    /// Creates a new Lua Deserializer for the `Value` with custom options.
+    pub fn new_with_options(value: Value<'lua>, options: Options) -> Self {
+        Deserializer { value, options }
     }
 }
 
@@ -25,7 +109,7 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     where
         V: de::Visitor<'de>,
     {
-        match self.0 {
+        match self.value {
             Value::Nil => visitor.visit_unit(),
             Value::Boolean(b) => visitor.visit_bool(b),
             #[allow(clippy::useless_conversion)]
@@ -43,7 +127,16 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
             | Value::Thread(_)
             | Value::UserData(_)
             | Value::LightUserData(_)
-            | Value::Error(_) => Err(de::Error::custom("invalid value type")),
+            | Value::Error(_) => {
+                if self.options.deny_unsupported_types {
+                    Err(de::Error::custom(format!(
+                        "unsupported value type `{}`",
+                        self.value.type_name()
+                    )))
+                } else {
+                    visitor.visit_unit()
+                }
+            }
         }
     }
 
@@ -52,7 +145,7 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     where
         V: de::Visitor<'de>,
     {
-        match self.0 {
+        match self.value {
             Value::Nil => visitor.visit_none(),
             Value::LightUserData(ud) if ud.0.is_null() => visitor.visit_none(),
             _ => visitor.visit_some(self),
@@ -69,7 +162,7 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     where
         V: de::Visitor<'de>,
     {
-        let (variant, value) = match self.0 {
+        let (variant, value) = match self.value {
             Value::Table(value) => {
                 let mut iter = value.pairs::<StdString, Value>();
                 let (variant, value) = match iter.next() {
@@ -94,7 +187,11 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
             _ => return Err(de::Error::custom("bad enum value")),
         };
 
-        visitor.visit_enum(EnumDeserializer { variant, value })
+        visitor.visit_enum(EnumDeserializer {
+            variant,
+            value,
+            options: self.options,
+        })
     }
 
     #[inline]
@@ -102,12 +199,15 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     where
         V: de::Visitor<'de>,
     {
-        match self.0 {
+        match self.value {
             Value::Table(t) => {
                 let len = t.raw_len() as usize;
-                let mut deserializer = SeqDeserializer(t.raw_sequence_values());
+                let mut deserializer = SeqDeserializer {
+                    seq: t.raw_sequence_values(),
+                    options: self.options,
+                };
                 let seq = visitor.visit_seq(&mut deserializer)?;
-                if deserializer.0.count() == 0 {
+                if deserializer.remaining() == 0 {
                     Ok(seq)
                 } else {
                     Err(de::Error::invalid_length(
@@ -146,11 +246,11 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     where
         V: de::Visitor<'de>,
     {
-        match self.0 {
+        match self.value {
             Value::Table(t) => {
-                let mut deserializer = MapDeserializer::new(t.pairs());
+                let mut deserializer = MapDeserializer::new(t, self.options)?;
                 let map = visitor.visit_map(&mut deserializer)?;
-                let count = deserializer.pairs.count();
+                let count = deserializer.remaining();
                 if count == 0 {
                     Ok(map)
                 } else {
@@ -168,13 +268,38 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     fn deserialize_struct<V>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        self.deserialize_map(visitor)
+        match self.value {
+            Value::Table(ref t) if self.options.get_with_metamethods => {
+                let mut inherited = Vec::new();
+                for &field in fields {
+                    if t.raw_get::<_, Value>(field)? == Value::Nil {
+                        match t.get::<_, Value>(field)? {
+                            Value::Nil => {}
+                            value => inherited.push((field, value)),
+                        }
+                    }
+                }
+                let mut deserializer = MapDeserializer::new(t.clone(), self.options)?;
+                deserializer.inherited = inherited.into_iter();
+                let map = visitor.visit_map(&mut deserializer)?;
+                let count = deserializer.remaining();
+                if count == 0 {
+                    Ok(map)
+                } else {
+                    Err(de::Error::invalid_length(
+                        deserializer.processed + count,
+                        &"fewer elements in the table",
+                    ))
+                }
+            }
+            _ => self.deserialize_map(visitor),
+        }
     }
 
     serde::forward_to_deserialize_any! {
@@ -184,7 +309,23 @@ impl<'lua, 'de> serde::Deserializer<'de> for Deserializer<'lua> {
     }
 }
 
-struct SeqDeserializer<'lua>(TableSequence<'lua, Value<'lua>>);
+struct SeqDeserializer<'lua> {
+    seq: TableSequence<'lua, Value<'lua>>,
+    options: Options,
+}
+
+impl<'lua> SeqDeserializer<'lua> {
+    // Counts elements left after the visitor has finished, ignoring skipped ones
+    fn remaining(&mut self) -> usize {
+        let options = self.options;
+        (&mut self.seq)
+            .filter(|value| match value {
+                Ok(value) => !skip_value(value, options),
+                Err(_) => true,
+            })
+            .count()
+    }
+}
 
 impl<'lua, 'de> de::SeqAccess<'de> for SeqDeserializer<'lua> {
     type Error = Error;
@@ -193,33 +334,88 @@ impl<'lua, 'de> de::SeqAccess<'de> for SeqDeserializer<'lua> {
     where
         T: de::DeserializeSeed<'de>,
     {
-        match self.0.next() {
-            Some(value) => seed.deserialize(Deserializer(value?)).map(Some),
-            None => Ok(None),
+        loop {
+            match self.seq.next() {
+                Some(value) => {
+                    let value = value?;
+                    if skip_value(&value, self.options) {
+                        continue;
+                    }
+                    let de = Deserializer::new_with_options(value, self.options);
+                    return seed.deserialize(de).map(Some);
+                }
+                None => return Ok(None),
+            }
         }
     }
 
     fn size_hint(&self) -> Option<usize> {
-        match self.0.size_hint() {
+        match self.seq.size_hint() {
             (lower, Some(upper)) if lower == upper => Some(upper),
             _ => None,
         }
     }
 }
 
+enum MapPairs<'lua> {
+    Iter(TablePairs<'lua, Value<'lua>, Value<'lua>>),
+    Sorted(vec::IntoIter<(Value<'lua>, Value<'lua>)>),
+}
+
+impl<'lua> Iterator for MapPairs<'lua> {
+    type Item = Result<(Value<'lua>, Value<'lua>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            MapPairs::Iter(iter) => iter.next(),
+            MapPairs::Sorted(iter) => iter.next().map(Ok),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            MapPairs::Iter(iter) => iter.size_hint(),
+            MapPairs::Sorted(iter) => iter.size_hint(),
+        }
+    }
+}
+
 struct MapDeserializer<'lua> {
-    pairs: TablePairs<'lua, Value<'lua>, Value<'lua>>,
+    pairs: MapPairs<'lua>,
+    // Struct fields resolved through the `__index` chain
+    inherited: vec::IntoIter<(&'static str, Value<'lua>)>,
     value: Option<Value<'lua>>,
+    options: Options,
     processed: usize,
 }
 
 impl<'lua> MapDeserializer<'lua> {
-    fn new(pairs: TablePairs<'lua, Value<'lua>, Value<'lua>>) -> Self {
-        MapDeserializer {
+    fn new(table: Table<'lua>, options: Options) -> Result<Self> {
+        let pairs = if options.sort_keys {
+            let mut pairs = table.pairs().collect::<Result<Vec<_>>>()?;
+            pairs.sort_by(|(a, _), (b, _)| cmp_keys(a, b));
+            MapPairs::Sorted(pairs.into_iter())
+        } else {
+            MapPairs::Iter(table.pairs())
+        };
+        Ok(MapDeserializer {
             pairs,
+            inherited: Vec::new().into_iter(),
             value: None,
+            options,
             processed: 0,
-        }
+        })
+    }
+
+    // Counts pairs left after the visitor has finished, ignoring skipped ones
+    fn remaining(&mut self) -> usize {
+        let options = self.options;
+        (&mut self.pairs)
+            .filter(|item| match item {
+                Ok((key, value)) => !skip_value(key, options) && !skip_value(value, options),
+                Err(_) => true,
+            })
+            .count()
     }
 }
 
@@ -230,15 +426,26 @@ impl<'lua, 'de> de::MapAccess<'de> for MapDeserializer<'lua> {
     where
         T: de::DeserializeSeed<'de>,
     {
-        match self.pairs.next() {
-            Some(item) => {
-                let (key, value) = item?;
-                self.processed += 1;
-                self.value = Some(value);
-                let key_de = Deserializer(key);
-                seed.deserialize(key_de).map(Some)
+        loop {
+            match self.pairs.next() {
+                Some(item) => {
+                    let (key, value) = item?;
+                    if skip_value(&key, self.options) || skip_value(&value, self.options) {
+                        continue;
+                    }
+                    self.processed += 1;
+                    self.value = Some(value);
+                    let key_de = Deserializer::new_with_options(key, self.options);
+                    return seed.deserialize(key_de).map(Some);
+                }
+                None => match self.inherited.next() {
+                    Some((key, value)) => {
+                        self.value = Some(value);
+                        return seed.deserialize(key.into_deserializer()).map(Some);
+                    }
+                    None => return Ok(None),
+                },
             }
-            None => Ok(None),
         }
     }
 
@@ -247,7 +454,7 @@ impl<'lua, 'de> de::MapAccess<'de> for MapDeserializer<'lua> {
         T: de::DeserializeSeed<'de>,
     {
         match self.value.take() {
-            Some(value) => seed.deserialize(Deserializer(value)),
+            Some(value) => seed.deserialize(Deserializer::new_with_options(value, self.options)),
             None => Err(de::Error::custom("value is missing")),
         }
     }
@@ -263,6 +470,7 @@ impl<'lua, 'de> de::MapAccess<'de> for MapDeserializer<'lua> {
 struct EnumDeserializer<'lua> {
     variant: StdString,
     value: Option<Value<'lua>>,
+    options: Options,
 }
 
 impl<'lua, 'de> de::EnumAccess<'de> for EnumDeserializer<'lua> {
@@ -274,13 +482,17 @@ impl<'lua, 'de> de::EnumAccess<'de> for EnumDeserializer<'lua> {
         T: de::DeserializeSeed<'de>,
     {
         let variant = self.variant.into_deserializer();
-        let variant_access = VariantDeserializer { value: self.value };
+        let variant_access = VariantDeserializer {
+            value: self.value,
+            options: self.options,
+        };
         seed.deserialize(variant).map(|v| (v, variant_access))
     }
 }
 
 struct VariantDeserializer<'lua> {
     value: Option<Value<'lua>>,
+    options: Options,
 }
 
 impl<'lua, 'de> de::VariantAccess<'de> for VariantDeserializer<'lua> {
@@ -301,7 +513,7 @@ impl<'lua, 'de> de::VariantAccess<'de> for VariantDeserializer<'lua> {
         T: de::DeserializeSeed<'de>,
     {
         match self.value {
-            Some(value) => seed.deserialize(Deserializer(value)),
+            Some(value) => seed.deserialize(Deserializer::new_with_options(value, self.options)),
             None => Err(de::Error::invalid_type(
                 de::Unexpected::UnitVariant,
                 &"newtype variant",
@@ -314,7 +526,10 @@ impl<'lua, 'de> de::VariantAccess<'de> for VariantDeserializer<'lua> {
         V: de::Visitor<'de>,
     {
         match self.value {
-            Some(value) => serde::Deserializer::deserialize_seq(Deserializer(value), visitor),
+            Some(value) => {
+                let de = Deserializer::new_with_options(value, self.options);
+                serde::Deserializer::deserialize_seq(de, visitor)
+            }
             None => Err(de::Error::invalid_type(
                 de::Unexpected::UnitVariant,
                 &"tuple variant",
@@ -327,7 +542,10 @@ impl<'lua, 'de> de::VariantAccess<'de> for VariantDeserializer<'lua> {
         V: de::Visitor<'de>,
     {
         match self.value {
-            Some(value) => serde::Deserializer::deserialize_map(Deserializer(value), visitor),
+            Some(value) => {
+                let de = Deserializer::new_with_options(value, self.options);
+                serde::Deserializer::deserialize_map(de, visitor)
+            }
             None => Err(de::Error::invalid_type(
                 de::Unexpected::UnitVariant,
                 &"struct variant",
@@ -335,3 +553,46 @@ impl<'lua, 'de> de::VariantAccess<'de> for VariantDeserializer<'lua> {
         }
     }
 }
+
+// Returns true if the value has a type that cannot be deserialized and should be skipped
+fn skip_value(value: &Value, options: Options) -> bool {
+    if options.deny_unsupported_types {
+        return false;
+    }
+    match value {
+        Value::LightUserData(ud) => !ud.0.is_null(),
+        Value::Function(_) | Value::Thread(_) | Value::UserData(_) | Value::Error(_) => true,
+        _ => false,
+    }
+}
+
+// Orders table keys for the `sort_keys` option: booleans, numbers, strings, then everything else
+fn cmp_keys(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Boolean(_) => 0,
+            Value::Integer(_) | Value::Number(_) => 1,
+            Value::String(_) => 2,
+            _ => 3,
+        }
+    }
+
+    match (a, b) {
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Integer(_), Value::Number(_))
+        | (Value::Number(_), Value::Integer(_))
+        | (Value::Number(_), Value::Number(_)) => {
+            let to_number = |value: &Value| match *value {
+                Value::Integer(i) => i as Number,
+                Value::Number(n) => n,
+                _ => unreachable!(),
+            };
+            to_number(a)
+                .partial_cmp(&to_number(b))
+                .unwrap_or(Ordering::Equal)
+        }
+        (Value::String(a), Value::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
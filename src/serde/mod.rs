@@ -1,11 +1,14 @@
 //! (De)Serialization support using serde.
 
+use std::collections::{HashMap, HashSet};
 use std::os::raw::c_void;
 use std::ptr;
+use std::result::Result as StdResult;
 
+use serde::ser::{SerializeMap, SerializeSeq};
 use serde::{Deserialize, Serialize};
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::ffi;
 use crate::lua::Lua;
 use crate::table::Table;
@@ -13,6 +16,9 @@ use crate::types::LightUserData;
 use crate::util::{assert_stack, check_stack, protect_lua, StackGuard};
 use crate::value::Value;
 
+#[cfg(any(feature = "json", feature = "yaml"))]
+use std::cell::RefCell;
+
 /// Trait for serializing/deserializing Lua values using Serde.
 pub trait LuaSerdeExt<'lua> {
     /// A special value (lightuserdata) to encode/decode optional (none) values.
@@ -104,9 +110,14 @@ pub trait LuaSerdeExt<'lua> {
 
     /// Converts `T` into a `Value` instance with options.
     ///
+    /// Integers are converted to Lua integers if they fit into [`Integer`], regardless of
+    /// options. Integers out of this range (e.g. `u64` values above `i64::MAX`) are converted to
+    /// Lua floats and may lose precision. Lua 5.1 and LuaJIT store all numbers as floats.
+    ///
     /// Requires `feature = "serialize"`
     ///
     /// [`Value`]: enum.Value.html
+    /// [`Integer`]: type.Integer.html
     ///
     /// # Example
     ///
@@ -131,8 +142,14 @@ pub trait LuaSerdeExt<'lua> {
 
     /// Deserializes a `Value` into any serde deserializable object.
     ///
+    /// Tables are deserialized as maps, so serde attributes such as `#[serde(deny_unknown_fields)]`
+    /// apply as usual. This is useful for strict config validation: an unexpected key results in
+    /// a [`DeserializeError`] naming that key.
+    ///
     /// Requires `feature = "serialize"`
     ///
+    /// [`DeserializeError`]: enum.Error.html#variant.DeserializeError
+    ///
     /// [`Value`]: enum.Value.html
     ///
     /// # Example
@@ -158,6 +175,77 @@ pub trait LuaSerdeExt<'lua> {
     /// }
     /// ```
     fn from_value<T: Deserialize<'lua>>(&'lua self, value: Value<'lua>) -> Result<T>;
+
+    /// Deserializes a `Value` into any serde deserializable object with options.
+    ///
+    /// Requires `feature = "serialize"`
+    ///
+    /// [`Value`]: enum.Value.html
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mlua::{Lua, Result, LuaSerdeExt, DeserializeOptions};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize, Debug, PartialEq)]
+    /// struct User {
+    ///     name: String,
+    ///     age: u8,
+    /// }
+    ///
+    /// fn main() -> Result<()> {
+    ///     let lua = Lua::new();
+    ///     let val = lua.load(r#"
+    ///         setmetatable({name = "John Smith"}, {__index = {age = 20}})
+    ///     "#).eval()?;
+    ///     let options = DeserializeOptions::new().get_with_metamethods(true);
+    ///     let u: User = lua.from_value_with(val, options)?;
+    ///
+    ///     assert_eq!(u, User { name: "John Smith".into(), age: 20 });
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    fn from_value_with<T: Deserialize<'lua>>(
+        &'lua self,
+        value: Value<'lua>,
+        options: de::Options,
+    ) -> Result<T>;
+
+    /// Rebuilds a value serialized with [`SerializeRefs`], linking the `{"__ref": id}` markers
+    /// back to the referenced tables.
+    ///
+    /// `value` is the deserialized form of the `{"root": ..., "refs": [...]}` table written by
+    /// [`SerializeRefs`], for example obtained with [`to_value`]. The tables of `value` are
+    /// reused, so the markers are replaced in place.
+    ///
+    /// Requires `feature = "serialize"`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mlua::{Lua, LuaSerdeExt, Table};
+    /// use mlua::serde::SerializeRefs;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let lua = Lua::new();
+    ///     let node = lua.load("local t = {} t.me = t return t").eval()?;
+    ///
+    ///     let json = serde_json::to_string(&SerializeRefs::new(node)?)?;
+    ///     assert_eq!(json, r#"{"root":{"__ref":1},"refs":[{"me":{"__ref":1}}]}"#);
+    ///
+    ///     let value = lua.to_value(&serde_json::from_str::<serde_json::Value>(&json)?)?;
+    ///     let node: Table = lua.unpack(lua.resolve_refs(value)?)?;
+    ///     assert!(node.get::<_, Table>("me")? == node);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// [`SerializeRefs`]: struct.SerializeRefs.html
+    /// [`to_value`]: #tymethod.to_value
+    fn resolve_refs(&'lua self, value: Value<'lua>) -> Result<Value<'lua>>;
 }
 
 impl<'lua> LuaSerdeExt<'lua> for Lua {
@@ -196,6 +284,324 @@ impl<'lua> LuaSerdeExt<'lua> for Lua {
     {
         T::deserialize(de::Deserializer::new(value))
     }
+
+    fn from_value_with<T>(&'lua self, value: Value<'lua>, options: de::Options) -> Result<T>
+    where
+        T: Deserialize<'lua>,
+    {
+        T::deserialize(de::Deserializer::new_with_options(value, options))
+    }
+
+    fn resolve_refs(&'lua self, value: Value<'lua>) -> Result<Value<'lua>> {
+        let graph = match value {
+            Value::Table(graph) => graph,
+            _ => {
+                return Err(Error::DeserializeError(
+                    "expected a table with `root` and `refs` fields".to_string(),
+                ))
+            }
+        };
+
+        let refs = match graph.raw_get::<_, Option<Table>>("refs")? {
+            Some(refs) => refs.raw_sequence_values().collect::<Result<Vec<Table>>>()?,
+            None => Vec::new(),
+        };
+        for table in &refs {
+            link_refs(self, table, &refs)?;
+        }
+
+        match graph.raw_get("root")? {
+            Value::Table(root) => match ref_target(self, &root, &refs)? {
+                Some(target) => Ok(Value::Table(target)),
+                None => {
+                    link_refs(self, &root, &refs)?;
+                    Ok(Value::Table(root))
+                }
+            },
+            root => Ok(root),
+        }
+    }
+}
+
+/// Serializes a Lua value that may contain shared or cyclic tables.
+///
+/// Tables reached more than once are serialized only once in a top-level `refs` list and replaced
+/// everywhere else with a `{"__ref": id}` marker, where `id` is the position in `refs` starting
+/// from 1. The output has the shape `{"root": value, "refs": [table, ...]}`, so a self-referential
+/// table serializes without error. Use [`LuaSerdeExt::resolve_refs`] to reconstruct the graph.
+///
+/// Requires `feature = "serialize"`
+///
+/// [`LuaSerdeExt::resolve_refs`]: trait.LuaSerdeExt.html#tymethod.resolve_refs
+pub struct SerializeRefs<'lua> {
+    root: Value<'lua>,
+    refs: Vec<Value<'lua>>,
+    ids: HashMap<*const c_void, usize>,
+}
+
+impl<'lua> SerializeRefs<'lua> {
+    /// Prepares `value` for serialization, finding the tables that are referenced more than once.
+    pub fn new(value: Value<'lua>) -> Result<Self> {
+        let mut refs = SerializeRefs {
+            root: Value::Nil,
+            refs: Vec::new(),
+            ids: HashMap::new(),
+        };
+        refs.find_refs(&value, &mut HashSet::new())?;
+        refs.root = value;
+        Ok(refs)
+    }
+
+    fn find_refs(&mut self, value: &Value<'lua>, seen: &mut HashSet<*const c_void>) -> Result<()> {
+        let table = match value {
+            Value::Table(table) => table,
+            _ => return Ok(()),
+        };
+
+        let ptr = table.to_pointer();
+        if !seen.insert(ptr) {
+            if !self.ids.contains_key(&ptr) {
+                self.refs.push(value.clone());
+                self.ids.insert(ptr, self.refs.len());
+            }
+            return Ok(());
+        }
+
+        for kv in table.clone().pairs::<Value, Value>() {
+            let (k, v) = kv?;
+            self.find_refs(&k, seen)?;
+            self.find_refs(&v, seen)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'lua> Serialize for SerializeRefs<'lua> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let refs = self
+            .refs
+            .iter()
+            .map(|value| RefsValue::new(value, self, true))
+            .collect::<Vec<_>>();
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("root", &RefsValue::new(&self.root, self, false))?;
+        map.serialize_entry("refs", &refs)?;
+        map.end()
+    }
+}
+
+// A value serialized as part of `SerializeRefs`. Tables with an id are written as markers, unless
+// `expand` is set for the top-level entry of `refs`.
+struct RefsValue<'a, 'lua> {
+    value: &'a Value<'lua>,
+    graph: &'a SerializeRefs<'lua>,
+    expand: bool,
+}
+
+impl<'a, 'lua> RefsValue<'a, 'lua> {
+    fn new(value: &'a Value<'lua>, graph: &'a SerializeRefs<'lua>, expand: bool) -> Self {
+        RefsValue {
+            value,
+            graph,
+            expand,
+        }
+    }
+}
+
+impl<'a, 'lua> Serialize for RefsValue<'a, 'lua> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let table = match self.value {
+            Value::Table(table) => table,
+            value => return value.serialize(serializer),
+        };
+
+        if !self.expand {
+            if let Some(id) = self.graph.ids.get(&table.to_pointer()) {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("__ref", id)?;
+                return map.end();
+            }
+        }
+
+        let len = table.raw_len() as usize;
+        if len > 0 || table.is_array() {
+            let mut seq = serializer.serialize_seq(Some(len))?;
+            for v in table.clone().raw_sequence_values_by_len::<Value>(None) {
+                let v = v.map_err(serde::ser::Error::custom)?;
+                seq.serialize_element(&RefsValue::new(&v, self.graph, false))?;
+            }
+            return seq.end();
+        }
+
+        let mut map = serializer.serialize_map(None)?;
+        for kv in table.clone().pairs::<Value, Value>() {
+            let (k, v) = kv.map_err(serde::ser::Error::custom)?;
+            map.serialize_entry(
+                &RefsValue::new(&k, self.graph, false),
+                &RefsValue::new(&v, self.graph, false),
+            )?;
+        }
+        map.end()
+    }
+}
+
+// Returns the referenced table if `table` is a `{"__ref": id}` marker.
+fn ref_target<'lua>(
+    lua: &'lua Lua,
+    table: &Table<'lua>,
+    refs: &[Table<'lua>],
+) -> Result<Option<Table<'lua>>> {
+    let id = match table.raw_get::<_, Value>("__ref")? {
+        Value::Nil => return Ok(None),
+        id => id,
+    };
+    if table.clone().pairs::<Value, Value>().nth(1).is_some() {
+        return Ok(None);
+    }
+
+    let id = lua.unpack::<usize>(id)?;
+    match id.checked_sub(1).and_then(|i| refs.get(i)) {
+        Some(target) => Ok(Some(target.clone())),
+        None => Err(Error::DeserializeError(format!(
+            "invalid reference id {}",
+            id
+        ))),
+    }
+}
+
+// Replaces the markers reachable from `table` with the referenced tables, in place.
+fn link_refs<'lua>(lua: &'lua Lua, table: &Table<'lua>, refs: &[Table<'lua>]) -> Result<()> {
+    let resolve = |value: Value<'lua>| -> Result<(Value<'lua>, bool)> {
+        match value {
+            Value::Table(t) => match ref_target(lua, &t, refs)? {
+                Some(target) => Ok((Value::Table(target), true)),
+                None => {
+                    link_refs(lua, &t, refs)?;
+                    Ok((Value::Table(t), false))
+                }
+            },
+            value => Ok((value, false)),
+        }
+    };
+
+    let pairs = table
+        .clone()
+        .pairs::<Value, Value>()
+        .collect::<Result<Vec<_>>>()?;
+    for (k, v) in pairs {
+        let (new_k, k_changed) = resolve(k.clone())?;
+        let (new_v, v_changed) = resolve(v)?;
+        if k_changed {
+            table.raw_set(k, Value::Nil)?;
+        }
+        if k_changed || v_changed {
+            table.raw_set(new_k, new_v)?;
+        }
+    }
+    Ok(())
+}
+
+/// Output format used by [`Lua::serialize_value_to_writer`].
+///
+/// Requires `feature = "json"` or `feature = "yaml"`
+///
+/// [`Lua::serialize_value_to_writer`]: ../struct.Lua.html#method.serialize_value_to_writer
+#[cfg(any(feature = "json", feature = "yaml"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "json", feature = "yaml"))))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SerializeFormat {
+    /// Compact JSON.
+    ///
+    /// Requires `feature = "json"`
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    Json,
+    /// Pretty-printed JSON.
+    ///
+    /// Requires `feature = "json"`
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    JsonPretty,
+    /// YAML.
+    ///
+    /// Requires `feature = "yaml"`
+    #[cfg(feature = "yaml")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "yaml")))]
+    Yaml,
+}
+
+/// Serializes a Lua value, failing on recursive tables instead of overflowing the stack.
+///
+/// Only tables on the path from the root are tracked, so a table referenced several times
+/// without forming a cycle is serialized every time it is reached.
+#[cfg(any(feature = "json", feature = "yaml"))]
+pub(crate) struct SerializeChecked<'a, 'lua> {
+    value: &'a Value<'lua>,
+    visited: &'a RefCell<HashSet<*const c_void>>,
+}
+
+#[cfg(any(feature = "json", feature = "yaml"))]
+impl<'a, 'lua> SerializeChecked<'a, 'lua> {
+    pub(crate) fn new(
+        value: &'a Value<'lua>,
+        visited: &'a RefCell<HashSet<*const c_void>>,
+    ) -> Self {
+        SerializeChecked { value, visited }
+    }
+
+    fn nested(&self, value: &'a Value<'lua>) -> Self {
+        SerializeChecked::new(value, self.visited)
+    }
+}
+
+#[cfg(any(feature = "json", feature = "yaml"))]
+impl<'a, 'lua> Serialize for SerializeChecked<'a, 'lua> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let table = match self.value {
+            Value::Table(table) => table,
+            value => return value.serialize(serializer),
+        };
+
+        let ptr = table.to_pointer();
+        if !self.visited.borrow_mut().insert(ptr) {
+            return Err(serde::ser::Error::custom("recursive table detected"));
+        }
+
+        let len = table.raw_len() as usize;
+        let res = if len > 0 || table.is_array() {
+            (|| {
+                let mut seq = serializer.serialize_seq(Some(len))?;
+                for v in table.clone().raw_sequence_values_by_len::<Value>(None) {
+                    let v = v.map_err(serde::ser::Error::custom)?;
+                    seq.serialize_element(&self.nested(&v))?;
+                }
+                seq.end()
+            })()
+        } else {
+            (|| {
+                let mut map = serializer.serialize_map(None)?;
+                for kv in table.clone().pairs::<Value, Value>() {
+                    let (k, v) = kv.map_err(serde::ser::Error::custom)?;
+                    map.serialize_entry(&self.nested(&k), &self.nested(&v))?;
+                }
+                map.end()
+            })()
+        };
+
+        self.visited.borrow_mut().remove(&ptr);
+        res
+    }
 }
 
 // Uses 6 stack spaces and calls checkstack.
@@ -12,6 +12,9 @@ use crate::types::Integer;
 use crate::util::{check_stack, protect_lua, StackGuard};
 use crate::value::{ToLua, Value};
 
+// Struct name used by `serde_json` for numbers with the `arbitrary_precision` feature
+const SERDE_JSON_NUMBER_TOKEN: &str = "$serde_json::private::Number";
+
 /// A struct for serializing Rust values into Lua values.
 #[derive(Debug)]
 pub struct Serializer<'lua> {
@@ -48,6 +51,17 @@ pub struct Options {
     /// [`null`]: ../trait.LuaSerdeExt.html#tymethod.null
     /// [`Nil`]: ../../enum.Value.html#variant.Nil
     pub serialize_unit_to_null: bool,
+
+    /// If true, detect `serde_json::Number` values serialized with the `arbitrary_precision`
+    /// feature of `serde_json` and convert them to Lua numbers instead of tables.
+    ///
+    /// Such a number is converted to a Lua integer if it is integral and fits into [`Integer`],
+    /// otherwise to a Lua float (possibly losing precision).
+    ///
+    /// Default: **false**
+    ///
+    /// [`Integer`]: ../../type.Integer.html
+    pub detect_serde_json_arbitrary_precision: bool,
 }
 
 impl Default for Options {
@@ -56,12 +70,13 @@ impl Default for Options {
             set_array_metatable: true,
             serialize_none_to_null: true,
             serialize_unit_to_null: true,
+            detect_serde_json_arbitrary_precision: false,
         }
     }
 }
 
 impl Options {
-    /// Retruns a new instance of `Options` with default parameters.
+    /// Returns a new instance of `Options` with default parameters.
     pub fn new() -> Self {
         Self::default()
     }
@@ -89,6 +104,14 @@ impl Options {
         self.serialize_unit_to_null = enabled;
         self
     }
+
+    /// Sets [`detect_serde_json_arbitrary_precision`] option.
+    ///
+    /// [`detect_serde_json_arbitrary_precision`]: #structfield.detect_serde_json_arbitrary_precision
+    pub fn detect_serde_json_arbitrary_precision(mut self, enabled: bool) -> Self {
+        self.detect_serde_json_arbitrary_precision = enabled;
+        self
+    }
 }
 
 impl<'lua> Serializer<'lua> {
@@ -123,7 +146,7 @@ impl<'lua> ser::Serializer for Serializer<'lua> {
     type SerializeTupleStruct = SerializeVec<'lua>;
     type SerializeTupleVariant = SerializeTupleVariant<'lua>;
     type SerializeMap = SerializeMap<'lua>;
-    type SerializeStruct = SerializeMap<'lua>;
+    type SerializeStruct = SerializeStruct<'lua>;
     type SerializeStructVariant = SerializeStructVariant<'lua>;
 
     #[inline]
@@ -280,8 +303,17 @@ impl<'lua> ser::Serializer for Serializer<'lua> {
     }
 
     #[inline]
-    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
-        self.serialize_map(Some(len))
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        if self.options.detect_serde_json_arbitrary_precision
+            && name == SERDE_JSON_NUMBER_TOKEN
+            && len == 1
+        {
+            return Ok(SerializeStruct::Number {
+                lua: self.lua,
+                value: None,
+            });
+        }
+        Ok(SerializeStruct::Map(self.serialize_map(Some(len))?))
     }
 
     #[inline]
@@ -433,7 +465,17 @@ impl<'lua> ser::SerializeMap for SerializeMap<'lua> {
     }
 }
 
-impl<'lua> ser::SerializeStruct for SerializeMap<'lua> {
+#[doc(hidden)]
+pub enum SerializeStruct<'lua> {
+    Map(SerializeMap<'lua>),
+    // `serde_json::Number` serialized with the `arbitrary_precision` feature
+    Number {
+        lua: &'lua Lua,
+        value: Option<Value<'lua>>,
+    },
+}
+
+impl<'lua> ser::SerializeStruct for SerializeStruct<'lua> {
     type Ok = Value<'lua>;
     type Error = Error;
 
@@ -441,12 +483,37 @@ impl<'lua> ser::SerializeStruct for SerializeMap<'lua> {
     where
         T: Serialize + ?Sized,
     {
-        ser::SerializeMap::serialize_key(self, key)?;
-        ser::SerializeMap::serialize_value(self, value)
+        match self {
+            SerializeStruct::Map(map) => {
+                ser::SerializeMap::serialize_key(map, key)?;
+                ser::SerializeMap::serialize_value(map, value)
+            }
+            SerializeStruct::Number { lua, value: number } => {
+                *number = Some(lua.to_value(value)?);
+                Ok(())
+            }
+        }
     }
 
     fn end(self) -> Result<Value<'lua>> {
-        ser::SerializeMap::end(self)
+        match self {
+            SerializeStruct::Map(map) => ser::SerializeMap::end(map),
+            SerializeStruct::Number {
+                value: Some(Value::String(s)),
+                ..
+            } => {
+                let s = s.to_str()?;
+                if !s.contains(&['.', 'e', 'E'][..]) {
+                    if let Ok(i) = s.parse() {
+                        return Ok(Value::Integer(i));
+                    }
+                }
+                s.parse()
+                    .map(Value::Number)
+                    .map_err(|_| ser::Error::custom(format!("invalid number '{}'", s)))
+            }
+            SerializeStruct::Number { .. } => Err(ser::Error::custom("invalid number")),
+        }
     }
 }
 
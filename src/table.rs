@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::os::raw::{c_int, c_void};
 
 #[cfg(feature = "serialize")]
 use {
@@ -112,6 +114,70 @@ impl<'lua> Table<'lua> {
         V::from_lua(value, lua)
     }
 
+    /// Gets the values associated to several keys in one batch.
+    ///
+    /// The table and all keys are pushed once and looked up in a single protected call, which is
+    /// cheaper than calling [`get`] for each key. Keys without a value yield `None`.
+    ///
+    /// This might invoke the `__index` metamethod, like [`get`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let config: Table = lua.load(r#"{host = "localhost", port = 8080}"#).eval()?;
+    ///
+    /// let values = config.get_many::<_, String>(&["host", "port", "user"])?;
+    /// assert_eq!(values, [Some("localhost".into()), Some("8080".into()), None]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    pub fn get_many<K: ToLua<'lua> + Clone, V: FromLua<'lua>>(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<Option<V>>> {
+        let lua = self.0.lua;
+        let keys = keys
+            .iter()
+            .map(|key| key.clone().to_lua(lua))
+            .collect::<Result<Vec<_>>>()?;
+        let nkeys = keys.len() as c_int;
+
+        let mut values = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, nkeys + 5)?;
+
+            lua.push_ref(&self.0);
+            for key in keys {
+                lua.push_value(key)?;
+            }
+            protect_lua(lua.state, nkeys + 1, nkeys, |state| {
+                for i in 2..=nkeys + 1 {
+                    ffi::lua_pushvalue(state, i);
+                    ffi::lua_gettable(state, 1);
+                    ffi::lua_replace(state, i);
+                }
+                ffi::lua_remove(state, 1);
+            })?;
+
+            let mut values = Vec::with_capacity(nkeys as usize);
+            for _ in 0..nkeys {
+                values.push(lua.pop_value());
+            }
+            values
+        };
+
+        values.reverse();
+        values
+            .into_iter()
+            .map(|value| Option::<V>::from_lua(value, lua))
+            .collect()
+    }
+
     /// Checks whether the table contains a non-nil value for `key`.
     pub fn contains_key<K: ToLua<'lua>>(&self, key: K) -> Result<bool> {
         let lua = self.0.lua;
@@ -220,13 +286,57 @@ impl<'lua> Table<'lua> {
         V::from_lua(value, lua)
     }
 
+    /// Gets the value associated to `key`, or inserts and returns the value produced by `f` if
+    /// there is none (the value is `nil`).
+    ///
+    /// Reading and inserting use raw access, so `__index` and `__newindex` metamethods are never
+    /// invoked, and `f` is called only when the key is absent. This makes it convenient for
+    /// nested configuration tables or memoization tables managed from Rust.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let config = lua.create_table()?;
+    ///
+    /// let network: Table = config.get_or_insert_with("network", || lua.create_table().unwrap())?;
+    /// network.set("port", 8080)?;
+    ///
+    /// let network: Table = config.get_or_insert_with("network", || unreachable!())?;
+    /// assert_eq!(network.get::<_, u16>("port")?, 8080);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_or_insert_with<K, V, F>(&self, key: K, f: F) -> Result<V>
+    where
+        K: ToLua<'lua>,
+        V: FromLua<'lua> + ToLua<'lua>,
+        F: FnOnce() -> V,
+    {
+        let lua = self.0.lua;
+        let key = key.to_lua(lua)?;
+
+        let value: Value = self.raw_get(key.clone())?;
+        if !matches!(value, Value::Nil) {
+            return V::from_lua(value, lua);
+        }
+        let value = f().to_lua(lua)?;
+        self.raw_set(key, value.clone())?;
+        V::from_lua(value, lua)
+    }
+
     /// Inserts element value at position `idx` to the table, shifting up the elements from `table[idx]`.
     /// The worst case complexity is O(n), where n is the table length.
+    ///
+    /// Works like the Lua `table.insert` function, but does not invoke metamethods.
+    /// Returns a `RuntimeError` if `idx` is not in the range `1..=n+1`.
     pub fn raw_insert<V: ToLua<'lua>>(&self, idx: Integer, value: V) -> Result<()> {
         let lua = self.0.lua;
         let size = self.raw_len();
         if idx < 1 || idx > size + 1 {
-            return Err(Error::RuntimeError("index out of bounds".to_string()));
+            return Err(Error::RuntimeError("position out of bounds".to_string()));
         }
 
         let value = value.to_lua(lua)?;
@@ -247,38 +357,49 @@ impl<'lua> Table<'lua> {
         }
     }
 
-    /// Removes a key from the table.
+    /// Removes a key from the table, returning the removed value.
     ///
     /// If `key` is an integer, mlua shifts down the elements from `table[key+1]`,
     /// and erases element `table[key]`. The complexity is O(n) in the worst case,
-    /// where n is the table length.
+    /// where n is the table length. This works like the Lua `table.remove` function, but does
+    /// not invoke metamethods: `key` must be in the range `1..=n+1` (or `0` for an empty table),
+    /// otherwise a `RuntimeError` is returned.
     ///
     /// For other key types this is equivalent to setting `table[key] = nil`.
-    pub fn raw_remove<K: ToLua<'lua>>(&self, key: K) -> Result<()> {
+    pub fn raw_remove<K: ToLua<'lua>>(&self, key: K) -> Result<Value<'lua>> {
         let lua = self.0.lua;
         let key = key.to_lua(lua)?;
         match key {
             Value::Integer(idx) => {
                 let size = self.raw_len();
-                if idx < 1 || idx > size {
-                    return Err(Error::RuntimeError("index out of bounds".to_string()));
+                if idx != size && (idx < 1 || idx > size + 1) {
+                    return Err(Error::RuntimeError("position out of bounds".to_string()));
                 }
                 unsafe {
                     let _sg = StackGuard::new(lua.state);
-                    check_stack(lua.state, 4)?;
+                    check_stack(lua.state, 5)?;
 
                     lua.push_ref(&self.0);
-                    protect_lua(lua.state, 1, 0, |state| {
-                        for i in idx..size {
-                            ffi::lua_rawgeti(state, -1, i + 1);
-                            ffi::lua_rawseti(state, -2, i);
+                    protect_lua(lua.state, 1, 1, |state| {
+                        ffi::lua_rawgeti(state, -1, idx);
+                        let mut i = idx;
+                        while i < size {
+                            // table[i] = table[i+1]
+                            ffi::lua_rawgeti(state, -2, i + 1);
+                            ffi::lua_rawseti(state, -3, i);
+                            i += 1;
                         }
                         ffi::lua_pushnil(state);
-                        ffi::lua_rawseti(state, -2, size);
-                    })
+                        ffi::lua_rawseti(state, -3, i);
+                    })?;
+                    Ok(lua.pop_value())
                 }
             }
-            _ => self.raw_set(key, Nil),
+            _ => {
+                let value = self.raw_get(key.clone())?;
+                self.raw_set(key, Nil)?;
+                Ok(value)
+            }
         }
     }
 
@@ -348,6 +469,228 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    /// Makes this table readonly for Lua code, or makes it writable again.
+    ///
+    /// A readonly table has a `__newindex` metamethod raising a runtime error
+    /// `attempt to modify readonly table`. The metatable is created if the table has none,
+    /// otherwise the `__newindex` field is added to the existing one. Making the table writable
+    /// again removes only the fields added by this method, and the created metatable once it is
+    /// left empty.
+    ///
+    /// Lua only invokes `__newindex` when assigning to a key that is not present in the table, so
+    /// this prevents adding new fields but not changing existing ones. Keep the data in another
+    /// table exposed through `__index` if existing fields must be protected too. Raw accesses
+    /// (including [`raw_set`]) are never restricted, and nested tables are not affected.
+    ///
+    /// Returns an error if the metatable already has a `__newindex` field that was not set by
+    /// this method.
+    ///
+    /// [`raw_set`]: #method.raw_set
+    pub fn set_readonly(&self, enabled: bool) -> Result<()> {
+        let lua = self.0.lua;
+        let metatable = self.get_metatable();
+
+        if !enabled {
+            if let Some(metatable) = metatable {
+                match metatable.raw_get::<_, Value>(READONLY_METATABLE_KEY)? {
+                    Value::Nil => {}
+                    created => {
+                        metatable.raw_set("__newindex", Nil)?;
+                        metatable.raw_set(READONLY_METATABLE_KEY, Nil)?;
+                        // Only drop a metatable created by this method, and only if nothing
+                        // else has been added to it since
+                        if created == Value::Boolean(true)
+                            && metatable.clone().pairs::<Value, Value>().next().is_none()
+                        {
+                            self.set_metatable(None);
+                        }
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        let (metatable, created) = match metatable {
+            Some(metatable) => {
+                if metatable.raw_get::<_, Value>(READONLY_METATABLE_KEY)? != Nil {
+                    return Ok(());
+                }
+                if metatable.raw_get::<_, Value>("__newindex")? != Nil {
+                    return Err(Error::RuntimeError(
+                        "table metatable already has __newindex".to_string(),
+                    ));
+                }
+                (metatable, false)
+            }
+            None => (lua.create_table()?, true),
+        };
+
+        let newindex = lua.create_function(|_, ()| -> Result<()> {
+            Err(Error::RuntimeError(
+                "attempt to modify readonly table".to_string(),
+            ))
+        })?;
+        metatable.raw_set("__newindex", newindex)?;
+        metatable.raw_set(READONLY_METATABLE_KEY, created)?;
+        self.set_metatable(Some(metatable));
+        Ok(())
+    }
+
+    /// Starts or stops reporting accesses to this table to the callback set by
+    /// [`Lua::trace_table_access`].
+    ///
+    /// Tracing moves the contents of the table into a hidden table and installs a metatable
+    /// whose `__index` and `__newindex` metamethods forward every read and write to it, so that
+    /// each access can be reported. `__len` and `__pairs` are forwarded as well, which makes `#`
+    /// and `pairs` work as usual in Lua 5.2+ (or in Lua 5.1 with [`Lua::enable_pairs_metamethod`]).
+    /// Raw accesses bypass the metatable and therefore see an empty table while tracing is
+    /// enabled: [`raw_get`] returns `nil`, [`raw_len`] returns 0 and [`pairs`] yields nothing.
+    /// Values stored with [`raw_set`] are not visible to Lua code. Disabling tracing moves the
+    /// contents back into the table, overwriting such raw values, and removes the metatable.
+    ///
+    /// Without a callback set, traced tables keep working and only pay for the forwarding.
+    ///
+    /// Returns an error if the table already has a metatable.
+    ///
+    /// [`Lua::trace_table_access`]: struct.Lua.html#method.trace_table_access
+    /// [`Lua::enable_pairs_metamethod`]: struct.Lua.html#method.enable_pairs_metamethod
+    /// [`raw_get`]: #method.raw_get
+    /// [`raw_len`]: #method.raw_len
+    /// [`pairs`]: #method.pairs
+    /// [`raw_set`]: #method.raw_set
+    pub fn set_traced(&self, enabled: bool) -> Result<()> {
+        let lua = self.0.lua;
+        let shadow = match self.get_metatable() {
+            Some(metatable) => match metatable.raw_get::<_, Value>(TRACED_METATABLE_KEY)? {
+                Value::Table(shadow) => Some(shadow),
+                _ => {
+                    return Err(Error::RuntimeError(
+                        "table already has a metatable".to_string(),
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        if !enabled {
+            if let Some(shadow) = shadow {
+                self.set_metatable(None);
+                for pair in shadow.pairs::<Value, Value>() {
+                    let (k, v) = pair?;
+                    self.raw_set(k, v)?;
+                }
+            }
+            return Ok(());
+        }
+        if shadow.is_some() {
+            return Ok(());
+        }
+
+        // Keys can't be removed while traversing the table, so collect them first
+        let shadow = lua.create_table()?;
+        let mut keys = Vec::new();
+        for pair in self.clone().pairs::<Value, Value>() {
+            let (k, v) = pair?;
+            shadow.raw_set(k.clone(), v)?;
+            keys.push(k);
+        }
+        for k in keys {
+            self.raw_set(k, Nil)?;
+        }
+
+        let index = lua.create_function(|lua, (shadow, table, key): (Table, Table, Value)| {
+            let value = shadow.raw_get::<_, Value>(key.clone())?;
+            let access = TableAccess {
+                kind: TableAccessKind::Read,
+                table,
+                key,
+                value: value.clone(),
+            };
+            lua.report_table_access(access)?;
+            Ok(value)
+        })?;
+        let newindex = lua.create_function(
+            |lua, (shadow, table, key, value): (Table, Table, Value, Value)| {
+                shadow.raw_set(key.clone(), value.clone())?;
+                let access = TableAccess {
+                    kind: TableAccessKind::Write,
+                    table,
+                    key,
+                    value,
+                };
+                lua.report_table_access(access)
+            },
+        )?;
+
+        let metatable = lua.create_table()?;
+        metatable.raw_set("__index", index.bind(shadow.clone())?)?;
+        metatable.raw_set("__newindex", newindex.bind(shadow.clone())?)?;
+        forward_len_and_pairs(&metatable, &shadow)?;
+        metatable.raw_set(TRACED_METATABLE_KEY, shadow)?;
+        self.set_metatable(Some(metatable));
+        Ok(())
+    }
+
+    // Creates a frozen deep copy of the table, see `Value::freeze`.
+    //
+    // `frozen` maps already copied tables to their frozen copies, so shared and cyclic
+    // references are preserved.
+    pub(crate) fn freeze(
+        &self,
+        frozen: &mut HashMap<*const c_void, Table<'lua>>,
+    ) -> Result<Table<'lua>> {
+        if self.is_frozen() {
+            return Ok(self.clone());
+        }
+        if let Some(copy) = frozen.get(&self.to_pointer()) {
+            return Ok(copy.clone());
+        }
+
+        let lua = self.0.lua;
+        let proxy = lua.create_table()?;
+        frozen.insert(self.to_pointer(), proxy.clone());
+
+        // Keys are kept as is, so that tables used as keys keep their identity
+        let data = lua.create_table()?;
+        for pair in self.clone().pairs::<Value, Value>() {
+            let (k, v) = pair?;
+            data.raw_set(k, v.freeze_with(frozen)?)?;
+        }
+
+        // The proxy stays empty, so its readonly `__newindex` catches every assignment
+        proxy.set_readonly(true)?;
+        let metatable = mlua_expect!(proxy.get_metatable(), "readonly table without metatable");
+        metatable.raw_set("__index", data.clone())?;
+        forward_len_and_pairs(&metatable, &data)?;
+        // Hides the metatable from Lua code, so it can't be replaced
+        metatable.raw_set("__metatable", false)?;
+        metatable.raw_set(FROZEN_METATABLE_KEY, true)?;
+        Ok(proxy)
+    }
+
+    fn is_frozen(&self) -> bool {
+        match self.get_metatable() {
+            Some(metatable) => metatable
+                .raw_get::<_, Value>(FROZEN_METATABLE_KEY)
+                .map(|v| v != Nil)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the table was made readonly using [`set_readonly`].
+    ///
+    /// [`set_readonly`]: #method.set_readonly
+    pub fn is_readonly(&self) -> bool {
+        match self.get_metatable() {
+            Some(metatable) => metatable
+                .raw_get::<_, Value>(READONLY_METATABLE_KEY)
+                .map(|v| v != Nil)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
     /// Consume this table and return an iterator over the pairs of the table.
     ///
     /// This works like the Lua `pairs` function, but does not invoke the `__pairs` metamethod.
@@ -442,7 +785,12 @@ impl<'lua> Table<'lua> {
 
     /// Consume this table and return an iterator over all values in the sequence part of the table.
     ///
-    /// Unlike the `sequence_values`, does not invoke `__index` metamethod when iterating.
+    /// Unlike the `sequence_values`, does not invoke `__index` metamethod when iterating. Values
+    /// are read with raw accesses starting from `t[1]` until the first `nil` is found.
+    ///
+    /// The iterator holds its own reference to the table, so the table stays alive for as long as
+    /// the iterator does. Call this method on a clone of the handle to keep using the table
+    /// afterwards.
     ///
     /// [`sequence_values`]: #method.sequence_values
     pub fn raw_sequence_values<V: FromLua<'lua>>(self) -> TableSequence<'lua, V> {
@@ -485,6 +833,17 @@ impl<'lua> Table<'lua> {
             ffi::lua_rawequal(lua.state, -1, -2) != 0
         }
     }
+
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+
+            lua.push_ref(&self.0);
+            ffi::lua_topointer(lua.state, -1)
+        }
+    }
 }
 
 impl<'lua> PartialEq for Table<'lua> {
@@ -642,6 +1001,70 @@ impl<'lua> Serialize for Table<'lua> {
     }
 }
 
+// Sets `__len` and `__pairs` metamethods forwarding to the `shadow` table, for proxy tables that
+// keep their contents elsewhere.
+fn forward_len_and_pairs<'lua>(metatable: &Table<'lua>, shadow: &Table<'lua>) -> Result<()> {
+    unsafe extern "C" fn shadow_next(state: *mut ffi::lua_State) -> c_int {
+        ffi::luaL_checktype(state, 1, ffi::LUA_TTABLE);
+        ffi::lua_settop(state, 2);
+        if ffi::lua_next(state, 1) != 0 {
+            2
+        } else {
+            ffi::lua_pushnil(state);
+            1
+        }
+    }
+
+    let lua = metatable.0.lua;
+    let next = unsafe {
+        let _sg = StackGuard::new(lua.state);
+        check_stack(lua.state, 1)?;
+        ffi::lua_pushcfunction(lua.state, shadow_next);
+        Function(lua.pop_ref())
+    };
+    let len = lua.create_function(|_, (shadow, _): (Table, Value)| Ok(shadow.raw_len()))?;
+    let pairs = lua.create_function(|_, (next, shadow, _): (Function, Table, Value)| {
+        Ok((next, shadow, Nil))
+    })?;
+    metatable.raw_set("__len", len.bind(shadow.clone())?)?;
+    metatable.raw_set("__pairs", pairs.bind((next, shadow.clone()))?)
+}
+
+// Metatable field marking frozen copies created by `Value::freeze`.
+const FROZEN_METATABLE_KEY: &str = "__mlua_frozen";
+
+// Metatable field marking readonly tables, set to `true` if the metatable was created for it.
+const READONLY_METATABLE_KEY: &str = "__mlua_readonly";
+
+// Metatable field of traced tables, holding the table with the actual contents.
+const TRACED_METATABLE_KEY: &str = "__mlua_traced";
+
+/// Kind of a [`TableAccess`].
+///
+/// [`TableAccess`]: struct.TableAccess.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableAccessKind {
+    /// A value was read from the table.
+    Read,
+    /// A value was assigned to a key of the table.
+    Write,
+}
+
+/// A read or write of a traced table, passed to the callback set by [`Lua::trace_table_access`].
+///
+/// [`Lua::trace_table_access`]: struct.Lua.html#method.trace_table_access
+#[derive(Clone, Debug)]
+pub struct TableAccess<'lua> {
+    /// Whether the value was read or written.
+    pub kind: TableAccessKind,
+    /// The traced table.
+    pub table: Table<'lua>,
+    /// The key that was accessed.
+    pub key: Value<'lua>,
+    /// The value that was read, or the new value that was written.
+    pub value: Value<'lua>,
+}
+
 /// An iterator over the pairs of a Lua table.
 ///
 /// This struct is created by the [`Table::pairs`] method.
@@ -1,14 +1,14 @@
 use std::cmp;
+use std::io;
 use std::os::raw::c_int;
 
 use crate::error::{Error, Result};
 use crate::ffi;
-use crate::types::LuaRef;
-use crate::util::{assert_stack, check_stack, error_traceback, pop_error, protect_lua, StackGuard};
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
-
-#[cfg(any(feature = "lua54", all(feature = "luajit", feature = "vendored"), doc))]
 use crate::function::Function;
+use crate::string::String;
+use crate::types::{LuaRef, MaybeSend};
+use crate::util::{assert_stack, check_stack, error_traceback, pop_error, protect_lua, StackGuard};
+use crate::value::{FromLuaMulti, MultiValue, Nil, ToLuaMulti};
 
 #[cfg(feature = "async")]
 use {
@@ -108,16 +108,68 @@ impl<'lua> Thread<'lua> {
     where
         A: ToLuaMulti<'lua>,
         R: FromLuaMulti<'lua>,
+    {
+        let (_, results) = self.resume_inner(args)?;
+        R::from_lua_multi(results, self.0.lua)
+    }
+
+    /// Resumes execution of this thread and returns its status together with the results.
+    ///
+    /// Works like [`resume`], but also returns the status of the thread right after resuming:
+    /// [`ThreadStatus::Resumable`] if the thread has yielded, or [`ThreadStatus::Unresumable`] if
+    /// it has returned from its main function. This avoids a separate call to [`status`].
+    ///
+    /// If the thread raises an error, the error is returned with the thread traceback attached.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Thread, ThreadStatus};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let thread: Thread = lua.load(r#"
+    ///     coroutine.create(function()
+    ///         coroutine.yield(1)
+    ///         return 2
+    ///     end)
+    /// "#).eval()?;
+    ///
+    /// assert_eq!(thread.resume_status::<_, u32>(())?, (ThreadStatus::Resumable, 1));
+    /// assert_eq!(thread.resume_status::<_, u32>(())?, (ThreadStatus::Unresumable, 2));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`resume`]: #method.resume
+    /// [`status`]: #method.status
+    /// [`ThreadStatus::Resumable`]: enum.ThreadStatus.html#variant.Resumable
+    /// [`ThreadStatus::Unresumable`]: enum.ThreadStatus.html#variant.Unresumable
+    pub fn resume_status<A, R>(&self, args: A) -> Result<(ThreadStatus, R)>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let (status, results) = self.resume_inner(args)?;
+        Ok((status, R::from_lua_multi(results, self.0.lua)?))
+    }
+
+    fn resume_inner<A>(&self, args: A) -> Result<(ThreadStatus, MultiValue<'lua>)>
+    where
+        A: ToLuaMulti<'lua>,
     {
         let lua = self.0.lua;
-        let args = args.to_lua_multi(lua)?;
+        let mut args = args.to_lua_multi(lua)?;
         let nargs = args.len() as c_int;
-        let results = unsafe {
+        unsafe {
             let _sg = StackGuard::new(lua.state);
-            check_stack(lua.state, cmp::min(nargs + 1, 3))?;
+            check_stack(lua.state, cmp::max(nargs + 1, 3))?;
 
             lua.push_ref(&self.0);
             let thread_state = ffi::lua_tothread(lua.state, -1);
+            // Threads created by a scope cannot be resumed once the scope has ended
+            if lua.is_thread_expired(-1) {
+                return Err(Error::CallbackDestructed);
+            }
             ffi::lua_pop(lua.state, 1);
 
             let status = ffi::lua_status(thread_state);
@@ -126,9 +178,10 @@ impl<'lua> Thread<'lua> {
             }
 
             check_stack(thread_state, nargs)?;
-            for arg in args {
+            for arg in args.drain_all() {
                 lua.push_value(arg)?;
             }
+            lua.cache_multivalue(args);
             ffi::lua_xmove(lua.state, thread_state, nargs);
 
             let mut nresults = 0;
@@ -139,16 +192,21 @@ impl<'lua> Thread<'lua> {
                 return Err(pop_error(thread_state, ret));
             }
 
-            let mut results = MultiValue::new();
+            let mut results = lua.new_or_cached_multivalue();
             check_stack(lua.state, nresults + 2)?; // 2 is extra for `lua.pop_value()` below
             ffi::lua_xmove(thread_state, lua.state, nresults);
 
             for _ in 0..nresults {
                 results.push_front(lua.pop_value());
             }
-            results
-        };
-        R::from_lua_multi(results, lua)
+
+            let status = if ret == ffi::LUA_YIELD {
+                ThreadStatus::Resumable
+            } else {
+                ThreadStatus::Unresumable
+            };
+            Ok((status, results))
+        }
     }
 
     /// Gets the status of the thread.
@@ -173,6 +231,34 @@ impl<'lua> Thread<'lua> {
         }
     }
 
+    /// Redirects output of `print` and `io.write` called from this thread to `sink`.
+    ///
+    /// On first use the global `print` and `io.write` functions are replaced with wrappers that
+    /// look up the sink of the currently running thread, falling back to the handlers set by
+    /// [`Lua::redirect_io`], [`Lua::set_print_handler`] and [`Lua::set_write_handler`] (or the
+    /// original functions) for threads without a sink. This allows different coroutines to write
+    /// to different sinks.
+    ///
+    /// [`Lua::redirect_io`]: struct.Lua.html#method.redirect_io
+    /// [`Lua::set_print_handler`]: struct.Lua.html#method.set_print_handler
+    /// [`Lua::set_write_handler`]: struct.Lua.html#method.set_write_handler
+    pub fn set_output<W>(&self, mut sink: W) -> Result<()>
+    where
+        W: 'static + MaybeSend + io::Write,
+    {
+        let lua = self.0.lua;
+        let write = lua.create_function_mut(move |_, data: String| {
+            sink.write_all(data.as_bytes()).map_err(Error::external)
+        })?;
+        lua.output_handlers()?.raw_set(self.clone(), write)
+    }
+
+    /// Removes the output sink previously set by `set_output`.
+    pub fn remove_output(&self) -> Result<()> {
+        let lua = self.0.lua;
+        lua.output_handlers()?.raw_set(self.clone(), Nil)
+    }
+
     /// Resets a thread
     ///
     /// In [Lua 5.4]: cleans its call stack and closes all pending to-be-closed variables.
@@ -182,22 +268,38 @@ impl<'lua> Thread<'lua> {
     /// In [LuaJIT]: resets to the initial state of a newly created Lua thread.
     /// Lua threads in arbitrary states (like yielded or errored) can be reset properly.
     ///
-    /// Sets a Lua function for the thread afterwards.
+    /// Sets a Lua function for the thread afterwards, so the thread can be reused (for example
+    /// from a pool) instead of creating a new one.
     ///
-    /// Requires `feature = "lua54"` OR `feature = "luajit,vendored"`
+    /// A thread that is currently running, either by itself or by resuming another thread,
+    /// cannot be reset and returns an `Error::RuntimeError`.
+    ///
+    /// Requires `feature = "lua54"` OR `feature = "luajit,vendored"`, other Lua versions always
+    /// return an `Error::RuntimeError` as resetting threads is not supported there.
     ///
     /// [Lua 5.4]: https://www.lua.org/manual/5.4/manual.html#lua_resetthread
     /// [LuaJIT]: https://github.com/openresty/luajit2#lua_resetthread
-    #[cfg(any(feature = "lua54", all(feature = "luajit", feature = "vendored"), doc))]
     pub fn reset(&self, func: Function<'lua>) -> Result<()> {
-        let lua = self.0.lua;
+        #[cfg(any(feature = "lua54", all(feature = "luajit", feature = "vendored")))]
         unsafe {
+            let lua = self.0.lua;
             let _sg = StackGuard::new(lua.state);
             check_stack(lua.state, 2)?;
 
             lua.push_ref(&self.0);
             let thread_state = ffi::lua_tothread(lua.state, -1);
 
+            // Same check as `coroutine.status` does for the "running" and "normal" statuses
+            let mut ar: ffi::lua_Debug = std::mem::zeroed();
+            if thread_state == lua.state
+                || (ffi::lua_status(thread_state) == ffi::LUA_OK
+                    && ffi::lua_getstack(thread_state, 0, &mut ar) != 0)
+            {
+                return Err(Error::RuntimeError(
+                    "cannot reset a running thread".to_string(),
+                ));
+            }
+
             let ret = ffi::lua_resetthread(lua.state, thread_state);
             if ret != ffi::LUA_OK {
                 return Err(pop_error(thread_state, ret));
@@ -208,6 +310,14 @@ impl<'lua> Thread<'lua> {
 
             Ok(())
         }
+
+        #[cfg(not(any(feature = "lua54", all(feature = "luajit", feature = "vendored"))))]
+        {
+            let _ = func;
+            Err(Error::RuntimeError(
+                "resetting threads is not supported by this Lua version".to_string(),
+            ))
+        }
     }
 
     /// Converts Thread to an AsyncThread which implements Future and Stream traits.
@@ -0,0 +1,66 @@
+//! Helpers for running async Lua code on the [tokio] runtime.
+//!
+//! This module provides the async building blocks that are otherwise reimplemented in almost
+//! every application embedding Lua with tokio.
+//!
+//! Functions created here are regular async functions (see [`Lua::create_async_function`]), so
+//! they must be called from a coroutine driven by a tokio runtime, for example using
+//! [`Chunk::exec_async`] or [`Function::call_async`]. Awaiting them yields the Lua coroutine
+//! instead of blocking the thread, so other tasks keep running while Lua code sleeps.
+//!
+//! Requires `feature = "tokio"`
+//!
+//! # Examples
+//!
+//! ```
+//! use mlua::{Lua, Result};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let lua = Lua::new();
+//!     lua.globals().set("sleep", lua.create_async_sleep()?)?;
+//!
+//!     lua.load(
+//!         r#"
+//!         sleep(0.01)
+//!         print("woke up")
+//!     "#,
+//!     )
+//!     .exec_async()
+//!     .await
+//! }
+//! ```
+//!
+//! [tokio]: https://tokio.rs
+//! [`Lua::create_async_function`]: ../struct.Lua.html#method.create_async_function
+//! [`Chunk::exec_async`]: ../struct.Chunk.html#method.exec_async
+//! [`Function::call_async`]: ../struct.Function.html#method.call_async
+
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::function::Function;
+use crate::lua::Lua;
+
+impl Lua {
+    /// Creates an async function `sleep(seconds)` that waits using [`tokio::time::sleep`].
+    ///
+    /// `seconds` may be fractional and must not be negative. The function returns nothing.
+    /// While sleeping, the calling coroutine is suspended and the tokio runtime is free to run
+    /// other tasks. The function must be polled within a tokio runtime with the time driver
+    /// enabled.
+    ///
+    /// Refer to the [module documentation] for an example of registering it.
+    ///
+    /// Requires `feature = "tokio"`
+    ///
+    /// [`tokio::time::sleep`]: https://docs.rs/tokio/1/tokio/time/fn.sleep.html
+    /// [module documentation]: tokio/index.html
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+    pub fn create_async_sleep(&self) -> Result<Function<'_>> {
+        self.create_async_function(|_, duration: Duration| async move {
+            ::tokio::time::sleep(duration).await;
+            Ok(())
+        })
+    }
+}
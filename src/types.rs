@@ -7,10 +7,11 @@ use std::{fmt, mem, ptr};
 #[cfg(feature = "async")]
 use futures_core::future::LocalBoxFuture;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::ffi;
 use crate::hook::Debug;
 use crate::lua::Lua;
+use crate::table::TableAccess;
 use crate::util::{assert_stack, StackGuard};
 use crate::value::MultiValue;
 
@@ -23,6 +24,92 @@ pub type Number = ffi::lua_Number;
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct LightUserData(pub *mut c_void);
 
+impl LightUserData {
+    /// Size of a pointer stored in a `LightUserData`, in bytes.
+    pub const POINTER_SIZE: usize = mem::size_of::<*mut c_void>();
+
+    /// Whether the Lua VM stores light userdata in tagged (NaN-boxed) values.
+    ///
+    /// This is the case for LuaJIT on 64-bit platforms, which limits the usable address bits to
+    /// [`ADDRESS_BITS`]. Pushing a pointer outside of this range to Lua raises an error.
+    ///
+    /// [`ADDRESS_BITS`]: #associatedconstant.ADDRESS_BITS
+    pub const VM_TAGGED_POINTERS: bool = cfg!(all(feature = "luajit", target_pointer_width = "64"));
+
+    /// Number of low address bits the Lua VM can store in a light userdata.
+    pub const ADDRESS_BITS: u32 = if Self::VM_TAGGED_POINTERS {
+        47
+    } else {
+        Self::POINTER_SIZE as u32 * 8
+    };
+
+    /// Number of low pointer bits used by [`with_tag`] to store a tag.
+    ///
+    /// [`with_tag`]: #method.with_tag
+    pub const TAG_BITS: u32 = 2;
+
+    const TAG_MASK: usize = (1 << Self::TAG_BITS) - 1;
+
+    /// Creates a light userdata from a pointer with a small integer tag stored in its low bits.
+    ///
+    /// The pointer must be aligned to at least `1 << TAG_BITS` bytes, so the low bits are always
+    /// zero and can hold the tag. Returns an error if the pointer is not aligned enough or if the
+    /// tag does not fit into [`TAG_BITS`] bits.
+    ///
+    /// The original pointer and tag can be recovered with [`untagged_ptr`] and [`tag`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, LightUserData, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let mut handle = 0u32;
+    /// let ud = LightUserData::with_tag(&mut handle as *mut u32, 3)?;
+    ///
+    /// let id: Function = lua.load("function(ud) return ud end").eval()?;
+    /// let ud: LightUserData = id.call(ud)?;
+    /// assert_eq!(ud.tag(), 3);
+    /// assert_eq!(ud.untagged_ptr() as *mut u32, &mut handle as *mut u32);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`TAG_BITS`]: #associatedconstant.TAG_BITS
+    /// [`untagged_ptr`]: #method.untagged_ptr
+    /// [`tag`]: #method.tag
+    pub fn with_tag<T>(ptr: *mut T, tag: usize) -> Result<LightUserData> {
+        if tag > Self::TAG_MASK {
+            return Err(Error::RuntimeError(format!(
+                "tag {} does not fit into {} bits",
+                tag,
+                Self::TAG_BITS
+            )));
+        }
+        let addr = ptr as usize;
+        if addr & Self::TAG_MASK != 0 {
+            return Err(Error::RuntimeError(format!(
+                "pointer {:p} is not aligned to {} bytes",
+                ptr,
+                1 << Self::TAG_BITS
+            )));
+        }
+        Ok(LightUserData((addr | tag) as *mut c_void))
+    }
+
+    /// Returns the tag stored by [`with_tag`].
+    ///
+    /// [`with_tag`]: #method.with_tag
+    pub fn tag(&self) -> usize {
+        self.0 as usize & Self::TAG_MASK
+    }
+
+    /// Returns the pointer with the tag bits cleared.
+    pub fn untagged_ptr(&self) -> *mut c_void {
+        (self.0 as usize & !Self::TAG_MASK) as *mut c_void
+    }
+}
+
 pub(crate) type Callback<'lua, 'a> =
     Box<dyn Fn(&'lua Lua, MultiValue<'lua>) -> Result<MultiValue<'lua>> + 'a>;
 
@@ -32,6 +119,14 @@ pub(crate) type AsyncCallback<'lua, 'a> =
 
 pub(crate) type HookCallback = Arc<RefCell<dyn FnMut(&Lua, Debug) -> Result<()>>>;
 
+pub(crate) type ErrorHandler = Arc<dyn Fn(&Error)>;
+
+pub(crate) type RegistryExpireHandler = Arc<dyn Fn(RegistryKey)>;
+
+pub(crate) type CloseHandler = Box<dyn FnOnce()>;
+
+pub(crate) type TableAccessHook = Arc<dyn Fn(&Lua, TableAccess) -> Result<()>>;
+
 #[cfg(feature = "send")]
 pub trait MaybeSend: Send {}
 #[cfg(feature = "send")]
@@ -42,6 +137,16 @@ pub trait MaybeSend {}
 #[cfg(not(feature = "send"))]
 impl<T> MaybeSend for T {}
 
+#[cfg(feature = "send")]
+pub trait MaybeSync: Sync {}
+#[cfg(feature = "send")]
+impl<T: Sync> MaybeSync for T {}
+
+#[cfg(not(feature = "send"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "send"))]
+impl<T> MaybeSync for T {}
+
 /// An auto generated key into the Lua registry.
 ///
 /// This is a handle to a value stored inside the Lua registry. It is not automatically
@@ -92,6 +197,18 @@ impl Drop for RegistryKey {
 }
 
 impl RegistryKey {
+    /// Returns the slot of the value in the Lua registry.
+    ///
+    /// It's unique among the keys of a Lua state that refer to non-nil values, but is reused once
+    /// the value is removed. Keys passed to the handler set by [`Lua::on_registry_expire`] have
+    /// the same id as the dropped key, so it can be used to find Rust-side data associated with
+    /// a key.
+    ///
+    /// [`Lua::on_registry_expire`]: struct.Lua.html#method.on_registry_expire
+    pub fn id(&self) -> c_int {
+        self.registry_id
+    }
+
     // Destroys the RegistryKey without adding to the drop list
     pub(crate) fn take(self) -> c_int {
         let registry_id = self.registry_id;
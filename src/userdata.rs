@@ -1,9 +1,13 @@
 use std::any::TypeId;
 use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::string::String as StdString;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 #[cfg(feature = "async")]
 use std::future::Future;
@@ -17,16 +21,14 @@ use {
 use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
-use crate::lua::Lua;
+use crate::lua::{Lua, StaticUserDataMethods};
 use crate::table::{Table, TablePairs};
-use crate::types::{Callback, LuaRef, MaybeSend};
+use crate::types::{Callback, LuaRef, MaybeSend, MaybeSync, RegistryKey};
 use crate::util::{
-    check_stack, get_destructed_userdata_metatable, get_userdata, push_string, StackGuard,
+    check_stack, get_destructed_userdata_metatable, get_userdata, protect_lua, push_string,
+    push_userdata_field_getters, take_userdata, StackGuard,
 };
-use crate::value::{FromLua, FromLuaMulti, ToLua, ToLuaMulti};
-
-#[cfg(any(feature = "lua52", feature = "lua51", feature = "luajit"))]
-use crate::value::Value;
+use crate::value::{FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti, Value};
 
 #[cfg(feature = "async")]
 use crate::types::AsyncCallback;
@@ -103,8 +105,11 @@ pub enum MetaMethod {
     ///
     /// This is not an operator, but it will be called by the built-in `pairs` function.
     ///
-    /// Requires `feature = "lua54/lua53/lua52"`
-    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52", doc))]
+    /// The metamethod must return an iterator triple `(next, state, control)` in the same way
+    /// as `pairs` does for tables. Lua 5.1 and LuaJIT `pairs` ignores `__pairs`, so there the
+    /// global `pairs` function must be replaced by calling [`Lua::enable_pairs_metamethod`].
+    ///
+    /// [`Lua::enable_pairs_metamethod`]: struct.Lua.html#method.enable_pairs_metamethod
     Pairs,
     /// The `__ipairs` metamethod.
     ///
@@ -119,14 +124,32 @@ pub enum MetaMethod {
     ///
     /// Executed when a variable, that marked as to-be-closed, goes out of scope.
     ///
+    /// The metamethod receives the userdata and the error object that caused the exit, or `nil`
+    /// when the scope was left normally, so it is suitable for deterministic cleanup of resources
+    /// such as file handles.
+    ///
     /// More information about to-be-closed variabled can be found in the Lua 5.4
     /// [documentation][lua_doc].
     ///
-    /// Requires `feature = "lua54"`
+    /// Requires `feature = "lua54"`. Other Lua versions have no to-be-closed variables, so the
+    /// variant does not exist there and registering it is a compile-time error.
     ///
     /// [lua_doc]: https://www.lua.org/manual/5.4/manual.html#3.3.8
     #[cfg(any(feature = "lua54", doc))]
     Close,
+    /// The `__serialize` metamethod.
+    ///
+    /// This is not a Lua metamethod, but it is called by mlua to serialize userdata with serde.
+    /// The metamethod receives the userdata and returns a Lua value (eg. a table) that is
+    /// serialized in its place. This allows userdata types that do not implement `Serialize` to
+    /// be serialized, for example as part of a table passed to [`LuaSerdeExt::to_value`].
+    /// If set, it takes precedence over the `Serialize` implementation of the userdata type.
+    ///
+    /// Requires `feature = "serialize"`
+    ///
+    /// [`LuaSerdeExt::to_value`]: trait.LuaSerdeExt.html#tymethod.to_value
+    #[cfg(any(feature = "serialize", doc))]
+    Serialize,
     /// A custom metamethod.
     ///
     /// Must not be in the protected list: `__gc`, `__metatable`, `__mlua*`.
@@ -190,7 +213,6 @@ impl MetaMethod {
             MetaMethod::Call => "__call",
             MetaMethod::ToString => "__tostring",
 
-            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
             MetaMethod::Pairs => "__pairs",
             #[cfg(feature = "lua52")]
             MetaMethod::IPairs => "__ipairs",
@@ -198,6 +220,9 @@ impl MetaMethod {
             #[cfg(feature = "lua54")]
             MetaMethod::Close => "__close",
 
+            #[cfg(feature = "serialize")]
+            MetaMethod::Serialize => "__serialize",
+
             MetaMethod::Custom(ref name) => name,
         }
     }
@@ -252,7 +277,6 @@ impl From<StdString> for MetaMethod {
             "__call" => MetaMethod::Call,
             "__tostring" => MetaMethod::ToString,
 
-            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
             "__pairs" => MetaMethod::Pairs,
             #[cfg(feature = "lua52")]
             "__ipairs" => MetaMethod::IPairs,
@@ -260,6 +284,9 @@ impl From<StdString> for MetaMethod {
             #[cfg(feature = "lua54")]
             "__close" => MetaMethod::Close,
 
+            #[cfg(feature = "serialize")]
+            "__serialize" => MetaMethod::Serialize,
+
             _ => MetaMethod::Custom(name),
         }
     }
@@ -320,6 +347,31 @@ pub trait UserDataMethods<'lua, T: UserData> {
         M: 'static + MaybeSend + Fn(&'lua Lua, T, A) -> MR,
         MR: 'lua + Future<Output = Result<R>>;
 
+    /// Add an async method which accepts an `Arc<T>` as the first parameter and returns Future.
+    ///
+    /// Unlike [`add_async_method`], `T` does not need to be `Clone`: the method is available on
+    /// userdata created from an `Arc<T>` and receives a clone of that `Arc`. All calls, including
+    /// concurrently running ones, share the same value, so any state mutated across await points
+    /// must use interior mutability (e.g. `Cell`, `RefCell` or `Mutex`). No userdata borrow is held
+    /// while the future is running.
+    ///
+    /// Calling the method on userdata that does not hold an `Arc<T>` results in
+    /// [`Error::UserDataTypeMismatch`].
+    ///
+    /// Requires `feature = "async"`
+    ///
+    /// [`add_async_method`]: #method.add_async_method
+    /// [`Error::UserDataTypeMismatch`]: enum.Error.html#variant.UserDataTypeMismatch
+    #[cfg(feature = "async")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    fn add_async_method_arc<S, A, R, M, MR>(&mut self, name: &S, method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, Arc<T>, A) -> MR,
+        MR: 'lua + Future<Output = Result<R>>;
+
     /// Add a regular method as a function which accepts generic arguments, the first argument will
     /// be a [`AnyUserData`] of type `T` if the method is called with Lua method syntax:
     /// `my_userdata:my_method(arg1, arg2)`, or it is passed in as the first argument:
@@ -421,6 +473,39 @@ pub trait UserDataMethods<'lua, T: UserData> {
         R: ToLuaMulti<'lua>,
         F: 'static + MaybeSend + FnMut(&'lua Lua, A) -> Result<R>;
 
+    /// Add a `__call` metamethod which accepts a `&T` as the first parameter.
+    ///
+    /// This allows the userdata to be called like a function from Lua: `ud(a, b)` invokes `method`
+    /// with the userdata borrowed as `&T` and `(a, b)` converted to `A`.
+    fn add_meta_method_call<A, R, M>(&mut self, method: M)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, &T, A) -> Result<R>,
+    {
+        self.add_meta_method(MetaMethod::Call, method);
+    }
+
+    /// Add an `__eq` metamethod derived from the `PartialEq` implementation of `T`.
+    ///
+    /// Without it, comparing two userdata in Lua checks for identity only. With it, two userdata
+    /// wrapping equal values of type `T` compare equal. Userdata of other types are never equal.
+    fn add_partial_eq(&mut self)
+    where
+        T: 'static + PartialEq,
+    {
+        self.add_meta_function(MetaMethod::Eq, |_, (a, b): (AnyUserData, AnyUserData)| {
+            let (a, b) = match (a.borrow::<T>(), b.borrow::<T>()) {
+                (Ok(a), Ok(b)) => (a, b),
+                (Err(Error::UserDataTypeMismatch), _) | (_, Err(Error::UserDataTypeMismatch)) => {
+                    return Ok(false)
+                }
+                (Err(err), _) | (_, Err(err)) => return Err(err),
+            };
+            Ok(*a == *b)
+        });
+    }
+
     //
     // Below are internal methods used in generated code
     //
@@ -588,6 +673,25 @@ pub trait UserData: Sized {
 
     /// Adds custom methods and operators specific to this userdata.
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(_methods: &mut M) {}
+
+    /// Returns whether to install a default `__tostring` metamethod showing the type name.
+    ///
+    /// When enabled and no `__tostring` metamethod is registered, `tostring` returns
+    /// `TypeName: 0x...` instead of `userdata: 0x...`, where `TypeName` is the Rust type name
+    /// without module paths. Disabled by default.
+    fn default_tostring() -> bool {
+        false
+    }
+}
+
+// Counts a userdata as live (see `Lua::set_max_userdata` and `Lua::clear_unused_metatables`) until
+// the Rust value is dropped or taken.
+pub(crate) struct LiveUserData(pub(crate) Arc<AtomicUsize>);
+
+impl Drop for LiveUserData {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 // Wraps UserData in a way to always implement `serde::Serialize` trait.
@@ -607,7 +711,7 @@ impl<T> UserDataCell<T> {
     }
 
     // Immutably borrows the wrapped value.
-    fn try_borrow(&self) -> Result<UserDataRef<T>> {
+    pub(crate) fn try_borrow(&self) -> Result<UserDataRef<T>> {
         self.0
             .try_borrow()
             .map(|r| UserDataRef(UserDataRefInner::Ref(r)))
@@ -615,12 +719,27 @@ impl<T> UserDataCell<T> {
     }
 
     // Mutably borrows the wrapped value.
-    fn try_borrow_mut(&self) -> Result<UserDataRefMut<T>> {
+    pub(crate) fn try_borrow_mut(&self) -> Result<UserDataRefMut<T>> {
         self.0
             .try_borrow_mut()
             .map(|r| UserDataRefMut(UserDataRefMutInner::Ref(r)))
             .map_err(|_| Error::UserDataBorrowMutError)
     }
+
+    // Marks the userdata as live until its value is dropped or taken.
+    pub(crate) fn set_live(&mut self, live: LiveUserData) {
+        self.0.get_mut().live = Some(live);
+    }
+
+    // Counts the value as an instance of its registered metatable until dropped or taken.
+    pub(crate) fn set_instance(&mut self, instance: LiveUserData) {
+        self.0.get_mut().instance = Some(instance);
+    }
+
+    // Consumes the cell, returning the wrapped value.
+    pub(crate) fn into_inner(self) -> T {
+        self.0.into_inner().into_inner()
+    }
 }
 
 #[cfg(feature = "serialize")]
@@ -708,6 +827,8 @@ pub(crate) struct UserDataWrapped<T> {
     pub(crate) data: *mut T,
     #[cfg(feature = "serialize")]
     ser: *mut dyn erased_serde::Serialize,
+    live: Option<LiveUserData>,
+    instance: Option<LiveUserData>,
 }
 
 impl<T> UserDataWrapped<T> {
@@ -716,6 +837,8 @@ impl<T> UserDataWrapped<T> {
             data: Box::into_raw(Box::new(data)),
             #[cfg(feature = "serialize")]
             ser: Box::into_raw(Box::new(UserDataSerializeError)),
+            live: None,
+            instance: None,
         }
     }
 
@@ -728,6 +851,24 @@ impl<T> UserDataWrapped<T> {
         UserDataWrapped {
             data: data_raw,
             ser: data_raw,
+            live: None,
+            instance: None,
+        }
+    }
+}
+
+impl<T> UserDataWrapped<T> {
+    // Moves the value out, freeing the wrapper without dropping the value.
+    fn into_inner(self) -> T {
+        let mut this = mem::ManuallyDrop::new(self);
+        this.live = None;
+        this.instance = None;
+        unsafe {
+            #[cfg(feature = "serialize")]
+            if this.data as *mut () != this.ser as *mut () {
+                drop(Box::from_raw(this.ser));
+            }
+            *Box::from_raw(this.data)
         }
     }
 }
@@ -820,6 +961,32 @@ impl<'lua> AnyUserData<'lua> {
         self.inspect(|cell| cell.try_borrow_mut())
     }
 
+    /// Moves the value out of this userdata if it is of type `T`.
+    ///
+    /// The userdata is left destructed, as if it was garbage collected: further uses from Lua raise
+    /// an error and the Rust methods return `UserDataDestructed`. The value is not dropped again
+    /// when the userdata is collected, or when the [`Scope`] that created it ends.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowMutError` if the userdata is currently borrowed, including from a
+    /// running method. Returns a `UserDataTypeMismatch` if the userdata is not of type `T`.
+    ///
+    /// [`Scope`]: struct.Scope.html
+    pub fn take<T: 'static + UserData>(&self) -> Result<T> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 3)?;
+
+            // Checks the type and makes sure the value is not borrowed
+            self.inspect::<T, _, _>(|cell| cell.try_borrow_mut().map(|_| ()))?;
+
+            lua.push_ref(&self.0);
+            Ok(take_userdata::<UserDataCell<T>>(lua.state).into_inner())
+        }
+    }
+
     /// Sets an associated value to this `AnyUserData`.
     ///
     /// The value may be any Lua value whatsoever, and can be retrieved with [`get_user_value`].
@@ -873,6 +1040,118 @@ impl<'lua> AnyUserData<'lua> {
         V::from_lua(res, lua)
     }
 
+    /// Returns the names of the fields that can be read from this userdata, sorted by name.
+    ///
+    /// The names are the ones registered using [`UserDataFields`] field getters.
+    ///
+    /// [`UserDataFields`]: trait.UserDataFields.html
+    pub fn field_names(&self) -> Result<Vec<StdString>> {
+        let getters = unsafe {
+            let lua = self.0.lua;
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 5)?;
+
+            lua.push_userdata_ref(&self.0, false)?;
+            ffi::lua_getmetatable(lua.state, -1); // Checked that non-empty on the previous call
+            if !push_userdata_field_getters(lua.state, -1)? {
+                return Ok(Vec::new());
+            }
+            Table(lua.pop_ref())
+        };
+
+        let mut names = getters
+            .pairs::<StdString, Value>()
+            .map(|pair| pair.map(|(name, _)| name))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Resolves the method `name` once and returns it bound to this userdata.
+    ///
+    /// Calling the returned function is equivalent to calling `ud:name(...)` from Lua, but the
+    /// method is looked up through `__index` only here rather than on every call, which helps when
+    /// the same method is called repeatedly. Methods replaced afterwards are not picked up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// struct Counter(u32);
+    ///
+    /// impl UserData for Counter {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method_mut("incr", |_, this, n: u32| {
+    ///             this.0 += n;
+    ///             Ok(this.0)
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// let counter = lua.create_userdata(Counter(0))?;
+    /// let incr = counter.bind_method("incr")?;
+    /// for _ in 0..9 {
+    ///     incr.call::<_, ()>(1)?;
+    /// }
+    /// assert_eq!(incr.call::<_, u32>(1)?, 10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn bind_method(&self, name: &str) -> Result<Function<'lua>> {
+        let lua = self.0.lua;
+        let method = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 5)?;
+
+            lua.push_userdata_ref(&self.0, false)?;
+            push_string(lua.state, name)?;
+            protect_lua(lua.state, 2, 1, |state| ffi::lua_gettable(state, -2))?;
+            lua.pop_value()
+        };
+        Function::from_lua(method, lua)?.bind(self.clone())
+    }
+
+    /// Creates a [`WeakUserData`] handle to this userdata.
+    ///
+    /// The handle does not keep the userdata alive, so it can be used to break reference cycles
+    /// between userdata values.
+    ///
+    /// [`WeakUserData`]: struct.WeakUserData.html
+    pub fn downgrade(&self) -> Result<WeakUserData<'lua>> {
+        let lua = self.0.lua;
+        let token = lua.create_table()?;
+        token.set_metatable(Some(lua.weak_userdata_token_metatable()?));
+        lua.weak_userdata()?.raw_set(token.clone(), self.clone())?;
+        Ok(WeakUserData(token.0))
+    }
+
+    /// Replaces the methods of the userdata type `T` at runtime.
+    ///
+    /// The closure registers the new methods and metamethods in the same way as
+    /// [`UserData::add_methods`]. The metatable of a `T: UserData + 'static` is shared among all
+    /// instances of type `T`, so every existing userdata of this type, including the ones already
+    /// referenced from Lua, uses the new methods afterwards. Methods that are not registered again
+    /// are removed, metamethods that are not registered again are kept. Fields are not affected.
+    ///
+    /// Returns [`UserDataTypeMismatch`] if this userdata is not of type `T`.
+    ///
+    /// [`UserData::add_methods`]: trait.UserData.html#method.add_methods
+    /// [`UserDataTypeMismatch`]: enum.Error.html#variant.UserDataTypeMismatch
+    pub fn replace_methods<T, F>(&self, f: F) -> Result<()>
+    where
+        T: 'static + UserData,
+        F: FnOnce(&mut StaticUserDataMethods<'lua, T>),
+    {
+        if !self.is::<T>() {
+            return Err(Error::UserDataTypeMismatch);
+        }
+        let mut methods = StaticUserDataMethods::default();
+        f(&mut methods);
+        self.0.lua.replace_userdata_methods(&self.0, methods)
+    }
+
     /// Returns a metatable of this `UserData`.
     ///
     /// Returned [`UserDataMetatable`] object wraps the original metatable and
@@ -897,6 +1176,7 @@ impl<'lua> AnyUserData<'lua> {
         }
     }
 
+    #[cfg(any(feature = "lua52", feature = "lua51", feature = "luajit"))]
     pub(crate) fn equals<T: AsRef<Self>>(&self, other: T) -> Result<bool> {
         let other = other.as_ref();
         // Uses lua_rawequal() under the hood
@@ -982,6 +1262,140 @@ impl<'lua> AsRef<AnyUserData<'lua>> for AnyUserData<'lua> {
     }
 }
 
+/// Weak handle to a Lua userdata.
+///
+/// Created by [`AnyUserData::downgrade`]. Unlike [`AnyUserData`], a weak handle does not prevent
+/// the userdata from being garbage collected. It is backed by a weak table in the Lua registry.
+///
+/// A weak handle can be converted to a Lua value, which allows storing it using
+/// [`Lua::create_registry_value`] inside of another userdata.
+///
+/// [`AnyUserData::downgrade`]: struct.AnyUserData.html#method.downgrade
+/// [`AnyUserData`]: struct.AnyUserData.html
+/// [`Lua::create_registry_value`]: struct.Lua.html#method.create_registry_value
+#[derive(Clone, Debug)]
+pub struct WeakUserData<'lua>(pub(crate) LuaRef<'lua>);
+
+impl<'lua> WeakUserData<'lua> {
+    /// Returns the userdata this handle points to, or `None` if it has been garbage collected.
+    pub fn upgrade(&self) -> Result<Option<AnyUserData<'lua>>> {
+        let lua = self.0.lua;
+        lua.weak_userdata()?.raw_get(Table(self.0.clone()))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+type DynamicMethod = Arc<dyn for<'lua> Fn(&'lua Lua, MultiValue<'lua>) -> Result<MultiValue<'lua>>>;
+#[cfg(feature = "send")]
+type DynamicMethod =
+    Arc<dyn for<'lua> Fn(&'lua Lua, MultiValue<'lua>) -> Result<MultiValue<'lua>> + Send + Sync>;
+
+/// Runtime dispatch table of methods for userdata created by [`Lua::create_dynamic_userdata`].
+///
+/// Clones share the same table, so methods inserted or removed after the userdata was created
+/// are visible from Lua immediately. Methods receive the userdata itself as the first argument,
+/// so they must be called with the `obj:method()` syntax.
+///
+/// [`Lua::create_dynamic_userdata`]: struct.Lua.html#method.create_dynamic_userdata
+#[derive(Clone, Default)]
+pub struct DynamicMethods(Arc<Mutex<HashMap<StdString, DynamicMethod>>>);
+
+impl DynamicMethods {
+    /// Creates an empty dispatch table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a method, replacing any previous method with the same name.
+    pub fn insert<S, F>(&self, name: S, method: F)
+    where
+        S: Into<StdString>,
+        F: 'static
+            + MaybeSend
+            + MaybeSync
+            + for<'lua> Fn(&'lua Lua, MultiValue<'lua>) -> Result<MultiValue<'lua>>,
+    {
+        self.methods().insert(name.into(), Arc::new(method));
+    }
+
+    /// Removes a method. Returns `true` if the method was present.
+    pub fn remove(&self, name: &str) -> bool {
+        self.methods().remove(name).is_some()
+    }
+
+    /// Checks whether a method with the given name is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.methods().contains_key(name)
+    }
+
+    fn get(&self, name: &str) -> Option<DynamicMethod> {
+        self.methods().get(name).cloned()
+    }
+
+    fn methods(&self) -> MutexGuard<'_, HashMap<StdString, DynamicMethod>> {
+        mlua_expect!(self.0.lock(), "dynamic methods are poisoned")
+    }
+}
+
+impl fmt::Debug for DynamicMethods {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.methods().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        fmt.debug_tuple("DynamicMethods").field(&names).finish()
+    }
+}
+
+// Userdata created by `Lua::create_dynamic_userdata`, its `__index` consults the dispatch table
+// on every lookup.
+pub(crate) struct DynamicUserData(pub(crate) DynamicMethods);
+
+impl UserData for DynamicUserData {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // The metatable is shared by all dynamic userdata, so the function returned for a method
+        // name is created once and cached, and resolves the method through its `self` argument.
+        let dispatchers: Mutex<Option<RegistryKey>> = Mutex::new(None);
+        methods.add_meta_method(MetaMethod::Index, move |lua, this, key: Value| {
+            let name = match key {
+                Value::String(ref name) => name.to_str()?,
+                _ => return Ok(Value::Nil),
+            };
+            if !this.0.contains(name) {
+                return Ok(Value::Nil);
+            }
+
+            let mut dispatchers = mlua_expect!(dispatchers.lock(), "dispatchers are poisoned");
+            let cache: Table = match *dispatchers {
+                Some(ref key) => lua.registry_value(key)?,
+                None => {
+                    let cache = lua.create_table()?;
+                    *dispatchers = Some(lua.create_registry_value(cache.clone())?);
+                    cache
+                }
+            };
+            drop(dispatchers);
+
+            if let Value::Function(function) = cache.raw_get(name)? {
+                return Ok(Value::Function(function));
+            }
+            let method_name = name.to_owned();
+            let function = lua.create_function(move |lua, args: MultiValue| {
+                // The method is looked up again, as it could be removed or replaced meanwhile
+                let this = match args.get(0) {
+                    Some(Value::UserData(ud)) => ud.borrow::<DynamicUserData>()?,
+                    _ => return Err(Error::UserDataTypeMismatch),
+                };
+                let method = (*this).0.get(&method_name).ok_or_else(|| {
+                    Error::RuntimeError(format!("dynamic method '{}' was removed", method_name))
+                })?;
+                drop(this);
+                method(lua, args)
+            })?;
+            cache.raw_set(name, function.clone())?;
+            Ok(Value::Function(function))
+        });
+    }
+}
+
 /// Handle to a `UserData` metatable.
 #[derive(Clone, Debug)]
 pub struct UserDataMetatable<'lua>(pub(crate) Table<'lua>);
@@ -1056,12 +1470,122 @@ where
     }
 }
 
+/// A set of metamethods that can be built once and applied to many userdata values.
+///
+/// Every metamethod is stored as an already created Lua [`Function`], so applying the set to
+/// another userdata only assigns the existing functions to its metatable, without boxing and
+/// creating new callbacks. This is mostly useful for userdata created by
+/// [`Scope::create_nonstatic_userdata`], which get a new metatable each time.
+///
+/// For `T: UserData + 'static` the metatable is shared among all instances of type `T`, so the
+/// set needs to be applied only once.
+///
+/// # Examples
+///
+/// ```
+/// # use mlua::{AnyUserData, Lua, MetaMethod, MetaMethodSet, Result, UserData, UserDataMethods};
+/// # fn main() -> Result<()> {
+/// # let lua = Lua::new();
+/// struct Counter(u32);
+///
+/// impl UserData for Counter {
+///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+///         methods.add_method("get", |_, this, ()| Ok(this.0));
+///     }
+/// }
+///
+/// let set = MetaMethodSet::new().add_function(&lua, MetaMethod::ToString, |_, _: AnyUserData| {
+///     Ok("counter")
+/// })?;
+///
+/// lua.scope(|scope| {
+///     for i in 0..10 {
+///         let ud = scope.create_nonstatic_userdata(Counter(i))?;
+///         set.apply(&ud)?;
+///         lua.globals().set("counter", ud)?;
+///         lua.load("assert(tostring(counter) == 'counter')").exec()?;
+///     }
+///     Ok(())
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Function`]: struct.Function.html
+/// [`Scope::create_nonstatic_userdata`]: struct.Scope.html#method.create_nonstatic_userdata
+#[derive(Clone, Debug, Default)]
+pub struct MetaMethodSet<'lua> {
+    methods: Vec<(MetaMethod, Function<'lua>)>,
+}
+
+impl<'lua> MetaMethodSet<'lua> {
+    /// Creates an empty set of metamethods.
+    pub fn new() -> Self {
+        MetaMethodSet::default()
+    }
+
+    /// Adds a metamethod to the set, replacing any previous function for the same metamethod.
+    ///
+    /// The same restrictions as for [`UserDataMetatable::set`] apply: restricted metamethods such
+    /// as `__gc`, `__metatable`, `__index` or `__newindex` will cause an error.
+    ///
+    /// [`UserDataMetatable::set`]: struct.UserDataMetatable.html#method.set
+    pub fn add<S: Into<MetaMethod>>(mut self, meta: S, function: Function<'lua>) -> Result<Self> {
+        let meta = meta.into().validate()?;
+        if meta == MetaMethod::Index || meta == MetaMethod::NewIndex {
+            return Err(Error::MetaMethodRestricted(meta.to_string()));
+        }
+        self.methods.retain(|(m, _)| *m != meta);
+        self.methods.push((meta, function));
+        Ok(self)
+    }
+
+    /// Wraps a Rust function into a Lua function and adds it to the set as a metamethod.
+    ///
+    /// The function is created only once, regardless of the number of userdata values the set is
+    /// applied to. See [`Lua::create_function`] for more details.
+    ///
+    /// [`Lua::create_function`]: struct.Lua.html#method.create_function
+    pub fn add_function<S, A, R, F>(self, lua: &'lua Lua, meta: S, function: F) -> Result<Self>
+    where
+        S: Into<MetaMethod>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + MaybeSend + Fn(&'lua Lua, A) -> Result<R>,
+    {
+        self.add(meta, lua.create_function(function)?)
+    }
+
+    /// Sets all metamethods from the set in the metatable of the given userdata.
+    pub fn apply(&self, ud: &AnyUserData<'lua>) -> Result<()> {
+        let metatable = ud.get_raw_metatable()?;
+        for (meta, function) in &self.methods {
+            metatable.raw_set(meta.name(), function.clone())?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(feature = "serialize")]
 impl<'lua> Serialize for AnyUserData<'lua> {
     fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
     where
         S: Serializer,
     {
+        let hook = self
+            .get_raw_metatable()
+            .and_then(|mt| mt.raw_get::<_, Option<Function>>(MetaMethod::Serialize.name()))
+            .map_err(ser::Error::custom)?;
+        if let Some(hook) = hook {
+            return match hook.call::<_, Value>(self.clone()) {
+                Ok(Value::UserData(ref ud)) if ud == self => Err(ser::Error::custom(
+                    "__serialize metamethod returned the userdata itself",
+                )),
+                Ok(value) => value.serialize(serializer),
+                Err(err) => Err(ser::Error::custom(err)),
+            };
+        }
+
         unsafe {
             let lua = self.0.lua;
             let _sg = StackGuard::new(lua.state);
@@ -38,6 +38,20 @@ pub unsafe fn check_stack(state: *mut ffi::lua_State, amount: c_int) -> Result<(
     }
 }
 
+// Asserts that the stack top of `state` is `expected` after a callback returned. A different top
+// means the callback (or a custom integration using the raw API) left values behind or popped too
+// many. Only checked in debug builds, the result of the callback is not affected.
+#[cfg(debug_assertions)]
+pub unsafe fn assert_callback_stack(state: *mut ffi::lua_State, expected: c_int) {
+    let top = ffi::lua_gettop(state);
+    debug_assert!(
+        top == expected,
+        "callback left the Lua stack unbalanced (expected {} values, found {})",
+        expected,
+        top
+    );
+}
+
 pub struct StackGuard {
     state: *mut ffi::lua_State,
     top: c_int,
@@ -291,6 +305,94 @@ pub unsafe fn get_gc_userdata<T: Any>(state: *mut ffi::lua_State, index: c_int)
     ud
 }
 
+// Wrapper to lookup in `field_getters` first, then `methods`, ending original `__index`.
+// Used only if `field_getters` or `methods` set.
+pub unsafe extern "C" fn meta_index_impl(state: *mut ffi::lua_State) -> c_int {
+    // stack: self, key
+    ffi::luaL_checkstack(state, 2, ptr::null());
+
+    // lookup in `field_getters` table
+    if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
+        ffi::lua_pushvalue(state, -1); // `key` arg
+        if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
+            ffi::lua_insert(state, -3); // move function
+            ffi::lua_pop(state, 1); // remove `key`
+            ffi::lua_call(state, 1, 1);
+            return 1;
+        }
+        ffi::lua_pop(state, 1); // pop the nil value
+    }
+    // lookup in `methods` table
+    if ffi::lua_isnil(state, ffi::lua_upvalueindex(3)) == 0 {
+        ffi::lua_pushvalue(state, -1); // `key` arg
+        if ffi::lua_rawget(state, ffi::lua_upvalueindex(3)) != ffi::LUA_TNIL {
+            ffi::lua_insert(state, -3);
+            ffi::lua_pop(state, 2);
+            return 1;
+        }
+        ffi::lua_pop(state, 1); // pop the nil value
+    }
+
+    // lookup in `__index`
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    match ffi::lua_type(state, -1) {
+        ffi::LUA_TNIL => {
+            ffi::lua_pop(state, 1); // pop the nil value
+            let field = ffi::lua_tostring(state, -1);
+            ffi::luaL_error(state, cstr!("attempt to get an unknown field '%s'"), field);
+        }
+        ffi::LUA_TTABLE => {
+            ffi::lua_insert(state, -2);
+            ffi::lua_gettable(state, -2);
+        }
+        ffi::LUA_TFUNCTION => {
+            ffi::lua_insert(state, -3);
+            ffi::lua_call(state, 2, 1);
+        }
+        _ => unreachable!(),
+    }
+
+    1
+}
+
+// Similar to `meta_index_impl`, checks `field_setters` table first, then `__newindex` metamethod.
+// Used only if `field_setters` set.
+unsafe extern "C" fn meta_newindex_impl(state: *mut ffi::lua_State) -> c_int {
+    // stack: self, key, value
+    ffi::luaL_checkstack(state, 2, ptr::null());
+
+    // lookup in `field_setters` table
+    ffi::lua_pushvalue(state, -2); // `key` arg
+    if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
+        ffi::lua_remove(state, -3); // remove `key`
+        ffi::lua_insert(state, -3); // move function
+        ffi::lua_call(state, 2, 0);
+        return 0;
+    }
+    ffi::lua_pop(state, 1); // pop the nil value
+
+    // lookup in `__newindex`
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    match ffi::lua_type(state, -1) {
+        ffi::LUA_TNIL => {
+            ffi::lua_pop(state, 1); // pop the nil value
+            let field = ffi::lua_tostring(state, -2);
+            ffi::luaL_error(state, cstr!("attempt to set an unknown field '%s'"), field);
+        }
+        ffi::LUA_TTABLE => {
+            ffi::lua_insert(state, -3);
+            ffi::lua_settable(state, -3);
+        }
+        ffi::LUA_TFUNCTION => {
+            ffi::lua_insert(state, -4);
+            ffi::lua_call(state, 3, 0);
+        }
+        _ => unreachable!(),
+    }
+
+    0
+}
+
 // Populates the given table with the appropriate members to be a userdata metatable for the given type.
 // This function takes the given table at the `metatable` index, and adds an appropriate `__gc` member
 // to it for the given type and a `__metatable` entry to protect the table from script access.
@@ -306,94 +408,6 @@ pub unsafe fn init_userdata_metatable<T>(
     field_setters: Option<c_int>,
     methods: Option<c_int>,
 ) -> Result<()> {
-    // Wrapper to lookup in `field_getters` first, then `methods`, ending original `__index`.
-    // Used only if `field_getters` or `methods` set.
-    unsafe extern "C" fn meta_index_impl(state: *mut ffi::lua_State) -> c_int {
-        // stack: self, key
-        ffi::luaL_checkstack(state, 2, ptr::null());
-
-        // lookup in `field_getters` table
-        if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
-            ffi::lua_pushvalue(state, -1); // `key` arg
-            if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
-                ffi::lua_insert(state, -3); // move function
-                ffi::lua_pop(state, 1); // remove `key`
-                ffi::lua_call(state, 1, 1);
-                return 1;
-            }
-            ffi::lua_pop(state, 1); // pop the nil value
-        }
-        // lookup in `methods` table
-        if ffi::lua_isnil(state, ffi::lua_upvalueindex(3)) == 0 {
-            ffi::lua_pushvalue(state, -1); // `key` arg
-            if ffi::lua_rawget(state, ffi::lua_upvalueindex(3)) != ffi::LUA_TNIL {
-                ffi::lua_insert(state, -3);
-                ffi::lua_pop(state, 2);
-                return 1;
-            }
-            ffi::lua_pop(state, 1); // pop the nil value
-        }
-
-        // lookup in `__index`
-        ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
-        match ffi::lua_type(state, -1) {
-            ffi::LUA_TNIL => {
-                ffi::lua_pop(state, 1); // pop the nil value
-                let field = ffi::lua_tostring(state, -1);
-                ffi::luaL_error(state, cstr!("attempt to get an unknown field '%s'"), field);
-            }
-            ffi::LUA_TTABLE => {
-                ffi::lua_insert(state, -2);
-                ffi::lua_gettable(state, -2);
-            }
-            ffi::LUA_TFUNCTION => {
-                ffi::lua_insert(state, -3);
-                ffi::lua_call(state, 2, 1);
-            }
-            _ => unreachable!(),
-        }
-
-        1
-    }
-
-    // Similar to `meta_index_impl`, checks `field_setters` table first, then `__newindex` metamethod.
-    // Used only if `field_setters` set.
-    unsafe extern "C" fn meta_newindex_impl(state: *mut ffi::lua_State) -> c_int {
-        // stack: self, key, value
-        ffi::luaL_checkstack(state, 2, ptr::null());
-
-        // lookup in `field_setters` table
-        ffi::lua_pushvalue(state, -2); // `key` arg
-        if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
-            ffi::lua_remove(state, -3); // remove `key`
-            ffi::lua_insert(state, -3); // move function
-            ffi::lua_call(state, 2, 0);
-            return 0;
-        }
-        ffi::lua_pop(state, 1); // pop the nil value
-
-        // lookup in `__newindex`
-        ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
-        match ffi::lua_type(state, -1) {
-            ffi::LUA_TNIL => {
-                ffi::lua_pop(state, 1); // pop the nil value
-                let field = ffi::lua_tostring(state, -2);
-                ffi::luaL_error(state, cstr!("attempt to set an unknown field '%s'"), field);
-            }
-            ffi::LUA_TTABLE => {
-                ffi::lua_insert(state, -3);
-                ffi::lua_settable(state, -3);
-            }
-            ffi::LUA_TFUNCTION => {
-                ffi::lua_insert(state, -4);
-                ffi::lua_call(state, 3, 0);
-            }
-            _ => unreachable!(),
-        }
-
-        0
-    }
-
     ffi::lua_pushvalue(state, metatable);
 
     if field_getters.is_some() || methods.is_some() {
@@ -445,6 +459,144 @@ pub unsafe fn init_userdata_metatable<T>(
     Ok(())
 }
 
+// Installs a `__tostring` metamethod formatting the userdata as `name: 0x...` into the metatable
+// at index `metatable`, unless it already has one.
+// Uses 3 stack spaces, does not call checkstack.
+pub unsafe fn init_userdata_tostring(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    name: &str,
+) -> Result<()> {
+    unsafe extern "C" fn userdata_tostring_impl(state: *mut ffi::lua_State) -> c_int {
+        let name = ffi::lua_tostring(state, ffi::lua_upvalueindex(1));
+        ffi::lua_pushfstring(state, cstr!("%s: %p"), name, ffi::lua_topointer(state, 1));
+        1
+    }
+
+    let metatable = ffi::lua_absindex(state, metatable);
+    push_string(state, "__tostring")?;
+    if ffi::lua_rawget(state, metatable) != ffi::LUA_TNIL {
+        ffi::lua_pop(state, 1);
+        return Ok(());
+    }
+    ffi::lua_pop(state, 1);
+
+    push_string(state, name)?;
+    protect_lua(state, 1, 1, |state| {
+        ffi::lua_pushcclosure(state, userdata_tostring_impl, 1)
+    })?;
+    rawset_field(state, metatable, "__tostring")
+}
+
+// Returns the name of the type `T` with module paths removed, e.g. `Wrapper<String>`.
+pub fn short_type_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let mut short = String::with_capacity(name.len());
+    // Start of the path being written, truncated to on every `::`
+    let mut path_start = 0;
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            short.truncate(path_start);
+            continue;
+        }
+        short.push(c);
+        if !(c.is_alphanumeric() || c == '_') {
+            path_start = short.len();
+        }
+    }
+    short
+}
+
+// Pushes the `field_getters` table installed by `init_userdata_metatable` for the userdata
+// metatable at index `metatable`. Returns false (and pushes nothing) if there are no field getters.
+// Uses 2 stack spaces, does not call checkstack.
+pub unsafe fn push_userdata_field_getters(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+) -> Result<bool> {
+    let metatable = ffi::lua_absindex(state, metatable);
+    push_string(state, "__index")?;
+    ffi::lua_rawget(state, metatable);
+    if is_meta_index_impl(state, -1) && !ffi::lua_getupvalue(state, -1, 2).is_null() {
+        if ffi::lua_istable(state, -1) != 0 {
+            ffi::lua_remove(state, -2);
+            return Ok(true);
+        }
+        ffi::lua_pop(state, 1);
+    }
+    ffi::lua_pop(state, 1);
+    Ok(false)
+}
+
+// Checks whether the value at index `idx` is the `__index` wrapper installed by
+// `init_userdata_metatable`.
+pub unsafe fn is_meta_index_impl(state: *mut ffi::lua_State, idx: c_int) -> bool {
+    ffi::lua_iscfunction(state, idx) != 0
+        && ffi::lua_tocfunction(state, idx) as usize
+            == meta_index_impl as ffi::lua_CFunction as usize
+}
+
+// Checks whether the value at index `idx` is the `__index` or `__newindex` wrapper installed by
+// `init_userdata_metatable`.
+unsafe fn is_userdata_meta_wrapper(state: *mut ffi::lua_State, idx: c_int) -> bool {
+    is_meta_index_impl(state, idx)
+        || ffi::lua_iscfunction(state, idx) != 0
+            && ffi::lua_tocfunction(state, idx) as usize
+                == meta_newindex_impl as ffi::lua_CFunction as usize
+}
+
+// Replaces the `methods` table used by the `__index` wrapper of the userdata metatable at index
+// `metatable` with the table at index `methods`. Installs the wrapper if it does not exist yet.
+// Uses 4 stack spaces, does not call checkstack.
+pub unsafe fn set_userdata_methods(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    methods: c_int,
+) -> Result<()> {
+    let metatable = ffi::lua_absindex(state, metatable);
+    let methods = ffi::lua_absindex(state, methods);
+    push_string(state, "__index")?;
+    ffi::lua_rawget(state, metatable);
+    if is_userdata_meta_wrapper(state, -1) {
+        ffi::lua_pushvalue(state, methods);
+        ffi::lua_setupvalue(state, -2, 3);
+        ffi::lua_pop(state, 1);
+        return Ok(());
+    }
+
+    // Wrap the original `__index` on top of the stack
+    ffi::lua_pushnil(state);
+    ffi::lua_pushvalue(state, methods);
+    protect_lua(state, 3, 1, |state| {
+        ffi::lua_pushcclosure(state, meta_index_impl, 3)
+    })?;
+    rawset_field(state, metatable, "__index")
+}
+
+// Pops the value on top of the stack and sets it as the metamethod `name` of the userdata
+// metatable at index `metatable`. If the `__index` or `__newindex` metamethod is wrapped by
+// `init_userdata_metatable`, the value replaces the original metamethod captured by the wrapper.
+// Uses 3 stack spaces, does not call checkstack.
+pub unsafe fn set_userdata_metamethod(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    name: &str,
+) -> Result<()> {
+    let metatable = ffi::lua_absindex(state, metatable);
+    push_string(state, name)?;
+    ffi::lua_rawget(state, metatable);
+    if is_userdata_meta_wrapper(state, -1) {
+        ffi::lua_insert(state, -2);
+        ffi::lua_setupvalue(state, -2, 1);
+        ffi::lua_pop(state, 1);
+        return Ok(());
+    }
+    ffi::lua_pop(state, 1);
+    rawset_field(state, metatable, name)
+}
+
 pub unsafe extern "C" fn userdata_destructor<T>(state: *mut ffi::lua_State) -> c_int {
     callback_error(state, |_| {
         check_stack(state, 1)?;
@@ -828,7 +980,6 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) -> Result<()> {
         "__newindex",
         "__call",
         "__tostring",
-        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
         "__pairs",
         #[cfg(any(feature = "lua53", feature = "lua52"))]
         "__ipairs",
@@ -898,5 +1049,18 @@ pub(crate) unsafe fn get_destructed_userdata_metatable(state: *mut ffi::lua_Stat
     ffi::lua_rawgetp(state, ffi::LUA_REGISTRYINDEX, key);
 }
 
+// Checks whether the userdata at index `idx` was destructed by `take_userdata`.
+// Uses 2 stack spaces, does not call checkstack.
+pub(crate) unsafe fn is_destructed_userdata(state: *mut ffi::lua_State, idx: c_int) -> bool {
+    let idx = ffi::lua_absindex(state, idx);
+    if ffi::lua_getmetatable(state, idx) == 0 {
+        return false;
+    }
+    get_destructed_userdata_metatable(state);
+    let destructed = ffi::lua_rawequal(state, -1, -2) != 0;
+    ffi::lua_pop(state, 2);
+    destructed
+}
+
 static DESTRUCTED_USERDATA_METATABLE: u8 = 0;
 static ERROR_PRINT_BUFFER_KEY: u8 = 0;
@@ -1,5 +1,9 @@
+use std::collections::HashMap;
 use std::iter::{self, FromIterator};
-use std::{slice, str, vec};
+use std::ops::Deref;
+use std::os::raw::c_void;
+use std::string::String as StdString;
+use std::{mem, ptr, slice, str, vec};
 
 #[cfg(feature = "serialize")]
 use {
@@ -8,6 +12,7 @@ use {
 };
 
 use crate::error::{Error, Result};
+use crate::ffi;
 use crate::function::Function;
 use crate::lua::Lua;
 use crate::string::String;
@@ -15,6 +20,7 @@ use crate::table::Table;
 use crate::thread::Thread;
 use crate::types::{Integer, LightUserData, Number};
 use crate::userdata::AnyUserData;
+use crate::util::{check_stack, protect_lua, StackGuard};
 
 /// A dynamically typed Lua value. The `String`, `Table`, `Function`, `Thread`, and `UserData`
 /// variants contain handle types into the internal Lua state. It is a logic error to mix handle
@@ -70,20 +76,266 @@ impl<'lua> Value<'lua> {
 
     /// Compares two values for equality.
     ///
+    /// This is the Lua `==` operator (`lua_compare` with `LUA_OPEQ`), so integers and floats with
+    /// the same mathematical value are equal and the `__eq` metamethod is respected.
     /// Equality comparisons do not convert strings to numbers or vice versa.
     /// Tables, Functions, Threads, and Userdata are compared by reference:
-    /// two objects are considered equal only if they are the same object.
+    /// two objects are considered equal only if they are the same object or `__eq` says so.
     ///
     /// If Tables or Userdata have `__eq` metamethod then mlua will try to invoke it.
     /// The first value is checked first. If that value does not define a metamethod
     /// for `__eq`, then mlua will check the second value.
     /// Then mlua calls the metamethod with the two values as arguments, if found.
+    /// Lua 5.2, 5.1 and LuaJIT only call `__eq` when both values share it, so there mlua emulates
+    /// the lookup above to give the same results on every Lua version.
+    ///
+    /// Unlike this method, the [`PartialEq`] implementation never calls into Lua: it compares
+    /// handles by identity and does not invoke metamethods.
+    ///
+    /// [`PartialEq`]: https://doc.rust-lang.org/std/cmp/trait.PartialEq.html
     pub fn equals<T: AsRef<Self>>(&self, other: T) -> Result<bool> {
-        match (self, other.as_ref()) {
+        let other = other.as_ref();
+        match (self, other) {
+            #[cfg(any(feature = "lua52", feature = "lua51", feature = "luajit"))]
             (Value::Table(a), Value::Table(b)) => a.equals(b),
+            #[cfg(any(feature = "lua52", feature = "lua51", feature = "luajit"))]
             (Value::UserData(a), Value::UserData(b)) => a.equals(b),
-            _ => Ok(self == other.as_ref()),
+            _ => self.compare(other, false),
+        }
+    }
+
+    /// Compares two values for equality without invoking the `__eq` metamethod.
+    ///
+    /// Equivalent to the Lua `rawequal` function. Integers and floats with the same mathematical
+    /// value are still equal.
+    pub fn raw_equals<T: AsRef<Self>>(&self, other: T) -> Result<bool> {
+        self.compare(other.as_ref(), true)
+    }
+
+    /// Converts the value to a string the same way as the Lua `tostring` function.
+    ///
+    /// Tables and userdata with a `__tostring` metamethod are rendered by calling it (errors from
+    /// the metamethod are returned), other references are rendered as e.g. `table: 0x...`.
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`. Unlike the
+    /// [`Debug`] output, the result is what scripts see, which makes it useful for logging.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let value: Value = lua
+    ///     .load("setmetatable({}, { __tostring = function() return 'point(1, 2)' end })")
+    ///     .eval()?;
+    /// assert_eq!(value.to_string_lossy()?, "point(1, 2)");
+    /// assert_eq!(Value::Number(0.5).to_string_lossy()?, "0.5");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Debug`]: https://doc.rust-lang.org/std/fmt/trait.Debug.html
+    pub fn to_string_lossy(&self) -> Result<StdString> {
+        let lua = match self.lua() {
+            Some(lua) => lua,
+            None => return Ok(primitive_to_string(self)),
+        };
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 4)?;
+
+            lua.push_value(self.clone())?;
+            protect_lua(lua.state, 1, 1, |state| {
+                ffi::luaL_tolstring(state, -1, ptr::null_mut());
+            })?;
+            // `luaL_tolstring` always leaves a string on the stack
+            let s = String(lua.pop_ref());
+            Ok(s.to_string_lossy().into_owned())
+        }
+    }
+
+    fn compare(&self, other: &Self, raw: bool) -> Result<bool> {
+        let lua = match self.lua().or_else(|| other.lua()) {
+            Some(lua) => lua,
+            // Neither value lives in Lua, so there are no metamethods to call
+            None => return Ok(primitive_eq(self, other)),
+        };
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 5)?;
+
+            lua.push_value(self.clone())?;
+            lua.push_value(other.clone())?;
+            if raw {
+                Ok(ffi::lua_rawequal(lua.state, -2, -1) != 0)
+            } else {
+                protect_lua(lua.state, 2, 0, |state| {
+                    ffi::lua_compare(state, -2, -1, ffi::LUA_OPEQ) != 0
+                })
+            }
+        }
+    }
+
+    /// Creates a deep, immutable snapshot of the value.
+    ///
+    /// Tables are copied recursively into frozen tables. Only values are frozen: keys are kept as
+    /// is, so tables used as keys keep their identity (and stay mutable). A frozen table is an
+    /// empty proxy made readonly with [`Table::set_readonly`], whose `__index` metamethod reads
+    /// from the copied data, so Lua code can read the value but any assignment fails. The
+    /// metatable of a frozen table is protected with a `__metatable` field. `#` and `pairs` are
+    /// forwarded to the copied data in Lua 5.2+ (or in Lua 5.1 with
+    /// [`Lua::enable_pairs_metamethod`]). Metatables of the original tables are not copied, and
+    /// shared or cyclic references are preserved in the copy. Note that `rawset` bypasses
+    /// metamethods and therefore can still add fields to a frozen table.
+    ///
+    /// Other values are immutable already and are kept as is. Functions, threads and userdata
+    /// are not copied, so their own state is not frozen.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let config = lua.load("{ name = 'app', limits = { depth = 3 } }").eval::<Value>()?;
+    /// lua.globals().set("config", config.freeze()?)?;
+    ///
+    /// assert_eq!(lua.load("config.limits.depth").eval::<i64>()?, 3);
+    /// assert!(lua.load("config.name = 'other'").exec().is_err());
+    /// assert!(lua.load("config.limits.depth = 10").exec().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Table::set_readonly`]: struct.Table.html#method.set_readonly
+    /// [`Lua::enable_pairs_metamethod`]: struct.Lua.html#method.enable_pairs_metamethod
+    pub fn freeze(&self) -> Result<FrozenValue<'lua>> {
+        self.clone()
+            .freeze_with(&mut HashMap::new())
+            .map(FrozenValue)
+    }
+
+    pub(crate) fn freeze_with(
+        self,
+        frozen: &mut HashMap<*const c_void, Table<'lua>>,
+    ) -> Result<Value<'lua>> {
+        match self {
+            Value::Table(t) => t.freeze(frozen).map(Value::Table),
+            value => Ok(value),
+        }
+    }
+
+    fn lua(&self) -> Option<&'lua Lua> {
+        match self {
+            Value::String(String(lref))
+            | Value::Table(Table(lref))
+            | Value::Function(Function(lref))
+            | Value::Thread(Thread(lref))
+            | Value::UserData(AnyUserData(lref)) => Some(lref.lua),
+            _ => None,
+        }
+    }
+}
+
+/// An immutable snapshot of a Lua value, created by [`Value::freeze`].
+///
+/// Dereferences to the frozen [`Value`], and converts to it when passed to Lua.
+///
+/// [`Value::freeze`]: enum.Value.html#method.freeze
+/// [`Value`]: enum.Value.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenValue<'lua>(Value<'lua>);
+
+impl<'lua> FrozenValue<'lua> {
+    /// Consumes the snapshot and returns the frozen value.
+    pub fn into_value(self) -> Value<'lua> {
+        self.0
+    }
+}
+
+impl<'lua> Deref for FrozenValue<'lua> {
+    type Target = Value<'lua>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'lua> ToLua<'lua> for FrozenValue<'lua> {
+    fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(self.0)
+    }
+}
+
+// Clears the vector and changes the lifetime of its (now absent) values, keeping the allocation.
+fn relabel_empty_vec<'a, 'b>(mut v: Vec<Value<'a>>) -> Vec<Value<'b>> {
+    // Values must be dropped with their own lifetime
+    v.clear();
+    let mut v = mem::ManuallyDrop::new(v);
+    // Safety: `Value<'a>` and `Value<'b>` have the same layout, and the vector holds no values
+    unsafe { Vec::from_raw_parts(v.as_mut_ptr().cast(), 0, v.capacity()) }
+}
+
+// Converts a value that is not a reference to a Lua object like `tostring` does.
+fn primitive_to_string(value: &Value) -> StdString {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::LightUserData(ud) => format!("userdata: {:p}", ud.0),
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) => number_to_string(*n),
+        Value::Error(e) => e.to_string(),
+        value => format!("{}: {:?}", value.type_name(), value),
+    }
+}
+
+// Formats a float with the `%.14g` format used by Lua (plus `.0` for integral values in Lua 5.3+).
+fn number_to_string(n: Number) -> StdString {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    // Scientific representation with 14 significant digits gives the exponent after rounding
+    let sci = format!("{:.13e}", n);
+    let (mantissa, exp) = sci.split_at(mlua_expect!(sci.find('e'), "missing exponent"));
+    let exp: i32 = mlua_expect!(exp[1..].parse(), "invalid exponent");
+    let s = if !(-4..14).contains(&exp) {
+        let mantissa = trim_fraction(mantissa);
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exp.abs())
+    } else {
+        trim_fraction(&format!("{:.*}", (13 - exp) as usize, n)).to_string()
+    };
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    if !s.contains(&['.', 'e', 'n', 'i'][..]) {
+        return s + ".0";
+    }
+    s
+}
+
+// Removes trailing zeros of the fractional part, and the decimal point if nothing is left.
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+// Compares values that are not references to Lua objects, following Lua number semantics.
+fn primitive_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        #[cfg(any(feature = "lua54", feature = "lua53"))]
+        (Value::Integer(i), Value::Number(n)) | (Value::Number(n), Value::Integer(i)) => {
+            // Integers and floats are compared by their exact mathematical values
+            n.fract() == 0.0
+                && *n >= -9223372036854775808.0
+                && *n < 9223372036854775808.0
+                && *n as Integer == *i
         }
+        _ => a == b,
     }
 }
 
@@ -152,6 +404,9 @@ pub trait FromLua<'lua>: Sized {
 }
 
 /// Multiple Lua values used for both argument passing and also for multiple return values.
+///
+/// Values are always exposed in argument order (first argument first), both when iterating and
+/// when converting to or from a `Vec`. The internal storage order is an implementation detail.
 #[derive(Debug, Clone)]
 pub struct MultiValue<'lua>(Vec<Value<'lua>>);
 
@@ -193,29 +448,88 @@ impl<'a, 'lua> IntoIterator for &'a MultiValue<'lua> {
 }
 
 impl<'lua> MultiValue<'lua> {
+    /// Creates a `MultiValue` from a vector of values in argument order.
+    ///
+    /// The first element of the vector becomes the first argument (or return value).
     pub fn from_vec(mut v: Vec<Value<'lua>>) -> MultiValue<'lua> {
         v.reverse();
         MultiValue(v)
     }
 
+    /// Consumes the `MultiValue` and returns its values in argument order.
+    ///
+    /// This is the inverse of [`from_vec`], so `MultiValue::from_vec(v).into_vec() == v`.
+    ///
+    /// [`from_vec`]: #method.from_vec
     pub fn into_vec(self) -> Vec<Value<'lua>> {
         let mut v = self.0;
         v.reverse();
         v
     }
 
+    // Drops the values and returns the emptied allocation, which no longer borrows `Lua`.
+    pub(crate) fn into_empty_vec(self) -> Vec<Value<'static>> {
+        relabel_empty_vec(self.0)
+    }
+
+    // Creates an empty `MultiValue` reusing an allocation returned by `into_empty_vec`.
+    pub(crate) fn from_empty_vec(v: Vec<Value<'static>>) -> MultiValue<'lua> {
+        MultiValue(relabel_empty_vec(v))
+    }
+
     pub(crate) fn reserve(&mut self, size: usize) {
         self.0.reserve(size);
     }
 
-    pub(crate) fn push_front(&mut self, value: Value<'lua>) {
+    // Removes all values in argument order, keeping the allocation.
+    pub(crate) fn drain_all(&mut self) -> iter::Rev<vec::Drain<'_, Value<'lua>>> {
+        self.0.drain(..).rev()
+    }
+
+    /// Inserts a value before the first one, so it becomes the first argument.
+    pub fn push_front(&mut self, value: Value<'lua>) {
         self.0.push(value);
     }
 
-    pub(crate) fn pop_front(&mut self) -> Option<Value<'lua>> {
+    /// Removes and returns the first value, or `None` if there are no values.
+    pub fn pop_front(&mut self) -> Option<Value<'lua>> {
         self.0.pop()
     }
 
+    /// Appends a value after the last one, so it becomes the last argument.
+    ///
+    /// Values are stored to make [`push_front`] and [`pop_front`] cheap, so this is `O(n)`.
+    /// Prefer collecting from an iterator or [`from_vec`] when building long argument lists.
+    ///
+    /// [`push_front`]: #method.push_front
+    /// [`pop_front`]: #method.pop_front
+    /// [`from_vec`]: #method.from_vec
+    pub fn push_back(&mut self, value: Value<'lua>) {
+        self.0.insert(0, value);
+    }
+
+    /// Removes and returns the last value, or `None` if there are no values.
+    ///
+    /// Like [`push_back`], this is `O(n)`.
+    ///
+    /// [`push_back`]: #method.push_back
+    pub fn pop_back(&mut self) -> Option<Value<'lua>> {
+        if self.0.is_empty() {
+            return None;
+        }
+        Some(self.0.remove(0))
+    }
+
+    /// Returns a reference to the value at `index`, where index 0 is the first argument.
+    pub fn get(&self, index: usize) -> Option<&Value<'lua>> {
+        let len = self.0.len();
+        if index < len {
+            self.0.get(len - 1 - index)
+        } else {
+            None
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -227,6 +541,21 @@ impl<'lua> MultiValue<'lua> {
     pub fn iter(&self) -> iter::Rev<slice::Iter<Value<'lua>>> {
         self.0.iter().rev()
     }
+
+    /// Converts the values into `T` one by one, stopping at the first value that fails.
+    ///
+    /// Unlike `FromLuaMulti` conversions, the values converted before the failure are not lost:
+    /// they are returned together with the error (if any).
+    pub fn try_into_partial<T: FromLua<'lua>>(self, lua: &'lua Lua) -> (Vec<T>, Option<Error>) {
+        let mut converted = Vec::with_capacity(self.len());
+        for value in self {
+            match T::from_lua(value, lua) {
+                Ok(v) => converted.push(v),
+                Err(err) => return (converted, Some(err)),
+            }
+        }
+        (converted, None)
+    }
 }
 
 /// Trait for types convertible to any number of Lua values.
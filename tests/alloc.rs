@@ -0,0 +1,53 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mlua::{Function, Lua, Result};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_call_reuses_multivalue() -> Result<()> {
+    let lua = Lua::new();
+
+    let sum: Function = lua.load("function(a, b, c) return a + b + c end").eval()?;
+    let callback = lua.create_function(|_, (a, b, c): (i64, i64, i64)| Ok(a + b + c))?;
+    lua.globals().set("callback", callback)?;
+    let call_callback: Function = lua
+        .load("function() for i = 1, 1000 do callback(i, i + 1, i + 2) end end")
+        .eval()?;
+
+    // Warm up the cache
+    sum.call::<_, i64>((1, 2, 3))?;
+    call_callback.call::<_, ()>(())?;
+
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    for i in 0..1000 {
+        assert_eq!(sum.call::<_, i64>((i, i, i))?, 3 * i);
+    }
+    call_callback.call::<_, ()>(())?;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - start;
+
+    assert!(
+        allocations < 10,
+        "2000 calls made {} allocations",
+        allocations
+    );
+
+    Ok(())
+}
@@ -10,6 +10,7 @@ use std::time::Duration;
 
 use futures_timer::Delay;
 use futures_util::stream::TryStreamExt;
+use tokio::sync::mpsc;
 
 use mlua::{
     Error, Function, Lua, Result, Table, TableExt, Thread, UserData, UserDataMethods, Value,
@@ -45,6 +46,51 @@ async fn test_async_sleep() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_async_uncaught_error_handler() -> Result<()> {
+    let lua = Lua::new();
+
+    let errors = Arc::new(AtomicI64::new(0));
+    let errors2 = errors.clone();
+    lua.set_uncaught_error_handler(move |_| {
+        errors2.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let sleep = lua.create_async_function(move |_lua, n: u64| async move {
+        Delay::new(Duration::from_millis(n)).await;
+        Ok(())
+    })?;
+    lua.globals().set("sleep", sleep)?;
+
+    lua.load("sleep(10)").exec_async().await?;
+    assert_eq!(errors.load(Ordering::Relaxed), 0);
+
+    assert!(lua
+        .load("sleep(10); error('boom')")
+        .exec_async()
+        .await
+        .is_err());
+    assert_eq!(errors.load(Ordering::Relaxed), 1);
+    assert!(lua
+        .load("sleep(10); error('boom')")
+        .eval_async::<()>()
+        .await
+        .is_err());
+    assert_eq!(errors.load(Ordering::Relaxed), 2);
+
+    // A chunk run from a callback is reported once, as part of the outermost chunk
+    let run = lua.create_function(|lua, code: String| lua.load(&code).exec())?;
+    lua.globals().set("run", run)?;
+    assert!(lua
+        .load("sleep(10); run('error(1)')")
+        .exec_async()
+        .await
+        .is_err());
+    assert_eq!(errors.load(Ordering::Relaxed), 3);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_async_call() -> Result<()> {
     let lua = Lua::new();
@@ -70,6 +116,48 @@ async fn test_async_call() -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "timeout")]
+#[tokio::test]
+async fn test_async_call_timeout() -> Result<()> {
+    let lua = Lua::new();
+
+    let sleep = lua.create_async_function(|_lua, n: u64| async move {
+        Delay::new(Duration::from_millis(n)).await;
+        Ok(n)
+    })?;
+    lua.globals().set("sleep", sleep)?;
+
+    let f = lua
+        .load(
+            r#"
+            function(n)
+                counter = (counter or 0) + 1
+                return sleep(n)
+            end
+            "#,
+        )
+        .eval::<Function>()?;
+
+    let timeout = Duration::from_millis(500);
+    assert_eq!(f.call_async_timeout::<_, u64>(10, timeout).await?, 10);
+
+    match f
+        .call_async_timeout::<_, u64>(10000, Duration::from_millis(10))
+        .await
+    {
+        Err(Error::Timeout) => {}
+        r => panic!("expected Timeout, got {:?}", r),
+    }
+    // Side effects performed before the timeout are kept
+    assert_eq!(lua.globals().get::<_, i64>("counter")?, 2);
+
+    // The state is still usable
+    assert_eq!(f.call_async_timeout::<_, u64>(10, timeout).await?, 10);
+    lua.gc_collect()?;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_async_bind_call() -> Result<()> {
     let lua = Lua::new();
@@ -318,6 +406,81 @@ async fn test_async_userdata() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_async_userdata_method_arc() -> Result<()> {
+    // Neither `Clone` nor cheap to copy
+    struct Accumulator {
+        total: AtomicI64,
+        rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<i64>>,
+    }
+
+    impl UserData for Accumulator {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_async_method_arc("receive", |_, acc, ()| async move {
+                let n = acc.rx.lock().await.recv().await;
+                let n = n.ok_or_else(|| Error::RuntimeError("channel closed".to_string()))?;
+                Ok(acc.total.fetch_add(n, Ordering::Relaxed) + n)
+            });
+
+            methods.add_method("total", |_, acc, ()| Ok(acc.total.load(Ordering::Relaxed)));
+
+            methods.add_method_mut("reset", |_, acc, ()| {
+                acc.total = AtomicI64::new(0);
+                Ok(())
+            });
+        }
+    }
+
+    let new_accumulator = || {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let acc = Accumulator {
+            total: AtomicI64::new(0),
+            rx: tokio::sync::Mutex::new(rx),
+        };
+        (tx, acc)
+    };
+
+    let lua = Lua::new();
+
+    let (tx, acc) = new_accumulator();
+    let acc = Arc::new(acc);
+    lua.globals()
+        .set("acc", lua.create_userdata(acc.clone())?)?;
+
+    // Both calls wait on the channel at the same time and see the same state
+    let receive: Function = lua.load("function() return acc:receive() end").eval()?;
+    let first = receive.call_async::<_, i64>(());
+    let second = receive.call_async::<_, i64>(());
+    tx.send(1).unwrap();
+    tx.send(2).unwrap();
+    let (a, b) = futures::try_join!(first, second)?;
+    assert_eq!(a.max(b), 3);
+    assert_eq!(acc.total.load(Ordering::Relaxed), 3);
+    assert_eq!(lua.load("acc:total()").eval::<i64>()?, 3);
+
+    // Shared values cannot be borrowed mutably
+    match lua.load("acc:reset()").exec() {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::UserDataBorrowMutError => {}
+            e => panic!("expected `UserDataBorrowMutError` error cause, got {:?}", e),
+        },
+        r => panic!("improper return for shared userdata: {:?}", r),
+    };
+
+    // Userdata not created from an `Arc` do not support the method
+    let (_tx, acc) = new_accumulator();
+    lua.globals().set("acc", lua.create_userdata(acc)?)?;
+    match lua.load("acc:receive()").exec_async().await {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::UserDataTypeMismatch => {}
+            e => panic!("expected `UserDataTypeMismatch` error cause, got {:?}", e),
+        },
+        r => panic!("improper return for non-`Arc` userdata: {:?}", r),
+    };
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_async_scope() -> Result<()> {
     let ref lua = Lua::new();
@@ -435,3 +598,32 @@ async fn test_async_scope_userdata() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_create_async_sleep() -> Result<()> {
+    let lua = Lua::new();
+    lua.globals().set("sleep", lua.create_async_sleep()?)?;
+
+    // Sleeping yields to the executor, so both calls make progress together
+    let f: Function = lua
+        .load("function(secs) sleep(secs) return secs end")
+        .eval()?;
+    let start = std::time::Instant::now();
+    let (a, b) = futures::try_join!(f.call_async::<_, f64>(0.1), f.call_async::<_, f64>(0.1))?;
+    let elapsed = start.elapsed();
+    assert_eq!((a, b), (0.1, 0.1));
+    assert!(elapsed >= Duration::from_millis(100));
+
+    lua.load("sleep(0)").exec_async().await?;
+
+    match lua.load("sleep(-1)").exec_async().await {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::FromLuaConversionError { .. } => {}
+            e => panic!("expected `FromLuaConversionError` error cause, got {:?}", e),
+        },
+        r => panic!("improper return for negative duration: {:?}", r),
+    };
+
+    Ok(())
+}
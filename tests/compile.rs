@@ -6,9 +6,11 @@ fn test_compilation() {
     t.compile_fail("tests/compile/function_borrow.rs");
     t.compile_fail("tests/compile/lua_norefunwindsafe.rs");
     t.compile_fail("tests/compile/ref_nounwindsafe.rs");
+    t.compile_fail("tests/compile/scope_borrow_nonstatic.rs");
     t.compile_fail("tests/compile/scope_callback_capture.rs");
     t.compile_fail("tests/compile/scope_callback_inner.rs");
     t.compile_fail("tests/compile/scope_callback_outer.rs");
+    t.compile_fail("tests/compile/scope_escape.rs");
     t.compile_fail("tests/compile/scope_invariance.rs");
     t.compile_fail("tests/compile/scope_mutable_aliasing.rs");
     t.compile_fail("tests/compile/scope_userdata_borrow.rs");
@@ -0,0 +1,18 @@
+use mlua::{Lua, UserData};
+
+fn main() {
+    // Should not allow borrowing non-'static userdata with a longer lifetime than its value
+    struct MyUserData<'a>(&'a str);
+    impl<'a> UserData for MyUserData<'a> {};
+
+    let lua = Lua::new();
+    let value = String::from("value");
+    let _s: &'static str = lua
+        .scope(|scope| {
+            let builder = scope.userdata_builder::<MyUserData<'static>>()?;
+            let ud = scope.create_nonstatic_userdata_cached(&builder, MyUserData(&value))?;
+            let s = scope.borrow_nonstatic(&builder, &ud)?.0;
+            Ok(s)
+        })
+        .unwrap();
+}
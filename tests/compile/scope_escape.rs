@@ -0,0 +1,13 @@
+use mlua::{AnyUserData, Lua, UserData};
+
+struct MyUserData<'a>(&'a i64);
+
+impl<'a> UserData for MyUserData<'a> {}
+
+fn main() {
+    let lua = Lua::new();
+    let i = 1;
+    let _escaped: AnyUserData = lua
+        .scope(|scope| scope.create_nonstatic_userdata(MyUserData(&i)))
+        .unwrap();
+}
@@ -1,9 +1,10 @@
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use maplit::{btreemap, btreeset, hashmap, hashset};
-use mlua::{Lua, Result};
+use mlua::{Error, Lua, Result};
 
 #[test]
 fn test_conv_vec() -> Result<()> {
@@ -97,6 +98,35 @@ fn test_conv_cow() -> Result<()> {
     let s2: String = lua.globals().get("s")?;
     assert_eq!(s, s2);
 
+    let owned: Cow<str> = Cow::Owned("owned".to_string());
+    lua.globals().set("s", owned)?;
+    assert_eq!(lua.globals().get::<_, String>("s")?, "owned");
+
+    let bytes: Cow<[u8]> = Cow::Borrowed(b"\xffbytes");
+    lua.globals().set("b", bytes)?;
+    let b: mlua::String = lua.globals().get("b")?;
+    assert_eq!(b.as_bytes(), b"\xffbytes");
+
+    Ok(())
+}
+
+#[test]
+fn test_conv_os_str() -> Result<()> {
+    let lua = Lua::new();
+
+    lua.globals().set("s", OsStr::new("hello"))?;
+    lua.globals().set("s2", OsString::from("world"))?;
+    assert_eq!(lua.load("s .. ' ' .. s2").eval::<String>()?, "hello world");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        match lua.globals().set("s", OsStr::from_bytes(b"\xff")) {
+            Err(Error::ToLuaConversionError { from: "OsStr", .. }) => {}
+            r => panic!("expected ToLuaConversionError, got {:?}", r),
+        }
+    }
+
     Ok(())
 }
 
@@ -123,3 +153,136 @@ fn test_conv_boxed_slice() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_conv_array() -> Result<()> {
+    let lua = Lua::new();
+
+    let v = [1, 2, 3];
+    lua.globals().set("v", v)?;
+    let v2: [i32; 3] = lua.globals().get("v")?;
+    assert_eq!(v, v2);
+
+    let v3: [String; 40] = std::array::from_fn(|i| i.to_string());
+    lua.globals().set("v3", &v3)?;
+    assert_eq!(lua.globals().get::<_, [String; 40]>("v3")?, v3);
+
+    let check_err = |code: &str, expected: &str| match lua.load(code).eval::<[i32; 3]>() {
+        Err(Error::FromLuaConversionError {
+            message: Some(ref message),
+            ..
+        }) => assert_eq!(message, expected),
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    };
+    check_err("{1, 2}", "expected table of length 3, got 2");
+    check_err("{1, 2, 3, 4}", "expected table of length 3, got 4");
+    // The constructor sizes the array part, so the border is at 3. LuaJIT may pick 1 instead.
+    #[cfg(not(feature = "luajit"))]
+    check_err("{1, nil, 3}", "missing value at index 2");
+    assert!(lua.load("'123'").eval::<[i32; 3]>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_conv_duration() -> Result<()> {
+    let lua = Lua::new();
+
+    lua.globals().set("d", Duration::from_millis(1500))?;
+    assert_eq!(lua.load("d").eval::<f64>()?, 1.5);
+    assert_eq!(
+        lua.load("d").eval::<Duration>()?,
+        Duration::from_millis(1500)
+    );
+    assert_eq!(lua.load("3").eval::<Duration>()?, Duration::from_secs(3));
+    assert_eq!(
+        lua.load("0.25").eval::<Duration>()?,
+        Duration::from_millis(250)
+    );
+
+    match lua.load("-1").eval::<Duration>() {
+        Err(Error::FromLuaConversionError { to: "Duration", .. }) => {}
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+    assert!(lua.load("-0.5").eval::<Duration>().is_err());
+    assert!(lua.load("0/0").eval::<Duration>().is_err());
+    assert!(lua.load("{}").eval::<Duration>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_conv_system_time() -> Result<()> {
+    let lua = Lua::new();
+
+    let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    lua.globals().set("t", time)?;
+    assert_eq!(lua.load("t").eval::<i64>()?, 1_600_000_000);
+    assert_eq!(
+        lua.load("t + 0.5").eval::<SystemTime>()?,
+        time + Duration::from_millis(500)
+    );
+    assert_eq!(
+        lua.load("-10").eval::<SystemTime>()?,
+        UNIX_EPOCH - Duration::from_secs(10)
+    );
+
+    let now = lua.load("os.time()").eval::<SystemTime>()?;
+    let diff = match SystemTime::now().duration_since(now) {
+        Ok(diff) => diff,
+        Err(err) => err.duration(),
+    };
+    assert!(diff < Duration::from_secs(5));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn test_conv_chrono_datetime() -> Result<()> {
+    use chrono::{DateTime, TimeZone, Utc};
+    use mlua::DateTimeFormat;
+
+    let lua = Lua::new();
+    let time = Utc.with_ymd_and_hms(2021, 5, 1, 12, 30, 0).unwrap();
+
+    // Timestamps by default
+    lua.globals().set("t", time)?;
+    assert_eq!(lua.load("t").eval::<i64>()?, 1_619_872_200);
+    assert_eq!(
+        lua.load("t + 0.25").eval::<DateTime<Utc>>()?,
+        time + chrono::Duration::milliseconds(250)
+    );
+    let time_ms = time + chrono::Duration::milliseconds(500);
+    lua.globals().set("t", time_ms)?;
+    assert_eq!(lua.load("t").eval::<f64>()?, 1_619_872_200.5);
+
+    // ISO 8601 strings when selected
+    lua.set_datetime_format(DateTimeFormat::Iso8601);
+    lua.globals().set("t", time)?;
+    assert_eq!(lua.load("t").eval::<String>()?, "2021-05-01T12:30:00Z");
+    assert_eq!(lua.load("t").eval::<DateTime<Utc>>()?, time);
+    lua.globals().set("t", time_ms)?;
+    assert_eq!(lua.load("t").eval::<String>()?, "2021-05-01T12:30:00.500Z");
+    assert_eq!(lua.load("t").eval::<DateTime<Utc>>()?, time_ms);
+    assert_eq!(
+        lua.load("'2021-05-01T14:30:00+02:00'")
+            .eval::<DateTime<Utc>>()?,
+        time
+    );
+
+    // Out of range or malformed values are rejected
+    match lua.load("1e300").eval::<DateTime<Utc>>() {
+        Err(Error::FromLuaConversionError { message, .. }) => {
+            assert_eq!(message.as_deref(), Some("time out of range"))
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+    match lua.load("'yesterday'").eval::<DateTime<Utc>>() {
+        Err(Error::FromLuaConversionError { .. }) => {}
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+    assert!(lua.load("{}").eval::<DateTime<Utc>>().is_err());
+
+    Ok(())
+}
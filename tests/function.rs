@@ -1,4 +1,6 @@
-use mlua::{Function, Lua, Result, String};
+use std::string::String as StdString;
+
+use mlua::{CoercionPolicy, Function, Lua, MultiValue, Result, String, Table, Value};
 
 #[test]
 fn test_function() -> Result<()> {
@@ -50,6 +52,138 @@ fn test_bind() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_bind_registry() -> Result<()> {
+    let lua = Lua::new();
+
+    let greet: Function = lua
+        .load(r#"function(self, name) return self.greeting..", "..name end"#)
+        .eval()?;
+    let this = lua.create_table()?;
+    this.set("greeting", "hello")?;
+
+    let key = lua.create_registry_value(greet.bind(this)?)?;
+    lua.gc_collect()?;
+
+    let bound = lua.registry_value::<Function>(&key)?;
+    assert_eq!(bound.call::<_, String>("world")?, "hello, world");
+    lua.globals().set("bound", bound)?;
+    lua.load(r#"assert(bound("lua") == "hello, lua")"#).exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_proxy_function() -> Result<()> {
+    let lua = Lua::new();
+
+    let concat: Function = lua
+        .load(r#"function(a, b) return a..b, "done" end"#)
+        .eval()?;
+
+    let proxy = lua.create_proxy_function(concat, |lua, target, args| {
+        // Uppercase all string arguments
+        let args = args
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => Ok(Value::String(
+                    lua.create_string(&s.to_str()?.to_uppercase())?,
+                )),
+                v => Ok(v),
+            })
+            .collect::<Result<MultiValue>>()?;
+        let mut results = target.call::<_, MultiValue>(args)?.into_vec();
+        // Drop the last result
+        results.pop();
+        Ok(MultiValue::from_vec(results))
+    })?;
+
+    lua.globals().set("proxy", proxy)?;
+    assert_eq!(
+        lua.load(r##"select("#", proxy("foo", "bar"))"##)
+            .eval::<i32>()?,
+        1
+    );
+    assert_eq!(
+        lua.load(r#"proxy("foo", "bar")"#).eval::<String>()?,
+        "FOOBAR"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_into_boxed_fn() -> Result<()> {
+    let lua = Lua::new();
+
+    let concat: Function = lua.load(r#"function(a, b) return a..b end"#).eval()?;
+    let concat = concat.into_boxed_fn::<(&str, &str), String>();
+    assert_eq!(concat(("foo", "bar"))?, "foobar");
+    assert_eq!(concat(("baz", "baf"))?, "bazbaf");
+
+    let failing: Function = lua.load(r#"function() error("boom") end"#).eval()?;
+    let failing = failing.into_boxed_fn::<(), ()>();
+    assert!(failing(()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_function_into_iter() -> Result<()> {
+    let lua = Lua::new();
+
+    let split: Function = lua
+        .load(
+            r#"
+            function(s, sep)
+                local parts = {}
+                for part in string.gmatch(s, "[^" .. sep .. "]+") do
+                    parts[#parts + 1] = part
+                end
+                return parts
+            end
+        "#,
+        )
+        .eval()?;
+    let parts = split
+        .clone()
+        .into_iter::<_, StdString>(("a,b,,c", ","))?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(parts, vec!["a", "b", "c"]);
+
+    // Elements are converted lazily
+    let mixed: Function = lua.load(r#"function() return {1, 2, "x", 4} end"#).eval()?;
+    let mut iter = mixed.into_iter::<_, i64>(())?;
+    assert_eq!(iter.next().transpose()?, Some(1));
+    assert_eq!(iter.next().transpose()?, Some(2));
+    assert!(iter.next().unwrap().is_err());
+
+    let not_table: Function = lua.load(r#"function() return 42 end"#).eval()?;
+    assert!(not_table.into_iter::<_, i64>(()).is_err());
+    let failing: Function = lua.load(r#"function() error("boom") end"#).eval()?;
+    assert!(failing.into_iter::<_, i64>(()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_function_call_raw() -> Result<()> {
+    let lua = Lua::new();
+
+    let f: Function = lua
+        .load("function(n) if n > 0 then return n, n * 2 end end")
+        .eval()?;
+
+    let results = f.call_raw(3)?;
+    assert_eq!(results.len(), 2);
+    let (a, b): (i64, i64) = lua.unpack_multi(results)?;
+    assert_eq!((a, b), (3, 6));
+
+    assert!(f.call_raw(0)?.is_empty());
+
+    Ok(())
+}
+
 #[test]
 fn test_rust_function() -> Result<()> {
     let lua = Lua::new();
@@ -89,3 +223,187 @@ fn test_dump() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_function_reentrant() -> Result<()> {
+    let lua = Lua::new();
+
+    // Every key lookup on `proxy` calls back into the function through `__index`
+    let lookup = lua.create_function_reentrant(0, |lua, depth: i64, calls| {
+        calls.set(calls.get() + 1);
+        if depth > 0 {
+            let proxy: Table = lua.globals().get("proxy")?;
+            proxy.get::<_, Value>(depth - 1)?;
+        }
+        Ok(calls.get())
+    })?;
+    lua.globals().set("lookup", lookup.clone())?;
+    lua.load("proxy = setmetatable({}, { __index = function(_, depth) return lookup(depth) end })")
+        .exec()?;
+
+    assert_eq!(lookup.call::<_, i64>(3)?, 4);
+    assert_eq!(lookup.call::<_, i64>(0)?, 5);
+
+    let lookup_mut = lua.create_function_mut(|lua, depth: i64| {
+        if depth > 0 {
+            let proxy: Table = lua.globals().get("proxy")?;
+            proxy.get::<_, Value>(depth - 1)?;
+        }
+        Ok(())
+    })?;
+    lua.globals().set("lookup", lookup_mut.clone())?;
+    assert!(lookup_mut.call::<_, ()>(3).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_function_source() -> Result<()> {
+    let lua = Lua::new();
+
+    let chunk = lua
+        .load(
+            r#"
+            local function add(a, b)
+                return a + b
+            end
+            return add
+        "#,
+        )
+        .set_name("@scripts/add.lua")?
+        .into_function()?;
+    let source = chunk.source()?.unwrap();
+    assert_eq!(source.name, "scripts/add.lua");
+    assert_eq!(source.line_defined, 0);
+    assert_eq!(source.text, None);
+
+    let add = chunk.call::<_, Function>(())?;
+    let source = add.source()?.unwrap();
+    assert_eq!(source.name, "scripts/add.lua");
+    assert_eq!((source.line_defined, source.last_line_defined), (2, 4));
+    assert_eq!(source.text, None);
+
+    // `load` keeps the source text as the chunk name
+    let mul: Function = lua
+        .load(r#"(loadstring or load)("return function(a, b) return a * b end")()"#)
+        .eval()?;
+    let source = mul.source()?.unwrap();
+    assert_eq!(source.line_defined, 1);
+    assert_eq!(
+        source.text.as_deref(),
+        Some("return function(a, b) return a * b end")
+    );
+
+    let print: Function = lua.globals().get("print")?;
+    assert_eq!(print.source()?, None);
+    let rust_func = lua.create_function(|_, ()| Ok(()))?;
+    assert_eq!(rust_func.source()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_function_call_with_policy() -> Result<()> {
+    let lua = Lua::new();
+
+    let describe = lua.create_function(|_, (n, s): (i64, StdString)| Ok(format!("{}:{}", n, s)))?;
+    let wrapper: Function = lua.load("function(f, ...) return f(...) end").eval()?;
+
+    // Lenient conversions match the default behavior
+    let lenient = CoercionPolicy::LENIENT;
+    assert_eq!(
+        describe.call_with_policy::<_, StdString>(("42", 7), lenient)?,
+        "42:7"
+    );
+    assert_eq!(
+        describe.call_with_policy::<_, StdString>((2.0, "x"), lenient)?,
+        "2:x"
+    );
+
+    // Strict policy applies to the arguments of the called function
+    let strict = CoercionPolicy::STRICT;
+    assert!(describe
+        .call_with_policy::<_, StdString>(("42", "x"), strict)
+        .is_err());
+    assert!(describe
+        .call_with_policy::<_, StdString>((42, 7), strict)
+        .is_err());
+    // Lua 5.1 and 5.2 have no separate integer type
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    assert!(describe
+        .call_with_policy::<_, StdString>((2.0, "x"), strict)
+        .is_err());
+
+    // Rust functions called from Lua code or from the called function are not affected
+    assert_eq!(
+        wrapper.call_with_policy::<_, StdString>((describe.clone(), "42", 7), strict)?,
+        "42:7"
+    );
+    let outer =
+        lua.create_function(|_, (f, n): (Function, i64)| f.call::<_, StdString>(("42", n)))?;
+    assert_eq!(
+        outer.call_with_policy::<_, StdString>((describe.clone(), 7), strict)?,
+        "42:7"
+    );
+    assert!(outer
+        .call_with_policy::<_, StdString>((describe.clone(), "7"), strict)
+        .is_err());
+
+    // Individual coercions can be enabled
+    let numbers_only = CoercionPolicy::STRICT.integer_float(true);
+    assert!(numbers_only.allows_integer_float() && !numbers_only.allows_string_number());
+    assert_eq!(
+        describe.call_with_policy::<_, StdString>((2.0, "x"), numbers_only)?,
+        "2:x"
+    );
+    assert!(describe
+        .call_with_policy::<_, StdString>(("2", "x"), numbers_only)
+        .is_err());
+
+    // Return values are converted with the policy too
+    let answer: Function = lua.load("function() return '42' end").eval()?;
+    assert!(answer.call_with_policy::<_, i64>((), strict).is_err());
+
+    // The previous policy is restored after the call
+    assert_eq!(describe.call::<_, StdString>(("42", 7))?, "42:7");
+    assert_eq!(lua.coerce_integer(Value::Number(3.0))?, Some(3));
+
+    Ok(())
+}
+
+#[test]
+fn test_function_call_slice() -> Result<()> {
+    let lua = Lua::new();
+
+    let describe: Function = lua
+        .load(
+            r##"
+            function(...)
+                local parts = {}
+                for i = 1, select("#", ...) do
+                    parts[i] = type((select(i, ...)))
+                end
+                return select("#", ...), table.concat(parts, ",")
+            end
+        "##,
+        )
+        .eval()?;
+
+    let mut args = vec![Value::Integer(1), Value::Nil, Value::Boolean(true)];
+    args.push(Value::String(lua.create_string("str")?));
+    args.push(Value::Table(lua.create_table()?));
+    let results = describe.call_slice(&args)?;
+    let (n, types) = lua.unpack_multi::<(usize, StdString)>(results)?;
+    assert_eq!(n, 5);
+    assert_eq!(types, "number,nil,boolean,string,table");
+    // The arguments are still usable afterwards
+    assert_eq!(args.len(), 5);
+    assert!(matches!(args[3], Value::String(ref s) if s.as_bytes() == b"str"));
+
+    assert_eq!(describe.call_slice(&[])?.len(), 2);
+
+    let fail: Function = lua.load("function(msg) error(msg) end").eval()?;
+    assert!(fail.call_slice(&[Value::Integer(1)]).is_err());
+
+    Ok(())
+}
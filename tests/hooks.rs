@@ -247,3 +247,177 @@ fn test_hook_swap_within_hook() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_max_lua_call_depth() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    let lines = Arc::new(Mutex::new(0));
+    let hook_lines = lines.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *hook_lines.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+    assert_eq!(lua.set_max_lua_call_depth(100)?, 0);
+
+    lua.load(
+        r#"
+        function sum(n)
+            if n == 0 then return 0 end
+            return n + sum(n - 1)
+        end
+        function count(n)
+            if n == 0 then return "done" end
+            return count(n - 1)
+        end
+    "#,
+    )
+    .exec()?;
+    let sum: mlua::Function = lua.globals().get("sum")?;
+
+    assert_eq!(sum.call::<_, i64>(50)?, 1275);
+    match sum.call::<_, i64>(1000) {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::RecursionLimit => {}
+            err => panic!("expected RecursionLimit, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    // The error is catchable from Lua and the state stays usable afterwards
+    assert!(!lua.load("(pcall(sum, 1000))").eval::<bool>()?);
+    assert_eq!(sum.call::<_, i64>(50)?, 1275);
+
+    // Tail calls do not grow the stack (Lua 5.1 keeps them in the call stack)
+    #[cfg(not(feature = "lua51"))]
+    assert_eq!(lua.load("count(1000)").eval::<String>()?, "done");
+
+    // The user hook keeps working alongside the limit
+    assert!(*lines.lock().unwrap() > 0);
+
+    assert_eq!(lua.set_max_lua_call_depth(0)?, 100);
+    assert_eq!(sum.call::<_, i64>(1000)?, 500500);
+
+    Ok(())
+}
+
+#[test]
+fn test_instruction_limit() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    let runaway = lua.load("while true do end").into_function()?;
+    let short = "local x = 0; for i = 1, 10 do x = x + i end; return x";
+
+    assert_eq!(lua.set_instruction_limit(10000)?, 0);
+    match runaway.call::<_, ()>(()) {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::InstructionLimitReached => {}
+            err => panic!("expected InstructionLimitReached, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+    // Lua code keeps being interrupted until the limit is reset
+    assert!(runaway.call::<_, ()>(()).is_err());
+    assert_eq!(lua.set_instruction_limit(10000)?, 10000);
+    assert_eq!(lua.load(short).eval::<i64>()?, 55);
+
+    // A user hook counting instructions keeps being called alongside the limit
+    let calls = Arc::new(Mutex::new(0));
+    let hook_calls = calls.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(100),
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *hook_calls.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+    assert!(runaway.call::<_, ()>(()).is_err());
+    let hook_calls = *calls.lock().unwrap();
+    assert!(hook_calls > 50 && hook_calls <= 100, "{}", hook_calls);
+
+    // Clearing the limit restores the user hook on its own
+    assert_eq!(lua.set_instruction_limit(0)?, 10000);
+    lua.load("for i = 1, 1000 do end").exec()?;
+    assert!(*calls.lock().unwrap() > hook_calls);
+
+    lua.remove_hook();
+    lua.set_instruction_limit(1000)?;
+    assert!(runaway.call::<_, ()>(()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_inspect_stack() -> Result<()> {
+    let lua = Lua::new();
+
+    let where_am_i = lua.create_function(|lua, ()| {
+        let what = lua.inspect_stack(0, |callback| callback.source().what.map(|s| s.to_vec()));
+        assert_eq!(what, Some(Some(b"C".to_vec())));
+
+        let caller = lua.inspect_stack(1, |caller| {
+            let source = caller.source();
+            let name = caller
+                .names()
+                .name
+                .map(|n| str::from_utf8(n).unwrap().to_string());
+            (
+                source
+                    .short_src
+                    .map(|s| str::from_utf8(s).unwrap().to_string()),
+                caller.curr_line(),
+                name,
+                source.what.map(|s| str::from_utf8(s).unwrap().to_string()),
+            )
+        });
+        Ok(caller.expect("caller frame"))
+    })?;
+    lua.globals().set("where_am_i", where_am_i)?;
+
+    let (src, line, name, what): (String, i32, Option<String>, String) = lua
+        .load(
+            r#"
+            local function locate()
+                local a, b, c, d = where_am_i()
+                return a, b, c, d
+            end
+            local a, b, c, d = locate()
+            return a, b, c, d
+        "#,
+        )
+        .set_name("=script")?
+        .eval()?;
+    assert_eq!(src, "script");
+    assert_eq!(line, 3);
+    assert_eq!(name.as_deref(), Some("locate"));
+    assert_eq!(what, "Lua");
+
+    // Levels past the top of the stack return `None`
+    let depth_check = lua.create_function(|lua, ()| {
+        Ok((
+            lua.inspect_stack(100, |_| ()).is_none(),
+            lua.inspect_stack(usize::MAX, |_| ()).is_none(),
+        ))
+    })?;
+    assert_eq!(depth_check.call::<_, (bool, bool)>(())?, (true, true));
+    assert!(lua.inspect_stack(0, |_| ()).is_none());
+
+    Ok(())
+}
@@ -27,3 +27,132 @@ fn test_chunk_macro() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_int_enum_derive() -> Result<()> {
+    use mlua::{Error, LuaIntEnum, Value};
+
+    #[derive(Clone, Copy, Debug, PartialEq, LuaIntEnum)]
+    enum Priority {
+        Low = 1,
+        Normal = 2,
+        High = 3,
+    }
+
+    let lua = Lua::new();
+
+    for (i, p) in [
+        (1, Priority::Low),
+        (2, Priority::Normal),
+        (3, Priority::High),
+    ] {
+        assert_eq!(lua.unpack::<Priority>(Value::Integer(i))?, p);
+        assert_eq!(lua.pack(p)?, Value::Integer(i));
+    }
+    assert_eq!(lua.load("2.0").eval::<Priority>()?, Priority::Normal);
+
+    match lua.unpack::<Priority>(Value::Integer(4)) {
+        Err(Error::FromLuaConversionError { to, message, .. }) => {
+            assert_eq!(to, "Priority");
+            assert_eq!(message.unwrap(), "invalid value 4, expected one of 1, 2, 3");
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+    assert!(lua.load("'high'").eval::<Priority>().is_err());
+    assert!(lua.load("1.5").eval::<Priority>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_from_lua_derive_flatten() -> Result<()> {
+    use mlua::{Error, FromLua};
+
+    #[derive(Debug, PartialEq, FromLua)]
+    struct Retry {
+        attempts: u32,
+    }
+
+    #[derive(Debug, PartialEq, FromLua)]
+    struct Limits {
+        max_depth: u32,
+        timeout: Option<f64>,
+        #[mlua(flatten)]
+        retry: Retry,
+    }
+
+    #[derive(Debug, PartialEq, FromLua)]
+    struct Config {
+        name: String,
+        #[mlua(flatten)]
+        limits: Limits,
+    }
+
+    let lua = Lua::new();
+
+    let config: Config = lua
+        .load("{ name = 'app', max_depth = 3, timeout = 1.5, attempts = 2 }")
+        .eval()?;
+    assert_eq!(
+        config,
+        Config {
+            name: "app".to_string(),
+            limits: Limits {
+                max_depth: 3,
+                timeout: Some(1.5),
+                retry: Retry { attempts: 2 },
+            },
+        }
+    );
+
+    // Flattened fields are not read from a nested table
+    assert!(lua
+        .load("{ name = 'app', limits = { max_depth = 3, attempts = 2 } }")
+        .eval::<Config>()
+        .is_err());
+
+    match lua.load("42").eval::<Config>() {
+        Err(Error::FromLuaConversionError { from, to, .. }) => {
+            assert_eq!(from, "integer");
+            assert_eq!(to, "Config");
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_from_lua_derive_deny_unknown_fields() -> Result<()> {
+    use mlua::{Error, FromLua};
+
+    #[derive(Debug, PartialEq, FromLua)]
+    #[mlua(deny_unknown_fields)]
+    struct Config {
+        name: String,
+        debug: Option<bool>,
+    }
+
+    let lua = Lua::new();
+
+    let config: Config = lua.load("{ name = 'srv', debug = true }").eval()?;
+    assert_eq!(
+        config,
+        Config {
+            name: "srv".to_string(),
+            debug: Some(true),
+        }
+    );
+
+    let check_err = |code: &str, expected: &str| match lua.load(code).eval::<Config>() {
+        Err(Error::FromLuaConversionError {
+            message: Some(ref message),
+            ..
+        }) => assert_eq!(message, expected),
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    };
+    check_err("{ name = 'srv', degub = true }", "unknown field `degub`");
+    check_err("{ name = 'srv', 'extra' }", "unknown field `1`");
+
+    Ok(())
+}
@@ -33,6 +33,33 @@ fn test_memory_limit() -> Result<()> {
     Ok(())
 }
 
+#[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+#[test]
+fn test_memory_limit_below_usage() -> Result<()> {
+    let lua = Lua::new();
+    lua.globals()
+        .set("data", lua.create_sequence_from(0..1000)?)?;
+
+    // New allocations fail, but the state stays usable
+    let used_memory = lua.used_memory();
+    assert_eq!(lua.set_memory_limit(used_memory / 2)?, 0);
+    for _ in 0..10 {
+        match lua
+            .load("local t = {}; for i = 1,10000 do t[i] = i end")
+            .exec()
+        {
+            Err(Error::MemoryError(_)) => {}
+            something_else => panic!("did not trigger memory error: {:?}", something_else),
+        }
+    }
+    assert!(lua.used_memory() <= used_memory);
+
+    assert_eq!(lua.set_memory_limit(0)?, used_memory / 2);
+    assert_eq!(lua.load("#data").eval::<i64>()?, 1000);
+
+    Ok(())
+}
+
 #[test]
 fn test_gc_control() -> Result<()> {
     let lua = Lua::new();
@@ -95,3 +122,46 @@ fn test_gc_error() {
         Ok(()) => panic!("__gc error did not result in error"),
     }
 }
+
+#[test]
+fn test_gc_pause_guard() -> Result<()> {
+    let lua = Lua::new();
+
+    let f = lua
+        .load("local t = {}; for i = 1,10000 do t[i] = {} end")
+        .into_function()?;
+
+    {
+        let _guard = lua.gc_pause_guard();
+        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+        assert!(!lua.gc_is_running());
+
+        // Memory usage never goes down, as no garbage is collected
+        let mut used_memory = lua.used_memory();
+        for _ in 0..10 {
+            f.call::<_, ()>(())?;
+            assert!(lua.used_memory() > used_memory);
+            used_memory = lua.used_memory();
+        }
+    }
+
+    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    {
+        assert!(lua.gc_is_running());
+
+        // Guard does not restart GC that was stopped before
+        lua.gc_stop();
+        drop(lua.gc_pause_guard());
+        assert!(!lua.gc_is_running());
+        lua.gc_restart();
+    }
+
+    let used_memory = lua.used_memory();
+    for _ in 0..10 {
+        f.call::<_, ()>(())?;
+    }
+    lua.gc_collect()?;
+    assert!(lua.used_memory() < used_memory * 2);
+
+    Ok(())
+}
@@ -1,10 +1,10 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::sync::Arc;
 
 use mlua::{
-    AnyUserData, Error, Function, Lua, MetaMethod, Result, String, UserData, UserDataFields,
-    UserDataMethods,
+    AnyUserData, Error, Function, Lua, MetaMethod, MetaMethodSet, Result, String, Thread, UserData,
+    UserDataFields, UserDataMethods,
 };
 
 #[test]
@@ -356,3 +356,463 @@ fn test_scope_nonstatic_userdata_drop() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_scope_invalidate_userdata() -> Result<()> {
+    let lua = Lua::new();
+
+    struct MyUserData(Rc<()>);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("method", |_, _, ()| Ok(()));
+        }
+    }
+
+    struct MyNonStaticUserData<'a>(&'a Cell<i64>, Rc<()>);
+
+    impl<'a> UserData for MyNonStaticUserData<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("inc", |_, data, ()| {
+                data.0.set(data.0.get() + 1);
+                Ok(())
+            });
+        }
+    }
+
+    struct Other;
+
+    impl UserData for Other {}
+
+    let rc = Rc::new(());
+    let i = Cell::new(1);
+    lua.scope(|scope| {
+        let ud = scope.create_userdata(MyUserData(rc.clone()))?;
+        let ud2 = scope.create_nonstatic_userdata(MyNonStaticUserData(&i, rc.clone()))?;
+        lua.globals().set("ud", ud.clone())?;
+        lua.globals().set("ud2", ud2.clone())?;
+        lua.load("ud:method(); ud2:inc()").exec()?;
+        assert_eq!(Rc::strong_count(&rc), 3);
+
+        scope.invalidate(&ud)?;
+        assert_eq!(Rc::strong_count(&rc), 2);
+        match lua.load("ud:method()").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+                Error::CallbackDestructed => {}
+                err => panic!("expected CallbackDestructed, got {:?}", err),
+            },
+            r => panic!("improper return for destructed userdata: {:?}", r),
+        };
+        assert!(scope.invalidate(&ud).is_err());
+
+        // Methods looked up before invalidation must not keep the value alive
+        lua.load("inc = ud2.inc").exec()?;
+        scope.invalidate(&ud2)?;
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert!(lua.load("ud2:inc()").exec().is_err());
+        assert!(lua.load("inc(ud2)").exec().is_err());
+
+        let other = lua.create_userdata(Other)?;
+        assert!(scope.invalidate(&other).is_err());
+
+        Ok(())
+    })?;
+
+    assert_eq!(i.get(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_userdata_take() -> Result<()> {
+    let lua = Lua::new();
+
+    struct MyUserData(Rc<()>);
+
+    impl UserData for MyUserData {}
+
+    let rc = Rc::new(());
+    lua.scope(|scope| {
+        let ud = scope.create_userdata(MyUserData(rc.clone()))?;
+        let value = ud.take::<MyUserData>()?;
+        assert_eq!(Rc::strong_count(&rc), 2);
+        drop(value);
+        assert_eq!(Rc::strong_count(&rc), 1);
+
+        // Invalidating a taken userdata does not drop the value again
+        scope.invalidate(&ud)?;
+
+        let ud2 = scope.create_userdata(MyUserData(rc.clone()))?;
+        drop(ud2.take::<MyUserData>()?);
+        Ok(())
+    })?;
+
+    // The scope destructor does not drop the taken value again
+    lua.gc_collect()?;
+    assert_eq!(Rc::strong_count(&rc), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_meta_method_set() -> Result<()> {
+    struct MyUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, data, ()| Ok(data.0.get()));
+        }
+    }
+
+    let lua = Lua::new();
+
+    let set = MetaMethodSet::new()
+        .add_function(&lua, MetaMethod::Len, |_, _: AnyUserData| Ok(42))?
+        .add(
+            MetaMethod::ToString,
+            lua.load(r#"function(ud) return "value " .. ud:get() end"#)
+                .eval()?,
+        )?;
+
+    assert!(matches!(
+        MetaMethodSet::new().add(MetaMethod::Index, lua.create_function(|_, ()| Ok(()))?),
+        Err(Error::MetaMethodRestricted(_))
+    ));
+
+    let values = (0..3).map(Cell::new).collect::<Vec<_>>();
+    lua.scope(|scope| {
+        for value in &values {
+            let ud = scope.create_nonstatic_userdata(MyUserData(value))?;
+            set.apply(&ud)?;
+            lua.globals().set("ud", ud)?;
+            lua.load(
+                r#"
+                assert(#ud == 42)
+                assert(tostring(ud) == "value " .. ud:get())
+            "#,
+            )
+            .exec()?;
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_nonstatic_userdata_cached() -> Result<()> {
+    struct MyUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("value", |_, data| Ok(data.0.get()));
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("inc", |_, data, ()| {
+                data.0.set(data.0.get() + 1);
+                Ok(())
+            });
+        }
+    }
+
+    let lua = Lua::new();
+
+    let values = (0..3).map(Cell::new).collect::<Vec<_>>();
+    lua.scope(|scope| {
+        let builder = scope.userdata_builder::<MyUserData>()?;
+        let uds = values
+            .iter()
+            .map(|v| scope.create_nonstatic_userdata_cached(&builder, MyUserData(v)))
+            .collect::<Result<Vec<_>>>()?;
+        let uncached = scope.create_nonstatic_userdata(MyUserData(&values[0]))?;
+        lua.globals().set("uds", uds.clone())?;
+        lua.globals().set("uncached", uncached)?;
+
+        lua.load(
+            r#"
+            for _, ud in ipairs(uds) do
+                ud:inc()
+            end
+            -- All instances share the metatable and methods
+            assert(getmetatable(uds[1]) == getmetatable(uds[2]))
+            assert(uds[1].inc == uds[3].inc)
+            uds[1].inc(uds[3])
+            assert(uds[3].value == 4)
+            assert(not pcall(uds[1].inc, uncached))
+            assert(not pcall(uncached.inc, uds[1]))
+        "#,
+        )
+        .exec()?;
+
+        // The metatable is still usable once some of the instances are destructed
+        scope.invalidate(&uds[0])?;
+        assert!(lua.load("uds[1]:inc()").exec().is_err());
+        lua.load("uds[2]:inc()").exec()?;
+        scope.invalidate(&uds[1])?;
+        scope.invalidate(&uds[2])?;
+
+        let ud = scope.create_nonstatic_userdata_cached(&builder, MyUserData(&values[0]))?;
+        lua.globals().set("ud", ud)?;
+        lua.load("ud:inc(); assert(ud.value == 2)").exec()?;
+
+        Ok(())
+    })?;
+
+    assert_eq!(
+        values.iter().map(Cell::get).collect::<Vec<_>>(),
+        vec![2, 3, 4]
+    );
+    assert!(lua.load("ud:inc()").exec().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_borrow_nonstatic() -> Result<()> {
+    struct MyUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("inc", |_, data, ()| {
+                data.0.set(data.0.get() + 1);
+                Ok(())
+            });
+        }
+    }
+
+    struct OtherUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for OtherUserData<'a> {}
+
+    struct MyStaticUserData;
+
+    impl UserData for MyStaticUserData {}
+
+    let lua = Lua::new();
+
+    let i = Cell::new(1);
+    lua.scope(|scope| {
+        let builder = scope.userdata_builder::<MyUserData>()?;
+        let ud = scope.create_nonstatic_userdata_cached(&builder, MyUserData(&i))?;
+        let other_builder = scope.userdata_builder::<OtherUserData>()?;
+        let other_ud = scope.create_nonstatic_userdata_cached(&other_builder, OtherUserData(&i))?;
+
+        assert_eq!(scope.borrow_nonstatic(&builder, &ud)?.0.get(), 1);
+        assert_eq!(
+            scope.borrow_nonstatic(&other_builder, &other_ud)?.0.get(),
+            1
+        );
+        assert!(matches!(
+            scope.borrow_nonstatic(&builder, &other_ud),
+            Err(Error::UserDataTypeMismatch)
+        ));
+        // Userdata not created with the builder cannot be borrowed, even if it has the same type
+        let uncached_ud = scope.create_nonstatic_userdata(MyUserData(&i))?;
+        assert!(matches!(
+            scope.borrow_nonstatic(&builder, &uncached_ud),
+            Err(Error::UserDataTypeMismatch)
+        ));
+        let static_ud = scope.create_userdata(MyStaticUserData)?;
+        assert!(matches!(
+            scope.borrow_nonstatic(&builder, &static_ud),
+            Err(Error::UserDataTypeMismatch)
+        ));
+
+        // The userdata cannot be mutably borrowed or invalidated while borrowed
+        lua.globals().set("ud", ud.clone())?;
+        {
+            let data = scope.borrow_nonstatic(&builder, &ud)?;
+            assert!(lua.load("ud:inc()").exec().is_err());
+            assert!(scope.invalidate(&ud).is_err());
+            assert_eq!(data.0.get(), 1);
+        }
+        lua.load("ud:inc()").exec()?;
+        assert_eq!(scope.borrow_nonstatic(&builder, &ud)?.0.get(), 2);
+
+        scope.invalidate(&ud)?;
+        assert!(matches!(
+            scope.borrow_nonstatic(&builder, &ud),
+            Err(Error::UserDataDestructed)
+        ));
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_thread() -> Result<()> {
+    let lua = Lua::new();
+
+    let mut sum = 0;
+    lua.scope(|scope| {
+        let add = scope.create_function_mut(|_, n: i64| {
+            sum += n;
+            Ok(())
+        })?;
+        lua.globals().set("add", add)?;
+
+        let body: Function = lua
+            .load(
+                r#"
+                function(n)
+                    while true do
+                        add(n)
+                        n = coroutine.yield(n)
+                        resumed = true
+                    end
+                end
+            "#,
+            )
+            .eval()?;
+        let thread = scope.create_thread(body.clone())?;
+        assert_eq!(thread.resume::<_, i64>(1)?, 1);
+        assert_eq!(thread.resume::<_, i64>(2)?, 2);
+        lua.globals().set("resumed", false)?;
+
+        lua.globals().set("thread", thread)?;
+        lua.globals().set("unstarted", scope.create_thread(body)?)?;
+        Ok(())
+    })?;
+
+    assert_eq!(sum, 3);
+
+    for name in &["thread", "unstarted"] {
+        let thread: Thread = lua.globals().get(*name)?;
+        match thread.resume::<_, ()>(3) {
+            Err(Error::CallbackDestructed) => {}
+            r => panic!("improper return for expired thread: {:?}", r),
+        }
+    }
+    lua.load(
+        r#"
+        assert(not coroutine.resume(thread, 3))
+        assert(not coroutine.resume(unstarted, 3))
+        assert(coroutine.status(thread) == "dead")
+        assert(coroutine.status(unstarted) == "dead")
+        -- No code of the expired thread runs after the scope has ended
+        assert(resumed == false)
+    "#,
+    )
+    .exec()?;
+    assert_eq!(sum, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_function_reentrant() -> Result<()> {
+    let lua = Lua::new();
+
+    let visited = RefCell::new(Vec::new());
+    lua.scope(|scope| {
+        let visit = scope.create_function_reentrant(
+            0usize,
+            |lua, (name, children): (String, Vec<String>), depth| {
+                visited
+                    .borrow_mut()
+                    .push(format!("{}:{}", depth.get(), name.to_str()?));
+                depth.set(depth.get() + 1);
+                let visit: Function = lua.globals().get("visit")?;
+                for child in children {
+                    visit.call::<_, ()>((child, Vec::<String>::new()))?;
+                }
+                depth.set(depth.get() - 1);
+                Ok(())
+            },
+        )?;
+        lua.globals().set("visit", visit)?;
+        lua.load(r#"visit("root", {"a", "b"})"#).exec()
+    })?;
+
+    assert_eq!(visited.into_inner(), vec!["0:root", "1:a", "1:b"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_return_owned() -> Result<()> {
+    struct Buffer<'a>(&'a [u8]);
+
+    impl<'a> UserData for Buffer<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("bytes", |lua, this, ()| lua.create_string(this.0));
+        }
+    }
+
+    let lua = Lua::new();
+
+    let data = vec![1u8, 2, 3];
+    let (bytes, key) = lua.scope(|scope| {
+        let buffer = scope.create_nonstatic_userdata(Buffer(&data))?;
+        let bytes: String = lua
+            .load("function(buffer) return buffer:bytes() end")
+            .eval::<Function>()?
+            .call(buffer)?;
+        let key = lua.create_registry_value(bytes.clone())?;
+        Ok((bytes.as_bytes().to_vec(), key))
+    })?;
+
+    assert_eq!(bytes, data);
+    let stored: String = lua.registry_value(&key)?;
+    assert_eq!(stored.as_bytes(), [1, 2, 3]);
+
+    Ok(())
+}
+
+#[test]
+fn test_scope_override_method() -> Result<()> {
+    struct Counter(i64);
+
+    impl UserData for Counter {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, this, ()| Ok(this.0));
+        }
+    }
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    let counter = lua.create_userdata(Counter(1))?;
+    globals.set("counter", counter.clone())?;
+    globals.set("other", lua.create_userdata(Counter(2))?)?;
+
+    let calls = Cell::new(0);
+    lua.scope(|scope| {
+        let mock = scope.create_function(|_, _: AnyUserData| {
+            calls.set(calls.get() + 1);
+            Ok(42)
+        })?;
+        scope.override_method(&counter, "get", mock)?;
+        assert_eq!(lua.load("counter:get()").eval::<i64>()?, 42);
+        // The override applies to all userdata of the same type
+        assert_eq!(lua.load("other:get()").eval::<i64>()?, 42);
+
+        // Methods that did not exist before are removed afterwards
+        let extra = scope.create_function(|_, _: AnyUserData| Ok(7))?;
+        scope.override_method(&counter, "extra", extra)?;
+        assert_eq!(lua.load("counter:extra()").eval::<i64>()?, 7);
+        Ok(())
+    })?;
+
+    assert_eq!(calls.get(), 2);
+    assert_eq!(lua.load("counter:get()").eval::<i64>()?, 1);
+    assert_eq!(lua.load("other:get()").eval::<i64>()?, 2);
+    assert!(lua.load("counter:extra()").exec().is_err());
+
+    // Userdata without any methods cannot be overridden
+    struct Plain;
+    impl UserData for Plain {}
+    let plain = lua.create_userdata(Plain)?;
+    lua.scope(|scope| {
+        let f = scope.create_function(|_, ()| Ok(()))?;
+        match scope.override_method(&plain, "get", f) {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
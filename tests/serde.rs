@@ -2,7 +2,10 @@
 
 use std::collections::HashMap;
 
-use mlua::{Error, Lua, LuaSerdeExt, Result as LuaResult, SerializeOptions, UserData, Value};
+use mlua::{
+    AnyUserData, DeserializeOptions, Error, Lua, LuaSerdeExt, MetaMethod, Result as LuaResult,
+    SerializeOptions, SerializeRefs, Table, UserData, UserDataMethods, Value,
+};
 use serde::{Deserialize, Serialize};
 
 #[test]
@@ -141,6 +144,49 @@ fn test_serialize_failure() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_serialize_metamethod() -> Result<(), Box<dyn std::error::Error>> {
+    struct Point(i64, i64);
+
+    impl UserData for Point {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Serialize, |lua, this, ()| {
+                let t = lua.create_table()?;
+                t.set("x", this.0)?;
+                t.set("y", this.1)?;
+                Ok(t)
+            });
+        }
+    }
+
+    struct Myself;
+
+    impl UserData for Myself {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_function(MetaMethod::Serialize, |_, ud: AnyUserData| Ok(ud));
+        }
+    }
+
+    let lua = Lua::new();
+    lua.globals().set("point", Point(1, 2))?;
+    let value = lua.load("{ name = 'a', at = point }").eval::<Value>()?;
+
+    let json = serde_json::json!({ "name": "a", "at": { "x": 1, "y": 2 } });
+    assert_eq!(serde_json::to_value(&value)?, json);
+
+    let copy = lua.to_value(&value)?;
+    let copy: serde_json::Value = lua.from_value(copy)?;
+    assert_eq!(copy, json);
+
+    let ud = Value::UserData(lua.create_userdata(Myself)?);
+    match serde_json::to_value(&ud) {
+        Ok(v) => panic!("expected serialization error, got {}", v),
+        Err(err) => assert!(err.to_string().contains("returned the userdata itself")),
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_to_value_struct() -> LuaResult<()> {
     let lua = Lua::new();
@@ -343,6 +389,156 @@ fn test_from_value_struct() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_from_value_with_metamethods() -> Result<(), Box<dyn std::error::Error>> {
+    let lua = Lua::new();
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Config {
+        name: String,
+        port: u16,
+        #[serde(default)]
+        debug: bool,
+    }
+
+    let value = lua
+        .load(
+            r#"
+            local base = {port = 8080, debug = true}
+            local parent = setmetatable({}, {__index = base})
+            return setmetatable({name = "srv"}, {__index = parent})
+        "#,
+        )
+        .eval::<Value>()?;
+
+    // Inherited fields are not visible by default
+    match lua.from_value::<Config>(value.clone()) {
+        Ok(v) => panic!("expected Error::DeserializeError, got {:?}", v),
+        Err(Error::DeserializeError(_)) => {}
+        Err(e) => panic!("expected Error::DeserializeError, got {}", e),
+    }
+
+    let options = DeserializeOptions::new().get_with_metamethods(true);
+    let got: Config = lua.from_value_with(value, options)?;
+    assert_eq!(
+        Config {
+            name: "srv".into(),
+            port: 8080,
+            debug: true,
+        },
+        got
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_value_with_options() -> Result<(), Box<dyn std::error::Error>> {
+    let lua = Lua::new();
+
+    // deny_unsupported_types
+    let value = lua
+        .load(r#"{ a = 1, f = function() end, list = { 1, print, 2 } }"#)
+        .eval::<Value>()?;
+    match lua.from_value::<HashMap<String, serde_json::Value>>(value.clone()) {
+        Err(Error::DeserializeError(err)) => {
+            assert!(err.contains("unsupported value type `function`"), "{}", err)
+        }
+        r => panic!("expected DeserializeError, got {:?}", r),
+    }
+    let options = DeserializeOptions::new().deny_unsupported_types(false);
+    let map: HashMap<String, serde_json::Value> = lua.from_value_with(value, options)?;
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["a"], serde_json::json!(1));
+    assert_eq!(map["list"], serde_json::json!([1, 2]));
+
+    let value = lua.load("function() end").eval::<Value>()?;
+    let () = lua.from_value_with(value, options)?;
+
+    // sort_keys
+    let value = lua
+        .load(r#"{ c = 3, a = 1, [2] = "two", b = 2, [true] = "yes", [1.5] = "x" }"#)
+        .eval::<Value>()?;
+    let options = DeserializeOptions::new().sort_keys(true);
+    let entries: Vec<(serde_json::Value, serde_json::Value)> =
+        lua.from_value_with::<OrderedEntries>(value, options)?.0;
+    let keys = entries.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        vec![
+            serde_json::json!(true),
+            serde_json::json!(1.5),
+            serde_json::json!(2),
+            serde_json::json!("a"),
+            serde_json::json!("b"),
+            serde_json::json!("c"),
+        ]
+    );
+
+    Ok(())
+}
+
+// Collects map entries in the order they are visited
+struct OrderedEntries(Vec<(serde_json::Value, serde_json::Value)>);
+
+impl<'de> Deserialize<'de> for OrderedEntries {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = OrderedEntries;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(OrderedEntries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+#[test]
+fn test_from_value_deny_unknown_fields() -> Result<(), Box<dyn std::error::Error>> {
+    let lua = Lua::new();
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct Config {
+        name: String,
+        #[serde(default)]
+        debug: bool,
+    }
+
+    let value = lua.load(r#"{name = "srv", debug = true}"#).eval()?;
+    let got: Config = lua.from_value(value)?;
+    assert_eq!(
+        Config {
+            name: "srv".into(),
+            debug: true,
+        },
+        got
+    );
+
+    let value = lua.load(r#"{name = "srv", degub = true}"#).eval()?;
+    match lua.from_value::<Config>(value) {
+        Err(Error::DeserializeError(msg)) => assert!(msg.contains("unknown field `degub`")),
+        r => panic!("expected Error::DeserializeError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_from_value_enum() -> Result<(), Box<dyn std::error::Error>> {
     let lua = Lua::new();
@@ -413,3 +609,252 @@ fn test_from_value_enum_untagged() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml() -> LuaResult<()> {
+    let lua = Lua::new();
+
+    let config = lua.value_from_toml(
+        r#"
+        name = "mlua"
+        ports = [8000, 8001]
+
+        [server]
+        host = "localhost"
+        debug = true
+    "#,
+    )?;
+    lua.globals().set("config", config.clone())?;
+    lua.load(
+        r#"
+        assert(config.name == "mlua")
+        assert(#config.ports == 2 and config.ports[2] == 8001)
+        assert(config.server.host == "localhost")
+        assert(config.server.debug == true)
+    "#,
+    )
+    .exec()?;
+
+    let document = lua.value_to_toml(config)?;
+    assert!(document.contains("[server]"));
+    lua.globals()
+        .set("config", lua.value_from_toml(&document)?)?;
+    lua.load(r#"assert(config.server.host == "localhost")"#)
+        .exec()?;
+
+    assert!(matches!(
+        lua.value_from_toml("name = "),
+        Err(Error::DeserializeError(_))
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml() -> LuaResult<()> {
+    let lua = Lua::new();
+
+    let config = lua.value_from_yaml(
+        r#"
+        name: mlua
+        ports: [8000, 8001]
+        server:
+          host: localhost
+          debug: true
+    "#,
+    )?;
+    lua.globals().set("config", config.clone())?;
+    lua.load(
+        r#"
+        assert(config.name == "mlua")
+        assert(#config.ports == 2 and config.ports[2] == 8001)
+        assert(config.server.host == "localhost")
+        assert(config.server.debug == true)
+    "#,
+    )
+    .exec()?;
+
+    let document = lua.value_to_yaml(config)?;
+    lua.globals()
+        .set("config", lua.value_from_yaml(&document)?)?;
+    lua.load(r#"assert(config.server.host == "localhost")"#)
+        .exec()?;
+
+    assert!(matches!(
+        lua.value_from_yaml("name: [mlua"),
+        Err(Error::DeserializeError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_to_value_arbitrary_precision() -> Result<(), Box<dyn std::error::Error>> {
+    // Mimics how `serde_json::Number` is serialized with the `arbitrary_precision` feature
+    struct Number(&'static str);
+
+    impl Serialize for Number {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeStruct;
+            let token = "$serde_json::private::Number";
+            let mut s = serializer.serialize_struct(token, 1)?;
+            s.serialize_field(token, self.0)?;
+            s.end()
+        }
+    }
+
+    let lua = Lua::new();
+    let options = SerializeOptions::new().detect_serde_json_arbitrary_precision(true);
+
+    assert!(matches!(lua.to_value(&Number("1"))?, Value::Table(_)));
+    assert_eq!(
+        lua.to_value_with(&Number("42"), options)?,
+        Value::Integer(42)
+    );
+    assert_eq!(
+        lua.to_value_with(&Number("1.5"), options)?,
+        Value::Number(1.5)
+    );
+    assert_eq!(
+        lua.to_value_with(&Number("18446744073709551615"), options)?,
+        Value::Number(u64::MAX as f64)
+    );
+    assert!(lua.to_value_with(&Number("abc"), options).is_err());
+
+    // Integers out of `Integer` range are converted to floats
+    assert_eq!(lua.to_value(&u64::MAX)?, Value::Number(u64::MAX as f64));
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_serialize_to_writer() -> Result<(), Box<dyn std::error::Error>> {
+    use mlua::SerializeFormat;
+
+    #[derive(Default)]
+    struct Sink {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl std::io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let lua = Lua::new();
+
+    let items = lua
+        .load(
+            r#"
+            local shared = {kind = "item"}
+            local items = {}
+            for i = 1, 10000 do
+                items[i] = {id = i, meta = shared}
+            end
+            return items
+        "#,
+        )
+        .eval::<Value>()?;
+
+    let mut sink = Sink::default();
+    lua.serialize_value_to_writer(items, &mut sink, SerializeFormat::Json)?;
+    assert!(sink.writes > 10000);
+
+    let json: serde_json::Value = serde_json::from_slice(&sink.data)?;
+    assert_eq!(json.as_array().map(|a| a.len()), Some(10000));
+    assert_eq!(json[9999]["id"], 10000);
+    assert_eq!(json[0]["meta"]["kind"], "item");
+
+    let cyclic = lua
+        .load(
+            r#"
+            local t = {1, 2, {3}}
+            t[3][2] = t
+            return t
+        "#,
+        )
+        .eval::<Value>()?;
+
+    let mut sink = Sink::default();
+    match lua.serialize_value_to_writer(cyclic, &mut sink, SerializeFormat::Json) {
+        Err(Error::SerializeError(msg)) => assert!(msg.contains("recursive table")),
+        r => panic!("expected SerializeError, got {:?}", r),
+    }
+    assert!(sink.data.starts_with(b"[1,2,[3,"));
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_refs() -> Result<(), Box<dyn std::error::Error>> {
+    let lua = Lua::new();
+
+    let value = lua
+        .load(
+            r#"
+            local shared = {kind = "shared"}
+            local root = {name = "root", items = {shared, {shared}}}
+            root.items[3] = root
+            root.me = root
+            return root
+        "#,
+        )
+        .eval::<Value>()?;
+
+    // Ids depend on the traversal order of table pairs
+    let json = serde_json::to_value(&SerializeRefs::new(value)?)?;
+    let deref = |marker: &serde_json::Value| {
+        let id = marker["__ref"].as_u64().expect("expected a marker") as usize;
+        json["refs"][id - 1].clone()
+    };
+    let root = deref(&json["root"]);
+    assert_eq!(json["refs"].as_array().map(|refs| refs.len()), Some(2));
+    assert_eq!(root["name"], "root");
+    assert_eq!(root["me"], json["root"]);
+    assert_eq!(root["items"][2], json["root"]);
+    assert_eq!(root["items"][1][0], root["items"][0]);
+    assert_eq!(
+        deref(&root["items"][0]),
+        serde_json::json!({"kind": "shared"})
+    );
+
+    let root: Table = lua.unpack(lua.resolve_refs(lua.to_value(&json)?)?)?;
+    lua.globals().set("root", root)?;
+    lua.load(
+        r#"
+        assert(root.name == "root" and root.me == root)
+        assert(root.items[3] == root)
+        assert(root.items[1].kind == "shared" and root.items[2][1] == root.items[1])
+    "#,
+    )
+    .exec()?;
+
+    // Values without shared tables are serialized as is
+    let value = lua.load(r#"{a = {1, 2}, b = "c"}"#).eval::<Value>()?;
+    let json = serde_json::to_value(&SerializeRefs::new(value)?)?;
+    assert_eq!(
+        json,
+        serde_json::json!({"root": {"a": [1, 2], "b": "c"}, "refs": []})
+    );
+    let value = lua.resolve_refs(lua.to_value(&json)?)?;
+    assert_eq!(lua.from_value::<serde_json::Value>(value)?, json["root"]);
+
+    let invalid = lua.to_value(&serde_json::json!({"root": {"__ref": 3}, "refs": []}))?;
+    match lua.resolve_refs(invalid) {
+        Err(Error::DeserializeError(msg)) => assert_eq!(msg, "invalid reference id 3"),
+        r => panic!("expected DeserializeError, got {:?}", r),
+    }
+
+    Ok(())
+}
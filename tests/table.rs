@@ -1,4 +1,7 @@
-use mlua::{Lua, Nil, Result, Table, TableExt, Value};
+use std::string::String as StdString;
+use std::sync::{Arc, Mutex};
+
+use mlua::{Error, Lua, Nil, Result, Table, TableAccessKind, TableExt, Value};
 
 #[test]
 fn test_set_get() -> Result<()> {
@@ -64,11 +67,11 @@ fn test_table() -> Result<()> {
             .clone()
             .pairs()
             .collect::<Result<Vec<(i64, i64)>>>()?,
-        vec![]
+        Vec::<(i64, i64)>::new()
     );
     assert_eq!(
         table2.sequence_values().collect::<Result<Vec<i64>>>()?,
-        vec![]
+        Vec::<i64>::new()
     );
 
     // sequence_values should only iterate until the first border
@@ -109,6 +112,55 @@ fn test_table() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_table_raw_insert_remove() -> Result<()> {
+    let lua = Lua::new();
+
+    let table = lua.create_sequence_from(vec!["a", "b", "d"])?;
+    let guard = lua
+        .load(r#"{__index = function() error("index") end, __newindex = function() error("newindex") end}"#)
+        .eval::<Table>()?;
+    table.set_metatable(Some(guard));
+
+    let values =
+        |t: &Table| -> Result<Vec<StdString>> { t.clone().raw_sequence_values().collect() };
+
+    table.raw_insert(3, "c")?;
+    assert_eq!(values(&table)?, vec!["a", "b", "c", "d"]);
+    table.raw_insert(5, "e")?;
+    assert_eq!(values(&table)?, vec!["a", "b", "c", "d", "e"]);
+
+    assert_eq!(table.raw_remove(1)?, Value::String(lua.create_string("a")?));
+    assert_eq!(values(&table)?, vec!["b", "c", "d", "e"]);
+    assert_eq!(table.raw_remove(5)?, Value::Nil);
+    assert_eq!(table.raw_len(), 4);
+
+    for pos in [0, 6] {
+        match table.raw_insert(pos, "x") {
+            Err(Error::RuntimeError(msg)) => assert_eq!(msg, "position out of bounds"),
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+    }
+    for pos in [-1, 0, 6] {
+        match table.raw_remove(pos) {
+            Err(Error::RuntimeError(msg)) => assert_eq!(msg, "position out of bounds"),
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+    }
+    assert_eq!(values(&table)?, vec!["b", "c", "d", "e"]);
+
+    // Empty tables accept position 0 like `table.remove`
+    let empty = lua.create_table()?;
+    assert_eq!(empty.raw_remove(0)?, Value::Nil);
+
+    // Non-integer keys are simply cleared
+    table.raw_set("key", 42)?;
+    assert_eq!(table.raw_remove("key")?, Value::Integer(42));
+    assert_eq!(table.raw_get::<_, Value>("key")?, Value::Nil);
+
+    Ok(())
+}
+
 #[test]
 fn test_table_sequence_from() -> Result<()> {
     let lua = Lua::new();
@@ -150,6 +202,219 @@ fn test_table_sequence_from() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_create_sequence_from_large() -> Result<()> {
+    let lua = Lua::new();
+
+    let data = (1..=100_000).collect::<Vec<i64>>();
+    let table = lua.create_sequence_from(data.clone())?;
+    assert_eq!(table.raw_len(), 100_000);
+    lua.globals().set("t", table.clone())?;
+    assert_eq!(lua.load("#t").eval::<i64>()?, 100_000);
+    assert_eq!(table.get::<_, i64>(100_000)?, 100_000);
+    assert_eq!(
+        table.sequence_values::<i64>().collect::<Result<Vec<_>>>()?,
+        data
+    );
+
+    // Iterators without an exact size hint still produce a proper sequence
+    let table = lua.create_sequence_from(data.into_iter().filter(|x| x % 2 == 0))?;
+    assert_eq!(table.raw_len(), 50_000);
+    assert_eq!(table.get::<_, i64>(50_000)?, 100_000);
+
+    Ok(())
+}
+
+#[test]
+fn test_create_nested_table() -> Result<()> {
+    let lua = Lua::new();
+
+    let existing = lua.create_table()?;
+    existing.set("x", 1)?;
+    let config = lua.create_nested_table(vec![
+        ("a.b", Value::Integer(1)),
+        ("a.c", Value::Integer(2)),
+        ("a.d.e", Value::Boolean(true)),
+        ("top", Value::Integer(3)),
+        ("t", Value::Table(existing)),
+    ])?;
+
+    let a: Table = config.get("a")?;
+    assert_eq!(a.len()?, 0);
+    assert_eq!(a.get::<_, i64>("b")?, 1);
+    assert_eq!(a.get::<_, i64>("c")?, 2);
+    assert!(a.get::<_, Table>("d")?.get::<_, bool>("e")?);
+    assert_eq!(config.get::<_, i64>("top")?, 3);
+    assert_eq!(config.get::<_, Table>("t")?.get::<_, i64>("x")?, 1);
+
+    // A key can't be both a value and a table of nested values
+    for pairs in vec![vec![("a", 1), ("a.b", 2)], vec![("a.b", 1), ("a", 2)]] {
+        match lua.create_nested_table(pairs) {
+            Err(Error::RuntimeError(msg)) => assert!(msg.starts_with("conflicting path")),
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+    }
+    // Table values are not merged with nested paths
+    let pairs = vec![
+        ("t", Value::Table(lua.create_table()?)),
+        ("t.x", Value::Integer(1)),
+    ];
+    assert!(lua.create_nested_table(pairs).is_err());
+    assert!(lua.create_nested_table(vec![("a..b", 1)]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_table_trace_access() -> Result<()> {
+    let lua = Lua::new();
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    lua.trace_table_access(move |_, access| {
+        let key = match access.key {
+            Value::String(ref s) => s.to_str().unwrap().to_string(),
+            _ => "?".to_string(),
+        };
+        let value = match access.value {
+            Value::Integer(i) => i,
+            _ => -1,
+        };
+        log2.lock().unwrap().push((access.kind, key, value));
+        Ok(())
+    });
+
+    let t = lua.create_table()?;
+    t.set("a", 1)?;
+    t.set_traced(true)?;
+    t.set_traced(true)?;
+    lua.globals().set("t", t.clone())?;
+
+    lua.load("t.b = t.a + 1; t.a = nil").exec()?;
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (TableAccessKind::Read, "a".to_string(), 1),
+            (TableAccessKind::Write, "b".to_string(), 2),
+            (TableAccessKind::Write, "a".to_string(), -1),
+        ]
+    );
+    // Raw accesses see an empty table while tracing
+    assert_eq!(t.raw_get::<_, Value>("b")?, Nil);
+    assert_eq!(t.raw_len(), 0);
+    assert_eq!(t.clone().pairs::<Value, Value>().count(), 0);
+
+    // `#` and `pairs` see the contents
+    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    {
+        lua.load("t[1] = 'x'; t[2] = 'y'").exec()?;
+        assert_eq!(lua.load("#t").eval::<i64>()?, 2);
+        let count = lua
+            .load("local n = 0; for _ in pairs(t) do n = n + 1 end; return n")
+            .eval::<i64>()?;
+        assert_eq!(count, 3);
+    }
+
+    // Without a callback accesses are not reported anymore
+    lua.remove_table_access_trace();
+    log.lock().unwrap().clear();
+    lua.load("t.c = t.b").exec()?;
+    assert!(log.lock().unwrap().is_empty());
+
+    // Errors are raised in Lua
+    lua.trace_table_access(|_, _| Err(Error::RuntimeError("denied".to_string())));
+    assert!(lua.load("t.d = 1").exec().is_err());
+    lua.remove_table_access_trace();
+
+    // Disabling tracing moves the contents back
+    t.set_traced(false)?;
+    assert!(t.get_metatable().is_none());
+    assert_eq!(t.raw_get::<_, Value>("a")?, Nil);
+    assert_eq!(t.raw_get::<_, i64>("b")?, 2);
+    assert_eq!(t.raw_get::<_, i64>("c")?, 2);
+    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    assert_eq!(t.raw_len(), 2);
+    t.set("e", 3)?;
+    assert_eq!(t.raw_get::<_, i64>("e")?, 3);
+
+    // Tables with a metatable can't be traced
+    let t = lua.create_table()?;
+    t.set_metatable(Some(lua.create_table()?));
+    assert!(t.set_traced(true).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_raw_sequence_values() -> Result<()> {
+    let lua = Lua::new();
+
+    let values = lua
+        .load(
+            r#"
+            local t = {1, 2, 3, nil, 5, key = "value"}
+            return setmetatable(t, {__index = function(_, i) return i * 10 end})
+        "#,
+        )
+        .eval::<Table>()?
+        .raw_sequence_values::<i64>();
+
+    // The iterator keeps the table alive on its own
+    lua.gc_collect()?;
+    assert_eq!(values.collect::<Result<Vec<_>>>()?, vec![1, 2, 3]);
+
+    Ok(())
+}
+
+#[test]
+fn test_range_table() -> Result<()> {
+    let lua = Lua::new();
+
+    let range = |start, stop, step| -> Result<Vec<i64>> {
+        lua.create_range_table(start, stop, step)?
+            .sequence_values()
+            .collect()
+    };
+
+    assert_eq!(range(1, 5, 1)?, vec![1, 2, 3, 4, 5]);
+    assert_eq!(range(1, 10, 4)?, vec![1, 5, 9]);
+    assert_eq!(range(5, 1, -2)?, vec![5, 3, 1]);
+    assert_eq!(range(5, 1, 1)?, Vec::<i64>::new());
+    assert!(lua.create_range_table(1, 2, 0).is_err());
+
+    // Oversized ranges are rejected instead of being allocated
+    match lua.create_range_table(0, i64::MAX, 1) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("range is too large")),
+        r => panic!("expected RuntimeError, got {:?}", r.map(|_| ())),
+    }
+    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    {
+        lua.set_memory_limit(lua.used_memory() + 1024 * 1024)?;
+        match lua.create_range_table(1, 100_000_000, 1) {
+            Err(Error::MemoryError(_)) => {}
+            r => panic!("expected MemoryError, got {:?}", r.map(|_| ())),
+        }
+        lua.set_memory_limit(0)?;
+    }
+
+    // Lua < 5.3 stores numbers as doubles, so integer limits are not representable
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    {
+        assert_eq!(
+            range(i64::MAX - 2, i64::MAX, 1)?,
+            vec![i64::MAX - 2, i64::MAX - 1, i64::MAX]
+        );
+        assert_eq!(range(i64::MAX - 1, i64::MAX, 2)?, vec![i64::MAX - 1]);
+        assert_eq!(
+            range(i64::MIN + 1, i64::MIN, -1)?,
+            vec![i64::MIN + 1, i64::MIN]
+        );
+        assert_eq!(range(i64::MIN, i64::MAX, i64::MAX)?.len(), 3);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_table_scope() -> Result<()> {
     let lua = Lua::new();
@@ -297,3 +562,134 @@ fn test_table_call() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_table_readonly() -> Result<()> {
+    let lua = Lua::new();
+
+    let config = lua.create_table()?;
+    config.set("name", "mlua")?;
+    config.set("nested", lua.create_table()?)?;
+    assert!(!config.is_readonly());
+
+    config.set_readonly(true)?;
+    config.set_readonly(true)?;
+    assert!(config.is_readonly());
+    lua.globals().set("config", config.clone())?;
+
+    match lua.load("config.debug = true").exec() {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::RuntimeError(msg) => assert_eq!(msg, "attempt to modify readonly table"),
+            err => panic!("expected RuntimeError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+    assert!(config.set("debug", true).is_err());
+    lua.load(r#"assert(config.name == "mlua"); config.nested.debug = true"#)
+        .exec()?;
+
+    config.set_readonly(false)?;
+    assert!(!config.is_readonly());
+    assert!(config.get_metatable().is_none());
+    config.set("debug", true)?;
+
+    // Fields added to the created metatable are kept
+    config.set_readonly(true)?;
+    let created = config.get_metatable().unwrap();
+    created.set("__index", lua.create_function(|_, ()| Ok("fallback"))?)?;
+    config.set_readonly(false)?;
+    assert!(config.get_metatable() == Some(created));
+    assert_eq!(config.get::<_, String>("missing")?, "fallback");
+    config.set_metatable(None);
+
+    // An existing metatable is kept
+    let metatable = lua.create_table()?;
+    metatable.set("__index", lua.create_function(|_, ()| Ok("default"))?)?;
+    config.set_metatable(Some(metatable.clone()));
+    config.set_readonly(true)?;
+    assert!(config.set("other", 1).is_err());
+    config.set_readonly(false)?;
+    assert!(config.get_metatable() == Some(metatable.clone()));
+    assert_eq!(config.get::<_, String>("other")?, "default");
+
+    metatable.set("__newindex", lua.create_function(|_, ()| Ok(()))?)?;
+    assert!(config.set_readonly(true).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_table_get_many() -> Result<()> {
+    let lua = Lua::new();
+
+    let config: Table = lua
+        .load(
+            r#"
+            return setmetatable({host = "localhost", port = 8080}, {
+                __index = function(_, key)
+                    if key == "user" then return "guest" end
+                end
+            })
+        "#,
+        )
+        .eval()?;
+
+    assert_eq!(
+        config.get_many::<_, String>(&["host", "port", "user", "password"])?,
+        vec![
+            Some("localhost".to_string()),
+            Some("8080".to_string()),
+            Some("guest".to_string()),
+            None
+        ]
+    );
+    assert_eq!(config.get_many::<&str, i64>(&[])?, vec![]);
+    assert!(config.get_many::<_, Table>(&["host"]).is_err());
+
+    // More keys than the minimum stack size of a C function
+    let sequence = lua.create_sequence_from(1..=100)?;
+    let keys = (1..=101).collect::<Vec<i64>>();
+    let values = sequence.get_many::<_, i64>(&keys)?;
+    assert_eq!(values.len(), 101);
+    assert!(values[..100]
+        .iter()
+        .zip(1..)
+        .all(|(value, expected)| *value == Some(expected)));
+    assert_eq!(values[100], None);
+
+    Ok(())
+}
+
+#[test]
+fn test_table_get_or_insert_with() -> Result<()> {
+    let lua = Lua::new();
+
+    let memo = lua.create_table()?;
+    let mut calls = 0;
+    for _ in 0..3 {
+        let v: i64 = memo.get_or_insert_with("answer", || {
+            calls += 1;
+            42
+        })?;
+        assert_eq!(v, 42);
+    }
+    assert_eq!(calls, 1);
+    assert_eq!(memo.raw_get::<_, i64>("answer")?, 42);
+
+    // Metamethods are skipped both for reading and inserting
+    let mt = lua
+        .load("{ __index = function() return 'meta' end, __newindex = function() error('newindex') end }")
+        .eval::<Table>()?;
+    memo.set_metatable(Some(mt));
+    let nested: Table = memo.get_or_insert_with("nested", || lua.create_table().unwrap())?;
+    nested.raw_set("x", 1)?;
+    let nested: Table = memo.get_or_insert_with("nested", || unreachable!())?;
+    assert_eq!(nested.raw_get::<_, i64>("x")?, 1);
+
+    // Existing values that don't convert to the requested type are reported
+    assert!(memo
+        .get_or_insert_with::<_, Table, _>("answer", || unreachable!())
+        .is_err());
+
+    Ok(())
+}
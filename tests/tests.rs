@@ -2,12 +2,13 @@ use std::collections::HashMap;
 use std::iter::FromIterator;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::string::String as StdString;
-use std::sync::Arc;
-use std::{error, f32, f64, fmt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{error, f32, f64, fmt, io};
 
 use mlua::{
-    ChunkMode, Error, ExternalError, Function, Lua, LuaOptions, Nil, Result, StdLib, String, Table,
-    UserData, Value, Variadic,
+    lua_State, BoundedVariadic, ChunkMode, Error, ExternalError, Function, Lua, LuaOptions, Nil,
+    Result, SeqTable, StdLib, String, Table, UserData, Validated, Value, Variadic,
 };
 
 #[test]
@@ -135,6 +136,9 @@ fn test_eval() -> Result<()> {
     assert_eq!(lua.load("1 + 1").eval::<i32>()?, 2);
     assert_eq!(lua.load("false == false").eval::<bool>()?, true);
     assert_eq!(lua.load("return 1 + 2").eval::<i32>()?, 3);
+    assert_eq!(lua.load("x = 5").eval::<Value>()?, Value::Nil);
+    assert_eq!(lua.load("x * 2").eval::<i32>()?, 10);
+    assert_eq!(lua.load("1, 2").eval::<(i32, i32)>()?, (1, 2));
     match lua.load("if true then").eval::<()>() {
         Err(Error::SyntaxError {
             incomplete_input: true,
@@ -149,6 +153,47 @@ fn test_eval() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_uncaught_error_handler() -> Result<()> {
+    let lua = Lua::new();
+
+    let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let errors2 = errors.clone();
+    lua.set_uncaught_error_handler(move |err| {
+        errors2.lock().unwrap().push(err.to_string());
+    });
+
+    lua.load("local x = 1").exec()?;
+    assert!(errors.lock().unwrap().is_empty());
+
+    match lua.load(r#"error("boom")"#).exec() {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("boom")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+    assert_eq!(errors.lock().unwrap().len(), 1);
+    assert!(errors.lock().unwrap()[0].contains("boom"));
+
+    assert!(lua.load("if true then").eval::<()>().is_err());
+    assert_eq!(errors.lock().unwrap().len(), 2);
+
+    // A chunk run from a callback is reported once, as part of the outermost chunk
+    let run = lua.create_function(|lua, code: StdString| lua.load(&code).exec())?;
+    lua.globals().set("run", run)?;
+    assert!(lua.load(r#"run("error('nested')")"#).exec().is_err());
+    assert_eq!(errors.lock().unwrap().len(), 3);
+
+    // Errors caught by the outer chunk are not reported
+    lua.load(r#"assert(not pcall(run, "error('caught')"))"#)
+        .exec()?;
+    assert_eq!(errors.lock().unwrap().len(), 3);
+
+    lua.remove_uncaught_error_handler();
+    assert!(lua.load(r#"error("boom")"#).exec().is_err());
+    assert_eq!(errors.lock().unwrap().len(), 3);
+
+    Ok(())
+}
+
 #[test]
 fn test_load_mode() -> Result<()> {
     let lua = unsafe { Lua::unsafe_new() };
@@ -173,13 +218,216 @@ fn test_load_mode() -> Result<()> {
             .eval::<i32>()?,
         2
     );
+    assert_eq!(
+        lua.load(&bytecode)
+            .set_mode(ChunkMode::TextAndBinary)
+            .eval::<i32>()?,
+        2
+    );
+    assert_eq!(
+        lua.load("1 + 1")
+            .set_mode(ChunkMode::TextAndBinary)
+            .eval::<i32>()?,
+        2
+    );
     match lua.load(&bytecode).set_mode(ChunkMode::Text).exec() {
-        Ok(_) => panic!("expected SyntaxError, got no error"),
-        Err(Error::SyntaxError { message: msg, .. }) => {
-            assert!(msg.contains("attempt to load a binary chunk"))
+        Ok(_) => panic!("expected RuntimeError, got no error"),
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("attempt to load a binary chunk")),
+        Err(e) => panic!("expected RuntimeError, got {:?}", e),
+    };
+
+    // Text mode also rejects bytecode in safe mode, without a safety error
+    let safe_lua = Lua::new();
+    match safe_lua.load(&bytecode).set_mode(ChunkMode::Text).exec() {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("attempt to load a binary chunk")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    };
+
+    Ok(())
+}
+
+#[test]
+fn test_load_from_reader() -> Result<()> {
+    // Hands out the source a few bytes at a time, failing once `fail_at` bytes were read
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        fail_at: Option<usize>,
+        read: usize,
+    }
+
+    impl io::Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_at.map_or(false, |at| self.read >= at) {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+            }
+            let n = buf.len().min(7).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.read += n;
+            Ok(n)
         }
-        Err(e) => panic!("expected SyntaxError, got {:?}", e),
+    }
+
+    let lua = Lua::new();
+
+    let mut source = StdString::from("local sum = 0\n");
+    for i in 1..=10_000 {
+        source.push_str(&format!("sum = sum + {}\n", i));
+    }
+    source.push_str("return sum");
+    let reader = SlowReader {
+        data: source.as_bytes(),
+        fail_at: None,
+        read: 0,
     };
+    let func = lua.load_from_reader(reader, "=generated")?;
+    assert_eq!(func.call::<_, i64>(())?, 50_005_000);
+
+    // Reads from any `io::Read`, reporting errors with the chunk name
+    match lua.load_from_reader(&b"return +"[..], "=broken") {
+        Err(Error::SyntaxError { message, .. }) => assert!(message.starts_with("broken:")),
+        r => panic!("expected SyntaxError, got {:?}", r),
+    }
+
+    let reader = SlowReader {
+        data: source.as_bytes(),
+        fail_at: Some(100),
+        read: 0,
+    };
+    match lua.load_from_reader(reader, "=generated") {
+        Err(Error::ExternalError(err)) => assert_eq!(err.to_string(), "disk on fire"),
+        r => panic!("expected ExternalError, got {:?}", r),
+    }
+
+    // Binary chunks are only allowed in unsafe mode
+    let bytecode = lua.load("return 42").into_function()?.dump(false);
+    match lua.load_from_reader(&bytecode[..], "=binary") {
+        Err(Error::SafetyError(_)) => {}
+        r => panic!("expected SafetyError, got {:?}", r),
+    }
+    let unsafe_lua = unsafe { Lua::unsafe_new() };
+    let func = unsafe_lua.load_from_reader(&bytecode[..], "=binary")?;
+    assert_eq!(func.call::<_, i64>(())?, 42);
+
+    Ok(())
+}
+
+#[test]
+fn test_load_bytecode() -> Result<()> {
+    let lua = unsafe { Lua::unsafe_new() };
+
+    let source = r#"
+        local function fib(n)
+            if n < 2 then return n end
+            return fib(n - 1) + fib(n - 2)
+        end
+        return fib(20), "done", ...
+    "#;
+    let bytecode = lua.load(source).into_bytecode()?;
+    let func = lua.load_bytecode(&bytecode, "=cached")?;
+    let expected: (i64, StdString, i64) = lua.load(source).call(7)?;
+    assert_eq!(func.call::<_, (i64, StdString, i64)>(7)?, expected);
+    assert_eq!(expected.0, 6765);
+
+    // Text chunks are rejected
+    assert!(lua.load_bytecode(b"return 1", "=text").is_err());
+
+    // Bytecode is not loaded in safe mode
+    let lua = Lua::new();
+    let bytecode = lua.load("return 1").into_bytecode()?;
+    match lua.load_bytecode(&bytecode, "=cached") {
+        Err(Error::RuntimeError(_)) => {}
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_disable_bytecode_loading() -> Result<()> {
+    let options = LuaOptions::new().disable_bytecode_loading(true);
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE, options) };
+    let bytecode = lua.load("return 1 + 1").into_bytecode()?;
+
+    let check_disabled = |r: Result<Function>| match r {
+        Err(Error::RuntimeError(msg)) => assert_eq!(msg, "bytecode loading is disabled"),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    };
+    check_disabled(lua.load(&bytecode).into_function());
+    check_disabled(
+        lua.load(&bytecode)
+            .set_mode(ChunkMode::Binary)
+            .into_function(),
+    );
+    check_disabled(lua.load_bytecode(&bytecode, "=cached"));
+    check_disabled(lua.load_from_reader(&bytecode[..], "=cached"));
+
+    // Text chunks starting with `#` are still passed to the parser
+    assert_eq!(lua.load("#'abc'").eval::<i64>()?, 3);
+    // (LuaJIT skips a leading `#` line, other versions fail to parse it)
+    if let Err(Error::RuntimeError(msg)) = lua.load("#!/usr/bin/lua").exec() {
+        panic!("text chunk rejected as bytecode: {}", msg);
+    }
+
+    // Scripts are not restricted by this option
+    assert_eq!(
+        lua.load("return (loadstring or load)(string.dump(function() return 5 end))()")
+            .eval::<i64>()?,
+        5
+    );
+
+    // Unless script bytecode loading is disabled as well
+    let options = LuaOptions::new().disable_script_bytecode_loading(true);
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE, options.clone()) };
+    let dir = std::env::temp_dir();
+    let binary_file = dir.join(format!("mlua_bytecode_{}.luac", std::process::id()));
+    let text_file = dir.join(format!("mlua_bytecode_{}.lua", std::process::id()));
+    std::fs::write(&binary_file, &bytecode).unwrap();
+    std::fs::write(&text_file, "#!/usr/bin/lua\nreturn 3").unwrap();
+    lua.globals()
+        .set("binary_file", binary_file.to_str().unwrap())?;
+    lua.globals()
+        .set("text_file", text_file.to_str().unwrap())?;
+    let res = lua
+        .load(
+            r#"
+            local function reader(s)
+                return function()
+                    local piece = s
+                    s = nil
+                    return piece
+                end
+            end
+            local dumped = string.dump(function() return 1 end)
+            local func, err = load(reader(dumped))
+            assert(func == nil and err:find("attempt to load a binary chunk"), err)
+            assert((loadstring or load)(dumped) == nil)
+            assert(loadfile(binary_file) == nil)
+            assert(not pcall(dofile, binary_file))
+
+            assert(load(reader("return 2"))() == 2)
+            assert(dofile(text_file) == 3)
+            if _VERSION ~= "Lua 5.1" then
+                assert(load("return x", "=env", "bt", {x = 4})() == 4)
+            end
+        "#,
+        )
+        .exec();
+    std::fs::remove_file(&binary_file).unwrap();
+    std::fs::remove_file(&text_file).unwrap();
+    res?;
+
+    // The option also applies to safe states
+    let lua = Lua::new_with(StdLib::ALL_SAFE, options)?;
+    assert_eq!(
+        lua.load("return (loadstring or load)(string.dump(function() return 5 end))")
+            .eval::<Value>()?,
+        Nil
+    );
+
+    // Bytecode can be loaded by default in unsafe mode
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE, LuaOptions::default()) };
+    assert_eq!(lua.load(&bytecode).eval::<i64>()?, 2);
 
     Ok(())
 }
@@ -380,6 +628,70 @@ fn test_error() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_traceback_frames() -> Result<()> {
+    let lua = Lua::new();
+
+    let fail = lua.create_function(|_, ()| -> Result<()> { Err("boom".to_lua_err()) })?;
+    lua.globals().set("rust_fail", fail)?;
+    let err = lua
+        .load(
+            r#"
+            local t = {}
+            function t.method(self) rust_fail() end
+            local function inner() t:method() end
+            inner()
+        "#,
+        )
+        .set_name("=script.lua")?
+        .exec()
+        .unwrap_err();
+
+    let frames = err.traceback_frames().expect("callback error");
+    assert!(frames[0].is_c());
+    assert_eq!(frames[0].line, None);
+    assert_eq!(frames[0].name.as_deref(), Some("rust_fail"));
+    let lua_frames = frames.iter().filter(|f| !f.is_c()).collect::<Vec<_>>();
+    assert_eq!(lua_frames.len(), 3);
+    assert_eq!(lua_frames[0].source, "script.lua");
+    assert_eq!(lua_frames[0].line, Some(3));
+    assert_eq!(lua_frames[0].name.as_deref(), Some("method"));
+    assert_eq!(lua_frames[1].line, Some(4));
+    assert_eq!(lua_frames[1].name.as_deref(), Some("inner"));
+    assert_eq!(lua_frames[2].line, Some(5));
+    assert_eq!(lua_frames[2].name, None);
+
+    // Other formats, as produced by different Lua versions
+    let err = Error::CallbackError {
+        traceback: [
+            "stack traceback:",
+            "\t[C]: ?",
+            "\t[string \"a: b\"]:2: in function <[string \"a: b\"]:1>",
+            "\t...",
+            "\tC:\\scripts\\main.lua:10: in function 'mod.run'",
+            "\t...\t(skipping 3 levels)",
+            "\t[C]: in ?",
+        ]
+        .join("\n"),
+        cause: Arc::new(Error::RuntimeError("boom".to_string())),
+    };
+    let frames = err.traceback_frames().unwrap();
+    assert_eq!(frames.len(), 4);
+    assert!(frames[0].is_c() && frames[0].name.is_none());
+    assert_eq!(frames[1].source, "[string \"a: b\"]");
+    assert_eq!((frames[1].line, frames[1].name.as_ref()), (Some(2), None));
+    assert_eq!(frames[2].source, "C:\\scripts\\main.lua");
+    assert_eq!(frames[2].line, Some(10));
+    assert_eq!(frames[2].name.as_deref(), Some("mod.run"));
+    assert!(frames[3].is_c());
+
+    assert!(Error::RuntimeError("boom".to_string())
+        .traceback_frames()
+        .is_none());
+
+    Ok(())
+}
+
 #[test]
 fn test_panic() -> Result<()> {
     fn make_lua(options: LuaOptions) -> Result<Lua> {
@@ -757,6 +1069,71 @@ fn test_named_registry_value() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_on_close() -> Result<()> {
+    struct Resource(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Drop for Resource {
+        fn drop(&mut self) {
+            self.0.lock().unwrap().push("userdata");
+        }
+    }
+
+    impl UserData for Resource {}
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    let lua = Lua::new();
+    lua.globals().set("resource", Resource(events.clone()))?;
+    for name in &["first", "second"] {
+        let events = events.clone();
+        lua.on_close(move || events.lock().unwrap().push(*name));
+    }
+    assert!(events.lock().unwrap().is_empty());
+
+    drop(lua);
+    // Userdata are finalized first, then the handlers run in LIFO order
+    assert_eq!(*events.lock().unwrap(), vec!["userdata", "second", "first"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_registry_namespace() -> Result<()> {
+    let lua = Lua::new();
+
+    let crate_a = lua.registry_namespace("crate_a")?;
+    let crate_b = lua.registry_namespace("crate_b")?;
+    assert_eq!(crate_a.name(), "crate_a");
+
+    lua.set_named_registry_value("config", "global")?;
+    crate_a.set("config", "a")?;
+    crate_b.set("config", "b")?;
+    assert_eq!(
+        lua.named_registry_value::<_, StdString>("config")?,
+        "global"
+    );
+    assert_eq!(crate_a.get::<_, StdString>("config")?, "a");
+    assert_eq!(crate_b.get::<_, StdString>("config")?, "b");
+
+    // The same name refers to the same namespace, also from callbacks
+    let f = lua.create_function(|lua, ()| {
+        lua.registry_namespace("crate_a")?
+            .get::<_, StdString>("config")
+    })?;
+    assert_eq!(f.call::<_, StdString>(())?, "a");
+
+    crate_a.remove("config")?;
+    assert_eq!(crate_a.get::<_, Value>("config")?, Nil);
+    assert_eq!(crate_b.get::<_, StdString>("config")?, "b");
+    assert_eq!(
+        lua.named_registry_value::<_, StdString>("config")?,
+        "global"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_registry_value() -> Result<()> {
     let lua = Lua::new();
@@ -799,6 +1176,76 @@ fn test_drop_registry_value() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_registry_len() -> Result<()> {
+    let lua = Lua::new();
+    assert_eq!(lua.registry_len(), 0);
+
+    let keys = (0..100)
+        .map(|i| lua.create_registry_value(i))
+        .collect::<Result<Vec<_>>>()?;
+    let nil_key = lua.create_registry_value(Nil)?;
+    assert_eq!(lua.registry_len(), 100);
+
+    lua.remove_registry_value(nil_key)?;
+    drop(keys);
+    let kept = lua.create_registry_value("kept")?;
+    // Dropped keys are counted until they are expired
+    assert_eq!(lua.registry_len(), 101);
+
+    // Expiring is safe from inside a callback
+    let expire = lua.create_function(|lua, ()| {
+        lua.expire_registry_values();
+        Ok(lua.registry_len())
+    })?;
+    assert_eq!(expire.call::<_, usize>(())?, 1);
+    assert_eq!(lua.registry_value::<StdString>(&kept)?, "kept");
+
+    lua.remove_registry_value(kept)?;
+    assert_eq!(lua.registry_len(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_on_registry_expire() -> Result<()> {
+    let lua = Lua::new();
+
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let expired2 = expired.clone();
+    lua.on_registry_expire(move |key| expired2.lock().unwrap().push(key));
+
+    let dropped = lua.create_registry_value("dropped")?;
+    let removed = lua.create_registry_value("removed")?;
+    let kept = lua.create_registry_value("kept")?;
+    let nil = lua.create_registry_value(Nil)?;
+    let dropped_id = dropped.id();
+    assert_ne!(dropped_id, kept.id());
+
+    // Explicitly removed values and nil values do not trigger the handler
+    lua.remove_registry_value(removed)?;
+    drop(dropped);
+    drop(nil);
+    lua.expire_registry_values();
+    {
+        let expired = expired.lock().unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id(), dropped_id);
+        // The expired key no longer refers to a value
+        match lua.registry_value::<Value>(&expired[0]) {
+            Err(Error::MismatchedRegistryKey) => {}
+            r => panic!("expected MismatchedRegistryKey, got {:?}", r),
+        }
+    }
+
+    // Nothing left to expire
+    lua.expire_registry_values();
+    assert_eq!(expired.lock().unwrap().len(), 1);
+    assert_eq!(lua.registry_value::<StdString>(&kept)?, "kept");
+
+    Ok(())
+}
+
 #[test]
 fn test_lua_registry_hash() -> Result<()> {
     let lua = Lua::new();
@@ -1032,6 +1479,43 @@ fn test_chunk_env() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_chunk_name_and_env() -> Result<()> {
+    let lua = Lua::new();
+
+    let source = r#"
+        counter = (counter or 0) + 1
+        if counter > 1 then error("loaded twice") end
+        return counter
+    "#;
+    let error: Function = lua.globals().get("error")?;
+    let env1 = lua.create_table_from(vec![("error", error.clone())])?;
+    let env2 = lua.create_table_from(vec![("error", error)])?;
+    let f1 = lua
+        .load(source)
+        .set_name("=plugins/counter.lua")?
+        .set_environment(env1.clone())?
+        .into_function()?;
+    let f2 = lua
+        .load(source)
+        .set_name("=plugins/counter.lua")?
+        .set_environment(env2)?
+        .into_function()?;
+    assert_eq!(f1.call::<_, i64>(())?, 1);
+    assert_eq!(f2.call::<_, i64>(())?, 1);
+    assert_eq!(env1.get::<_, i64>("counter")?, 1);
+    assert!(!lua.globals().contains_key("counter")?);
+
+    match f1.call::<_, i64>(()) {
+        Err(Error::RuntimeError(msg)) => {
+            assert!(msg.starts_with("plugins/counter.lua:3: loaded twice"))
+        }
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_context_thread() -> Result<()> {
     let lua = Lua::new();
@@ -1086,3 +1570,551 @@ fn test_jit_version() -> Result<()> {
         .contains("LuaJIT"));
     Ok(())
 }
+
+#[test]
+fn test_nil_index_handler() -> Result<()> {
+    let lua = Lua::new();
+
+    let check_err = |code: &str, needle: &str| match lua.load(code).exec() {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains(needle), "{}", msg),
+        Err(Error::CallbackError { ref cause, .. }) => {
+            assert!(cause.to_string().contains(needle), "{}", cause)
+        }
+        r => panic!("expected error, got {:?}", r),
+    };
+
+    // Lua names the variable by itself
+    check_err("local config; return config.debug", "local 'config'");
+
+    lua.set_nil_index_handler(|_, key| {
+        Err(Error::RuntimeError(format!(
+            "attempt to read '{}' from nil",
+            key.type_name()
+        )))
+    })?;
+    check_err(
+        "local config; return config.debug",
+        "attempt to read 'string' from nil",
+    );
+
+    lua.set_nil_index_handler(|lua, key| match key {
+        Value::String(ref s) if s.as_bytes() == b"default" => {
+            Ok(Value::String(lua.create_string("fallback")?))
+        }
+        _ => Ok(Nil),
+    })?;
+    assert_eq!(
+        lua.load("local t = {}; return t.a.default")
+            .eval::<StdString>()?,
+        "fallback"
+    );
+
+    // The handler applies to every chunk and coroutine of the state
+    assert_eq!(
+        lua.load("return coroutine.wrap(function() local x; return x.default end)()")
+            .eval::<StdString>()?,
+        "fallback"
+    );
+
+    // Once removed, indexing nil raises the ordinary Lua errors again
+    lua.remove_nil_index_handler()?;
+    check_err("local t = {}; return t.a.default", "attempt to index");
+    check_err("local config; return config.debug", "local 'config'");
+    check_err("return (nil)[1]", "attempt to index a nil value");
+
+    Ok(())
+}
+
+#[test]
+fn test_const_global() -> Result<()> {
+    let lua = Lua::new();
+
+    lua.globals().set("VERSION", "0.0.0")?;
+    lua.set_const_global("VERSION", "1.2.3")?;
+    lua.set_const_global("MAX_USERS", 100)?;
+
+    lua.load(
+        r#"
+        assert(VERSION == "1.2.3")
+        assert(MAX_USERS == 100)
+        other = 1
+        other = 2
+        assert(other == 2)
+    "#,
+    )
+    .exec()?;
+
+    match lua.load(r#"VERSION = "2.0""#).exec() {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::RuntimeError(msg) => {
+                assert_eq!(msg, "cannot assign to constant global 'VERSION'")
+            }
+            err => panic!("expected RuntimeError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+    assert_eq!(lua.globals().get::<_, String>("VERSION")?, "1.2.3");
+
+    lua.set_const_global("VERSION", "1.2.4")?;
+    assert_eq!(lua.load("VERSION").eval::<String>()?, "1.2.4");
+
+    // Raw accesses bypass constants
+    lua.load(
+        r#"
+        assert(rawget(_G, "VERSION") == nil)
+        rawset(_G, "VERSION", "shadowed")
+        assert(VERSION == "shadowed")
+    "#,
+    )
+    .exec()?;
+
+    // Conflicting globals metatable
+    let lua = Lua::new();
+    lua.load("setmetatable(_G, {__index = function() end})")
+        .exec()?;
+    assert!(lua.set_const_global("VERSION", "1.2.3").is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "anyhow")]
+#[test]
+fn test_anyhow_error() -> Result<()> {
+    use anyhow::Context;
+
+    let lua = Lua::new();
+
+    let read_config = lua.create_function(|_, path: StdString| -> Result<()> {
+        Err(anyhow::anyhow!("file not found").context(format!("reading {}", path)))?;
+        Ok(())
+    })?;
+    lua.globals().set("read_config", read_config)?;
+
+    let res = lua
+        .load(r#"read_config("plugin.toml")"#)
+        .set_name("=loader")?
+        .exec()
+        .context("loading plugin");
+    let err = match res {
+        Err(err) => err,
+        Ok(_) => panic!("expected error"),
+    };
+
+    let chain = err.chain().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(chain[0], "loading plugin");
+    assert!(chain[1].starts_with("callback error"));
+    assert!(chain[1].contains("stack traceback:"));
+    assert!(chain[1].contains("loader:1"));
+    assert_eq!(chain[2], "reading plugin.toml");
+    assert_eq!(chain[3], "file not found");
+
+    Ok(())
+}
+
+#[test]
+fn test_globals_snapshot() -> Result<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set("config", lua.create_table()?)?;
+    globals.set("version", 1)?;
+
+    let snapshot = lua.snapshot_globals()?;
+    for _ in 0..2 {
+        lua.load(
+            r#"
+            leaked = "yes"
+            version = 2
+            string = nil
+            config.changed = true
+        "#,
+        )
+        .exec()?;
+        lua.restore_globals(&snapshot)?;
+
+        assert_eq!(globals.get::<_, Value>("leaked")?, Nil);
+        assert_eq!(globals.get::<_, i64>("version")?, 1);
+        assert_eq!(lua.load("string.rep('a', 2)").eval::<StdString>()?, "aa");
+        // Snapshots are shallow, nested tables are shared
+        assert!(globals
+            .get::<_, Table>("config")?
+            .get::<_, bool>("changed")?);
+    }
+
+    // Snapshots can't be restored into another state
+    let other = Lua::new();
+    match other.restore_globals(&snapshot) {
+        Err(Error::MismatchedRegistryKey) => {}
+        r => panic!("expected MismatchedRegistryKey, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_child_env() -> Result<()> {
+    let lua = Lua::new();
+
+    lua.globals().set("secret", "hunter2")?;
+    lua.globals().set("shared", 1)?;
+
+    let plugin_env = lua.child_env(lua.globals(), &["shared", "assert", "tostring"])?;
+    lua.load(
+        r#"
+        assert(shared == 1)
+        assert(secret == nil)
+        assert(print == nil)
+        shared = 2
+        plugin_value = "plugin"
+    "#,
+    )
+    .set_environment(plugin_env.clone())?
+    .exec()?;
+
+    assert_eq!(lua.globals().get::<_, i64>("shared")?, 1);
+    assert_eq!(plugin_env.raw_get::<_, i64>("shared")?, 2);
+    assert!(!lua.globals().contains_key("plugin_value")?);
+
+    // Nested environment, only sees what the plugin allows
+    let nested_env = lua.child_env(plugin_env.clone(), &["shared", "plugin_value", "secret"])?;
+    assert!(lua
+        .load("tostring(1)")
+        .set_environment(nested_env.clone())?
+        .exec()
+        .is_err());
+    lua.load(r#"result = shared == 2 and plugin_value == "plugin" and secret == nil"#)
+        .set_environment(nested_env.clone())?
+        .exec()?;
+    assert!(nested_env.get::<_, bool>("result")?);
+    assert!(!plugin_env.contains_key("result")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_variadic_error_position() -> Result<()> {
+    let lua = Lua::new();
+
+    let sum = lua.create_function(|_, (scale, vals): (f64, Variadic<f64>)| {
+        Ok(scale * vals.iter().sum::<f64>())
+    })?;
+    assert_eq!(sum.call::<_, f64>((2, 1, 2, 3))?, 12.0);
+
+    match sum.call::<_, f64>((2, 1, 2, "three")) {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::FromLuaConversionError {
+                from: "string",
+                to: "f64",
+                message: Some(message),
+            } => assert!(message.starts_with("bad vararg #3: ")),
+            err => panic!("expected FromLuaConversionError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bounded_variadic() -> Result<()> {
+    let lua = Lua::new();
+
+    let count =
+        lua.create_function(|_, (_, vals): (String, BoundedVariadic<i64, 3>)| Ok(vals.len()))?;
+    assert_eq!(count.call::<_, usize>("a")?, 0);
+    assert_eq!(count.call::<_, usize>(("a", 1, 2, 3))?, 3);
+
+    match count.call::<_, usize>(("a", 1, 2, 3, 4)) {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::FromLuaConversionError {
+                to: "BoundedVariadic",
+                message: Some(message),
+                ..
+            } => assert_eq!(message, "too many values: expected at most 3, got 4"),
+            err => panic!("expected FromLuaConversionError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    // The bound is checked before any value is converted
+    let result = count.call::<_, usize>(
+        std::iter::once(Value::String(lua.create_string("a")?))
+            .chain((0..10).map(|_| Value::Boolean(true)))
+            .collect::<Variadic<_>>(),
+    );
+    match result {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::FromLuaConversionError {
+                to: "BoundedVariadic",
+                ..
+            } => {}
+            err => panic!("expected FromLuaConversionError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_validated_return() -> Result<()> {
+    let lua = Lua::new();
+
+    let checked_div = lua.create_function(|_, (a, b): (f64, f64)| {
+        Ok(Validated::new(
+            (a / b, a % b),
+            |(q, _): &(f64, f64)| match q.is_finite() {
+                true => Ok(()),
+                false => Err(Error::RuntimeError("result is not finite".to_string())),
+            },
+        ))
+    })?;
+    assert_eq!(checked_div.call::<_, (f64, f64)>((7.0, 2.0))?, (3.5, 1.0));
+
+    match checked_div.call::<_, (f64, f64)>((1.0, 0.0)) {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::RuntimeError(msg) => assert_eq!(msg, "result is not finite"),
+            err => panic!("expected RuntimeError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    // The validator is not called when the value is unwrapped
+    let validated = Validated::new(5, |_: &i32| -> Result<()> { unreachable!() });
+    assert_eq!(validated.into_inner(), 5);
+
+    Ok(())
+}
+
+#[test]
+fn test_seq_table_return() -> Result<()> {
+    let lua = Lua::new();
+
+    let seq = lua.create_function(|_, t: Table| Ok(SeqTable::new(t)))?;
+    lua.globals().set("seq", seq)?;
+
+    lua.load(
+        r#"
+        local t = {1, 2, 3}
+        assert(seq(t) == t)
+        assert(#seq({}) == 0)
+    "#,
+    )
+    .exec()?;
+
+    for code in [
+        "seq({1, nil, 3})",
+        "seq({1, 2, x = 3})",
+        "seq({[0] = 0, 1})",
+        "seq({1, [2.5] = 2})",
+    ] {
+        match lua.load(code).exec() {
+            Err(Error::CallbackError { cause, .. }) => match cause.as_ref() {
+                Error::ToLuaConversionError {
+                    from: "SeqTable", ..
+                } => {}
+                err => panic!("expected ToLuaConversionError, got {:?}", err),
+            },
+            r => panic!("expected CallbackError for `{}`, got {:?}", code, r),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_redirect_io() -> Result<()> {
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let lua = Lua::new();
+
+    let (stdout, stderr) = (Sink::default(), Sink::default());
+    lua.redirect_io(stdout.clone(), stderr.clone())?;
+    lua.load(
+        r#"
+        io.write("a", 1, "\n")
+        assert(io.stdout:write("b"):write("c\n") == io.stdout)
+        assert(io.output() == io.stdout)
+        io.output():write("d\n")
+        print("e", 2)
+        io.stderr:write("f")
+        assert(io.stdout:flush() == io.stdout)
+        assert(io.close() == nil)
+    "#,
+    )
+    .exec()?;
+    assert_eq!(*stdout.0.lock().unwrap(), b"a1\nbc\nd\ne\t2\n");
+    assert_eq!(*stderr.0.lock().unwrap(), b"f");
+
+    // Output sinks of threads take precedence
+    let thread_sink = Sink::default();
+    let thread = lua.create_thread(lua.load(r#"function() io.write("g") end"#).eval()?)?;
+    thread.set_output(thread_sink.clone())?;
+    thread.resume::<_, ()>(())?;
+    assert_eq!(*thread_sink.0.lock().unwrap(), b"g");
+
+    // Replacing the sinks
+    let stdout2 = Sink::default();
+    lua.redirect_io(stdout2.clone(), stderr.clone())?;
+    lua.load(r#"io.write("h")"#).exec()?;
+    assert_eq!(*stdout2.0.lock().unwrap(), b"h");
+    assert_eq!(*stdout.0.lock().unwrap(), b"a1\nbc\nd\ne\t2\n");
+
+    // The print handler replaces the `print` stream only
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let lines2 = lines.clone();
+    lua.set_print_handler(move |_, line| lines2.lock().unwrap().push(line.to_vec()))?;
+    lua.load(r#"print("i") io.write("j")"#).exec()?;
+    assert_eq!(*lines.lock().unwrap(), vec![b"i\n".to_vec()]);
+    assert_eq!(*stdout2.0.lock().unwrap(), b"hj");
+
+    Ok(())
+}
+
+#[test]
+fn test_module_loader() -> Result<()> {
+    let lua = Lua::new();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+    lua.set_module_loader(move |lua, name| {
+        calls2.fetch_add(1, Ordering::Relaxed);
+        match name {
+            "foo.bar" => Ok(Some(
+                lua.load("local name = ... return { name = name }")
+                    .into_function()?,
+            )),
+            _ => Ok(None),
+        }
+    })?;
+
+    lua.load(
+        r#"
+        local m = require("foo.bar")
+        assert(m.name == "foo.bar")
+        assert(require("foo.bar") == m)
+        assert(package.loaded["foo.bar"] == m)
+    "#,
+    )
+    .exec()?;
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    // `None` defers to the next searchers
+    match lua.load(r#"require("foo.baz")"#).exec() {
+        Err(Error::RuntimeError(msg)) => {
+            assert!(msg.contains("no module 'foo.baz' in Rust loader"));
+            assert!(msg.contains("no field package.preload['foo.baz']"));
+        }
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    // `package.preload` takes precedence
+    lua.load(r#"package.preload["foo.qux"] = function() return "preload" end"#)
+        .exec()?;
+    assert_eq!(
+        lua.load(r#"require("foo.qux")"#).eval::<String>()?,
+        "preload"
+    );
+
+    // Replacing the loader
+    let calls_before = calls.load(Ordering::Relaxed);
+    lua.set_module_loader(|lua, _| Ok(Some(lua.load("return 1").into_function()?)))?;
+    assert_eq!(lua.load(r#"require("foo.baz")"#).eval::<i64>()?, 1);
+    assert_eq!(calls.load(Ordering::Relaxed), calls_before);
+
+    Ok(())
+}
+
+#[test]
+fn test_print_handler() -> Result<()> {
+    let lua = Lua::new();
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let lines2 = lines.clone();
+    lua.set_print_handler(move |_, line| lines2.lock().unwrap().push(line.to_vec()))?;
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let written2 = written.clone();
+    lua.set_write_handler(move |_, data| written2.lock().unwrap().push(data.to_vec()))?;
+
+    lua.load(
+        r#"
+        print("a", 1, nil, true)
+        print()
+        print(setmetatable({}, {__tostring = function() return "b" end}))
+        assert(io.write("c", 2, "\n") == io.output())
+    "#,
+    )
+    .exec()?;
+    assert_eq!(
+        *lines.lock().unwrap(),
+        vec![
+            b"a\t1\tnil\ttrue\n".to_vec(),
+            b"\n".to_vec(),
+            b"b\n".to_vec()
+        ]
+    );
+    assert_eq!(*written.lock().unwrap(), vec![b"c2\n".to_vec()]);
+
+    // Numbers are formatted like the stock `io.write` does
+    lua.load(r#"io.write(1.0, " ", 0.1, " ", 2^53, " ", -7)"#)
+        .exec()?;
+    assert_eq!(
+        written.lock().unwrap().pop().unwrap(),
+        b"1 0.1 9.007199254741e+15 -7"
+    );
+
+    match lua.load(r#"io.write({})"#).exec() {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("bad argument #1 to 'write'")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    // Removing the handlers restores the default behavior
+    lua.remove_print_handler()?;
+    lua.remove_write_handler()?;
+    lua.load(r#"print("d") io.write("e\n")"#).exec()?;
+    assert_eq!(lines.lock().unwrap().len(), 3);
+    assert_eq!(written.lock().unwrap().len(), 1);
+
+    Ok(())
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn test_unbalanced_callback_stack() -> Result<()> {
+    extern "C" {
+        fn luaL_newstate() -> *mut lua_State;
+        fn lua_pushnil(state: *mut lua_State);
+    }
+
+    let state = unsafe { luaL_newstate() };
+    let lua = unsafe { Lua::init_from_ptr(state) };
+
+    let balanced = lua.create_function(|_, ()| Ok(1))?;
+    assert_eq!(balanced.call::<_, i64>(())?, 1);
+
+    // Leave a value behind on the raw stack
+    let state_addr = state as usize;
+    let unbalanced = lua.create_function(move |_, ()| {
+        unsafe { lua_pushnil(state_addr as *mut lua_State) };
+        Ok(1)
+    })?;
+    match catch_unwind(AssertUnwindSafe(|| unbalanced.call::<_, i64>(()))) {
+        Err(p) => match p.downcast_ref::<StdString>() {
+            Some(msg) => assert!(msg.contains("left the Lua stack unbalanced"), "{}", msg),
+            None => panic!("unexpected panic payload"),
+        },
+        Ok(r) => panic!("expected the debug assertion to fail, got {:?}", r),
+    }
+
+    Ok(())
+}
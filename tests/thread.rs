@@ -1,6 +1,9 @@
+use std::io;
 use std::panic::catch_unwind;
+use std::string::String as StdString;
+use std::sync::{Arc, Mutex};
 
-use mlua::{Error, Function, Lua, Result, Thread, ThreadStatus};
+use mlua::{Error, Function, Lua, MultiValue, Result, Thread, ThreadStatus, Variadic};
 
 #[test]
 fn test_thread() -> Result<()> {
@@ -93,6 +96,31 @@ fn test_thread() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_thread_resume_many_args() -> Result<()> {
+    let lua = Lua::new();
+
+    // Resuming must reserve stack space for every argument, not just a few of them
+    let thread = lua.create_thread(
+        lua.load(
+            r##"
+            function(...)
+                local sum = 0
+                for i = 1, select("#", ...) do
+                    sum = sum + select(i, ...)
+                end
+                return sum
+            end
+        "##,
+        )
+        .eval()?,
+    )?;
+    let args = (1..=1000).collect::<Variadic<i64>>();
+    assert_eq!(thread.resume::<_, i64>(args)?, 500500);
+
+    Ok(())
+}
+
 #[test]
 #[cfg(any(feature = "lua54", all(feature = "luajit", feature = "vendored")))]
 fn test_thread_reset() -> Result<()> {
@@ -136,6 +164,262 @@ fn test_thread_reset() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_thread_reset_running() -> Result<()> {
+    let lua = Lua::new();
+
+    let body: Function = lua.load("function() return 1 end").eval()?;
+    let reset = lua.create_function(move |_, (thread, body): (Thread, Function)| {
+        match thread.reset(body) {
+            Err(Error::RuntimeError(msg)) => Ok(msg),
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+    })?;
+    lua.globals().set("reset", reset)?;
+    lua.globals().set("body", body.clone())?;
+
+    // Resetting the running thread, or a thread waiting on another one, fails
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function()
+                local this = coroutine.running()
+                local msg1 = reset(this, body)
+                local inner = coroutine.create(function() return reset(this, body) end)
+                local _, msg2 = coroutine.resume(inner)
+                return msg1, msg2
+            end)
+        "#,
+        )
+        .eval()?;
+    let (msg1, msg2) = thread.resume::<_, (StdString, StdString)>(())?;
+
+    #[cfg(any(feature = "lua54", all(feature = "luajit", feature = "vendored")))]
+    {
+        assert_eq!(msg1, "cannot reset a running thread");
+        assert_eq!(msg2, "cannot reset a running thread");
+        // A finished thread can be reused
+        thread.reset(body)?;
+        assert_eq!(thread.resume::<_, i64>(())?, 1);
+    }
+
+    #[cfg(not(any(feature = "lua54", all(feature = "luajit", feature = "vendored"))))]
+    {
+        let unsupported = "resetting threads is not supported by this Lua version";
+        assert_eq!(msg1, unsupported);
+        assert_eq!(msg2, unsupported);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_resume_round() -> Result<()> {
+    let lua = Lua::new();
+
+    let func: Function = lua
+        .load(
+            r#"
+            function(n)
+                for i = 1, n - 1 do
+                    coroutine.yield(i)
+                end
+            end
+        "#,
+        )
+        .eval()?;
+
+    let short = lua.create_thread(func.clone())?;
+    let long = lua.create_thread(func)?;
+    let failing = lua.create_thread(lua.load(r#"function() error("boom") end"#).eval()?)?;
+
+    let args = |n: i64| -> Result<MultiValue> { lua.pack_multi(n) };
+    let pool = [(&short, args(1)?), (&long, args(3)?), (&failing, args(0)?)];
+
+    let statuses = lua.resume_round(&pool);
+    assert_eq!(statuses.len(), 3);
+    assert_eq!(statuses[0].as_ref().ok(), Some(&ThreadStatus::Unresumable));
+    assert_eq!(statuses[1].as_ref().ok(), Some(&ThreadStatus::Resumable));
+    assert!(statuses[2].is_err());
+
+    let pool = [(&short, MultiValue::new()), (&long, MultiValue::new())];
+    let statuses = lua.resume_round(&pool);
+    match statuses[0] {
+        Err(Error::CoroutineInactive) => {}
+        ref r => panic!("expected CoroutineInactive, got {:?}", r),
+    }
+    assert_eq!(statuses[1].as_ref().ok(), Some(&ThreadStatus::Resumable));
+
+    let statuses = lua.resume_round(&pool[1..]);
+    assert_eq!(statuses[0].as_ref().ok(), Some(&ThreadStatus::Unresumable));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_coroutine() -> Result<()> {
+    let lua = Lua::new();
+
+    let func: Function = lua
+        .load(
+            r#"
+            function(a, b)
+                coroutine.yield(a)
+                coroutine.yield(b, a + b)
+                coroutine.yield()
+                return a * b, "done"
+            end
+        "#,
+        )
+        .eval()?;
+
+    let (yields, (product, tag)) = lua.run_coroutine::<_, (i64, StdString)>(func, (2, 3))?;
+    assert_eq!(yields.len(), 3);
+    assert_eq!(lua.unpack_multi::<i64>(yields[0].clone())?, 2);
+    assert_eq!(lua.unpack_multi::<(i64, i64)>(yields[1].clone())?, (3, 5));
+    assert!(yields[2].is_empty());
+    assert_eq!(product, 6);
+    assert_eq!(tag, "done");
+
+    let failing: Function = lua
+        .load(r#"function() coroutine.yield(1) error("boom") end"#)
+        .eval()?;
+    match lua.run_coroutine::<_, ()>(failing, ()) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("boom")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_resume_status() -> Result<()> {
+    let lua = Lua::new();
+
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function(a)
+                local b = coroutine.yield(a + 1, "yielded")
+                if b == "fail" then
+                    error("boom")
+                end
+                return b * 2
+            end)
+        "#,
+        )
+        .eval()?;
+
+    assert_eq!(
+        thread.resume_status::<_, (i64, String)>(1)?,
+        (ThreadStatus::Resumable, (2, "yielded".to_string()))
+    );
+    assert_eq!(
+        thread.resume_status::<_, i64>(21)?,
+        (ThreadStatus::Unresumable, 42)
+    );
+    assert_eq!(thread.status(), ThreadStatus::Unresumable);
+    match thread.resume_status::<_, ()>(()) {
+        Err(Error::CoroutineInactive) => {}
+        r => panic!("expected CoroutineInactive, got {:?}", r),
+    }
+
+    let thread: Thread = lua
+        .load("coroutine.create(function() coroutine.yield(); error('boom') end)")
+        .eval()?;
+    thread.resume_status::<_, ()>(())?;
+    match thread.resume_status::<_, ()>(()) {
+        Err(Error::RuntimeError(msg)) => {
+            assert!(msg.contains("boom"));
+            assert!(msg.contains("stack traceback"));
+        }
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+    assert_eq!(thread.status(), ThreadStatus::Error);
+
+    Ok(())
+}
+
+#[test]
+fn test_thread_output() -> Result<()> {
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let lua = Lua::new();
+
+    let func: Function = lua
+        .load(
+            r#"
+            function(name)
+                print("hello", name)
+                coroutine.yield()
+                io.write(name, " ", 42, "\n")
+            end
+        "#,
+        )
+        .eval()?;
+
+    let thread1 = lua.create_thread(func.clone())?;
+    let thread2 = lua.create_thread(func)?;
+    let (sink1, sink2) = (Sink::default(), Sink::default());
+    thread1.set_output(sink1.clone())?;
+    thread2.set_output(sink2.clone())?;
+
+    thread1.resume::<_, ()>("one")?;
+    thread2.resume::<_, ()>("two")?;
+    thread1.resume::<_, ()>(())?;
+    thread2.resume::<_, ()>(())?;
+
+    assert_eq!(*sink1.0.lock().unwrap(), b"hello\tone\none 42\n");
+    assert_eq!(*sink2.0.lock().unwrap(), b"hello\ttwo\ntwo 42\n");
+
+    thread2.remove_output()?;
+    let thread3 = lua.create_thread(lua.load(r#"function() print("three") end"#).eval()?)?;
+    thread3.set_output(sink2.clone())?;
+    thread3.resume::<_, ()>(())?;
+    assert_eq!(*sink2.0.lock().unwrap(), b"hello\ttwo\ntwo 42\nthree\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_is_in_coroutine() -> Result<()> {
+    let lua = Lua::new();
+
+    assert!(!lua.is_in_coroutine());
+
+    let check = lua.create_function(|lua, ()| Ok((lua.is_in_coroutine(), lua.current_thread())))?;
+    lua.globals().set("check", check)?;
+
+    let (in_coroutine, _): (bool, Thread) = lua.load("check()").eval()?;
+    assert!(!in_coroutine);
+
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function()
+                local in_coroutine, thread = check()
+                assert(thread == coroutine.running())
+                coroutine.yield(in_coroutine)
+            end)
+        "#,
+        )
+        .eval()?;
+    assert!(thread.resume::<_, bool>(())?);
+
+    Ok(())
+}
+
 #[test]
 fn test_coroutine_from_closure() -> Result<()> {
     let lua = Lua::new();
@@ -24,3 +24,29 @@ fn test_lightuserdata() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_lightuserdata_tag() -> Result<()> {
+    let lua = Lua::new();
+    let id: Function = lua.load("function(a) return a end").eval()?;
+
+    let mut handles = [0u32; 2];
+    for (i, handle) in handles.iter_mut().enumerate() {
+        let ptr = handle as *mut u32;
+        let ud = id.call::<_, LightUserData>(LightUserData::with_tag(ptr, i + 2)?)?;
+        assert_eq!(ud.tag(), i + 2);
+        assert_eq!(ud.untagged_ptr() as *mut u32, ptr);
+    }
+
+    let ptr = handles.as_mut_ptr();
+    assert!(LightUserData::with_tag(ptr, 4).is_err());
+    assert!(LightUserData::with_tag((ptr as *mut u8).wrapping_add(1), 0).is_err());
+
+    assert_eq!(
+        LightUserData::POINTER_SIZE,
+        std::mem::size_of::<*mut c_void>()
+    );
+    assert!(LightUserData::ADDRESS_BITS <= LightUserData::POINTER_SIZE as u32 * 8);
+
+    Ok(())
+}
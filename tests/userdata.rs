@@ -1,3 +1,4 @@
+use std::string::String as StdString;
 use std::sync::{Arc, Mutex, RwLock};
 
 #[cfg(not(feature = "send"))]
@@ -7,8 +8,8 @@ use std::{cell::RefCell, rc::Rc};
 use std::sync::atomic::{AtomicI64, Ordering};
 
 use mlua::{
-    AnyUserData, Error, ExternalError, Function, Lua, MetaMethod, Nil, Result, String, UserData,
-    UserDataFields, UserDataMethods, Value,
+    AnyUserData, DynamicMethods, Error, ExternalError, Function, Lua, MetaMethod, MultiValue, Nil,
+    Result, String, ToLuaMulti, UserData, UserDataFields, UserDataMethods, Value, WeakUserData,
 };
 
 #[test]
@@ -166,6 +167,68 @@ fn test_metamethods() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_metamethod_pairs() -> Result<()> {
+    struct OrderedMap(Vec<(StdString, i64)>);
+
+    impl UserData for OrderedMap {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_function(MetaMethod::Pairs, |lua, ud: AnyUserData| {
+                let next =
+                    lua.create_function(|_, (ud, key): (AnyUserData, Option<StdString>)| {
+                        let map = ud.borrow::<OrderedMap>()?;
+                        let i = match key {
+                            Some(key) => map
+                                .0
+                                .iter()
+                                .position(|(k, _)| *k == key)
+                                .map_or(0, |i| i + 1),
+                            None => 0,
+                        };
+                        match map.0.get(i) {
+                            Some((k, v)) => Ok((Some(k.clone()), Some(*v))),
+                            None => Ok((None, None)),
+                        }
+                    })?;
+                Ok((next, ud, Value::Nil))
+            });
+        }
+    }
+
+    let lua = Lua::new();
+    lua.enable_pairs_metamethod()?;
+
+    let map = OrderedMap(vec![
+        ("zeta".into(), 1),
+        ("alpha".into(), 2),
+        ("mu".into(), 3),
+    ]);
+    lua.globals().set("map", map)?;
+
+    let (keys, sum) = lua
+        .load(
+            r#"
+            local keys, sum = {}, 0
+            for k, v in pairs(map) do
+                keys[#keys + 1] = k
+                sum = sum + v
+            end
+            return table.concat(keys, ","), sum
+        "#,
+        )
+        .eval::<(StdString, i64)>()?;
+    assert_eq!(keys, "zeta,alpha,mu");
+    assert_eq!(sum, 6);
+
+    // Tables still iterate as usual
+    let count = lua
+        .load("local n = 0 for _ in pairs({1, 2, x = 3}) do n = n + 1 end return n")
+        .eval::<i64>()?;
+    assert_eq!(count, 3);
+
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "lua54")]
 fn test_metamethod_close() -> Result<()> {
@@ -212,6 +275,65 @@ fn test_metamethod_close() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_partial_eq() -> Result<()> {
+    #[derive(Clone, PartialEq)]
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_partial_eq();
+        }
+    }
+
+    struct Other(i64);
+
+    impl UserData for Other {}
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set("ud1", MyUserData(1))?;
+    globals.set("ud2", MyUserData(1))?;
+    globals.set("ud3", MyUserData(2))?;
+    globals.set("other", Other(1))?;
+
+    lua.load(
+        r#"
+        assert(ud1 == ud1)
+        assert(ud1 == ud2)
+        assert(ud1 ~= ud3)
+        assert(ud1 ~= other)
+        assert(ud1 ~= 1)
+    "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_meta_method_call() -> Result<()> {
+    struct Adder(i64);
+
+    impl UserData for Adder {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method_call(|_, this, (a, b): (i64, Option<i64>)| {
+                Ok(this.0 + a + b.unwrap_or(0))
+            });
+        }
+    }
+
+    let lua = Lua::new();
+    lua.globals().set("add10", Adder(10))?;
+
+    lua.load(
+        r#"
+        assert(add10(1) == 11)
+        assert(add10(1, 2) == 13)
+        assert(not pcall(add10, "x"))
+    "#,
+    )
+    .exec()
+}
+
 #[test]
 fn test_gc_userdata() -> Result<()> {
     struct MyUserdata {
@@ -395,6 +517,62 @@ fn test_fields() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_field_names() -> Result<()> {
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("val", |_, data| Ok(data.0));
+            fields.add_field_method_set("val", |_, data, val| {
+                data.0 = val;
+                Ok(())
+            });
+            fields.add_field_method_get("double", |_, data| Ok(data.0 * 2));
+            fields.add_field_method_set("write_only", |_, _, _: i64| Ok(()));
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("method", |_, _, ()| Ok(()));
+            methods.add_meta_method(MetaMethod::Index, |_, _, ()| Ok(0));
+        }
+    }
+
+    struct MethodsOnly;
+
+    impl UserData for MethodsOnly {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("method", |_, _, ()| Ok(()));
+        }
+    }
+
+    struct IndexOnly;
+
+    impl UserData for IndexOnly {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Index, |_, _, ()| Ok(0));
+        }
+    }
+
+    let lua = Lua::new();
+
+    let ud = lua.create_userdata(MyUserData(1))?;
+    assert_eq!(ud.field_names()?, vec!["double", "val"]);
+    assert!(lua.create_userdata(MethodsOnly)?.field_names()?.is_empty());
+    assert!(lua.create_userdata(IndexOnly)?.field_names()?.is_empty());
+
+    lua.scope(|scope| {
+        let ud = scope.create_nonstatic_userdata(MyUserData(1))?;
+        assert_eq!(ud.field_names()?, vec!["double", "val"]);
+        lua.globals().set("ud", ud)?;
+        Ok(())
+    })?;
+    let ud: AnyUserData = lua.globals().get("ud")?;
+    assert!(matches!(ud.field_names(), Err(Error::UserDataDestructed)));
+
+    Ok(())
+}
+
 #[test]
 fn test_metatable() -> Result<()> {
     #[derive(Copy, Clone)]
@@ -529,3 +707,527 @@ fn test_userdata_wrapped() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_weak_userdata() -> Result<()> {
+    struct Node {
+        value: i64,
+        parent: Option<mlua::RegistryKey>,
+        _guard: Arc<()>,
+    }
+
+    impl UserData for Node {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("parent_value", |lua, this, ()| {
+                let parent = match this.parent {
+                    Some(ref key) => lua.registry_value::<WeakUserData>(key)?.upgrade()?,
+                    None => None,
+                };
+                match parent {
+                    Some(parent) => Ok(Some(parent.borrow::<Node>()?.value)),
+                    None => Ok(None),
+                }
+            });
+        }
+    }
+
+    let lua = Lua::new();
+    let guard = Arc::new(());
+
+    let parent = lua.create_userdata(Node {
+        value: 1,
+        parent: None,
+        _guard: guard.clone(),
+    })?;
+    let weak = parent.downgrade()?;
+    assert_eq!(weak.upgrade()?.unwrap(), parent);
+    assert_eq!(weak.clone().upgrade()?.unwrap(), parent);
+
+    // Only tables created by `downgrade` are weak userdata handles
+    match lua.unpack::<WeakUserData>(Value::Table(lua.create_table()?)) {
+        Err(Error::FromLuaConversionError { .. }) => {}
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+    let weak2 = lua.unpack::<WeakUserData>(lua.pack(weak.clone())?)?;
+    assert_eq!(weak2.upgrade()?.unwrap(), parent);
+
+    let child = lua.create_userdata(Node {
+        value: 2,
+        parent: Some(lua.create_registry_value(weak.clone())?),
+        _guard: guard.clone(),
+    })?;
+    lua.globals().set("child", child)?;
+    assert_eq!(
+        lua.load("child:parent_value()").eval::<Option<i64>>()?,
+        Some(1)
+    );
+
+    // The child handle to the parent is weak, so the parent can be collected
+    drop(parent);
+    lua.gc_collect()?;
+    lua.gc_collect()?;
+    assert_eq!(Arc::strong_count(&guard), 2);
+    assert!(weak.upgrade()?.is_none());
+    assert_eq!(
+        lua.load("child:parent_value()").eval::<Option<i64>>()?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_replace_methods() -> Result<()> {
+    #[derive(Clone, Copy)]
+    struct Counter(i64);
+
+    impl UserData for Counter {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("value", |_, this| Ok(this.0));
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("step", |_, this, ()| {
+                this.0 += 1;
+                Ok(this.0)
+            });
+            methods.add_method("old", |_, _, ()| Ok(()));
+            methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+                Ok(format!("Counter({})", this.0))
+            });
+        }
+    }
+
+    struct Plain;
+    impl UserData for Plain {}
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    let counter = lua.create_userdata(Counter(0))?;
+    globals.set("counter", counter.clone())?;
+    globals.set("other", Counter(10))?;
+    assert_eq!(lua.load("counter:step()").eval::<i64>()?, 1);
+
+    counter.replace_methods::<Counter, _>(|methods| {
+        methods.add_method_mut("step", |_, this, ()| {
+            this.0 += 10;
+            Ok(this.0)
+        });
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.0));
+    })?;
+
+    // Existing instances, including the ones only referenced from Lua, use the new methods
+    assert_eq!(lua.load("counter:step()").eval::<i64>()?, 11);
+    assert_eq!(lua.load("other:step()").eval::<i64>()?, 20);
+    assert_eq!(lua.load("#counter").eval::<i64>()?, 11);
+    assert_eq!(lua.load("tostring(other)").eval::<String>()?, "Counter(20)");
+    assert_eq!(lua.load("counter.value").eval::<i64>()?, 11);
+    assert!(lua.load("counter:old()").exec().is_err());
+
+    assert!(matches!(
+        counter.replace_methods::<Plain, _>(|_| {}),
+        Err(Error::UserDataTypeMismatch)
+    ));
+
+    // A type without any methods gets an `__index` wrapper installed
+    let plain = lua.create_userdata(Plain)?;
+    globals.set("plain", plain.clone())?;
+    plain.replace_methods::<Plain, _>(|methods| {
+        methods.add_method("hello", |_, _, ()| Ok("hello"));
+    })?;
+    assert_eq!(lua.load("plain:hello()").eval::<String>()?, "hello");
+
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_userdata() -> Result<()> {
+    let lua = Lua::new();
+
+    let methods = DynamicMethods::new();
+    let plugin = lua.create_dynamic_userdata(methods.clone())?;
+    lua.globals().set("plugin", plugin.clone())?;
+    assert!(lua.load("plugin:add(1, 2)").exec().is_err());
+    assert_eq!(lua.load("plugin.add").eval::<Value>()?, Nil);
+
+    methods.insert("add", |lua, args: MultiValue| {
+        let (this, a, b): (AnyUserData, i64, i64) = lua.unpack_multi(args)?;
+        assert!(this == lua.globals().get::<_, AnyUserData>("plugin")?);
+        (a + b).to_lua_multi(lua)
+    });
+    assert!(methods.contains("add"));
+    assert_eq!(lua.load("plugin:add(1, 2)").eval::<i64>()?, 3);
+
+    // A method fetched before removal errors when called
+    lua.load("add = plugin.add").exec()?;
+    assert!(methods.remove("add"));
+    assert!(!methods.remove("add"));
+    assert!(lua.load("plugin:add(1, 2)").exec().is_err());
+    match lua.load("add(plugin, 1, 2)").exec() {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::RuntimeError(msg) => assert_eq!(msg, "dynamic method 'add' was removed"),
+            err => panic!("expected RuntimeError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    // Lookups of the same method return the same function, which needs `self`
+    methods.insert("sub", |lua, args: MultiValue| {
+        let (_, a, b): (AnyUserData, i64, i64) = lua.unpack_multi(args)?;
+        (a - b).to_lua_multi(lua)
+    });
+    assert!(lua.load("plugin.sub == plugin.sub").eval::<bool>()?);
+    assert!(lua.load("plugin.sub(1, 2)").exec().is_err());
+    assert_eq!(lua.load("plugin:sub(5, 2)").eval::<i64>()?, 3);
+    methods.remove("sub");
+
+    // Methods can extend the dispatch table while being called
+    let inner = methods.clone();
+    methods.insert("define", move |lua, args: MultiValue| {
+        let (_, name, value): (AnyUserData, std::string::String, i64) = lua.unpack_multi(args)?;
+        inner.insert(name, move |lua, _| value.to_lua_multi(lua));
+        Ok(MultiValue::new())
+    });
+    assert_eq!(
+        lua.load("plugin:define('answer', 42); return plugin:answer()")
+            .eval::<i64>()?,
+        42
+    );
+    assert_eq!(
+        format!("{:?}", methods),
+        r#"DynamicMethods(["answer", "define"])"#
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_bind_method() -> Result<()> {
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("add", |_, this, n: i64| {
+                this.0 += n;
+                Ok(this.0)
+            });
+        }
+    }
+
+    let lua = Lua::new();
+
+    let ud = lua.create_userdata(MyUserData(0))?;
+    let add = ud.bind_method("add")?;
+    for i in 1..=10 {
+        assert_eq!(add.call::<_, i64>(i)?, i * (i + 1) / 2);
+    }
+    assert_eq!(ud.borrow::<MyUserData>()?.0, 55);
+
+    // Bound methods are regular functions usable from Lua
+    lua.globals().set("add", add)?;
+    assert_eq!(lua.load("add(5)").eval::<i64>()?, 60);
+
+    match ud.bind_method("missing") {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("unknown field 'missing'")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_userdata_take() -> Result<()> {
+    #[derive(Debug)]
+    struct Config {
+        name: std::string::String,
+        drops: Arc<Mutex<u32>>,
+    }
+
+    impl Drop for Config {
+        fn drop(&mut self) {
+            *self.drops.lock().unwrap() += 1;
+        }
+    }
+
+    impl UserData for Config {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("set_name", |_, this, name: std::string::String| {
+                this.name = name;
+                Ok(())
+            });
+            methods.add_method("take_self", |lua, _, ()| {
+                let ud: AnyUserData = lua.globals().get("config")?;
+                ud.take::<Config>().map(|_| ())
+            });
+        }
+    }
+
+    let lua = Lua::new();
+    let drops = Arc::new(Mutex::new(0));
+
+    let ud = lua.create_userdata(Config {
+        name: "default".into(),
+        drops: drops.clone(),
+    })?;
+    lua.globals().set("config", ud.clone())?;
+    lua.load(r#"config:set_name("custom")"#).exec()?;
+
+    // Borrowed values cannot be moved out
+    {
+        let _config = ud.borrow::<Config>()?;
+        match ud.take::<Config>() {
+            Err(Error::UserDataBorrowMutError) => {}
+            r => panic!("expected UserDataBorrowMutError, got {:?}", r),
+        }
+    }
+    match lua.load("config:take_self()").exec() {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.as_ref() {
+            Error::UserDataBorrowMutError => {}
+            err => panic!("expected UserDataBorrowMutError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+    #[derive(Debug)]
+    struct Other;
+    impl UserData for Other {}
+    match ud.take::<Other>() {
+        Err(Error::UserDataTypeMismatch) => {}
+        r => panic!("expected UserDataTypeMismatch, got {:?}", r),
+    }
+
+    let config = ud.take::<Config>()?;
+    assert_eq!(config.name, "custom");
+    assert_eq!(*drops.lock().unwrap(), 0);
+
+    match ud.borrow::<Config>() {
+        Err(Error::UserDataDestructed) => {}
+        r => panic!("expected UserDataDestructed, got {:?}", r),
+    }
+    match ud.take::<Config>() {
+        Err(Error::UserDataDestructed) => {}
+        r => panic!("expected UserDataDestructed, got {:?}", r),
+    }
+    assert!(lua.load(r#"config:set_name("other")"#).exec().is_err());
+
+    // The value is dropped exactly once
+    drop(config);
+    lua.globals().set("config", Nil)?;
+    drop(ud);
+    lua.gc_collect()?;
+    lua.gc_collect()?;
+    assert_eq!(*drops.lock().unwrap(), 1);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "lua54")]
+fn test_metamethod_close_error() -> Result<()> {
+    struct File(Arc<Mutex<Vec<std::string::String>>>);
+
+    impl UserData for File {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Close, |_, this, err: Option<String>| {
+                let err = match err {
+                    Some(err) => err.to_str()?.to_string(),
+                    None => "ok".to_string(),
+                };
+                this.0.lock().unwrap().push(err);
+                Ok(())
+            });
+        }
+    }
+
+    let lua = Lua::new();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    lua.globals().set(
+        "open",
+        lua.create_function(move |_, ()| Ok(File(log2.clone())))?,
+    )?;
+
+    lua.load(
+        r#"
+        do
+            local f <close> = open()
+        end
+        local ok = pcall(function()
+            local f <close> = open()
+            error("boom", 0)
+        end)
+        assert(not ok)
+    "#,
+    )
+    .exec()?;
+
+    assert_eq!(*log.lock().unwrap(), vec!["ok", "boom"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_userdata_default_tostring() -> Result<()> {
+    struct Plain;
+    impl UserData for Plain {}
+
+    struct Named<T>(T);
+    impl<T> UserData for Named<T> {
+        fn default_tostring() -> bool {
+            true
+        }
+    }
+
+    struct Custom;
+    impl UserData for Custom {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::ToString, |_, _, ()| Ok("custom"));
+        }
+
+        fn default_tostring() -> bool {
+            true
+        }
+    }
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set("plain", Plain)?;
+    globals.set("named", Named(1u8))?;
+    globals.set("generic", Named(std::string::String::new()))?;
+    globals.set("custom", Custom)?;
+
+    let tostring = |name: &str| -> Result<std::string::String> {
+        lua.load(&format!("tostring({})", name)).eval()
+    };
+    assert!(tostring("plain")?.starts_with("userdata: "));
+    let named = tostring("named")?;
+    assert!(named.starts_with("Named<u8>: "), "{}", named);
+    let generic = tostring("generic")?;
+    assert!(generic.starts_with("Named<String>: "), "{}", generic);
+    assert_eq!(tostring("custom")?, "custom");
+
+    // The address matches the one Lua shows by default
+    let address = lua
+        .load("return string.format('%p', named)")
+        .eval::<std::string::String>();
+    if let Ok(address) = address {
+        assert_eq!(named, format!("Named<u8>: {}", address));
+    }
+
+    lua.scope(|scope| {
+        let ud = scope.create_nonstatic_userdata(Named(&named))?;
+        let s = lua
+            .load("return tostring(...)")
+            .call::<_, std::string::String>(ud)?;
+        assert!(s.starts_with("Named<&String>: "), "{}", s);
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_max_userdata() -> Result<()> {
+    #[derive(Clone)]
+    struct MyUserData;
+    impl UserData for MyUserData {}
+
+    let lua = Lua::new();
+    assert_eq!(lua.set_max_userdata(3), 0);
+    lua.globals().set(
+        "new",
+        lua.create_function(|lua, ()| lua.create_userdata(MyUserData))?,
+    )?;
+
+    let created = lua
+        .load(
+            r#"
+            local list = {}
+            while true do
+                local ok, ud = pcall(new)
+                if not ok then
+                    assert(tostring(ud):find("too many userdata"))
+                    break
+                end
+                list[#list + 1] = ud
+            end
+            return #list
+        "#,
+        )
+        .eval::<i64>()?;
+    assert_eq!(created, 3);
+
+    // Collected userdata no longer count against the limit
+    lua.gc_collect()?;
+    lua.gc_collect()?;
+    let ud1 = lua.create_userdata(MyUserData)?;
+    let ud2 = lua.create_userdata(MyUserData)?;
+    let ud3 = lua.create_userdata(MyUserData)?;
+    match lua.create_userdata(MyUserData) {
+        Err(Error::UserDataLimitReached) => {}
+        r => panic!("expected UserDataLimitReached, got {:?}", r),
+    }
+
+    // Taking the value out releases its slot, as does leaving a scope
+    ud1.take::<MyUserData>()?;
+    lua.scope(|scope| {
+        scope.create_userdata(MyUserData)?;
+        assert!(scope.create_nonstatic_userdata(MyUserData).is_err());
+        Ok(())
+    })?;
+    lua.create_userdata(MyUserData)?;
+    drop((ud2, ud3));
+
+    // Raising the limit allows more userdata
+    assert_eq!(lua.set_max_userdata(0), 3);
+    let all = (0..10)
+        .map(|_| lua.create_userdata(MyUserData))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(all.len(), 10);
+
+    Ok(())
+}
+
+#[test]
+fn test_registered_metatables() -> Result<()> {
+    struct Kept(i64);
+    struct Temporary;
+
+    impl UserData for Kept {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, this, ()| Ok(this.0));
+        }
+    }
+    impl UserData for Temporary {}
+
+    let lua = Lua::new();
+    assert!(lua.registered_metatables().is_empty());
+
+    let globals = lua.globals();
+    globals.set("kept", Kept(7))?;
+    globals.set("temporary", Temporary)?;
+    let taken = lua.create_userdata(Temporary)?;
+    let names = lua.registered_metatables();
+    assert_eq!(names.len(), 2);
+    assert!(names.iter().any(|name| name.ends_with("::Kept")));
+    assert!(names.iter().any(|name| name.ends_with("::Temporary")));
+
+    // Live values keep their metatables
+    assert_eq!(lua.clear_unused_metatables(), 0);
+
+    globals.set("temporary", Nil)?;
+    lua.gc_collect()?;
+    assert_eq!(lua.clear_unused_metatables(), 0);
+    taken.take::<Temporary>()?;
+    assert_eq!(lua.clear_unused_metatables(), 1);
+    let names = lua.registered_metatables();
+    assert_eq!(names.len(), 1);
+    assert!(names[0].ends_with("::Kept"));
+
+    // Remaining values still work and pruned types are registered again on demand
+    assert_eq!(lua.load("kept:get()").eval::<i64>()?, 7);
+    let temporary = lua.create_userdata(Temporary)?;
+    assert!(temporary.borrow::<Temporary>().is_ok());
+    assert_eq!(lua.registered_metatables().len(), 2);
+
+    Ok(())
+}
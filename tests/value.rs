@@ -1,4 +1,9 @@
-use mlua::{Lua, Result, Value};
+use std::string::String as StdString;
+
+use mlua::{
+    Error, Function, Lua, MetaMethod, MultiValue, Nil, Result, Table, ToLua, UserData,
+    UserDataMethods, Value,
+};
 
 #[test]
 fn test_value_eq() -> Result<()> {
@@ -55,3 +60,271 @@ fn test_value_eq() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_value_raw_equals() -> Result<()> {
+    let lua = Lua::new();
+
+    let (t1, t2, t3): (Value, Value, Value) = lua
+        .load(
+            r#"
+            local mt = { __eq = function(a, b) return a.id == b.id end }
+            local bad = { __eq = function() error("cannot compare") end }
+            return setmetatable({id = 1}, mt), setmetatable({id = 1}, mt), setmetatable({}, bad)
+        "#,
+        )
+        .eval()?;
+
+    assert!(t1 != t2);
+    assert!(t1.equals(&t2)?);
+    assert!(!t1.raw_equals(&t2)?);
+    assert!(t1.raw_equals(t1.clone())?);
+    assert!(!t1.equals(lua.create_table()?.to_lua(&lua)?)?);
+    match t3.equals(t3.clone()) {
+        Ok(true) => {}
+        r => panic!("expected identical tables to be equal, got {:?}", r),
+    }
+    match t3.equals(&t1) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("cannot compare")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    // Numbers follow Lua semantics regardless of subtype
+    let one: Value = lua.load("1").eval()?;
+    let one_float: Value = lua.load("1.0").eval()?;
+    assert!(one.equals(&one_float)?);
+    assert!(one.raw_equals(&one_float)?);
+    assert!(Value::Integer(1).equals(Value::Number(1.0))?);
+    assert!(!Value::Integer(1).equals(Value::Number(1.5))?);
+    assert!(Value::Nil.raw_equals(Value::Nil)?);
+    assert!(!Value::Nil.equals(Value::Boolean(false))?);
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    assert!(!Value::Integer(i64::MAX).equals(Value::Number(9223372036854775808.0))?);
+
+    // Strings are compared by content but never coerced to numbers
+    let s1 = Value::String(lua.create_string("1")?);
+    assert!(s1.equals(Value::String(lua.create_string("1")?))?);
+    assert!(!s1.equals(&one)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_multi_value_partial() -> Result<()> {
+    let lua = Lua::new();
+
+    let values = lua.load(r#"1, "two", 3"#).eval::<MultiValue>()?;
+    let (converted, err) = values.try_into_partial::<i64>(&lua);
+    assert_eq!(converted, vec![1]);
+    match err {
+        Some(Error::FromLuaConversionError { .. }) => {}
+        e => panic!("expected FromLuaConversionError, got {:?}", e),
+    }
+
+    let values = lua.load("1, 2, 3").eval::<MultiValue>()?;
+    let (converted, err) = values.try_into_partial::<i64>(&lua);
+    assert_eq!(converted, vec![1, 2, 3]);
+    assert!(err.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_multi_value_vec_order() -> Result<()> {
+    let lua = Lua::new();
+
+    let values = vec![Value::Integer(1), Value::Boolean(true), Value::Nil];
+    let multi = MultiValue::from_vec(values.clone());
+    assert_eq!(multi.iter().cloned().collect::<Vec<_>>(), values);
+    assert_eq!(multi.clone().into_vec(), values);
+    assert_eq!(
+        MultiValue::from_vec(multi.clone().into_vec()).into_vec(),
+        values
+    );
+
+    // The same order is used when passing arguments to and from Lua
+    let pack = lua
+        .load("return function(...) return ... end")
+        .eval::<Function>()?;
+    let returned = pack.call::<_, MultiValue>(multi)?.into_vec();
+    assert_eq!(returned, values);
+
+    let multi = lua.load("return 'a', 'b', 'c'").eval::<MultiValue>()?;
+    let strings = multi
+        .into_vec()
+        .into_iter()
+        .map(|v| lua.unpack::<String>(v))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(strings, vec!["a", "b", "c"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_multi_value_deque() -> Result<()> {
+    let lua = Lua::new();
+
+    let mut multi = MultiValue::new();
+    multi.push_back(Value::Integer(2));
+    multi.push_back(Value::Integer(3));
+    multi.push_front(Value::Integer(1));
+    assert_eq!(multi.len(), 3);
+    assert_eq!(multi.get(0), Some(&Value::Integer(1)));
+    assert_eq!(multi.get(2), Some(&Value::Integer(3)));
+    assert_eq!(multi.get(3), None);
+
+    let sum = lua
+        .load("return function(a, b, c) return a * 100 + b * 10 + c end")
+        .eval::<Function>()?;
+    assert_eq!(sum.call::<_, i64>(multi.clone())?, 123);
+
+    assert_eq!(multi.pop_back(), Some(Value::Integer(3)));
+    assert_eq!(multi.pop_front(), Some(Value::Integer(1)));
+    assert_eq!(multi.pop_back(), Some(Value::Integer(2)));
+    assert_eq!(multi.pop_back(), None);
+    assert_eq!(multi.pop_front(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_value_freeze() -> Result<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+
+    let original = lua
+        .load(
+            r#"
+            local t = { name = "app", limits = { depth = 3 }, list = { 1, 2, 3 } }
+            t.self = t
+            return t
+        "#,
+        )
+        .eval::<Value>()?;
+    let frozen = original.freeze()?;
+    assert!(matches!(*frozen, Value::Table(_)));
+    assert_ne!(*frozen, original);
+    globals.set("original", original)?;
+    globals.set("frozen", frozen)?;
+
+    lua.load(
+        r#"
+        assert(frozen.name == "app")
+        assert(frozen.limits.depth == 3)
+        assert(frozen.list[2] == 2)
+        assert(frozen.self == frozen)
+        assert(frozen.limits ~= original.limits)
+    "#,
+    )
+    .exec()?;
+
+    for code in &[
+        "frozen.name = 'other'",
+        "frozen.missing = 1",
+        "frozen.limits.depth = 10",
+        "frozen.list[4] = 4",
+        "setmetatable(frozen, nil)",
+    ] {
+        assert!(lua.load(code).exec().is_err(), "`{}` must fail", code);
+    }
+    lua.load("assert(frozen.limits.depth == 3)").exec()?;
+
+    lua.load(
+        r#"
+        original.name = "changed"
+        assert(frozen.name == "app")
+    "#,
+    )
+    .exec()?;
+
+    #[cfg(not(any(feature = "lua51", feature = "luajit")))]
+    lua.load(
+        r#"
+        assert(#frozen.list == 3)
+        local sum = 0
+        for _, v in pairs(frozen.list) do sum = sum + v end
+        assert(sum == 6)
+    "#,
+    )
+    .exec()?;
+
+    // Keys are not frozen, so tables used as keys can still be looked up
+    let (key, with_keys) = lua
+        .load("local k = {} return k, { [k] = { 1 } }")
+        .eval::<(Table, Value)>()?;
+    globals.set("key", key)?;
+    globals.set("frozen", with_keys.freeze()?)?;
+    lua.load(
+        r#"
+        assert(frozen[key][1] == 1)
+        key.x = 1
+        assert(not pcall(function() frozen[key][1] = 2 end))
+    "#,
+    )
+    .exec()?;
+
+    let value = Value::Integer(42).freeze()?;
+    assert_eq!(value.into_value(), Value::Integer(42));
+
+    Ok(())
+}
+
+#[test]
+fn test_value_to_string_lossy() -> Result<()> {
+    struct Point(i64, i64);
+
+    impl UserData for Point {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+                Ok(format!("point({}, {})", this.0, this.1))
+            });
+        }
+    }
+
+    let lua = Lua::new();
+
+    let point = Value::UserData(lua.create_userdata(Point(1, 2))?);
+    assert_eq!(point.to_string_lossy()?, "point(1, 2)");
+
+    let table = lua.create_table()?;
+    assert!(Value::Table(table.clone())
+        .to_string_lossy()?
+        .starts_with("table: "));
+    let bad = lua.load("setmetatable({}, { __tostring = function() error('oops') end })");
+    assert!(bad.eval::<Value>()?.to_string_lossy().is_err());
+
+    let s = Value::String(lua.create_string(b"caf\xe9")?);
+    assert_eq!(s.to_string_lossy()?, "caf\u{FFFD}");
+
+    // Values not living in Lua are formatted like Lua does
+    let tostring: Function = lua.globals().get("tostring")?;
+    let mut values = vec![Nil, Value::Boolean(true), Value::Integer(-42)];
+    for &n in &[
+        0.0,
+        -0.0,
+        0.5,
+        1.0,
+        -3.0,
+        0.1,
+        1.0 / 3.0,
+        123456.789,
+        1e15,
+        1e100,
+        1.5e-5,
+        2.5e-300,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+    ] {
+        values.push(Value::Number(n));
+    }
+    for value in values {
+        assert_eq!(
+            value.to_string_lossy()?,
+            tostring.call::<_, StdString>(value.clone())?,
+            "{:?}",
+            value
+        );
+    }
+
+    Ok(())
+}